@@ -26,8 +26,16 @@
 #include "verilator_sim_ctrl.h"
 #endif
 
+// Transactions are received from the host as frames: a two byte big-endian
+// header holding the payload length, followed by the payload. If FRAME_HOLD_CS
+// is set in the header, CSB stays asserted after the payload has been clocked
+// out, so that the next frame continues the same transaction. A frame without
+// payload only changes the state of CSB.
+#define FRAME_HEADER_LEN 2
+#define FRAME_HOLD_CS 0x8000
+#define MAX_TRANSACTION 0x7fff
+
 // This holds the necessary SPI state.
-#define MAX_TRANSACTION 4
 struct spidpi_ctx {
   int loglevel;
   char ptyname[64];
@@ -48,6 +56,9 @@ struct spidpi_ctx {
   int nmax;
   char driving;
   int state;
+  int hold;
+  int nhdr;
+  unsigned char hdr[FRAME_HEADER_LEN];
   char buf[MAX_TRANSACTION];
 };
 
@@ -57,6 +68,7 @@ struct spidpi_ctx {
 #define SP_DMOVE 2
 #define SP_LASTBIT 3
 #define SP_CSRISE 4
+#define SP_HOLD 5
 #define SP_FINISH 99
 
 // Enable this define to stop tracing at cycle 4
@@ -73,7 +85,9 @@ void *spidpi_create(const char *name, int mode, int loglevel) {
   ctx->mon = monitor_spi_init(mode);
   ctx->tick = 0;
   ctx->msbfirst = 1;
-  ctx->nmax = MAX_TRANSACTION;
+  ctx->nmax = 0;
+  ctx->nhdr = 0;
+  ctx->hold = 0;
   ctx->nin = 0;
   ctx->nout = 0;
   ctx->bout = 0;
@@ -110,7 +124,7 @@ void *spidpi_create(const char *name, int mode, int loglevel) {
       "\n"
       "SPI: Created %s for %s. Connect to it with any terminal program, e.g.\n"
       "$ screen %s\n"
-      "NOTE: a SPI transaction is run for every 4 characters entered.\n",
+      "NOTE: transactions are framed by a two byte big-endian length header.\n",
       ctx->ptyname, name, ctx->ptyname);
 
   rv = snprintf(ctx->mon_pathname, PATH_MAX, "%s/%s.log", cwd, name);
@@ -131,6 +145,65 @@ void *spidpi_create(const char *name, int mode, int loglevel) {
   return (void *)ctx;
 }
 
+// Returns true if the read from the host completed, having read `len` bytes.
+static int spidpi_read_host(struct spidpi_ctx *ctx, void *buf, int len,
+                            int *count) {
+  int n = read(ctx->host, buf, len);
+  if (n == -1) {
+    if (errno != EAGAIN) {
+      fprintf(stderr, "Read on SPI FIFO gave %s\n", strerror(errno));
+    }
+    return 0;
+  }
+  *count += n;
+  return n == len;
+}
+
+// Receives (part of) a frame from the host, and starts clocking it out once
+// complete.
+static void spidpi_read_frame(struct spidpi_ctx *ctx) {
+  if (ctx->nhdr < FRAME_HEADER_LEN) {
+    if (!spidpi_read_host(ctx, &ctx->hdr[ctx->nhdr],
+                          FRAME_HEADER_LEN - ctx->nhdr, &ctx->nhdr)) {
+      return;
+    }
+    int header = (ctx->hdr[0] << 8) | ctx->hdr[1];
+    ctx->hold = (header & FRAME_HOLD_CS) != 0;
+    ctx->nmax = header & ~FRAME_HOLD_CS;
+    ctx->nin = 0;
+  }
+  if (ctx->nin < ctx->nmax) {
+    if (!spidpi_read_host(ctx, &ctx->buf[ctx->nin], ctx->nmax - ctx->nin,
+                          &ctx->nin)) {
+      return;
+    }
+  }
+  ctx->nhdr = 0;
+  ctx->nin = 0;
+  if (ctx->nmax == 0) {
+    if (ctx->hold) {
+      // CSB low, clock stopped
+      ctx->driving = ctx->cpol ? P2D_SCK : 0;
+      ctx->state = SP_HOLD;
+    } else {
+      // CSB high, clock stopped
+      ctx->driving = P2D_CSB | (ctx->cpol ? P2D_SCK : 0);
+      ctx->state = SP_IDLE;
+    }
+    return;
+  }
+  ctx->nout = 0;
+  ctx->bout = ctx->msbfirst ? 0x80 : 0x01;
+  ctx->bin = ctx->msbfirst ? 0x80 : 0x01;
+  ctx->din = 0;
+  ctx->state = SP_CSFALL;
+#ifdef VERILATOR
+#ifdef CONTROL_TRACE
+  VerilatorSimCtrl::GetInstance().TraceOn();
+#endif
+#endif
+}
+
 char spidpi_tick(void *ctx_void, const svLogicVecVal *d2p_data) {
   struct spidpi_ctx *ctx = (struct spidpi_ctx *)ctx_void;
   assert(ctx);
@@ -150,31 +223,12 @@ char spidpi_tick(void *ctx_void, const svLogicVecVal *d2p_data) {
   monitor_spi(ctx->mon, ctx->mon_file, ctx->loglevel, ctx->tick, ctx->driving,
               d2p);
 
-  if (ctx->state == SP_IDLE) {
-    int n = read(ctx->host, &(ctx->buf[ctx->nin]), ctx->nmax - ctx->nin);
-    if (n == -1) {
-      if (errno != EAGAIN) {
-        fprintf(stderr, "Read on SPI FIFO gave %s\n", strerror(errno));
-      }
-    } else {
-      ctx->nin += n;
-      if (ctx->nin == ctx->nmax) {
-        ctx->nout = 0;
-        ctx->nin = 0;
-        ctx->bout = ctx->msbfirst ? 0x80 : 0x01;
-        ctx->bin = ctx->msbfirst ? 0x80 : 0x01;
-        ctx->din = 0;
-        ctx->state = SP_CSFALL;
-#ifdef VERILATOR
-#ifdef CONTROL_TRACE
-        VerilatorSimCtrl::GetInstance().TraceOn();
-#endif
-#endif
-      }
-    }
+  if (ctx->state == SP_IDLE || ctx->state == SP_HOLD) {
+    spidpi_read_frame(ctx);
   }
   // SPI clock toggles every 4th tick (i.e. freq=primary_frequency/8)
-  if ((ctx->tick & 3) || (ctx->state == SP_IDLE)) {
+  if ((ctx->tick & 3) || (ctx->state == SP_IDLE) ||
+      (ctx->state == SP_HOLD)) {
     return ctx->driving;
   }
 
@@ -232,9 +286,15 @@ char spidpi_tick(void *ctx_void, const svLogicVecVal *d2p_data) {
         ctx->state = SP_DMOVE;
         break;
       case SP_CSRISE:
-        // CSB high, clock stopped
-        ctx->driving = P2D_CSB;
-        ctx->state = SP_IDLE;
+        if (ctx->hold) {
+          // CSB stays low, clock stopped, awaiting the next frame
+          ctx->driving = ctx->cpol ? P2D_SCK : 0;
+          ctx->state = SP_HOLD;
+        } else {
+          // CSB high, clock stopped
+          ctx->driving = P2D_CSB;
+          ctx->state = SP_IDLE;
+        }
         break;
       case SP_FINISH:
 #ifdef VERILATOR
@@ -11,12 +11,13 @@
 
 #ifdef OT_IS_ENGLISH_BREAKFAST_REDUCED_SUPPORT_FOR_INTERNAL_USE_ONLY_
 
-#include "aon_timer_regs.h"  // Generated.
-#include "clkmgr_regs.h"     // Generated.
-#include "lc_ctrl_regs.h"    // Generated.
-#include "otp_ctrl_regs.h"   // Generated.
-#include "rstmgr_regs.h"     // Generated.
-#include "uart_regs.h"       // Generated.
+#include "aon_timer_regs.h"   // Generated.
+#include "clkmgr_regs.h"      // Generated.
+#include "flash_ctrl_regs.h"  // Generated.
+#include "lc_ctrl_regs.h"     // Generated.
+#include "otp_ctrl_regs.h"    // Generated.
+#include "rstmgr_regs.h"      // Generated.
+#include "uart_regs.h"        // Generated.
 
 #else
 
@@ -54,7 +54,7 @@ pub fn process_config_file(env: &mut TransportWrapperBuilder, conf_file: &Path)
         let path = subdir.join(included_conf_file);
         process_config_file(env, &path)?
     }
-    env.add_configuration_file(res)
+    env.add_configuration_file(res, conf_file)
 }
 
 static BUILTINS: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
@@ -74,5 +74,6 @@ static BUILTINS: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
         "/__builtin__/hyperdebug_teacup.json" => include_str!("hyperdebug_teacup.json"),
         "/__builtin__/opentitan_ultradebug.json" => include_str!("opentitan_ultradebug.json"),
         "/__builtin__/opentitan_verilator.json" => include_str!("opentitan_verilator.json"),
+        "/__builtin__/opentitan_sim.json" => include_str!("opentitan_sim.json"),
     }
 });
@@ -7,8 +7,9 @@
 use crate::io::gpio::{PinMode, PullMode};
 use crate::io::spi::TransferMode;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::time::Duration;
 
 /// Configuration of a particular GPIO pin.
 #[derive(Deserialize, Clone, Debug)]
@@ -74,6 +75,38 @@ pub struct StrappingConfiguration {
     pub pins: Vec<PinConfiguration>,
 }
 
+/// One step of a `SequenceConfiguration`, written as an object with a single key, e.g.
+/// `{ apply_strapping: "RESET" }` or `{ delay: "10ms" }`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SequenceStep {
+    /// Apply the named strapping.
+    ApplyStrapping(String),
+    /// Return the pins of the named strapping to their default configuration.
+    RemoveStrapping(String),
+    /// Drive the named pin to the given level.
+    SetPin { pin: String, level: bool },
+    /// Pause for the given duration.
+    Delay(#[serde(with = "humantime_serde")] Duration),
+    /// Poll the named pin until it reads the given level, failing if that has not happened
+    /// within the given duration.
+    WaitForPin {
+        pin: String,
+        level: bool,
+        #[serde(with = "humantime_serde")]
+        timeout: Duration,
+    },
+}
+
+/// Named sequence of strapping changes, pin writes and delays, such as for power sequencing.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SequenceConfiguration {
+    /// The user-visible name of the sequence.
+    pub name: String,
+    /// Steps to perform, in order.
+    pub steps: Vec<SequenceStep>,
+}
+
 /// Parity configuration for UART communication.
 #[derive(Deserialize, Clone, Debug)]
 pub enum UartParity {
@@ -93,7 +126,7 @@ pub enum UartStopBits {
 }
 
 /// Configuration of a particular UART port.
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Default, Deserialize, Clone, Debug)]
 pub struct UartConfiguration {
     /// The user-visible name of the UART.
     pub name: String,
@@ -103,6 +136,10 @@ pub struct UartConfiguration {
     pub parity: Option<UartParity>,
     /// Stop bits configuration for UART communication.
     pub stopbits: Option<UartStopBits>,
+    /// Whether to honor XON/XOFF characters from the device before transmitting.
+    pub flow_control: Option<bool>,
+    /// Whether to enable RTS/CTS handshake signals, for backends having them.
+    pub hardware_flow_control: Option<bool>,
     /// Name of the UART as defined by the transport.
     pub alias_of: Option<String>,
 }
@@ -128,6 +165,13 @@ pub struct SpiConfiguration {
     pub host_in_device_out: Option<String>,
     /// Which GPIO pin should be used for chip select.
     pub chip_select: Option<String>,
+    /// Which GPIO pin should be used for the third data lane (IO2) in quad mode.
+    pub io2: Option<String>,
+    /// Which GPIO pin should be used for the fourth data lane (IO3) in quad mode.
+    pub io3: Option<String>,
+    /// Generate SPI transactions by bitbanging the GPIO pins given above, rather than using a
+    /// SPI controller of the transport.
+    pub bitbang: Option<bool>,
     /// Name of the SPI controller as defined by the transport.
     pub alias_of: Option<String>,
 }
@@ -149,9 +193,13 @@ pub struct I2cConfiguration {
 /// confguration file.
 #[derive(Deserialize, Clone, Debug)]
 pub struct ConfigurationFile {
-    /// Optional specification of transport backend, for which this
-    /// configuration applies (to be implemented).
+    /// Optional specification of transport backend to use, unless specified on the command
+    /// line, or by other configuration files, which must agree.
     pub interface: Option<String>,
+    /// List of transport backends to which this configuration applies.  If not empty, it is an
+    /// error to use this file with any other backend.
+    #[serde(default)]
+    pub interfaces: Vec<String>,
     /// List of names of other configuration files to include recursively.
     #[serde(default)]
     pub includes: Vec<String>,
@@ -168,6 +216,9 @@ pub struct ConfigurationFile {
     /// List of named sets of additional GPIO pin configurations (pullup/pulldown).
     #[serde(default)]
     pub strappings: Vec<StrappingConfiguration>,
+    /// List of named sequences of strapping changes, pin writes and delays.
+    #[serde(default)]
+    pub sequences: Vec<SequenceConfiguration>,
     /// List of SPI port configurations.
     #[serde(default)]
     pub spi: Vec<SpiConfiguration>,
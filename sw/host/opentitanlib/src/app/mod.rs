@@ -12,28 +12,30 @@ mod spi;
 
 use crate::debug::openocd::OpenOcdJtagChain;
 use crate::io::emu::Emulator;
-use crate::io::gpio::{GpioBitbanging, GpioMonitoring, GpioPin, PinMode, PullMode};
+use crate::io::gpio::{GpioBitbanging, GpioError, GpioMonitoring, GpioPin, PinMode, PullMode};
 use crate::io::i2c::Bus;
 use crate::io::ioexpander::IoExpander;
 use crate::io::jtag::{JtagChain, JtagParams};
 use crate::io::nonblocking_help::NonblockingHelp;
-use crate::io::spi::{Target, TransferMode};
+use crate::io::spi::{SpiError, Target, TransferMode};
 use crate::io::uart::Uart;
+use crate::transport::common::spi::BitbangSpi;
 use crate::transport::{
-    ioexpander, Capability, ProgressIndicator, ProxyOps, Transport, TransportError,
-    TransportInterfaceType,
+    ioexpander, Capability, ProgressIndicator, ProxyOps, RequiresMismatch, Transport,
+    TransportError, TransportInterfaceType,
 };
 
 use anyhow::{bail, ensure, Result};
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
 use serde_annotate::Annotate;
 use std::any::Any;
 use std::cell::{Cell, RefCell};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
 pub struct NoProgressBar;
@@ -114,18 +116,23 @@ impl ProgressIndicator for StagedProgressBar {
     }
 }
 
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, Debug, Serialize)]
 pub struct PinConfiguration {
     /// The input/output mode of the GPIO pin.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub mode: Option<PinMode>,
     /// The default/initial level of the pin (true means high), has effect only in `PushPull` or
     /// `OpenDrain` modes.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub level: Option<bool>,
     /// Whether the pin has pullup/down resistor enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub pull_mode: Option<PullMode>,
     /// The default/initial analog level of the pin in Volts, has effect only in `AnalogOutput`
     /// mode.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub volts: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub invert: Option<bool>,
 }
 
@@ -172,38 +179,138 @@ impl PinConfiguration {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize)]
 pub struct SpiConfiguration {
     pub underlying_instance: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub mode: Option<TransferMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub serial_clock: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub host_out_device_in: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub host_in_device_out: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub chip_select: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io2: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io3: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitbang: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub bits_per_word: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub bits_per_sec: Option<u32>,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize)]
 pub struct I2cConfiguration {
     pub underlying_instance: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub default_addr: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub bits_per_sec: Option<u32>,
 }
 
+/// Names of the configuration files declaring each entry of the configuration, as reported by
+/// `TransportWrapper::resolved_configuration()`.
+#[derive(Default)]
+struct ConfSources {
+    provides: HashMap<String, String>,
+    pins: HashMap<String, Vec<String>>,
+    strappings: HashMap<String, Vec<String>>,
+    sequences: HashMap<String, Vec<String>>,
+    spi: HashMap<String, Vec<String>>,
+    i2c: HashMap<String, Vec<String>>,
+    uarts: HashMap<String, Vec<String>>,
+}
+
+impl ConfSources {
+    fn add(map: &mut HashMap<String, Vec<String>>, name: &str, source: &str) {
+        let sources = map.entry(name.to_string()).or_default();
+        if !sources.iter().any(|s| s == source) {
+            sources.push(source.to_string());
+        }
+    }
+}
+
+/// An entry of a `ResolvedConfiguration`, along with the configuration files declaring it.
+#[derive(Serialize)]
+pub struct Sourced<T> {
+    #[serde(flatten)]
+    pub conf: T,
+    pub sources: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedPin {
+    /// Name of the pin as known to the transport, if this is an alias.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias_of: Option<String>,
+    #[serde(flatten)]
+    pub conf: PinConfiguration,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedStrapping {
+    pub pins: BTreeMap<String, PinConfiguration>,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedSequence {
+    pub steps: Vec<config::SequenceStep>,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedUart {
+    /// Name of the UART as known to the transport.
+    pub alias_of: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow_control: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardware_flow_control: Option<bool>,
+}
+
+#[derive(Serialize)]
+pub struct ResolvedProvides {
+    pub value: String,
+    pub source: String,
+}
+
+/// The configuration of a `TransportWrapper`, after merging all configuration files and
+/// resolving aliases.
+#[derive(Serialize)]
+pub struct ResolvedConfiguration {
+    pub interface: String,
+    pub provides: BTreeMap<String, ResolvedProvides>,
+    pub pins: BTreeMap<String, Sourced<ResolvedPin>>,
+    pub strappings: BTreeMap<String, Sourced<ResolvedStrapping>>,
+    pub sequences: BTreeMap<String, Sourced<ResolvedSequence>>,
+    pub spi: BTreeMap<String, Sourced<SpiConfiguration>>,
+    pub i2c: BTreeMap<String, Sourced<I2cConfiguration>>,
+    pub uarts: BTreeMap<String, Sourced<ResolvedUart>>,
+}
+
 pub struct TransportWrapperBuilder {
     interface: String,
     disable_dft_on_reset: bool,
     openocd_adapter_config: Option<PathBuf>,
-    provides_list: Vec<(String, String)>,
-    requires_list: Vec<(String, String)>,
+    // Entries of `provides` and `requires` maps, and lists of `interfaces`, each along with the
+    // name of the configuration file declaring them.
+    provides_list: Vec<(String, String, String)>,
+    requires_list: Vec<(String, String, String)>,
+    interfaces_list: Vec<(Vec<String>, String)>,
+    conf_sources: ConfSources,
     pin_alias_map: HashMap<String, String>,
     pin_on_io_expander_map: HashMap<String, config::IoExpanderPin>,
     uart_map: HashMap<String, String>,
+    uart_conf_map: HashMap<String, config::UartConfiguration>,
     pin_conf_list: Vec<(String, PinConfiguration)>,
     spi_conf_map: HashMap<String, config::SpiConfiguration>,
     i2c_conf_map: HashMap<String, config::I2cConfiguration>,
     strapping_conf_map: HashMap<String, Vec<(String, PinConfiguration)>>,
+    sequence_conf_map: HashMap<String, Vec<config::SequenceStep>>,
     io_expander_conf_map: HashMap<String, config::IoExpander>,
 }
 
@@ -212,16 +319,22 @@ pub struct TransportWrapperBuilder {
 // transport will have been computed from a number ConfigurationFiles.
 pub struct TransportWrapper {
     transport: Rc<dyn Transport>,
+    interface: String,
+    conf_sources: ConfSources,
     disable_dft_on_reset: Cell<bool>,
     openocd_adapter_config: Option<PathBuf>,
     provides_map: HashMap<String, String>,
     pin_map: HashMap<String, String>,
     artificial_pin_map: HashMap<String, Rc<dyn GpioPin>>,
     uart_map: HashMap<String, String>,
+    uart_conf_map: HashMap<String, config::UartConfiguration>,
+    // Flow control settings merged across all aliases, keyed by the name of the underlying UART.
+    resolved_uart_conf_map: HashMap<String, config::UartConfiguration>,
     pin_conf_map: HashMap<String, PinConfiguration>,
     spi_conf_map: HashMap<String, SpiConfiguration>,
     i2c_conf_map: HashMap<String, I2cConfiguration>,
     strapping_conf_map: HashMap<String, HashMap<String, PinConfiguration>>,
+    sequence_conf_map: HashMap<String, Vec<config::SequenceStep>>,
     //
     // Below fields are lazily populated, as instances are requested.
     //
@@ -235,6 +348,8 @@ pub struct TransportWrapper {
     spi_logical_map: RefCell<HashMap<String, Rc<spi::LogicalSpiWrapper>>>,
     i2c_physical_map: RefCell<HashMap<String, Rc<i2c::PhysicalI2cWrapper>>>,
     i2c_logical_map: RefCell<HashMap<String, Rc<i2c::LogicalI2cWrapper>>>,
+    // Names of UARTs which have had flow control settings from configuration files applied.
+    uart_configured: RefCell<HashSet<String>>,
 }
 
 impl TransportWrapperBuilder {
@@ -245,13 +360,17 @@ impl TransportWrapperBuilder {
             openocd_adapter_config: None,
             provides_list: Vec::new(),
             requires_list: Vec::new(),
+            interfaces_list: Vec::new(),
+            conf_sources: ConfSources::default(),
             pin_alias_map: HashMap::new(),
             pin_on_io_expander_map: HashMap::new(),
             uart_map: HashMap::new(),
+            uart_conf_map: HashMap::new(),
             pin_conf_list: Vec::new(),
             spi_conf_map: HashMap::new(),
             i2c_conf_map: HashMap::new(),
             strapping_conf_map: HashMap::new(),
+            sequence_conf_map: HashMap::new(),
             io_expander_conf_map: HashMap::new(),
         }
     }
@@ -307,6 +426,9 @@ impl TransportWrapperBuilder {
         merge_field(&mut entry.host_out_device_in, &spi_conf.host_out_device_in)?;
         merge_field(&mut entry.host_in_device_out, &spi_conf.host_in_device_out)?;
         merge_field(&mut entry.chip_select, &spi_conf.chip_select)?;
+        merge_field(&mut entry.io2, &spi_conf.io2)?;
+        merge_field(&mut entry.io3, &spi_conf.io3)?;
+        merge_field(&mut entry.bitbang, &spi_conf.bitbang)?;
         merge_field(&mut entry.alias_of, &spi_conf.alias_of)?;
         Ok(())
     }
@@ -327,7 +449,39 @@ impl TransportWrapperBuilder {
         Ok(())
     }
 
-    pub fn add_configuration_file(&mut self, file: config::ConfigurationFile) -> Result<()> {
+    fn record_uart_conf(
+        uart_conf_map: &mut HashMap<String, config::UartConfiguration>,
+        uart_conf: &config::UartConfiguration,
+    ) -> Result<(), ()> {
+        let entry = uart_conf_map
+            .entry(uart_conf.name.to_uppercase())
+            .or_insert_with(|| config::UartConfiguration {
+                name: uart_conf.name.to_uppercase(),
+                ..Default::default()
+            });
+        Self::record_uart_conf_fields(entry, uart_conf)
+    }
+
+    fn record_uart_conf_fields(
+        entry: &mut config::UartConfiguration,
+        uart_conf: &config::UartConfiguration,
+    ) -> Result<(), ()> {
+        merge_field(&mut entry.flow_control, &uart_conf.flow_control)?;
+        merge_field(
+            &mut entry.hardware_flow_control,
+            &uart_conf.hardware_flow_control,
+        )?;
+        Ok(())
+    }
+
+    /// Merges the content of a configuration file, `source` is its name, as reported in error
+    /// messages and by `TransportWrapper::resolved_configuration()`.
+    pub fn add_configuration_file(
+        &mut self,
+        file: config::ConfigurationFile,
+        source: &Path,
+    ) -> Result<()> {
+        let source = source.display().to_string();
         if let Some(interface) = file.interface {
             if self.interface.is_empty() {
                 self.interface = interface;
@@ -341,14 +495,22 @@ impl TransportWrapperBuilder {
                 ))
             }
         }
+        if !file.interfaces.is_empty() {
+            self.interfaces_list.push((file.interfaces, source.clone()));
+        }
         for (key, value) in file.provides {
-            self.provides_list.push((key, value));
+            self.provides_list.push((key, value, source.clone()));
         }
         for (key, value) in file.requires {
-            self.requires_list.push((key, value));
+            self.requires_list.push((key, value, source.clone()));
         }
         // Merge content of configuration file into pin_map and other members.
         for pin_conf in file.pins {
+            ConfSources::add(
+                &mut self.conf_sources.pins,
+                &pin_conf.name.to_uppercase(),
+                &source,
+            );
             if let Some(alias_of) = &pin_conf.alias_of {
                 self.pin_alias_map
                     .insert(pin_conf.name.to_uppercase(), alias_of.clone());
@@ -375,6 +537,11 @@ impl TransportWrapperBuilder {
             Self::record_pin_conf(&mut self.pin_conf_list, &pin_conf);
         }
         for strapping_conf in file.strappings {
+            ConfSources::add(
+                &mut self.conf_sources.strappings,
+                &strapping_conf.name.to_uppercase(),
+                &source,
+            );
             let strapping_pin_map = self
                 .strapping_conf_map
                 .entry(strapping_conf.name.to_uppercase())
@@ -397,7 +564,37 @@ impl TransportWrapperBuilder {
                 Self::record_pin_conf(strapping_pin_map, &pin_conf);
             }
         }
+        for sequence_conf in file.sequences {
+            let name = sequence_conf.name.to_uppercase();
+            ConfSources::add(&mut self.conf_sources.sequences, &name, &source);
+            // Strapping names are case-insensitive, like in the `strappings` section.
+            let steps: Vec<config::SequenceStep> = sequence_conf
+                .steps
+                .into_iter()
+                .map(|step| match step {
+                    config::SequenceStep::ApplyStrapping(strapping) => {
+                        config::SequenceStep::ApplyStrapping(strapping.to_uppercase())
+                    }
+                    config::SequenceStep::RemoveStrapping(strapping) => {
+                        config::SequenceStep::RemoveStrapping(strapping.to_uppercase())
+                    }
+                    step => step,
+                })
+                .collect();
+            match self.sequence_conf_map.entry(name) {
+                Entry::Vacant(v) => {
+                    v.insert(steps);
+                }
+                Entry::Occupied(o) => {
+                    ensure!(
+                        *o.get() == steps,
+                        TransportError::InconsistentSequenceConf(o.key().to_string())
+                    );
+                }
+            }
+        }
         for spi_conf in file.spi {
+            ConfSources::add(&mut self.conf_sources.spi, &spi_conf.name, &source);
             Self::record_spi_conf(&mut self.spi_conf_map, &spi_conf).map_err(|_| {
                 TransportError::InconsistentConf(
                     TransportInterfaceType::Spi,
@@ -406,6 +603,7 @@ impl TransportWrapperBuilder {
             })?;
         }
         for i2c_conf in file.i2c {
+            ConfSources::add(&mut self.conf_sources.i2c, &i2c_conf.name, &source);
             Self::record_i2c_conf(&mut self.i2c_conf_map, &i2c_conf).map_err(|_| {
                 TransportError::InconsistentConf(
                     TransportInterfaceType::I2c,
@@ -415,9 +613,27 @@ impl TransportWrapperBuilder {
         }
         for uart_conf in file.uarts {
             if let Some(alias_of) = &uart_conf.alias_of {
+                ConfSources::add(
+                    &mut self.conf_sources.uarts,
+                    &uart_conf.name.to_uppercase(),
+                    &source,
+                );
                 self.uart_map
                     .insert(uart_conf.name.to_uppercase(), alias_of.clone());
             }
+            if uart_conf.flow_control.is_some() || uart_conf.hardware_flow_control.is_some() {
+                ConfSources::add(
+                    &mut self.conf_sources.uarts,
+                    &uart_conf.name.to_uppercase(),
+                    &source,
+                );
+                Self::record_uart_conf(&mut self.uart_conf_map, &uart_conf).map_err(|_| {
+                    TransportError::InconsistentConf(
+                        TransportInterfaceType::Uart,
+                        uart_conf.name.to_string(),
+                    )
+                })?;
+            }
             // TODO(#8769): Record baud / parity configration for later
             // use when opening uart.
         }
@@ -440,12 +656,14 @@ impl TransportWrapperBuilder {
 
     fn consolidate_provides_map(
         result_provides_map: &mut HashMap<String, String>,
-        provides_list: Vec<(String, String)>,
+        provides_sources: &mut HashMap<String, String>,
+        provides_list: Vec<(String, String, String)>,
     ) -> Result<()> {
-        for (key, value) in provides_list {
+        for (key, value, source) in provides_list {
             match result_provides_map.entry(key.clone()) {
                 Entry::Vacant(v) => {
                     v.insert(value);
+                    provides_sources.insert(key, source);
                 }
                 Entry::Occupied(v) => {
                     if v.get() != &value {
@@ -460,23 +678,50 @@ impl TransportWrapperBuilder {
         Ok(())
     }
 
+    /// Verifies all requirements, reporting every one not satisfied.
     fn verify_requires_list(
         provides_map: &HashMap<String, String>,
-        requires_list: &Vec<(String, String)>,
+        provides_sources: &HashMap<String, String>,
+        requires_list: &Vec<(String, String, String)>,
     ) -> Result<()> {
-        for (key, required_value) in requires_list {
-            match provides_map.get(key) {
-                Some(actual_value) if actual_value == required_value => (),
-                Some(actual_value) => bail!(TransportError::RequiresUnequal(
-                    key.to_string(),
-                    required_value.to_string(),
-                    actual_value.to_string()
-                )),
-                None => bail!(TransportError::RequiresMissing(
-                    key.to_string(),
-                    required_value.to_string()
-                )),
+        let mut mismatches = Vec::new();
+        for (key, required_value, source) in requires_list {
+            let provided = provides_map.get(key);
+            if provided == Some(required_value) {
+                continue;
             }
+            mismatches.push(RequiresMismatch {
+                key: key.to_string(),
+                required: required_value.to_string(),
+                required_by: source.to_string(),
+                provided: provided.map(|value| {
+                    let source = provides_sources
+                        .get(key)
+                        .map_or("transport", String::as_str);
+                    (value.to_string(), source.to_string())
+                }),
+            });
+        }
+        ensure!(
+            mismatches.is_empty(),
+            TransportError::RequiresUnsatisfied(mismatches)
+        );
+        Ok(())
+    }
+
+    fn verify_interfaces_list(
+        interface: &str,
+        interfaces_list: &Vec<(Vec<String>, String)>,
+    ) -> Result<()> {
+        for (interfaces, source) in interfaces_list {
+            ensure!(
+                interfaces.iter().any(|i| i == interface),
+                TransportError::ConfInterfaceMismatch(
+                    source.to_string(),
+                    interface.to_string(),
+                    interfaces.clone()
+                )
+            );
         }
         Ok(())
     }
@@ -525,6 +770,15 @@ impl TransportWrapperBuilder {
             if let Some(chip_select) = entry.chip_select.as_ref() {
                 conf.chip_select = Some(map_name(pin_alias_map, chip_select));
             }
+            if let Some(io2) = entry.io2.as_ref() {
+                conf.io2 = Some(map_name(pin_alias_map, io2));
+            }
+            if let Some(io3) = entry.io3.as_ref() {
+                conf.io3 = Some(map_name(pin_alias_map, io3));
+            }
+            if let Some(bitbang) = entry.bitbang {
+                conf.bitbang = Some(bitbang);
+            }
             if let Some(mode) = entry.mode {
                 conf.mode = Some(mode);
             }
@@ -586,6 +840,28 @@ impl TransportWrapperBuilder {
         }
     }
 
+    /// Merges the flow control settings declared for every alias of each UART, failing if any
+    /// two of them disagree.
+    fn consolidate_uart_conf_map(
+        uart_map: &HashMap<String, String>,
+        uart_conf_map: &HashMap<String, config::UartConfiguration>,
+    ) -> Result<HashMap<String, config::UartConfiguration>> {
+        let mut resolved_uart_conf_map = HashMap::new();
+        for (name, uart_conf) in uart_conf_map {
+            let resolved_name = map_name(uart_map, name);
+            let entry = resolved_uart_conf_map
+                .entry(resolved_name.clone())
+                .or_insert_with(|| config::UartConfiguration {
+                    name: resolved_name.clone(),
+                    ..Default::default()
+                });
+            Self::record_uart_conf_fields(entry, uart_conf).map_err(|_| {
+                TransportError::InconsistentConf(TransportInterfaceType::Uart, resolved_name)
+            })?;
+        }
+        Ok(resolved_uart_conf_map)
+    }
+
     fn consolidate_i2c_conf_map(
         i2c_conf_map: &HashMap<String, config::I2cConfiguration>,
     ) -> Result<HashMap<String, I2cConfiguration>> {
@@ -605,9 +881,10 @@ impl TransportWrapperBuilder {
     }
 
     pub fn build(
-        self,
+        mut self,
         transport: Box<dyn crate::transport::Transport>,
     ) -> Result<TransportWrapper> {
+        Self::verify_interfaces_list(&self.interface, &self.interfaces_list)?;
         let mut provides_map = if transport
             .capabilities()?
             .request(Capability::PROXY)
@@ -618,8 +895,16 @@ impl TransportWrapperBuilder {
         } else {
             HashMap::new()
         };
-        Self::consolidate_provides_map(&mut provides_map, self.provides_list)?;
-        Self::verify_requires_list(&provides_map, &self.requires_list)?;
+        Self::consolidate_provides_map(
+            &mut provides_map,
+            &mut self.conf_sources.provides,
+            self.provides_list,
+        )?;
+        Self::verify_requires_list(
+            &provides_map,
+            &self.conf_sources.provides,
+            &self.requires_list,
+        )?;
 
         let pin_conf_map =
             Self::consolidate_pin_conf_map(&self.pin_alias_map, &self.pin_conf_list)?;
@@ -633,23 +918,31 @@ impl TransportWrapperBuilder {
         }
         let spi_conf_map = Self::consolidate_spi_conf_map(&self.spi_conf_map, &self.pin_alias_map)?;
         let i2c_conf_map = Self::consolidate_i2c_conf_map(&self.i2c_conf_map)?;
+        let resolved_uart_conf_map =
+            Self::consolidate_uart_conf_map(&self.uart_map, &self.uart_conf_map)?;
         let mut transport_wrapper = TransportWrapper {
             transport: Rc::from(transport),
+            interface: self.interface,
+            conf_sources: self.conf_sources,
             disable_dft_on_reset: Cell::new(self.disable_dft_on_reset),
             openocd_adapter_config: self.openocd_adapter_config,
             provides_map,
             pin_map: self.pin_alias_map,
             artificial_pin_map: HashMap::new(),
             uart_map: self.uart_map,
+            uart_conf_map: self.uart_conf_map,
+            resolved_uart_conf_map,
             pin_conf_map,
             spi_conf_map,
             i2c_conf_map,
             strapping_conf_map,
+            sequence_conf_map: self.sequence_conf_map,
             pin_instance_map: RefCell::new(HashMap::new()),
             spi_physical_map: RefCell::new(HashMap::new()),
             spi_logical_map: RefCell::new(HashMap::new()),
             i2c_physical_map: RefCell::new(HashMap::new()),
             i2c_logical_map: RefCell::new(HashMap::new()),
+            uart_configured: RefCell::new(HashSet::new()),
         };
         let mut io_expanders: HashMap<String, IoExpander> = HashMap::new();
         for (name, conf) in self.io_expander_conf_map {
@@ -704,6 +997,143 @@ impl TransportWrapper {
         Ok(&self.provides_map)
     }
 
+    /// Returns the configuration resulting from all configuration files, along with the files
+    /// declaring each entry.
+    pub fn resolved_configuration(&self) -> ResolvedConfiguration {
+        let sources = &self.conf_sources;
+        let sourced = |map: &HashMap<String, Vec<String>>, name: &str| {
+            map.get(name).cloned().unwrap_or_default()
+        };
+        ResolvedConfiguration {
+            interface: self.interface.clone(),
+            provides: self
+                .provides_map
+                .iter()
+                .map(|(key, value)| {
+                    let source = sources.provides.get(key);
+                    let resolved = ResolvedProvides {
+                        value: value.clone(),
+                        source: source.map_or("transport", String::as_str).to_string(),
+                    };
+                    (key.clone(), resolved)
+                })
+                .collect(),
+            pins: sources
+                .pins
+                .keys()
+                .map(|name| {
+                    let resolved_name = map_name(&self.pin_map, name);
+                    let pin = ResolvedPin {
+                        conf: self
+                            .pin_conf_map
+                            .get(&resolved_name)
+                            .copied()
+                            .unwrap_or_default(),
+                        alias_of: (resolved_name != *name).then_some(resolved_name),
+                    };
+                    (
+                        name.clone(),
+                        Sourced {
+                            conf: pin,
+                            sources: sourced(&sources.pins, name),
+                        },
+                    )
+                })
+                .collect(),
+            strappings: self
+                .strapping_conf_map
+                .iter()
+                .map(|(name, pins)| {
+                    let strapping = ResolvedStrapping {
+                        pins: pins
+                            .iter()
+                            .map(|(pin, conf)| (pin.clone(), *conf))
+                            .collect(),
+                    };
+                    (
+                        name.clone(),
+                        Sourced {
+                            conf: strapping,
+                            sources: sourced(&sources.strappings, name),
+                        },
+                    )
+                })
+                .collect(),
+            sequences: self
+                .sequence_conf_map
+                .iter()
+                .map(|(name, steps)| {
+                    (
+                        name.clone(),
+                        Sourced {
+                            conf: ResolvedSequence {
+                                steps: steps.clone(),
+                            },
+                            sources: sourced(&sources.sequences, name),
+                        },
+                    )
+                })
+                .collect(),
+            spi: self
+                .spi_conf_map
+                .iter()
+                .map(|(name, conf)| {
+                    (
+                        name.clone(),
+                        Sourced {
+                            conf: SpiConfiguration {
+                                underlying_instance: conf.underlying_instance.clone(),
+                                serial_clock: conf.serial_clock.clone(),
+                                host_out_device_in: conf.host_out_device_in.clone(),
+                                host_in_device_out: conf.host_in_device_out.clone(),
+                                chip_select: conf.chip_select.clone(),
+                                io2: conf.io2.clone(),
+                                io3: conf.io3.clone(),
+                                ..*conf
+                            },
+                            sources: sourced(&sources.spi, name),
+                        },
+                    )
+                })
+                .collect(),
+            i2c: self
+                .i2c_conf_map
+                .iter()
+                .map(|(name, conf)| {
+                    (
+                        name.clone(),
+                        Sourced {
+                            conf: I2cConfiguration {
+                                underlying_instance: conf.underlying_instance.clone(),
+                                ..*conf
+                            },
+                            sources: sourced(&sources.i2c, name),
+                        },
+                    )
+                })
+                .collect(),
+            uarts: self
+                .uart_map
+                .keys()
+                .chain(self.uart_conf_map.keys())
+                .map(|name| {
+                    let conf = self.uart_conf_map.get(name);
+                    (
+                        name.clone(),
+                        Sourced {
+                            conf: ResolvedUart {
+                                alias_of: map_name(&self.uart_map, name),
+                                flow_control: conf.and_then(|c| c.flow_control),
+                                hardware_flow_control: conf.and_then(|c| c.hardware_flow_control),
+                            },
+                            sources: sourced(&sources.uarts, name),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
     pub fn query_provides(&self, key: &str) -> Result<&str> {
         self.provides_map
             .get(key)
@@ -723,14 +1153,14 @@ impl TransportWrapper {
                 opts,
             )?));
         }
-        // Use JTAG functionality of the transport driver itself.  (Currently, HyperDebug is the
-        // only transport which has such support.)
+        // Use JTAG functionality of the transport driver itself.  (Currently, HyperDebug,
+        // Verilator and the session proxy are the only transports which have such support.)
         self.transport.jtag(opts)
     }
 
     /// Returns a SPI [`Target`] implementation.
     pub fn spi(&self, name: &str) -> Result<Rc<dyn Target>> {
-        let name = name.to_uppercase();
+        let name = canonical_name(name);
         let mut spi_logical_map = self.spi_logical_map.borrow_mut();
         if let Some(instance) = spi_logical_map.get(&name) {
             return Ok(Rc::clone(instance) as Rc<dyn Target>);
@@ -744,9 +1174,12 @@ impl TransportWrapper {
             {
                 Rc::clone(instance)
             } else {
-                let instance = Rc::new(spi::PhysicalSpiWrapper::new(
-                    self.transport.spi(spi_conf.underlying_instance.as_str())?,
-                ));
+                let underlying_target = if spi_conf.bitbang == Some(true) {
+                    self.bitbang_spi(spi_conf)?
+                } else {
+                    self.transport.spi(spi_conf.underlying_instance.as_str())?
+                };
+                let instance = Rc::new(spi::PhysicalSpiWrapper::new(underlying_target));
                 spi_physical_map.insert(spi_conf.underlying_instance.clone(), Rc::clone(&instance));
                 instance
             };
@@ -765,9 +1198,36 @@ impl TransportWrapper {
         }
     }
 
+    /// Creates a software-defined SPI host, bitbanging the GPIO pins of the given configuration.
+    fn bitbang_spi(&self, spi_conf: &SpiConfiguration) -> Result<Rc<dyn Target>> {
+        let pin = |pin: &Option<String>, role: &str| -> Result<Rc<dyn GpioPin>> {
+            let Some(pin) = pin else {
+                bail!(SpiError::InvalidOption(format!(
+                    "Bitbanged SPI {} requires a {} pin",
+                    spi_conf.underlying_instance, role
+                )));
+            };
+            self.transport.gpio_pin(pin)
+        };
+        let optional_pin = |pin: &Option<String>| -> Result<Option<Rc<dyn GpioPin>>> {
+            pin.as_ref()
+                .map(|pin| self.transport.gpio_pin(pin))
+                .transpose()
+        };
+        Ok(Rc::new(BitbangSpi::new(
+            self.transport.gpio_bitbanging()?,
+            pin(&spi_conf.serial_clock, "serial_clock")?,
+            pin(&spi_conf.host_out_device_in, "host_out_device_in")?,
+            pin(&spi_conf.host_in_device_out, "host_in_device_out")?,
+            pin(&spi_conf.chip_select, "chip_select")?,
+            optional_pin(&spi_conf.io2)?,
+            optional_pin(&spi_conf.io3)?,
+        )))
+    }
+
     /// Returns a I2C [`Bus`] implementation.
     pub fn i2c(&self, name: &str) -> Result<Rc<dyn Bus>> {
-        let name = name.to_uppercase();
+        let name = canonical_name(name);
         let mut i2c_logical_map = self.i2c_logical_map.borrow_mut();
         if let Some(instance) = i2c_logical_map.get(&name) {
             return Ok(Rc::clone(instance) as Rc<dyn Bus>);
@@ -802,9 +1262,24 @@ impl TransportWrapper {
         }
     }
 
-    /// Returns a [`Uart`] implementation.
+    /// Returns a [`Uart`] implementation.  Flow control declared in configuration files, for
+    /// the UART or any of its aliases, is applied the first time it is requested.
     pub fn uart(&self, name: &str) -> Result<Rc<dyn Uart>> {
-        self.transport.uart(map_name(&self.uart_map, name).as_str())
+        let resolved_name = map_name(&self.uart_map, name);
+        let uart = self.transport.uart(resolved_name.as_str())?;
+        if self.uart_configured.borrow().contains(&resolved_name) {
+            return Ok(uart);
+        }
+        if let Some(conf) = self.resolved_uart_conf_map.get(&resolved_name) {
+            if let Some(flow_control) = conf.flow_control {
+                uart.set_flow_control(flow_control)?;
+            }
+            if let Some(hardware_flow_control) = conf.hardware_flow_control {
+                uart.set_hardware_flow_control(hardware_flow_control)?;
+            }
+        }
+        self.uart_configured.borrow_mut().insert(resolved_name);
+        Ok(uart)
     }
 
     /// Returns a [`GpioPin`] implementation.
@@ -876,6 +1351,42 @@ impl TransportWrapper {
         })
     }
 
+    /// Performs the steps of a sequence declared in configuration files, in order.
+    pub fn run_sequence(&self, name: &str) -> Result<()> {
+        let Some(steps) = self.sequence_conf_map.get(&name.to_uppercase()) else {
+            bail!(TransportError::InvalidSequenceName(name.to_string()));
+        };
+        for step in steps {
+            log::debug!("Sequence {}: {:?}", name, step);
+            match step {
+                config::SequenceStep::ApplyStrapping(strapping) => {
+                    self.pin_strapping(strapping)?.apply()?
+                }
+                config::SequenceStep::RemoveStrapping(strapping) => {
+                    self.pin_strapping(strapping)?.remove()?
+                }
+                config::SequenceStep::SetPin { pin, level } => self.gpio_pin(pin)?.write(*level)?,
+                config::SequenceStep::Delay(duration) => std::thread::sleep(*duration),
+                config::SequenceStep::WaitForPin {
+                    pin,
+                    level,
+                    timeout,
+                } => {
+                    let gpio_pin = self.gpio_pin(pin)?;
+                    let deadline = Instant::now() + *timeout;
+                    while gpio_pin.read()? != *level {
+                        ensure!(
+                            Instant::now() < deadline,
+                            GpioError::PinWaitTimeout(pin.to_string(), *level)
+                        );
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Returns a [`Emulator`] implementation.
     pub fn emulator(&self) -> Result<Rc<dyn Emulator>> {
         self.transport.emulator()
@@ -967,6 +1478,14 @@ impl TransportWrapper {
         Ok(())
     }
 
+    /// Reestablishes the connection to the debugger, after it has reset or been power-cycled,
+    /// and applies the default configuration from configuration files again, as the debugger
+    /// will have lost it.  Previously returned instances of `Uart`, `Target`, etc. remain usable.
+    pub fn reconnect(&self) -> Result<()> {
+        self.transport.reconnect()?;
+        self.apply_default_configuration(None)
+    }
+
     pub fn reset_target(&self, reset_delay: Duration, clear_uart_rx: bool) -> Result<()> {
         log::info!("Asserting the reset signal");
         if self.disable_dft_on_reset.get() {
@@ -991,10 +1510,21 @@ impl TransportWrapper {
     }
 }
 
+/// Returns the form of a pin/uart/spi/i2c port name used for lookups, that is, in uppercase.
+/// Names starting with `/` are paths (e.g. of device nodes), which are kept as they are, as the
+/// file system is likely case-sensitive.
+fn canonical_name(name: &str) -> String {
+    if name.starts_with('/') {
+        name.to_string()
+    } else {
+        name.to_uppercase()
+    }
+}
+
 /// Given an pin/uart/spi/i2c port name, if the name is a known alias, return the underlying
 /// name/number, otherwise return the string as is.
 fn map_name(map: &HashMap<String, String>, name: &str) -> String {
-    let name = name.to_uppercase();
+    let name = canonical_name(name);
     match map.get(&name) {
         Some(v) => {
             if v.eq(&name) {
@@ -1127,3 +1657,197 @@ impl PinStrapping {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(key: &str, value: &str, source: &str) -> (String, String, String) {
+        (key.to_string(), value.to_string(), source.to_string())
+    }
+
+    #[test]
+    fn test_requires_reports_all_mismatches() {
+        let mut provides_map = HashMap::new();
+        let mut provides_sources = HashMap::new();
+        TransportWrapperBuilder::consolidate_provides_map(
+            &mut provides_map,
+            &mut provides_sources,
+            vec![entry("board", "cw310", "board.json5")],
+        )
+        .unwrap();
+        let requires_list = vec![
+            entry("board", "cw310", "a.json5"),
+            entry("board", "cw340", "b.json5"),
+            entry("chip", "earlgrey", "c.json5"),
+        ];
+        let err = TransportWrapperBuilder::verify_requires_list(
+            &provides_map,
+            &provides_sources,
+            &requires_list,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Requirements of configuration files not satisfied:\n\
+             - board = \"cw340\" (required by b.json5)\n\
+             + board = \"cw310\" (provided by board.json5)\n\
+             - chip = \"earlgrey\" (required by c.json5)\n\
+             + chip not provided"
+        );
+    }
+
+    #[test]
+    fn test_map_name_keeps_paths() {
+        let map = HashMap::from([
+            ("CONSOLE".to_string(), "/dev/ttyUSB0".to_string()),
+            ("RESET".to_string(), "gpiochip0:3".to_string()),
+        ]);
+        assert_eq!(map_name(&map, "console"), "/dev/ttyUSB0");
+        assert_eq!(map_name(&map, "reset"), "GPIOCHIP0:3");
+        assert_eq!(map_name(&map, "/dev/spidev0.0"), "/dev/spidev0.0");
+        assert_eq!(map_name(&map, "spidev0.0"), "SPIDEV0.0");
+    }
+
+    #[test]
+    fn test_sequences() {
+        let conf = |steps: &str| -> config::ConfigurationFile {
+            serde_annotate::from_str(&format!(
+                "{{ sequences: [{{ name: \"power_cycle\", steps: [{steps}] }}] }}"
+            ))
+            .unwrap()
+        };
+        let steps = r#"
+            { apply_strapping: "reset" },
+            { set_pin: { pin: "VCC_EN", level: false } },
+            { delay: "100ms" },
+            { wait_for_pin: { pin: "POWER_GOOD", level: true, timeout: "1s" } },
+            { remove_strapping: "reset" },
+        "#;
+        let mut builder = TransportWrapperBuilder::new(String::new(), false);
+        builder
+            .add_configuration_file(conf(steps), Path::new("a.json5"))
+            .unwrap();
+        builder
+            .add_configuration_file(conf(steps), Path::new("b.json5"))
+            .unwrap();
+        assert_eq!(
+            builder.sequence_conf_map["POWER_CYCLE"],
+            vec![
+                config::SequenceStep::ApplyStrapping("RESET".to_string()),
+                config::SequenceStep::SetPin {
+                    pin: "VCC_EN".to_string(),
+                    level: false
+                },
+                config::SequenceStep::Delay(Duration::from_millis(100)),
+                config::SequenceStep::WaitForPin {
+                    pin: "POWER_GOOD".to_string(),
+                    level: true,
+                    timeout: Duration::from_secs(1)
+                },
+                config::SequenceStep::RemoveStrapping("RESET".to_string()),
+            ]
+        );
+        let err = builder
+            .add_configuration_file(conf(r#"{ delay: "1s" }"#), Path::new("c.json5"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Sequence \"POWER_CYCLE\" defined differently in multiple configuration files"
+        );
+    }
+
+    #[test]
+    fn test_uart_flow_control() {
+        let conf = |uart: &str| -> config::ConfigurationFile {
+            serde_annotate::from_str(&format!("{{ uarts: [{uart}] }}")).unwrap()
+        };
+        let mut builder = TransportWrapperBuilder::new(String::new(), false);
+        builder
+            .add_configuration_file(
+                conf(r#"{ name: "console", alias_of: "UART0", flow_control: true }"#),
+                Path::new("a.json5"),
+            )
+            .unwrap();
+        builder
+            .add_configuration_file(
+                conf(r#"{ name: "CONSOLE", hardware_flow_control: false }"#),
+                Path::new("b.json5"),
+            )
+            .unwrap();
+        let uart_conf = &builder.uart_conf_map["CONSOLE"];
+        assert_eq!(uart_conf.flow_control, Some(true));
+        assert_eq!(uart_conf.hardware_flow_control, Some(false));
+        assert_eq!(
+            builder.conf_sources.uarts["CONSOLE"],
+            vec!["a.json5".to_string(), "b.json5".to_string()]
+        );
+        let err = builder
+            .add_configuration_file(
+                conf(r#"{ name: "console", flow_control: false }"#),
+                Path::new("c.json5"),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Inconsistent configuration for Uart instance \"console\""
+        );
+    }
+
+    #[test]
+    fn test_uart_flow_control_aliases() {
+        let conf = |uarts: &str| -> config::ConfigurationFile {
+            serde_annotate::from_str(&format!("{{ uarts: [{uarts}] }}")).unwrap()
+        };
+        let mut builder = TransportWrapperBuilder::new(String::new(), false);
+        builder
+            .add_configuration_file(
+                conf(
+                    r#"{ name: "console", alias_of: "UART0", flow_control: true },
+                       { name: "uart0", hardware_flow_control: false }"#,
+                ),
+                Path::new("a.json5"),
+            )
+            .unwrap();
+        let resolved = TransportWrapperBuilder::consolidate_uart_conf_map(
+            &builder.uart_map,
+            &builder.uart_conf_map,
+        )
+        .unwrap();
+        assert_eq!(resolved["UART0"].flow_control, Some(true));
+        assert_eq!(resolved["UART0"].hardware_flow_control, Some(false));
+
+        builder
+            .add_configuration_file(
+                conf(r#"{ name: "debug", alias_of: "UART0", flow_control: false }"#),
+                Path::new("b.json5"),
+            )
+            .unwrap();
+        let err = TransportWrapperBuilder::consolidate_uart_conf_map(
+            &builder.uart_map,
+            &builder.uart_conf_map,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Inconsistent configuration for Uart instance \"UART0\""
+        );
+    }
+
+    #[test]
+    fn test_interfaces() {
+        let interfaces_list = vec![(
+            vec!["hyper310".to_string(), "hyper340".to_string()],
+            "hyperdebug.json5".to_string(),
+        )];
+        assert!(
+            TransportWrapperBuilder::verify_interfaces_list("hyper340", &interfaces_list).is_ok()
+        );
+        let err =
+            TransportWrapperBuilder::verify_interfaces_list("cw310", &interfaces_list).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Configuration file hyperdebug.json5 applies to interfaces [\"hyper310\", \"hyper340\"], not \"cw310\""
+        );
+    }
+}
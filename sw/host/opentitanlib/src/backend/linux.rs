@@ -0,0 +1,28 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use clap::Args;
+use std::path::PathBuf;
+
+use crate::transport::linux::{Linux, Options};
+use crate::transport::Transport;
+
+#[derive(Debug, Args)]
+pub struct LinuxOpts {
+    /// Directory in which the device nodes of SPI, I2C, GPIO and UART instances are found.
+    #[arg(long, default_value = "/dev")]
+    linux_dev_dir: PathBuf,
+
+    /// Initial baud rate of UARTs.
+    #[arg(long, default_value_t = 115200)]
+    linux_uart_baud: u32,
+}
+
+pub fn create(args: &LinuxOpts) -> Result<Box<dyn Transport>> {
+    Ok(Box::new(Linux::new(Options {
+        dev_dir: args.linux_dev_dir.clone(),
+        uart_baud: args.linux_uart_baud,
+    })))
+}
@@ -14,12 +14,16 @@ use crate::transport::dediprog::Dediprog;
 use crate::transport::hyperdebug::{
     C2d2Flavor, ChipWhispererFlavor, ServoMicroFlavor, StandardFlavor, Ti50Flavor,
 };
+use crate::transport::record::RecordingTransport;
 use crate::transport::{EmptyTransport, Transport};
 use crate::util::parse_int::ParseInt;
 
 mod chip_whisperer;
 mod hyperdebug;
+mod linux;
 mod proxy;
+mod replay;
+mod sim;
 mod ti50emulator;
 mod ultradebug;
 mod verilator;
@@ -53,9 +57,18 @@ pub struct BackendOpts {
     #[command(flatten)]
     pub verilator_opts: verilator::VerilatorOpts,
 
+    #[command(flatten)]
+    pub linux_opts: linux::LinuxOpts,
+
     #[command(flatten)]
     pub proxy_opts: proxy::ProxyOpts,
 
+    #[command(flatten)]
+    pub replay_opts: replay::ReplayOpts,
+
+    #[command(flatten)]
+    pub sim_opts: sim::SimOpts,
+
     #[command(flatten)]
     pub ti50emulator_opts: ti50emulator::Ti50EmulatorOpts,
 
@@ -69,6 +82,11 @@ pub struct BackendOpts {
     /// this argument must be specified if using JTAG.)
     #[arg(long)]
     pub openocd_adapter_config: Option<PathBuf>,
+
+    /// Record all operations on the UARTs, SPI and I2C buses, GPIO pins and JTAG of the
+    /// interface into a trace file, for later use with `--interface=replay`.
+    #[arg(long)]
+    pub record: Option<PathBuf>,
 }
 
 #[derive(Error, Debug)]
@@ -87,11 +105,17 @@ pub fn create(args: &BackendOpts) -> Result<TransportWrapper> {
     }
     let (backend, default_conf) = match env.get_interface() {
         "" => (create_empty_transport()?, None),
+        "linux" => (linux::create(&args.linux_opts)?, None),
         "proxy" => (proxy::create(&args.proxy_opts)?, None),
+        "replay" => (replay::create(&args.replay_opts)?, None),
         "verilator" => (
             verilator::create(&args.verilator_opts)?,
             Some(Path::new("/__builtin__/opentitan_verilator.json")),
         ),
+        "sim" => (
+            sim::create(&args.sim_opts)?,
+            Some(Path::new("/__builtin__/opentitan_sim.json")),
+        ),
         "ti50emulator" => (
             ti50emulator::create(&args.ti50emulator_opts)?,
             Some(Path::new("/__builtin__/ti50emulator.json")),
@@ -146,6 +170,10 @@ pub fn create(args: &BackendOpts) -> Result<TransportWrapper> {
             process_config_file(&mut env, conf_file)?
         }
     }
+    let backend: Box<dyn Transport> = match &args.record {
+        Some(trace) => Box::new(RecordingTransport::new(backend, trace)?),
+        None => backend,
+    };
     env.set_openocd_adapter_config(&args.openocd_adapter_config);
     env.build(backend)
 }
@@ -4,8 +4,9 @@
 
 use anyhow::Result;
 use clap::Args;
+use std::path::PathBuf;
 
-use crate::transport::proxy::Proxy;
+use crate::transport::proxy::{Proxy, ProxySecurity};
 use crate::transport::Transport;
 
 #[derive(Debug, Args)]
@@ -14,8 +15,50 @@ pub struct ProxyOpts {
     proxy: Option<String>,
     #[arg(long, default_value = "9900")]
     port: u16,
+    /// Connect to a session process listening on the given Unix domain socket, instead of TCP.
+    #[arg(long, conflicts_with_all = ["proxy", "proxy_command"])]
+    proxy_unix: Option<PathBuf>,
+    /// Shell command whose standard input and output are connected to a session process,
+    /// instead of TCP, e.g. "ssh lab-host opentitansession --stdio --interface=hyper310".
+    #[arg(long, conflicts_with = "proxy")]
+    proxy_command: Option<String>,
+    /// Name of the transport to operate, if the session process serves more than one.
+    #[arg(long)]
+    proxy_target: Option<String>,
+
+    /// Connect to the session process using TLS.
+    #[arg(long)]
+    proxy_tls: bool,
+    /// PEM file with CA certificates for verifying the session process, instead of the system
+    /// defaults.
+    #[arg(long)]
+    proxy_tls_ca: Option<PathBuf>,
+    /// PEM file with client certificate chain, if required by the session process.
+    #[arg(long)]
+    proxy_tls_cert: Option<PathBuf>,
+    /// PEM file with the private key of the client certificate.
+    #[arg(long)]
+    proxy_tls_key: Option<PathBuf>,
+    /// Pre-shared token, if required by the session process.
+    #[arg(long, env = "OPENTITAN_PROXY_TOKEN", hide_env_values = true)]
+    proxy_token: Option<String>,
 }
 
 pub fn create(args: &ProxyOpts) -> Result<Box<dyn Transport>> {
-    Ok(Box::new(Proxy::open(args.proxy.as_deref(), args.port)?))
+    let security = ProxySecurity {
+        tls: args.proxy_tls,
+        tls_ca: args.proxy_tls_ca.clone(),
+        tls_cert: args.proxy_tls_cert.clone(),
+        tls_key: args.proxy_tls_key.clone(),
+        token: args.proxy_token.clone(),
+    };
+    let target = args.proxy_target.as_deref();
+    let proxy = if let Some(path) = &args.proxy_unix {
+        Proxy::open_unix(path, &security, target)?
+    } else if let Some(command) = &args.proxy_command {
+        Proxy::open_command(command, &security, target)?
+    } else {
+        Proxy::open(args.proxy.as_deref(), args.port, &security, target)?
+    };
+    Ok(Box::new(proxy))
 }
@@ -0,0 +1,25 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{Context, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+use crate::transport::proxy::Replay;
+use crate::transport::Transport;
+
+#[derive(Debug, Args)]
+pub struct ReplayOpts {
+    /// Trace file previously recorded using --record, to be served by the "replay" interface.
+    #[arg(long)]
+    replay_trace: Option<PathBuf>,
+}
+
+pub fn create(args: &ReplayOpts) -> Result<Box<dyn Transport>> {
+    let trace = args
+        .replay_trace
+        .as_ref()
+        .context("--replay-trace is required by the replay interface")?;
+    Ok(Box::new(Replay::open(trace)?))
+}
@@ -0,0 +1,85 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{ensure, Result};
+use clap::Args;
+use std::path::PathBuf;
+
+use crate::transport::sim::{ConsoleScript, Options, Sim};
+use crate::transport::Transport;
+
+#[derive(Debug, Args)]
+pub struct SimOpts {
+    /// Size in bytes of the flash of the simulated target, a power of two of at most 256MiB.
+    #[arg(long, default_value_t = 1024 * 1024, value_parser = parse_flash_size)]
+    sim_flash_size: usize,
+
+    /// Initial content of the flash of the simulated target.
+    #[arg(long)]
+    sim_flash_image: Option<PathBuf>,
+
+    /// Echo data written to the console UART of the simulated target.
+    #[arg(long)]
+    sim_uart_loopback: bool,
+
+    /// HJSON file describing boot banner and responses of the simulated console.
+    #[arg(long)]
+    sim_console_script: Option<PathBuf>,
+
+    /// Make the SPI flash of the simulated target behave like the ROM bootstrap.
+    #[arg(long)]
+    sim_rom_bootstrap: bool,
+}
+
+/// Largest flash size whose density in bits fits the 32-bit field of the simulated SFDP table.
+const MAX_FLASH_SIZE: usize = 256 * 1024 * 1024;
+
+/// Parses the size of the simulated flash, which must be a non-zero power of two, as the flash
+/// wraps addresses around and describes its size in SFDP.
+fn parse_flash_size(s: &str) -> Result<usize> {
+    let size = s.parse::<usize>()?;
+    ensure!(
+        size.is_power_of_two(),
+        "Flash size must be a non-zero power of two, got {}",
+        size
+    );
+    ensure!(
+        size <= MAX_FLASH_SIZE,
+        "Flash size must be at most {} bytes, got {}",
+        MAX_FLASH_SIZE,
+        size
+    );
+    Ok(size)
+}
+
+pub fn create(args: &SimOpts) -> Result<Box<dyn Transport>> {
+    let options = Options {
+        flash_size: args.sim_flash_size,
+        flash_image: match &args.sim_flash_image {
+            Some(path) => std::fs::read(path)?,
+            None => Vec::new(),
+        },
+        uart_loopback: args.sim_uart_loopback,
+        console_script: match &args.sim_console_script {
+            Some(path) => ConsoleScript::from_file(path)?,
+            None => ConsoleScript::default(),
+        },
+        rom_bootstrap: args.sim_rom_bootstrap,
+    };
+    Ok(Box::new(Sim::new(options)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_flash_size() {
+        assert_eq!(parse_flash_size("1048576").unwrap(), 1024 * 1024);
+        assert_eq!(parse_flash_size("268435456").unwrap(), MAX_FLASH_SIZE);
+        for size in ["0", "1000", "536870912", "-1"] {
+            assert!(parse_flash_size(size).is_err(), "{}", size);
+        }
+    }
+}
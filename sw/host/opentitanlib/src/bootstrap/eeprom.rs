@@ -2,21 +2,169 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use std::ops::Range;
+use std::path::PathBuf;
 
 use crate::app::TransportWrapper;
-use crate::bootstrap::{Bootstrap, UpdateProtocol};
+use crate::bootstrap::{Bootstrap, BootstrapError, BootstrapOptions, UpdateProtocol};
+use crate::io::spi::Target;
 use crate::spiflash::SpiFlash;
 use crate::transport::{Capability, ProgressIndicator};
 
+/// Number of JEDEC continuation codes preceding lowRISC's manufacturer ID.
+const LOWRISC_JEDEC_CONT_CODES: usize = 12;
+/// lowRISC's manufacturer ID, in bank 13 of JEP106.
+const LOWRISC_JEDEC_MANUF_ID: u8 = 0xef;
+/// Bit of the first device ID byte set by the ROM bootstrap.
+const ROM_BOOTSTRAP_DEV_ID_BIT: u8 = 0x08;
+
 /// Implements the SPI EEPROM bootstrap protocol.
-pub struct Eeprom;
+pub struct Eeprom {
+    verify: bool,
+    incremental: bool,
+    base_image: Option<PathBuf>,
+}
 
 impl Eeprom {
-    /// Creates a new `Eeprom` protocol updater.
-    pub fn new() -> Self {
-        Eeprom
+    /// Creates a new `Eeprom` protocol updater from `options`.
+    pub fn new(options: &BootstrapOptions) -> Self {
+        Eeprom {
+            verify: options.verify,
+            incremental: options.incremental || options.base_image.is_some(),
+            base_image: options.base_image.clone(),
+        }
+    }
+
+    /// Erases and programs only the erase sectors whose contents differ between the current
+    /// flash contents (either read back from the device, or given by `base_image`) and the new
+    /// `payload`.
+    fn update_incremental(
+        &self,
+        flash: &SpiFlash,
+        spi: &dyn Target,
+        payload: &[u8],
+        progress: &dyn ProgressIndicator,
+    ) -> Result<()> {
+        let sector_size = flash
+            .erase
+            .last()
+            .ok_or_else(|| BootstrapError::NoSectorErase("--incremental".into()))?
+            .size as usize;
+        let current = match &self.base_image {
+            Some(path) => std::fs::read(path)
+                .with_context(|| format!("Failed to read base image {}", path.display()))?,
+            None => {
+                // Read back whole sectors covering the payload.  Anything beyond the last
+                // sector touched by the payload is left as-is.
+                let len = std::cmp::min(
+                    (payload.len() + sector_size - 1) / sector_size * sector_size,
+                    flash.size as usize,
+                );
+                let mut buf = vec![0u8; len];
+                flash.read_with_progress(spi, 0, &mut buf, progress, false)?;
+                buf
+            }
+        };
+        let sectors = changed_sectors(&current, payload, sector_size);
+        log::info!(
+            "Updating {} of {} sector(s)",
+            sectors.len(),
+            (std::cmp::max(current.len(), payload.len()) + sector_size - 1) / sector_size
+        );
+        progress.new_stage("", sectors.len() * sector_size);
+        for (i, sector) in sectors.iter().enumerate() {
+            let old = padded(&current, sector.clone());
+            if !old.iter().all(|&x| x == 0xff) {
+                flash.erase(spi, sector.start as u32, sector_size as u32)?;
+            }
+            if sector.start < payload.len() {
+                let end = std::cmp::min(sector.end, payload.len());
+                flash.program(spi, sector.start as u32, &payload[sector.start..end])?;
+            }
+            progress.progress((i + 1) * sector_size);
+        }
+        Ok(())
+    }
+
+    /// Reads back the flash contents and compares them against `payload`, reporting every
+    /// mismatching program page.
+    fn verify(
+        &self,
+        flash: &SpiFlash,
+        spi: &dyn Target,
+        payload: &[u8],
+        progress: &dyn ProgressIndicator,
+    ) -> Result<()> {
+        let mut buf = vec![0u8; payload.len()];
+        flash.read_with_progress(spi, 0, &mut buf, progress, false)?;
+        let pages = mismatched_pages(payload, &buf, flash.program_size as usize);
+        if pages.is_empty() {
+            return Ok(());
+        }
+        for page in pages.iter() {
+            let range = page.start as usize..std::cmp::min(page.end as usize, payload.len());
+            let offset = range.start
+                + payload[range.clone()]
+                    .iter()
+                    .zip(&buf[range])
+                    .position(|(a, b)| a != b)
+                    .unwrap();
+            log::error!(
+                "Mismatch in page {:#x}..{:#x}, first at {:#x}: expected {:#04x}, read {:#04x}",
+                page.start,
+                page.end,
+                offset,
+                payload[offset],
+                buf[offset],
+            );
+        }
+        Err(BootstrapError::VerifyFailed(pages.iter().map(|p| p.start).collect()).into())
+    }
+}
+
+/// Returns whether `jedec_id` identifies the ROM bootstrap, which erases the whole flash upon the
+/// first erase command of any kind and does not implement READ.
+fn is_rom_bootstrap(jedec_id: &[u8]) -> bool {
+    let cont_codes = jedec_id.iter().take_while(|&&b| b == 0x7f).count();
+    cont_codes == LOWRISC_JEDEC_CONT_CODES
+        && jedec_id.get(cont_codes) == Some(&LOWRISC_JEDEC_MANUF_ID)
+        && jedec_id
+            .get(cont_codes + 1)
+            .map_or(false, |&b| b & ROM_BOOTSTRAP_DEV_ID_BIT != 0)
+}
+
+/// Returns the contents of `data` within `range`, padded with 0xff (erased flash) past its end.
+fn padded(data: &[u8], range: Range<usize>) -> Vec<u8> {
+    let mut result = vec![0xffu8; range.len()];
+    if range.start < data.len() {
+        let end = std::cmp::min(range.end, data.len());
+        result[..end - range.start].copy_from_slice(&data[range.start..end]);
     }
+    result
+}
+
+/// Returns the byte ranges of the sectors of size `sector_size` whose contents differ between
+/// `old` and `new`.  Either image is considered to be padded with 0xff.
+fn changed_sectors(old: &[u8], new: &[u8], sector_size: usize) -> Vec<Range<usize>> {
+    let len = std::cmp::max(old.len(), new.len());
+    (0..len)
+        .step_by(sector_size)
+        .map(|start| start..start + sector_size)
+        .filter(|sector| padded(old, sector.clone()) != padded(new, sector.clone()))
+        .collect()
+}
+
+/// Returns the address ranges of the pages of size `page_size` in which `actual` differs from
+/// `expected`.
+fn mismatched_pages(expected: &[u8], actual: &[u8], page_size: usize) -> Vec<Range<u32>> {
+    expected
+        .chunks(page_size)
+        .zip(actual.chunks(page_size))
+        .enumerate()
+        .filter(|(_, (e, a))| e != a)
+        .map(|(i, _)| (i * page_size) as u32..((i + 1) * page_size) as u32)
+        .collect()
 }
 
 impl UpdateProtocol for Eeprom {
@@ -45,10 +193,71 @@ impl UpdateProtocol for Eeprom {
         progress: &dyn ProgressIndicator,
     ) -> Result<()> {
         let spi = container.spi_params.create(transport, "BOOTSTRAP")?;
+        if self.incremental || self.verify {
+            let jedec_id = SpiFlash::read_jedec_id(&*spi, LOWRISC_JEDEC_CONT_CODES + 3)?;
+            if is_rom_bootstrap(&jedec_id) {
+                let option = if self.incremental {
+                    "--incremental"
+                } else {
+                    "--verify"
+                };
+                return Err(BootstrapError::UnsupportedByRom(option.into()).into());
+            }
+        }
         let flash = SpiFlash::from_spi(&*spi)?;
-        flash.chip_erase(&*spi)?;
-        flash.program_with_progress(&*spi, 0, payload, progress)?;
+        if self.incremental {
+            self.update_incremental(&flash, &*spi, payload, progress)?;
+        } else {
+            flash.chip_erase(&*spi)?;
+            flash.program_with_progress(&*spi, 0, payload, progress)?;
+        }
+        if self.verify {
+            self.verify(&flash, &*spi, payload, progress)?;
+        }
         SpiFlash::chip_reset(&*spi)?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_sectors() {
+        let old = [[0u8; 16], [1u8; 16], [2u8; 16]].concat();
+        let mut new = old.clone();
+        new[20] = 0x55;
+        // Shorter image: the last sector must be erased.
+        new.truncate(40);
+        assert_eq!(changed_sectors(&old, &new, 16), vec![16..32, 32..48]);
+        // Longer image with a trailing erased sector is not a change.
+        let mut new = old.clone();
+        new.extend_from_slice(&[0xff; 16]);
+        assert!(changed_sectors(&old, &new, 16).is_empty());
+    }
+
+    #[test]
+    fn test_mismatched_pages() {
+        let expected = [0x5au8; 40];
+        let mut actual = expected;
+        actual[3] = 0;
+        actual[39] = 0;
+        assert_eq!(
+            mismatched_pages(&expected, &actual, 16),
+            vec![0..16, 32..48]
+        );
+        assert!(mismatched_pages(&expected, &expected, 16).is_empty());
+    }
+
+    #[test]
+    fn test_is_rom_bootstrap() {
+        let mut id = [[0x7fu8; 12].as_slice(), &[0xef, 0x08, 0x14]].concat();
+        assert!(is_rom_bootstrap(&id));
+        // ROM_EXT or owner firmware presenting the same chip without the bootstrap bit.
+        id[13] = 0x00;
+        assert!(!is_rom_bootstrap(&id));
+        // A Winbond flash, whose manufacturer ID lacks the continuation codes.
+        assert!(!is_rom_bootstrap(&[0xef, 0x48, 0x14]));
+    }
+}
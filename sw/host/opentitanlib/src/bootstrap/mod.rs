@@ -6,6 +6,7 @@ use anyhow::Result;
 use clap::{Args, ValueEnum};
 use humantime::parse_duration;
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::time::Duration;
 use thiserror::Error;
@@ -29,6 +30,16 @@ pub use legacy_rescue::LegacyRescueError;
 pub enum BootstrapError {
     #[error("Invalid hash length: {0}")]
     InvalidHashLength(usize),
+    #[error("Read-back verification failed, mismatching pages at (hex): {0:x?}")]
+    VerifyFailed(Vec<u32>),
+    #[error(
+        "{0} is not supported by the ROM bootstrap, which implements neither sector erase nor READ"
+    )]
+    UnsupportedByRom(String),
+    #[error("Target does not describe any erase command, as needed by {0}")]
+    NoSectorErase(String),
+    #[error("{0} is not supported by the {1:?} bootstrap protocol")]
+    UnsupportedOption(String, BootstrapProtocol),
 }
 impl_serializable_error!(BootstrapError);
 
@@ -97,6 +108,22 @@ pub struct BootstrapOptions {
     /// Duration of the flash-erase delay.
     #[arg(long, value_parser = parse_duration)]
     pub flash_erase_delay: Option<Duration>,
+    /// Read back the flash contents after programming and compare against the image.
+    /// Refused by protocols other than eeprom.  For the eeprom protocol, the target must support
+    /// the READ command, which the ROM bootstrap does not.
+    #[arg(long)]
+    pub verify: bool,
+    /// Only erase and program the flash sectors which differ from the existing contents, which
+    /// are read back from the target unless `--base-image` is given.  Refused by protocols other
+    /// than eeprom.  For the eeprom protocol, the target must support sector erase and the READ
+    /// command.  The ROM bootstrap erases the whole flash on the first erase command and is
+    /// therefore refused.
+    #[arg(long)]
+    pub incremental: bool,
+    /// Image known to be currently in flash, to compare against in incremental mode instead of
+    /// reading back the flash contents.  Implies `--incremental`.
+    #[arg(long)]
+    pub base_image: Option<PathBuf>,
 }
 
 /// Bootstrap wraps and drives the various bootstrap protocols.
@@ -142,11 +169,28 @@ impl<'a> Bootstrap<'a> {
             transport.proxy_ops()?.bootstrap(options, payload)?;
             return Ok(());
         }
+        if options.protocol != BootstrapProtocol::Eeprom {
+            // Only the eeprom protocol is able to read back the flash contents.
+            let option = if options.verify {
+                Some("--verify")
+            } else if options.incremental {
+                Some("--incremental")
+            } else if options.base_image.is_some() {
+                Some("--base-image")
+            } else {
+                None
+            };
+            if let Some(option) = option {
+                return Err(
+                    BootstrapError::UnsupportedOption(option.into(), options.protocol).into(),
+                );
+            }
+        }
         let updater: Box<dyn UpdateProtocol> = match options.protocol {
             BootstrapProtocol::Primitive => Box::new(primitive::Primitive::new(options)),
             BootstrapProtocol::Legacy => Box::new(legacy::Legacy::new(options)),
             BootstrapProtocol::LegacyRescue => Box::new(legacy_rescue::LegacyRescue::new(options)),
-            BootstrapProtocol::Eeprom => Box::new(eeprom::Eeprom::new()),
+            BootstrapProtocol::Eeprom => Box::new(eeprom::Eeprom::new(options)),
             BootstrapProtocol::Emulator => {
                 // Not intended to be implemented by this struct.
                 unimplemented!();
@@ -51,6 +51,21 @@ with_unknown! {
     }
 }
 
+impl BootSvcKind {
+    /// Returns the kind of the response the ROM_EXT sends for a request of this kind, or
+    /// `None` if this kind is not a request.
+    pub fn response(self) -> Option<BootSvcKind> {
+        match self {
+            Self::MinBl0SecVerRequest => Some(Self::MinBl0SecVerResponse),
+            Self::NextBl0SlotRequest => Some(Self::NextBl0SlotResponse),
+            Self::PrimaryBl0SlotRequest => Some(Self::PrimaryBl0SlotResponse),
+            Self::OwnershipUnlockRequest => Some(Self::OwnershipUnlockResponse),
+            Self::OwnershipActivateRequest => Some(Self::OwnershipActivateResponse),
+            _ => None,
+        }
+    }
+}
+
 /// The Boot Services header common to all boot services commands and responses.
 #[derive(Debug, Default, Serialize, Annotate)]
 pub struct Header {
@@ -200,15 +215,15 @@ impl TryFrom<&[u8]> for BootSvc {
     fn try_from(buf: &[u8]) -> std::result::Result<Self, Self::Error> {
         let header = Header::try_from(buf)?;
         let len = header.length as usize;
-        if buf.len() - Header::SIZE < len {
+        if len < Header::SIZE || buf.len() < len {
             return Err(ChipDataError::BadSize(len, buf.len()));
         }
-        let mut digest = Sha256::digest(&buf[Header::HASH_LEN..Header::SIZE]);
+        let mut digest = Sha256::digest(&buf[Header::HASH_LEN..len]);
         digest.reverse();
-        if digest[..] == buf[..Header::HASH_LEN] {
+        if digest[..] != buf[..Header::HASH_LEN] {
             return Err(ChipDataError::InvalidDigest);
         }
-        let buf = &buf[Header::SIZE..];
+        let buf = &buf[Header::SIZE..len];
         let message = match header.kind {
             BootSvcKind::Empty => Message::Empty(TryFrom::try_from(buf)?),
             BootSvcKind::MinBl0SecVerRequest => {
@@ -270,6 +285,35 @@ impl BootSvc {
         Ok(data)
     }
 
+    /// The status of a successful request (`kErrorOk`).
+    pub const STATUS_OK: u32 = 0x739;
+
+    /// Returns the status carried by a response message, or `None` if this is not a response.
+    pub fn status(&self) -> Option<u32> {
+        match &self.message {
+            Message::MinBl0SecVerResponse(m) => Some(m.status),
+            Message::NextBl0SlotResponse(m) => Some(m.status),
+            Message::PrimaryBl0SlotResponse(m) => Some(m.status),
+            Message::OwnershipUnlockResponse(m) => Some(m.status),
+            Message::OwnershipActivateResponse(m) => Some(m.status),
+            _ => None,
+        }
+    }
+
+    pub fn empty(payload: &[u32]) -> Self {
+        BootSvc {
+            header: Header {
+                digest: [0u32; 8],
+                identifier: Header::IDENTIFIER,
+                kind: BootSvcKind::Empty,
+                length: (Header::SIZE + Empty::SIZE) as u32,
+            },
+            message: Message::Empty(Empty {
+                payload: payload.to_vec(),
+            }),
+        }
+    }
+
     pub fn min_bl0_sec_ver(ver: u32) -> Self {
         BootSvc {
             header: Header {
@@ -604,3 +648,69 @@ impl OwnershipActivateResponse {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn min_bl0_sec_ver_response(ver: u32, status: u32) -> BootSvc {
+        BootSvc {
+            header: Header {
+                digest: [0u32; 8],
+                identifier: Header::IDENTIFIER,
+                kind: BootSvcKind::MinBl0SecVerResponse,
+                length: (Header::SIZE + MinBl0SecVerResponse::SIZE) as u32,
+            },
+            message: Message::MinBl0SecVerResponse(MinBl0SecVerResponse { ver, status }),
+        }
+    }
+
+    #[test]
+    fn test_response_roundtrip() -> Result<()> {
+        let mut data = min_bl0_sec_ver_response(5, BootSvc::STATUS_OK).to_bytes()?;
+        // The ROM_EXT returns the whole boot services area, trailing bytes must be ignored.
+        data.resize(Header::SIZE + Empty::SIZE, 0);
+        let bsvc = BootSvc::try_from(data.as_slice())?;
+        assert_eq!(bsvc.header.kind, BootSvcKind::MinBl0SecVerResponse);
+        assert_eq!(bsvc.status(), Some(BootSvc::STATUS_OK));
+        let Message::MinBl0SecVerResponse(m) = bsvc.message else {
+            panic!("unexpected message {:?}", bsvc.message);
+        };
+        assert_eq!(m.ver, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_digest() -> Result<()> {
+        let mut data = min_bl0_sec_ver_response(5, BootSvc::STATUS_OK).to_bytes()?;
+        data[Header::SIZE] ^= 1;
+        assert!(matches!(
+            BootSvc::try_from(data.as_slice()),
+            Err(ChipDataError::InvalidDigest)
+        ));
+        assert!(matches!(
+            BootSvc::try_from(&data[..Header::SIZE]),
+            Err(ChipDataError::BadSize(_, _))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_response_kinds() -> Result<()> {
+        let request = BootSvc::min_bl0_sec_ver(5);
+        assert_eq!(request.status(), None);
+        assert_eq!(
+            request.header.kind.response(),
+            Some(BootSvcKind::MinBl0SecVerResponse)
+        );
+        assert_eq!(BootSvcKind::Empty.response(), None);
+        let data = BootSvc::empty(&[1, 2]).to_bytes()?;
+        assert_eq!(data.len(), Header::SIZE + Empty::SIZE);
+        let bsvc = BootSvc::try_from(data.as_slice())?;
+        let Message::Empty(m) = bsvc.message else {
+            panic!("unexpected message {:?}", bsvc.message);
+        };
+        assert_eq!(&m.payload[..3], &[1, 2, 0]);
+        Ok(())
+    }
+}
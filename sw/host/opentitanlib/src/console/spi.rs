@@ -5,30 +5,82 @@
 use anyhow::Result;
 use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
-use std::time::Duration;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
-use crate::io::console::ConsoleDevice;
+use crate::io::console::{ConsoleDevice, ConsoleError};
+use crate::io::eeprom::{AddressMode, Transaction, MODE_111};
+use crate::io::gpio::GpioPin;
 use crate::io::spi::{Target, Transfer};
+use crate::io::uart::Uart;
+use crate::spiflash::SpiFlash;
 
-pub struct SpiConsoleDevice<'a> {
-    spi: &'a dyn Target,
+/// Console of a device communicating over its SPI device port, rather than a UART.  Each
+/// direction carries frames made of an 8-byte header (little endian frame number and data
+/// length) followed by the data, padded to a multiple of 4 bytes.
+///
+/// Frames from the device are read whenever the optional "ready" pin is asserted, or by polling
+/// if none is given: the host clocks out four 0xff bytes followed by the header and, if the
+/// frame number is the expected one, four 0xff bytes followed by the padded data.
+///
+/// Frames to the device carry at most `SPI_MAX_WRITE_LENGTH` bytes of data, such that they fit in
+/// a single page.  The device is in flash emulation mode, and each frame is sent as
+/// `WRITE_ENABLE` followed by `PAGE_PROGRAM` at address zero, with the frame (padded with 0xff)
+/// as payload.  The device sets the busy bit of its status register upon receiving the page
+/// program, and clears it once it has consumed the frame.  The host polls `READ_STATUS` until
+/// then, and does not send the next frame before, which provides flow control.  Frame numbers
+/// count from zero when the console is opened, allowing the device to detect lost frames.
+///
+/// Experimental: no device firmware implements frames sent by the host yet (the OTTF does not
+/// support the SPI device as console at all), only the simulated target does, see
+/// `crate::transport::sim`.  Writing therefore fails unless explicitly enabled through
+/// `enable_experimental_writes()`.
+///
+/// The `Uart` implementation allows the SPI console to be used wherever a UART console is
+/// expected, such as in `UartConsole` or ujson RPC (`UartSend` and `UartRecv`).
+pub struct SpiConsoleDevice {
+    spi: Rc<dyn Target>,
+    device_ready_pin: Option<Rc<dyn GpioPin>>,
     console_next_frame_number: Cell<u32>,
+    host_next_frame_number: Cell<u32>,
     rx_buf: RefCell<VecDeque<u8>>,
+    experimental_writes: bool,
 }
 
-impl<'a> SpiConsoleDevice<'a> {
+impl SpiConsoleDevice {
     const SPI_FRAME_HEADER_SIZE: usize = 8;
     const SPI_MAX_DATA_LENGTH: usize = 2032;
+    /// Frames to the device must fit in a single page program command.
+    const SPI_MAX_WRITE_LENGTH: usize =
+        SpiFlash::LEGACY_PAGE_SIZE as usize - Self::SPI_FRAME_HEADER_SIZE;
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+    // Not really forever, see `SerialPortUart::FOREVER`.
+    const FOREVER: Duration = Duration::from_secs(100 * 365 * 86400);
 
-    pub fn new(spi: &'a dyn Target) -> Self {
+    pub fn new(spi: Rc<dyn Target>, device_ready_pin: Option<Rc<dyn GpioPin>>) -> Self {
         Self {
             spi,
-            rx_buf: RefCell::new(VecDeque::new()),
+            device_ready_pin,
             console_next_frame_number: Cell::new(0),
+            host_next_frame_number: Cell::new(0),
+            rx_buf: RefCell::new(VecDeque::new()),
+            experimental_writes: false,
         }
     }
 
+    /// Allows sending data to the device, using the experimental framing described above.
+    pub fn enable_experimental_writes(mut self, enable: bool) -> Self {
+        self.experimental_writes = enable;
+        self
+    }
+
     fn read_from_spi(&self) -> Result<usize> {
+        if let Some(pin) = &self.device_ready_pin {
+            if !pin.read()? {
+                return Ok(0);
+            }
+        }
+
         // Read the SPI console frame header.
         let mut header = vec![0u8; SpiConsoleDevice::SPI_FRAME_HEADER_SIZE];
         self.spi
@@ -53,22 +105,153 @@ impl<'a> SpiConsoleDevice<'a> {
         self.rx_buf.borrow_mut().extend(&data[..data_len_bytes]);
         Ok(data_len_bytes)
     }
+
+    fn write_to_spi(&self, buf: &[u8]) -> Result<()> {
+        if !self.experimental_writes {
+            return Err(ConsoleError::UnsupportedError(
+                "Writing to the SPI console is experimental and has not been enabled".into(),
+            )
+            .into());
+        }
+        for chunk in buf.chunks(SpiConsoleDevice::SPI_MAX_WRITE_LENGTH) {
+            let frame_number = self.host_next_frame_number.get();
+            let mut frame =
+                Vec::with_capacity(SpiConsoleDevice::SPI_FRAME_HEADER_SIZE + chunk.len());
+            frame.extend_from_slice(&frame_number.to_le_bytes());
+            frame.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            frame.extend_from_slice(chunk);
+            frame.resize((frame.len() + 3) & !3, 0xff);
+            self.spi.run_eeprom_transactions(&mut [
+                Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+                Transaction::Write(
+                    MODE_111.cmd_addr(SpiFlash::PAGE_PROGRAM, 0, AddressMode::Mode3b),
+                    &frame,
+                ),
+                // Wait for the device to consume the frame before sending the next.
+                Transaction::WaitForBusyClear,
+            ])?;
+            self.host_next_frame_number
+                .set(frame_number.wrapping_add(1));
+        }
+        Ok(())
+    }
+
+    /// Copies from the internal data queue to the output buffer.
+    fn read_buffer(&self, buf: &mut [u8]) -> usize {
+        let mut rx_buf = self.rx_buf.borrow_mut();
+        let len = std::cmp::min(buf.len(), rx_buf.len());
+        for (dst, src) in buf.iter_mut().zip(rx_buf.drain(..len)) {
+            *dst = src;
+        }
+        len
+    }
 }
 
-impl<'a> ConsoleDevice for SpiConsoleDevice<'a> {
-    fn console_read(&self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+impl Uart for SpiConsoleDevice {
+    /// The SPI console has no baudrate, always returns zero.
+    fn get_baudrate(&self) -> Result<u32> {
+        Ok(0)
+    }
+
+    /// The SPI console has no baudrate, this is a no-op.
+    fn set_baudrate(&self, _baudrate: u32) -> Result<()> {
+        Ok(())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let len = self.read_timeout(buf, Self::FOREVER)?;
+            if len > 0 {
+                return Ok(len);
+            }
+        }
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let deadline = Instant::now() + timeout;
         // Attempt to refill the internal data queue if it is empty.
-        if self.rx_buf.borrow().is_empty() && self.read_from_spi()? == 0 {
-            return Ok(0);
+        while self.rx_buf.borrow().is_empty() && self.read_from_spi()? == 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(0);
+            }
+            std::thread::sleep(std::cmp::min(deadline - now, Self::POLL_INTERVAL));
         }
+        Ok(self.read_buffer(buf))
+    }
 
-        // Copy from the internal data queue to the output buffer.
-        let mut i: usize = 0;
-        while !self.rx_buf.borrow().is_empty() && i < buf.len() {
-            buf[i] = self.rx_buf.borrow_mut().pop_front().unwrap();
-            i += 1;
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        self.write_to_spi(buf)
+    }
+
+    fn clear_rx_buffer(&self) -> Result<()> {
+        self.rx_buf.borrow_mut().clear();
+        while self.read_from_spi()? > 0 {
+            self.rx_buf.borrow_mut().clear();
         }
+        Ok(())
+    }
+}
+
+impl ConsoleDevice for SpiConsoleDevice {
+    fn console_read(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.read_timeout(buf, timeout)
+    }
+
+    fn console_write(&self, buf: &[u8]) -> Result<()> {
+        self.write_to_spi(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::rpc::{UartRecv, UartSend};
+    use crate::transport::sim::{ConsoleResponse, ConsoleScript, Options, Sim};
+    use crate::transport::Transport;
+    use crc::{Crc, CRC_32_ISO_HDLC};
+
+    #[test]
+    fn test_writes_disabled() -> Result<()> {
+        let sim = Sim::new(Options::default())?;
+        let chip = sim.chip();
+        let console = SpiConsoleDevice::new(sim.spi("0")?, None);
+        assert!(console.console_write(b"hello").is_err());
+        assert!(chip.console_received().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_frames() -> Result<()> {
+        let sim = Sim::new(Options::default())?;
+        let chip = sim.chip();
+        let console = SpiConsoleDevice::new(sim.spi("0")?, None).enable_experimental_writes(true);
+        // Spans several frames, the last one requiring padding.
+        let data = (0..601).map(|i| i as u8).collect::<Vec<u8>>();
+        console.console_write(&data)?;
+        assert_eq!(chip.console_received(), data);
+        assert_eq!(console.host_next_frame_number.get(), 3);
+        Ok(())
+    }
 
-        Ok(i)
+    #[test]
+    fn test_rpc() -> Result<()> {
+        let response = r#"{"reply":"pong"}"#;
+        let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC).checksum(response.as_bytes());
+        let sim = Sim::new(Options {
+            console_script: ConsoleScript {
+                responses: vec![ConsoleResponse {
+                    expect: r#"\{"request":"ping"\}"#.to_string(),
+                    reply: format!("RESP_OK:{} CRC:{}\n", response, crc),
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        })?;
+        let console = SpiConsoleDevice::new(sim.spi("0")?, None).enable_experimental_writes(true);
+        serde_json::json!({"request": "ping"}).send(&console)?;
+        let reply = serde_json::Value::recv(&console, Duration::from_millis(100), true)?;
+        assert_eq!(reply["reply"], "pong");
+        Ok(())
     }
 }
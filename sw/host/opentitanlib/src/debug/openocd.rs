@@ -231,6 +231,8 @@ impl OpenOcd {
 pub struct OpenOcdJtagChain {
     /// OpenOCD server instance.
     openocd: OpenOcd,
+    /// Timeout for RISC-V debug module commands, overriding the OpenOCD default.
+    riscv_command_timeout: Option<Duration>,
 }
 
 /// Errors related to the OpenOCD server.
@@ -255,7 +257,16 @@ impl OpenOcdJtagChain {
         openocd.execute("transport select jtag")?;
         openocd.execute("scan_chain")?;
 
-        Ok(OpenOcdJtagChain { openocd })
+        Ok(OpenOcdJtagChain {
+            openocd,
+            riscv_command_timeout: None,
+        })
+    }
+
+    /// Override how long OpenOCD waits for the RISC-V debug module to complete a command.
+    /// Useful for slow targets, such as simulations.
+    pub fn set_riscv_command_timeout(&mut self, timeout: Duration) {
+        self.riscv_command_timeout = Some(timeout);
     }
 }
 
@@ -267,6 +278,12 @@ impl JtagChain for OpenOcdJtagChain {
             JtagTap::LcTap => include_str!(env!("openocd_lc_target_cfg")),
         };
         self.openocd.execute(target)?;
+        if let (JtagTap::RiscvTap, Some(timeout)) = (tap, self.riscv_command_timeout) {
+            self.openocd.execute(&format!(
+                "riscv set_command_timeout_sec {}",
+                timeout.as_secs().max(1)
+            ))?;
+        }
 
         // Capture outputs during initialization to see if error has occured during the process.
         let resp = self.openocd.execute("capture init")?;
@@ -312,7 +312,7 @@ impl DifLcCtrlToken {
     }
 }
 
-#[derive(IntoPrimitive, Clone, Debug, strum::EnumString)]
+#[derive(IntoPrimitive, Clone, Debug, Deserialize, Serialize, strum::EnumString)]
 #[strum(serialize_all = "snake_case")]
 #[repr(u32)]
 pub enum LcCtrlReg {
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{ensure, Result};
+use bindgen::dif;
 use memoffset::offset_of;
 use std::collections::HashSet;
 use std::convert::TryInto;
@@ -23,6 +24,7 @@ use crate::image::manifest::{
 };
 use crate::image::manifest_def::{ManifestSigverifyBuffer, ManifestSpec};
 use crate::image::manifest_ext::{ManifestExtEntry, ManifestExtSpec};
+use crate::ownership::OwnerFlashConfig;
 use crate::util::file::{FromReader, ToWriter};
 use crate::util::parse_int::ParseInt;
 
@@ -44,6 +46,14 @@ pub enum ImageError {
     MisplacedSignedExtension(u32),
     #[error("Invalid manifest major version: {0}. ECDSA support requires major version {1}.")]
     InvalidManifestVersionforEcdsa(u16, u16),
+    #[error("Invalid chunk offset or slot name: {0}")]
+    BadChunkOffset(String),
+    #[error("Chunk offset 0x{0:x} is beyond the assembled image size 0x{1:x}")]
+    ChunkOutOfBounds(usize, usize),
+    #[error("Chunks {} and {} overlap", .0.display(), .1.display())]
+    ChunkOverlap(PathBuf, PathBuf),
+    #[error("Chunk {} at 0x{1:x}..0x{2:x} is not within a single programmable flash region", .0.display())]
+    ChunkNotInRegion(PathBuf, usize, usize),
 }
 
 pub enum SigverifyParams {
@@ -108,6 +118,43 @@ pub struct SubImage<'a> {
 pub enum ImageChunk {
     Concat(PathBuf),
     Offset(PathBuf, usize),
+    Slot(PathBuf, FlashSlot),
+}
+
+impl ImageChunk {
+    /// Returns the file and offset of this chunk, given the end `pos` of the previous chunk.
+    fn placement(&self, pos: usize) -> (&Path, usize) {
+        match self {
+            ImageChunk::Concat(path) => (path, pos),
+            ImageChunk::Offset(path, offset) => (path, *offset),
+            ImageChunk::Slot(path, slot) => (path, slot.offset()),
+        }
+    }
+}
+
+/// Named flash slots which may be used in place of an explicit offset when assembling images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::EnumString, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+pub enum FlashSlot {
+    RomExtA,
+    OwnerA,
+    RomExtB,
+    OwnerB,
+}
+
+impl FlashSlot {
+    /// The size of one flash bank (i.e. one A/B side).
+    pub const BANK_SIZE: usize = dif::FLASH_CTRL_PARAM_BYTES_PER_BANK as usize;
+
+    /// Returns the offset of the slot from the start of flash.
+    pub fn offset(self) -> usize {
+        match self {
+            FlashSlot::RomExtA => 0,
+            FlashSlot::OwnerA => CHIP_ROM_EXT_SIZE_MAX as usize,
+            FlashSlot::RomExtB => Self::BANK_SIZE,
+            FlashSlot::OwnerB => Self::BANK_SIZE + CHIP_ROM_EXT_SIZE_MAX as usize,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -509,6 +556,9 @@ impl Image {
 }
 
 impl ImageAssembler {
+    /// The unit in which the regions of an owner flash configuration are expressed.
+    const FLASH_PAGE_SIZE: usize = dif::FLASH_CTRL_PARAM_BYTES_PER_PAGE as usize;
+
     /// Creates an `ImageAssembler` with a given `size` and mirroring parameters.
     pub fn with_params(size: usize, mirrored: bool) -> Self {
         ImageAssembler {
@@ -525,14 +575,18 @@ impl ImageAssembler {
 
     /// Parse a list of strings into chunks to be assembled.
     /// Each string may be a filename or a filename@offset describing where in the assembled image the contents of the file should appear.
-    /// The offset is an integer expressed in any of the bases accepted by [`ParseInt`].
+    /// The offset is an integer expressed in any of the bases accepted by [`ParseInt`], or the name of a [`FlashSlot`] (e.g. `owner_b`).
     pub fn parse(&mut self, chunks: &[impl AsRef<str>]) -> Result<()> {
         for chunk in chunks {
             if let Some((file, offset)) = chunk.as_ref().split_once('@') {
-                self.chunks.push(ImageChunk::Offset(
-                    PathBuf::from(file),
-                    usize::from_str(offset)?,
-                ));
+                let file = PathBuf::from(file);
+                if let Ok(offset) = usize::from_str(offset) {
+                    self.chunks.push(ImageChunk::Offset(file, offset));
+                } else if let Ok(slot) = offset.parse::<FlashSlot>() {
+                    self.chunks.push(ImageChunk::Slot(file, slot));
+                } else {
+                    return Err(ImageError::BadChunkOffset(offset.to_string()).into());
+                }
             } else {
                 self.chunks
                     .push(ImageChunk::Concat(PathBuf::from(chunk.as_ref())));
@@ -550,27 +604,67 @@ impl ImageAssembler {
         Ok(n)
     }
 
-    /// Assemble the image according to the parameters and parsed chunk specifications.
-    pub fn assemble(&self) -> Result<Vec<u8>> {
-        let size = if self.mirrored {
+    /// Returns whether any of the chunks is placed by slot name.
+    pub fn uses_slots(&self) -> bool {
+        self.chunks
+            .iter()
+            .any(|c| matches!(c, ImageChunk::Slot(_, _)))
+    }
+
+    fn assembled_size(&self) -> usize {
+        if self.mirrored {
             self.size / 2
         } else {
             self.size
-        };
-        let mut image = vec![0xff; size];
+        }
+    }
+
+    /// Checks that every chunk lies within a single flash region of `config` which permits
+    /// program and erase operations, and that no two chunks overlap.
+    pub fn validate(&self, config: &OwnerFlashConfig) -> Result<()> {
+        let mut placements = Vec::new();
         let mut pos = 0;
         for chunk in &self.chunks {
-            match chunk {
-                ImageChunk::Concat(path) => {
-                    let n = Self::read(path, &mut image[pos..])?;
-                    pos += n;
-                }
-                ImageChunk::Offset(path, offset) => {
-                    let n = Self::read(path, &mut image[*offset..])?;
-                    pos = offset + n;
-                }
+            let (path, start) = chunk.placement(pos);
+            let len = std::fs::metadata(path)?.len() as usize;
+            pos = start + len;
+            placements.push((path, start..pos));
+            if self.mirrored {
+                let half = self.assembled_size();
+                placements.push((path, start + half..pos + half));
+            }
+        }
+        for (i, (path, range)) in placements.iter().enumerate() {
+            let in_region = config.config.iter().any(|r| {
+                let start = r.start as usize * Self::FLASH_PAGE_SIZE;
+                let end = start + r.size as usize * Self::FLASH_PAGE_SIZE;
+                r.flags.program && r.flags.erase && start <= range.start && range.end <= end
+            });
+            ensure!(
+                in_region,
+                ImageError::ChunkNotInRegion(path.to_path_buf(), range.start, range.end)
+            );
+            for (other, other_range) in &placements[..i] {
+                ensure!(
+                    range.end <= other_range.start || other_range.end <= range.start,
+                    ImageError::ChunkOverlap(other.to_path_buf(), path.to_path_buf())
+                );
             }
         }
+        Ok(())
+    }
+
+    /// Assemble the image according to the parameters and parsed chunk specifications.
+    pub fn assemble(&self) -> Result<Vec<u8>> {
+        let size = self.assembled_size();
+        let mut image = vec![0xff; size];
+        let mut pos = 0;
+        for chunk in &self.chunks {
+            let (path, offset) = chunk.placement(pos);
+            ensure!(offset <= size, ImageError::ChunkOutOfBounds(offset, size));
+            let n = Self::read(path, &mut image[offset..])?;
+            pos = offset + n;
+        }
         if self.mirrored {
             image.extend_from_within(..size);
         }
@@ -638,6 +732,63 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_assemble_slots() -> Result<()> {
+        // Test image assembly by named slots.
+        let mut image = ImageAssembler::with_params(0x100000, false);
+        image.parse(&[
+            testdata!("hello.txt@rom_ext_a").to_str().unwrap(),
+            testdata!("world.txt@owner_b").to_str().unwrap(),
+        ])?;
+        assert!(image.uses_slots());
+        image.validate(&OwnerFlashConfig::basic())?;
+        let data = image.assemble()?;
+        assert_eq!(&data[..5], b"Hello");
+        assert_eq!(&data[0x90000..0x90005], b"World");
+        assert!(data[5..0x90000].iter().all(|&b| b == 0xff));
+
+        // Slot B does not fit into one half of a mirrored image.
+        let mut image = ImageAssembler::with_params(0x100000, true);
+        image.parse(&[testdata!("world.txt@owner_b").to_str().unwrap()])?;
+        let err = image.assemble().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Chunk offset 0x90000 is beyond the assembled image size 0x80000"
+        );
+
+        let mut image = ImageAssembler::new();
+        let err = image
+            .parse(&[testdata!("hello.txt@owner_c").to_str().unwrap()])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid chunk offset or slot name: owner_c"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_layout() -> Result<()> {
+        let config = OwnerFlashConfig::basic();
+        // Overlapping chunks.
+        let mut image = ImageAssembler::new();
+        image.parse(&[
+            testdata!("hello.txt@0").to_str().unwrap(),
+            testdata!("world.txt@0x2").to_str().unwrap(),
+        ])?;
+        let err = image.validate(&config).unwrap_err();
+        assert!(err.to_string().ends_with("world.txt overlap"));
+
+        // A chunk crossing the boundary between the ROM_EXT and owner regions.
+        let mut image = ImageAssembler::new();
+        image.parse(&[testdata!("hello.txt@0xfffe").to_str().unwrap()])?;
+        let err = image.validate(&config).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("at 0xfffe..0x10003 is not within a single programmable flash region"));
+        Ok(())
+    }
+
     #[test]
     fn test_load_image() {
         // Read and write back image.
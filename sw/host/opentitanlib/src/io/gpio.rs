@@ -44,6 +44,8 @@ pub enum GpioError {
     InvalidBitbangData(usize),
     #[error("Bitbang delay of zero, or at end of sequence, not permitted")]
     InvalidBitbangDelay,
+    #[error("Timeout waiting for pin {0} to read {1}")]
+    PinWaitTimeout(String, bool),
     #[error("Generic error: {0}")]
     Generic(String),
 }
@@ -75,7 +75,7 @@ pub enum Transfer<'rd, 'wr> {
 }
 
 /// Status of I2C read operations (data from device to host).
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum ReadStatus {
     /// Host has asked to read data, debugger device is currently stretching the clock waiting to
     /// be told what data to transmit via I2C.  Parameter is 7-bit I2C address.
@@ -89,7 +89,7 @@ pub enum ReadStatus {
 
 /// Record of one transfer initiated by the I2C host, to which the debugger responded as I2C
 /// device.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum DeviceTransfer {
     /// The I2C host read a number of previously prepared bytes.
     Read {
@@ -104,7 +104,7 @@ pub enum DeviceTransfer {
 
 /// A log of I2C operations performed by the I2C host since last time, as well as whether the I2C
 /// host is currently waiting to read data.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DeviceStatus {
     /// Log of transfers completed since the last time.
     pub transfers: Vec<DeviceTransfer>,
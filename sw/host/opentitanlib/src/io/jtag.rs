@@ -15,7 +15,7 @@ use crate::debug::openocd::OpenOcd;
 use crate::dif::lc_ctrl::LcCtrlReg;
 use crate::impl_serializable_error;
 
-#[derive(Debug, Args, Clone)]
+#[derive(Debug, Args, Clone, Deserialize, Serialize)]
 pub struct JtagParams {
     /// OpenOCD binary path.
     #[arg(long, default_value = "openocd")]
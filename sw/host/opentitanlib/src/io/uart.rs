@@ -6,7 +6,7 @@ use std::io::{self, Read};
 use std::rc::Rc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use clap::Args;
 use serde::{Deserialize, Serialize};
 pub use serialport::Parity;
@@ -28,19 +28,31 @@ pub struct UartParams {
     #[arg(long)]
     baudrate: Option<u32>,
 
-    /// Enable software flow control.
+    /// Enable software (XON/XOFF) flow control.
     #[arg(long)]
     flow_control: bool,
+
+    /// Enable hardware (RTS/CTS) flow control.
+    #[arg(long)]
+    hardware_flow_control: bool,
 }
 
 impl UartParams {
+    /// Returns the UART, with flow control enabled if requested, in addition to any declared in
+    /// configuration files.
     pub fn create(&self, transport: &TransportWrapper) -> Result<Rc<dyn Uart>> {
         let uart = transport.uart(&self.uart)?;
         if let Some(baudrate) = self.baudrate {
             uart.set_baudrate(baudrate)?;
         }
-        log::info!("set_flow_control to {}", self.flow_control);
-        uart.set_flow_control(self.flow_control)?;
+        if self.flow_control {
+            log::info!("set_flow_control to true");
+            uart.set_flow_control(true)?;
+        }
+        if self.hardware_flow_control {
+            log::info!("set_hardware_flow_control to true");
+            uart.set_hardware_flow_control(true)?;
+        }
         Ok(uart)
     }
 }
@@ -64,11 +76,15 @@ pub trait Uart {
     /// Sets the UART baudrate.  May do nothing for virtual UARTs.
     fn set_baudrate(&self, baudrate: u32) -> Result<()>;
 
-    /// Enables software flow control for `write`s.
+    /// Enables software (XON/XOFF) flow control for `write`s.
     fn set_flow_control(&self, flow_control: bool) -> Result<()> {
-        if flow_control {
-            unimplemented!();
-        }
+        ensure!(!flow_control, TransportError::UnsupportedOperation);
+        Ok(())
+    }
+
+    /// Enables hardware (RTS/CTS) flow control, for backends having the signals.
+    fn set_hardware_flow_control(&self, enable: bool) -> Result<()> {
+        ensure!(!enable, TransportError::UnsupportedOperation);
         Ok(())
     }
 
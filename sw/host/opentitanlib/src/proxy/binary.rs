@@ -0,0 +1,689 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compact binary encoding of the serde data model, used as an alternative to JSON for the
+//! session protocol.
+//!
+//! Like JSON, the encoding is self-describing, and represents structs as maps keyed by field
+//! name and enum variants by name, such that it supports the same types, and copes with the
+//! same protocol differences between client and server.  The difference is that numbers are
+//! encoded in binary, and byte strings (fields marked `#[serde(with = "serde_bytes")]`) as raw
+//! bytes, rather than as arrays of decimal numbers.
+//!
+//! Each value starts with a one-byte tag, followed by:
+//!  * `UINT`: LEB128 encoded integer.
+//!  * `NINT`: LEB128 encoding of `!n`, for negative integer `n`.
+//!  * `F32`, `F64`: little-endian IEEE 754 number.
+//!  * `STR`, `BYTES`: LEB128 encoded length, followed by UTF-8 or raw data.
+//!  * `SEQ`: any number of values, followed by `END`.
+//!  * `MAP`: any number of key and value pairs, followed by `END`.
+//!  * `NULL`, `FALSE`, `TRUE`: nothing.
+
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use serde::Deserialize;
+use std::fmt::Display;
+use thiserror::Error;
+
+const NULL: u8 = 0x00;
+const FALSE: u8 = 0x01;
+const TRUE: u8 = 0x02;
+const UINT: u8 = 0x03;
+const NINT: u8 = 0x04;
+const F32: u8 = 0x05;
+const F64: u8 = 0x06;
+const STR: u8 = 0x07;
+const BYTES: u8 = 0x08;
+const SEQ: u8 = 0x09;
+const MAP: u8 = 0x0A;
+const END: u8 = 0x0B;
+
+/// Deepest nesting of sequences, maps and enum variants accepted when decoding, such that
+/// malformed input cannot exhaust the stack.  Same as the limit of `serde_json`.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, Error)]
+pub enum BinaryError {
+    #[error("{0}")]
+    Custom(String),
+    #[error("Unexpected end of data")]
+    UnexpectedEnd,
+    #[error("Invalid tag {0:#04x}")]
+    InvalidTag(u8),
+    #[error("Integer out of range")]
+    IntegerOverflow,
+    #[error("Invalid UTF-8 string")]
+    InvalidUtf8,
+    #[error("Trailing data after value")]
+    TrailingData,
+    #[error("Nesting deeper than {MAX_DEPTH} levels")]
+    RecursionLimitExceeded,
+    #[error("Message of {0} bytes exceeds the maximum size")]
+    MessageTooLarge(usize),
+}
+
+impl ser::Error for BinaryError {
+    fn custom<T: Display>(msg: T) -> Self {
+        BinaryError::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for BinaryError {
+    fn custom<T: Display>(msg: T) -> Self {
+        BinaryError::Custom(msg.to_string())
+    }
+}
+
+type Result<T> = std::result::Result<T, BinaryError>;
+
+/// Appends the encoding of `value` to `output`.
+pub fn to_writer<T: Serialize + ?Sized>(output: &mut Vec<u8>, value: &T) -> Result<()> {
+    value.serialize(&mut Serializer { output })
+}
+
+/// Decodes a value, which must occupy all of `input`.
+pub fn from_slice<'de, T: Deserialize<'de>>(input: &'de [u8]) -> Result<T> {
+    let mut deserializer = Deserializer {
+        input,
+        remaining_depth: MAX_DEPTH,
+    };
+    let value = T::deserialize(&mut deserializer)?;
+    if !deserializer.input.is_empty() {
+        return Err(BinaryError::TrailingData);
+    }
+    Ok(value)
+}
+
+struct Serializer<'a> {
+    output: &'a mut Vec<u8>,
+}
+
+impl Serializer<'_> {
+    fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.output.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.output.push(value as u8);
+    }
+
+    fn write_data(&mut self, tag: u8, data: &[u8]) {
+        self.output.push(tag);
+        self.write_varint(data.len() as u64);
+        self.output.extend_from_slice(data);
+    }
+}
+
+impl<'a, 'b> ser::Serializer for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = BinaryError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.output.push(if v { TRUE } else { FALSE });
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        if v >= 0 {
+            self.serialize_u64(v as u64)
+        } else {
+            self.output.push(NINT);
+            self.write_varint(!v as u64);
+            Ok(())
+        }
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.output.push(UINT);
+        self.write_varint(v);
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.output.push(F32);
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.output.push(F64);
+        self.output.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(v.encode_utf8(&mut [0u8; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.write_data(STR, v.as_bytes());
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.write_data(BYTES, v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.output.push(NULL);
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.output.push(MAP);
+        self.write_data(STR, variant.as_bytes());
+        value.serialize(&mut *self)?;
+        self.output.push(END);
+        Ok(())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self> {
+        self.output.push(SEQ);
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Self> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        // The `END` of the map is written along with that of the sequence.
+        self.output.push(MAP);
+        self.write_data(STR, variant.as_bytes());
+        self.output.push(SEQ);
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self> {
+        self.output.push(MAP);
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        // The `END` of the outer map is written along with that of the inner one.
+        self.output.push(MAP);
+        self.write_data(STR, variant.as_bytes());
+        self.output.push(MAP);
+        Ok(self)
+    }
+}
+
+impl<'a, 'b> ser::SerializeSeq for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = BinaryError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeTuple for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = BinaryError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeTupleStruct for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = BinaryError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeTupleVariant for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = BinaryError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.extend_from_slice(&[END, END]);
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeMap for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = BinaryError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeStruct for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = BinaryError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        ser::Serializer::serialize_str(&mut **self, key)?;
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.push(END);
+        Ok(())
+    }
+}
+
+impl<'a, 'b> ser::SerializeStructVariant for &'a mut Serializer<'b> {
+    type Ok = ();
+    type Error = BinaryError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        ser::Serializer::serialize_str(&mut **self, key)?;
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        self.output.extend_from_slice(&[END, END]);
+        Ok(())
+    }
+}
+
+struct Deserializer<'de> {
+    input: &'de [u8],
+    remaining_depth: usize,
+}
+
+impl<'de> Deserializer<'de> {
+    fn peek_tag(&self) -> Result<u8> {
+        self.input
+            .first()
+            .copied()
+            .ok_or(BinaryError::UnexpectedEnd)
+    }
+
+    fn read_tag(&mut self) -> Result<u8> {
+        let tag = self.peek_tag()?;
+        self.input = &self.input[1..];
+        Ok(tag)
+    }
+
+    fn expect_tag(&mut self, expected: u8) -> Result<()> {
+        match self.read_tag()? {
+            tag if tag == expected => Ok(()),
+            tag => Err(BinaryError::InvalidTag(tag)),
+        }
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'de [u8]> {
+        if self.input.len() < len {
+            return Err(BinaryError::UnexpectedEnd);
+        }
+        let (data, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(data)
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_slice(1)?[0];
+            let bits = (byte & 0x7F) as u64;
+            if bits << shift >> shift != bits {
+                return Err(BinaryError::IntegerOverflow);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BinaryError::IntegerOverflow)
+    }
+
+    fn read_data(&mut self) -> Result<&'de [u8]> {
+        let len = self.read_varint()?;
+        self.read_slice(len.try_into().map_err(|_| BinaryError::IntegerOverflow)?)
+    }
+
+    fn read_str(&mut self) -> Result<&'de str> {
+        std::str::from_utf8(self.read_data()?).map_err(|_| BinaryError::InvalidUtf8)
+    }
+
+    /// Decodes the contents of a sequence or map using `f`, followed by the `END` tag.
+    fn read_compound<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.remaining_depth = self
+            .remaining_depth
+            .checked_sub(1)
+            .ok_or(BinaryError::RecursionLimitExceeded)?;
+        let value = f(self)?;
+        self.remaining_depth += 1;
+        self.expect_tag(END)?;
+        Ok(value)
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
+    type Error = BinaryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.read_tag()? {
+            NULL => visitor.visit_unit(),
+            FALSE => visitor.visit_bool(false),
+            TRUE => visitor.visit_bool(true),
+            UINT => visitor.visit_u64(self.read_varint()?),
+            NINT => visitor.visit_i64(!(self.read_varint()? as i64)),
+            F32 => visitor.visit_f32(f32::from_le_bytes(self.read_slice(4)?.try_into().unwrap())),
+            F64 => visitor.visit_f64(f64::from_le_bytes(self.read_slice(8)?.try_into().unwrap())),
+            STR => visitor.visit_borrowed_str(self.read_str()?),
+            BYTES => visitor.visit_borrowed_bytes(self.read_data()?),
+            SEQ => self.read_compound(|de| visitor.visit_seq(Compound { de })),
+            MAP => self.read_compound(|de| visitor.visit_map(Compound { de })),
+            tag => Err(BinaryError::InvalidTag(tag)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        if self.peek_tag()? == NULL {
+            self.read_tag()?;
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self.read_tag()? {
+            STR => visitor.visit_enum(self.read_str()?.into_deserializer()),
+            MAP => self.read_compound(|de| visitor.visit_enum(Compound { de })),
+            tag => Err(BinaryError::InvalidTag(tag)),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// Access to the elements of a sequence or map, or to the variant of an enum.
+struct Compound<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for Compound<'a, 'de> {
+    type Error = BinaryError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.de.peek_tag()? == END {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+impl<'de, 'a> de::MapAccess<'de> for Compound<'a, 'de> {
+    type Error = BinaryError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.de.peek_tag()? == END {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+impl<'de, 'a> de::EnumAccess<'de> for Compound<'a, 'de> {
+    type Error = BinaryError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let variant = seed.deserialize(&mut *self.de)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, 'a> de::VariantAccess<'de> for Compound<'a, 'de> {
+    type Error = BinaryError;
+
+    fn unit_variant(self) -> Result<()> {
+        self.de.expect_tag(NULL)
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self.de, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_map(self.de, visitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Sample {
+        Unit,
+        Newtype(Option<i32>),
+        Tuple(u8, String),
+        Struct {
+            #[serde(with = "serde_bytes")]
+            data: Vec<u8>,
+            words: Vec<u32>,
+            ratio: f32,
+        },
+    }
+
+    fn round_trip(value: &Sample) -> Result<Sample> {
+        let mut encoded = Vec::new();
+        to_writer(&mut encoded, value)?;
+        from_slice(&encoded)
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let values = [
+            Sample::Unit,
+            Sample::Newtype(None),
+            Sample::Newtype(Some(-1000)),
+            Sample::Tuple(255, "héllo".to_string()),
+            Sample::Struct {
+                data: vec![0, 1, 2, 0x80, 0xFF],
+                words: vec![0, u32::MAX],
+                ratio: 0.5,
+            },
+        ];
+        for value in &values {
+            assert_eq!(&round_trip(value)?, value);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_are_raw() -> Result<()> {
+        let mut encoded = Vec::new();
+        to_writer(
+            &mut encoded,
+            &Sample::Struct {
+                data: vec![0xAA; 1000],
+                words: Vec::new(),
+                ratio: 0.0,
+            },
+        )?;
+        assert!(encoded.len() < 1050);
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut encoded = Vec::new();
+        to_writer(&mut encoded, &Sample::Tuple(1, "abc".to_string())).unwrap();
+        encoded.pop();
+        assert!(matches!(
+            from_slice::<Sample>(&encoded),
+            Err(BinaryError::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn test_recursion_limit() -> Result<()> {
+        let nested = |depth: usize| {
+            let mut encoded = vec![SEQ; depth];
+            encoded.extend(vec![END; depth]);
+            encoded
+        };
+        from_slice::<serde::de::IgnoredAny>(&nested(MAX_DEPTH))?;
+        assert!(matches!(
+            from_slice::<serde::de::IgnoredAny>(&nested(MAX_DEPTH + 1)),
+            Err(BinaryError::RecursionLimitExceeded)
+        ));
+        Ok(())
+    }
+}
@@ -121,6 +121,7 @@ impl From<anyhow::Error> for SerializedError {
             crate::io::uart::UartError,
             crate::transport::TransportError,
             crate::transport::proxy::ProxyError,
+            crate::transport::proxy::ReplayError,
         );
     }
 }
@@ -2,7 +2,7 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 
 use mio::{Registry, Token};
 use std::borrow::Borrow;
@@ -11,20 +11,23 @@ use std::rc::Rc;
 use std::time::Duration;
 
 use super::errors::SerializedError;
+use super::lease::LeaseRegistry;
 use super::protocol::{
-    BitbangEntryRequest, BitbangEntryResponse, EmuRequest, EmuResponse, GpioBitRequest,
+    BitbangEntryRequest, BitbangEntryResponse, EmuRequest, EmuResponse, Encoding, GpioBitRequest,
     GpioBitResponse, GpioMonRequest, GpioMonResponse, GpioRequest, GpioResponse, I2cRequest,
-    I2cResponse, I2cTransferRequest, I2cTransferResponse, Message, ProxyRequest, ProxyResponse,
-    Request, Response, SpiRequest, SpiResponse, SpiTransferRequest, SpiTransferResponse,
-    UartRequest, UartResponse,
+    I2cResponse, I2cTransferRequest, I2cTransferResponse, JtagRequest, JtagResponse, Message,
+    ProxyRequest, ProxyResponse, Request, Response, SpiRequest, SpiResponse, SpiTransferRequest,
+    SpiTransferResponse, UartRequest, UartResponse,
 };
-use super::CommandHandler;
+use super::{CommandHandler, SessionJtagOpts};
 use crate::app::TransportWrapper;
 use crate::bootstrap::Bootstrap;
 use crate::io::gpio::{BitbangEntry, GpioPin};
+use crate::io::jtag::{Jtag, JtagError, JtagParams};
 use crate::io::{i2c, nonblocking_help, spi};
 use crate::proxy::nonblocking_uart::NonblockingUartRegistry;
 use crate::transport::TransportError;
+use crate::util::usb;
 
 /// Implementation of the handling of each protocol request, by means of an underlying
 /// `Transport` implementation.
@@ -32,15 +35,23 @@ pub struct TransportCommandHandler<'a> {
     transport: &'a TransportWrapper,
     nonblocking_help: Rc<dyn nonblocking_help::NonblockingHelp>,
     spi_chip_select: HashMap<String, Vec<spi::AssertChipSelect>>,
+    /// JTAG session, along with the connection which established it, and is the only one
+    /// allowed to use it.
+    jtag: Option<(Token, Box<dyn Jtag>)>,
+    jtag_opts: SessionJtagOpts,
+    leases: LeaseRegistry,
 }
 
 impl<'a> TransportCommandHandler<'a> {
-    pub fn new(transport: &'a TransportWrapper) -> Result<Self> {
+    pub fn new(transport: &'a TransportWrapper, jtag_opts: SessionJtagOpts) -> Result<Self> {
         let nonblocking_help = transport.nonblocking_help()?;
         Ok(Self {
             transport,
             nonblocking_help,
             spi_chip_select: HashMap::new(),
+            jtag: None,
+            jtag_opts,
+            leases: LeaseRegistry::new(),
         })
     }
 
@@ -52,6 +63,142 @@ impl<'a> TransportCommandHandler<'a> {
         }
     }
 
+    fn connected_jtag(&mut self, conn_token: Token) -> Result<&mut Box<dyn Jtag>> {
+        match &mut self.jtag {
+            Some((owner, jtag)) if *owner == conn_token => Ok(jtag),
+            Some(_) => bail!(JtagError::Generic(
+                "JTAG session belongs to another connection".to_string()
+            )),
+            None => bail!(TransportError::InvalidOperation),
+        }
+    }
+
+    fn do_execute_jtag_cmd(
+        &mut self,
+        conn_token: Token,
+        command: &JtagRequest,
+    ) -> Result<JtagResponse> {
+        match command {
+            JtagRequest::Connect {
+                adapter_speed_khz,
+                tap,
+            } => {
+                if *adapter_speed_khz > self.jtag_opts.max_adapter_speed_khz {
+                    bail!(JtagError::Generic(format!(
+                        "adapter speed {} kHz exceeds the session limit of {} kHz",
+                        adapter_speed_khz, self.jtag_opts.max_adapter_speed_khz
+                    )));
+                }
+                // Any previous session of this connection is dropped, shutting down its OpenOCD
+                // instance, before a new one is started.
+                if self.jtag.is_some() {
+                    self.connected_jtag(conn_token)?;
+                }
+                if let Some((_, jtag)) = self.jtag.take() {
+                    jtag.disconnect()?;
+                }
+                let params = JtagParams {
+                    openocd: self.jtag_opts.openocd.clone(),
+                    adapter_speed_khz: *adapter_speed_khz,
+                };
+                let jtag = self.transport.jtag(&params)?.connect(*tap)?;
+                self.jtag = Some((conn_token, jtag));
+                Ok(JtagResponse::Connect)
+            }
+            JtagRequest::Disconnect => {
+                self.connected_jtag(conn_token)?;
+                let (_, jtag) = self.jtag.take().unwrap();
+                jtag.disconnect()?;
+                Ok(JtagResponse::Disconnect)
+            }
+            JtagRequest::ReadLcCtrlReg { reg } => {
+                let value = self.connected_jtag(conn_token)?.read_lc_ctrl_reg(reg)?;
+                Ok(JtagResponse::ReadLcCtrlReg { value })
+            }
+            JtagRequest::WriteLcCtrlReg { reg, value } => {
+                self.connected_jtag(conn_token)?
+                    .write_lc_ctrl_reg(reg, *value)?;
+                Ok(JtagResponse::WriteLcCtrlReg)
+            }
+            JtagRequest::ReadMemory { addr, len } => {
+                let mut data = vec![0u8; *len as usize];
+                let count = self
+                    .connected_jtag(conn_token)?
+                    .read_memory(*addr, &mut data)?;
+                data.truncate(count);
+                Ok(JtagResponse::ReadMemory { data })
+            }
+            JtagRequest::ReadMemory32 { addr, len } => {
+                let mut data = vec![0u32; *len as usize];
+                let count = self
+                    .connected_jtag(conn_token)?
+                    .read_memory32(*addr, &mut data)?;
+                data.truncate(count);
+                Ok(JtagResponse::ReadMemory32 { data })
+            }
+            JtagRequest::WriteMemory { addr, data } => {
+                self.connected_jtag(conn_token)?.write_memory(*addr, data)?;
+                Ok(JtagResponse::WriteMemory)
+            }
+            JtagRequest::WriteMemory32 { addr, data } => {
+                self.connected_jtag(conn_token)?
+                    .write_memory32(*addr, data)?;
+                Ok(JtagResponse::WriteMemory32)
+            }
+            JtagRequest::Halt => {
+                self.connected_jtag(conn_token)?.halt()?;
+                Ok(JtagResponse::Halt)
+            }
+            JtagRequest::WaitHalt { timeout_millis } => {
+                self.connected_jtag(conn_token)?
+                    .wait_halt(Duration::from_millis(*timeout_millis))?;
+                Ok(JtagResponse::WaitHalt)
+            }
+            JtagRequest::Resume => {
+                self.connected_jtag(conn_token)?.resume()?;
+                Ok(JtagResponse::Resume)
+            }
+            JtagRequest::ResumeAt { addr } => {
+                self.connected_jtag(conn_token)?.resume_at(*addr)?;
+                Ok(JtagResponse::ResumeAt)
+            }
+            JtagRequest::Step => {
+                self.connected_jtag(conn_token)?.step()?;
+                Ok(JtagResponse::Step)
+            }
+            JtagRequest::StepAt { addr } => {
+                self.connected_jtag(conn_token)?.step_at(*addr)?;
+                Ok(JtagResponse::StepAt)
+            }
+            JtagRequest::Reset { run } => {
+                self.connected_jtag(conn_token)?.reset(*run)?;
+                Ok(JtagResponse::Reset)
+            }
+            JtagRequest::ReadRiscvReg { reg } => {
+                let value = self.connected_jtag(conn_token)?.read_riscv_reg(reg)?;
+                Ok(JtagResponse::ReadRiscvReg { value })
+            }
+            JtagRequest::WriteRiscvReg { reg, value } => {
+                self.connected_jtag(conn_token)?
+                    .write_riscv_reg(reg, *value)?;
+                Ok(JtagResponse::WriteRiscvReg)
+            }
+            JtagRequest::SetBreakpoint { addr, hw } => {
+                self.connected_jtag(conn_token)?
+                    .set_breakpoint(*addr, *hw)?;
+                Ok(JtagResponse::SetBreakpoint)
+            }
+            JtagRequest::RemoveBreakpoint { addr } => {
+                self.connected_jtag(conn_token)?.remove_breakpoint(*addr)?;
+                Ok(JtagResponse::RemoveBreakpoint)
+            }
+            JtagRequest::RemoveAllBreakpoints => {
+                self.connected_jtag(conn_token)?.remove_all_breakpoints()?;
+                Ok(JtagResponse::RemoveAllBreakpoints)
+            }
+        }
+    }
+
     /// This method will perform whatever action on the underlying `Transport` that is requested
     /// by the given `Request`, and return a response to be sent to the client.  Any `Err`
     /// return from this method will be propagated to the remote client, without any server-side
@@ -63,6 +210,18 @@ impl<'a> TransportCommandHandler<'a> {
         others: &mut NonblockingUartRegistry,
         req: &Request,
     ) -> Result<Response> {
+        match req {
+            // Requests which do not affect the state of the transport are permitted regardless
+            // of leases held by other connections.
+            Request::GetCapabilities
+            | Request::Proxy(
+                ProxyRequest::Provides
+                | ProxyRequest::AcquireLease { .. }
+                | ProxyRequest::ReleaseLease
+                | ProxyRequest::GetLeases,
+            ) => (),
+            _ => self.leases.check(conn_token)?,
+        }
         match req {
             Request::GetCapabilities => {
                 Ok(Response::GetCapabilities(self.transport.capabilities()?))
@@ -71,6 +230,10 @@ impl<'a> TransportCommandHandler<'a> {
                 self.transport.apply_default_configuration(None)?;
                 Ok(Response::ApplyDefaultConfiguration)
             }
+            Request::Reconnect => {
+                self.transport.reconnect()?;
+                Ok(Response::Reconnect)
+            }
             Request::Gpio { id, command } => {
                 let instance = self.transport.gpio_pin(id)?;
                 match command {
@@ -195,10 +358,22 @@ impl<'a> TransportCommandHandler<'a> {
                         instance.set_baudrate(*rate)?;
                         Ok(Response::Uart(UartResponse::SetBaudrate))
                     }
+                    UartRequest::SetFlowControl(enable) => {
+                        instance.set_flow_control(*enable)?;
+                        Ok(Response::Uart(UartResponse::SetFlowControl))
+                    }
+                    UartRequest::SetHardwareFlowControl(enable) => {
+                        instance.set_hardware_flow_control(*enable)?;
+                        Ok(Response::Uart(UartResponse::SetHardwareFlowControl))
+                    }
                     UartRequest::SetParity(parity) => {
                         instance.set_parity(*parity)?;
                         Ok(Response::Uart(UartResponse::SetParity))
                     }
+                    UartRequest::SetBreak(enable) => {
+                        instance.set_break(*enable)?;
+                        Ok(Response::Uart(UartResponse::SetBreak))
+                    }
                     UartRequest::Read {
                         timeout_millis,
                         len,
@@ -454,10 +629,16 @@ impl<'a> TransportCommandHandler<'a> {
                     }
                 }
             }
+            Request::Jtag { command } => Ok(Response::Jtag(
+                self.do_execute_jtag_cmd(conn_token, command)?,
+            )),
             Request::Proxy(command) => match command {
                 ProxyRequest::Provides {} => {
                     let provides_map = self.transport.provides_map()?.clone();
-                    Ok(Response::Proxy(ProxyResponse::Provides { provides_map }))
+                    Ok(Response::Proxy(ProxyResponse::Provides {
+                        provides_map,
+                        encodings: Encoding::ALL.to_vec(),
+                    }))
                 }
                 ProxyRequest::Bootstrap { options, payload } => {
                     Bootstrap::update(self.transport, options, payload)?;
@@ -478,11 +659,82 @@ impl<'a> TransportCommandHandler<'a> {
                         ProxyResponse::ApplyDefaultConfigurationWithStrapping,
                     ))
                 }
+                ProxyRequest::AcquireLease {
+                    kind,
+                    timeout_millis,
+                    owner,
+                } => {
+                    self.leases.acquire(
+                        conn_token,
+                        *kind,
+                        timeout_millis.map(Duration::from_millis),
+                        owner,
+                    )?;
+                    Ok(Response::Proxy(ProxyResponse::AcquireLease))
+                }
+                ProxyRequest::ReleaseLease => {
+                    self.leases.release(conn_token)?;
+                    Ok(Response::Proxy(ProxyResponse::ReleaseLease))
+                }
+                ProxyRequest::GetLeases => {
+                    let leases = self.leases.list(conn_token);
+                    Ok(Response::Proxy(ProxyResponse::GetLeases { leases }))
+                }
+                ProxyRequest::ListTargets | ProxyRequest::SelectTarget { .. } => {
+                    // Handled by `TargetCommandHandler`, before reaching any particular target.
+                    bail!(TransportError::UnsupportedOperation)
+                }
             },
         }
     }
 }
 
+/// Tells whether performing `req` twice has the same effect as performing it once, such that it
+/// can be retried after the debugger disconnected while it was being performed.
+fn is_idempotent(req: &Request) -> bool {
+    match req {
+        Request::GetCapabilities | Request::ApplyDefaultConfiguration | Request::Reconnect => true,
+        Request::Gpio { .. } => true,
+        Request::Uart { command, .. } => matches!(
+            command,
+            UartRequest::GetBaudrate
+                | UartRequest::SetBaudrate { .. }
+                | UartRequest::SetParity(_)
+                | UartRequest::SupportsNonblockingRead
+                | UartRequest::SetFlowControl(_)
+                | UartRequest::SetHardwareFlowControl(_)
+                | UartRequest::SetBreak(_)
+        ),
+        Request::Spi { command, .. } => matches!(
+            command,
+            SpiRequest::GetTransferMode
+                | SpiRequest::SetTransferMode { .. }
+                | SpiRequest::GetBitsPerWord
+                | SpiRequest::SetBitsPerWord { .. }
+                | SpiRequest::GetMaxSpeed
+                | SpiRequest::SetMaxSpeed { .. }
+                | SpiRequest::SupportsBidirectionalTransfer
+                | SpiRequest::SetPins { .. }
+                | SpiRequest::GetMaxTransferCount
+                | SpiRequest::GetMaxTransferSizes
+                | SpiRequest::GetEepromMaxTransferSizes
+                | SpiRequest::SetVoltage { .. }
+        ),
+        Request::I2c { command, .. } => matches!(
+            command,
+            I2cRequest::SetModeHost
+                | I2cRequest::SetModeDevice { .. }
+                | I2cRequest::GetMaxSpeed
+                | I2cRequest::SetMaxSpeed { .. }
+        ),
+        Request::Proxy(command) => {
+            matches!(command, ProxyRequest::Provides | ProxyRequest::GetLeases)
+        }
+        // Transfers, bootstrapping, GPIO monitoring and bitbanging, emulator and JTAG operations.
+        _ => false,
+    }
+}
+
 impl<'a> CommandHandler<Message, NonblockingUartRegistry> for TransportCommandHandler<'a> {
     /// This method will perform whatever action on the underlying `Transport` that is requested
     /// by the given `Message`, and return a response to be sent to the client.  Any `Err`
@@ -496,15 +748,40 @@ impl<'a> CommandHandler<Message, NonblockingUartRegistry> for TransportCommandHa
         msg: &Message,
     ) -> Result<Message> {
         if let Message::Req(req) = msg {
+            let mut result = self.do_execute_cmd(conn_token, registry, others, req);
+            if let Err(e) = &result {
+                if usb::is_disconnected(e) {
+                    // The debugger has likely reset, reconnect and retry once, if the request
+                    // can safely be repeated.  Otherwise, it may have taken partial effect, and
+                    // it is up to the client to decide how to proceed.
+                    log::warn!("Debugger disconnected, reconnecting: {:?}", e);
+                    match self.transport.reconnect() {
+                        Ok(()) if is_idempotent(req) => {
+                            result = self.do_execute_cmd(conn_token, registry, others, req)
+                        }
+                        Ok(()) => {
+                            result = result.context("Debugger reconnected, request not retried")
+                        }
+                        Err(e) => log::error!("Unable to reconnect: {:?}", e),
+                    }
+                }
+            }
             // Package either `Ok()` or `Err()` into a `Message`, to be sent via network.
-            return Ok(Message::Res(
-                self.do_execute_cmd(conn_token, registry, others, req)
-                    .map_err(SerializedError::from),
-            ));
+            return Ok(Message::Res(result.map_err(SerializedError::from)));
         }
         bail!("Client sent non-Request to server!!!");
     }
 
+    fn connection_closed(&mut self, conn_token: Token) {
+        self.leases.connection_closed(conn_token);
+        if matches!(&self.jtag, Some((owner, _)) if *owner == conn_token) {
+            let (_, jtag) = self.jtag.take().unwrap();
+            if let Err(e) = jtag.disconnect() {
+                log::error!("Unable to disconnect JTAG of closed connection: {:?}", e);
+            }
+        }
+    }
+
     fn register_nonblocking_help(&self, registry: &mio::Registry, token: mio::Token) -> Result<()> {
         self.nonblocking_help
             .register_nonblocking_help(registry, token)
@@ -514,3 +791,274 @@ impl<'a> CommandHandler<Message, NonblockingUartRegistry> for TransportCommandHa
         self.nonblocking_help.nonblocking_help()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::TransportWrapperBuilder;
+    use crate::debug::openocd::OpenOcd;
+    use crate::dif::lc_ctrl::LcCtrlReg;
+    use crate::io::gpio::{PinMode, PullMode};
+    use crate::io::jtag::{JtagChain, JtagTap, RiscvReg};
+    use crate::io::uart::Uart;
+    use crate::transport::{Capabilities, Capability, Transport};
+    use std::cell::Cell;
+
+    /// Debugger which can be made to disappear from the bus, until reconnected.
+    #[derive(Default)]
+    struct Debugger {
+        disconnected: Cell<bool>,
+        writes: Cell<u32>,
+        reconnects: Cell<u32>,
+        jtag_disconnects: Cell<u32>,
+    }
+
+    impl Debugger {
+        fn write(&self) -> Result<()> {
+            self.writes.set(self.writes.get() + 1);
+            if self.disconnected.get() {
+                bail!(rusb::Error::NoDevice);
+            }
+            Ok(())
+        }
+    }
+
+    struct FlakyTransport(Rc<Debugger>);
+    struct FlakyInterface(Rc<Debugger>);
+
+    impl Transport for FlakyTransport {
+        fn capabilities(&self) -> Result<Capabilities> {
+            Ok(Capabilities::new(Capability::GPIO | Capability::UART))
+        }
+
+        fn reconnect(&self) -> Result<()> {
+            self.0.reconnects.set(self.0.reconnects.get() + 1);
+            self.0.disconnected.set(false);
+            Ok(())
+        }
+
+        fn gpio_pin(&self, _instance: &str) -> Result<Rc<dyn GpioPin>> {
+            Ok(Rc::new(FlakyInterface(Rc::clone(&self.0))))
+        }
+
+        fn uart(&self, _instance: &str) -> Result<Rc<dyn Uart>> {
+            Ok(Rc::new(FlakyInterface(Rc::clone(&self.0))))
+        }
+
+        fn jtag(&self, _opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
+            Ok(Box::new(FlakyInterface(Rc::clone(&self.0))))
+        }
+    }
+
+    impl JtagChain for FlakyInterface {
+        fn connect(self: Box<Self>, _tap: JtagTap) -> Result<Box<dyn Jtag>> {
+            Ok(self)
+        }
+        fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+            unimplemented!()
+        }
+    }
+
+    impl Jtag for FlakyInterface {
+        fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+            unimplemented!()
+        }
+        fn as_raw(&mut self) -> Result<&mut OpenOcd> {
+            unimplemented!()
+        }
+        fn disconnect(self: Box<Self>) -> Result<()> {
+            self.0
+                .jtag_disconnects
+                .set(self.0.jtag_disconnects.get() + 1);
+            Ok(())
+        }
+        fn tap(&self) -> JtagTap {
+            JtagTap::RiscvTap
+        }
+        fn read_lc_ctrl_reg(&mut self, _reg: &LcCtrlReg) -> Result<u32> {
+            unimplemented!()
+        }
+        fn write_lc_ctrl_reg(&mut self, _reg: &LcCtrlReg, _value: u32) -> Result<()> {
+            unimplemented!()
+        }
+        fn read_memory(&mut self, _addr: u32, _buf: &mut [u8]) -> Result<usize> {
+            unimplemented!()
+        }
+        fn read_memory32(&mut self, _addr: u32, _buf: &mut [u32]) -> Result<usize> {
+            unimplemented!()
+        }
+        fn write_memory(&mut self, _addr: u32, _buf: &[u8]) -> Result<()> {
+            unimplemented!()
+        }
+        fn write_memory32(&mut self, _addr: u32, _buf: &[u32]) -> Result<()> {
+            unimplemented!()
+        }
+        fn halt(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn wait_halt(&mut self, _timeout: Duration) -> Result<()> {
+            unimplemented!()
+        }
+        fn resume(&mut self) -> Result<()> {
+            unimplemented!()
+        }
+        fn resume_at(&mut self, _addr: u32) -> Result<()> {
+            unimplemented!()
+        }
+        fn step(&mut self) -> Result<()> {
+            unimplemented!()
+        }
+        fn step_at(&mut self, _addr: u32) -> Result<()> {
+            unimplemented!()
+        }
+        fn reset(&mut self, _run: bool) -> Result<()> {
+            unimplemented!()
+        }
+        fn read_riscv_reg(&mut self, _reg: &RiscvReg) -> Result<u32> {
+            unimplemented!()
+        }
+        fn write_riscv_reg(&mut self, _reg: &RiscvReg, _val: u32) -> Result<()> {
+            unimplemented!()
+        }
+        fn set_breakpoint(&mut self, _addr: u32, _hw: bool) -> Result<()> {
+            unimplemented!()
+        }
+        fn remove_breakpoint(&mut self, _addr: u32) -> Result<()> {
+            unimplemented!()
+        }
+        fn remove_all_breakpoints(&mut self) -> Result<()> {
+            unimplemented!()
+        }
+    }
+
+    impl GpioPin for FlakyInterface {
+        fn read(&self) -> Result<bool> {
+            Ok(false)
+        }
+        fn write(&self, _value: bool) -> Result<()> {
+            self.0.write()
+        }
+        fn set_mode(&self, _mode: PinMode) -> Result<()> {
+            Ok(())
+        }
+        fn set_pull_mode(&self, _mode: PullMode) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Uart for FlakyInterface {
+        fn get_baudrate(&self) -> Result<u32> {
+            Ok(115200)
+        }
+        fn set_baudrate(&self, _baudrate: u32) -> Result<()> {
+            Ok(())
+        }
+        fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+            Ok(0)
+        }
+        fn read_timeout(&self, _buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+            Ok(0)
+        }
+        fn write(&self, _buf: &[u8]) -> Result<()> {
+            self.0.write()
+        }
+    }
+
+    #[test]
+    fn test_reconnect() -> Result<()> {
+        let debugger = Rc::new(Debugger::default());
+        let transport = TransportWrapperBuilder::new("flaky".to_string(), false)
+            .build(Box::new(FlakyTransport(Rc::clone(&debugger))))?;
+        let jtag_opts = SessionJtagOpts {
+            openocd: "openocd".into(),
+            max_adapter_speed_khz: 10000,
+        };
+        let mut handler = TransportCommandHandler::new(&transport, jtag_opts)?;
+        let poll = mio::Poll::new()?;
+        let mut others = NonblockingUartRegistry::new();
+        let mut execute = |req: Request| -> Result<Result<Response, SerializedError>> {
+            match handler.execute_cmd(Token(1), poll.registry(), &mut others, &Message::Req(req))? {
+                Message::Res(res) => Ok(res),
+                _ => bail!("unexpected message"),
+            }
+        };
+
+        // Setting a pin can safely be repeated after reconnecting.
+        debugger.disconnected.set(true);
+        let res = execute(Request::Gpio {
+            id: "RESET".to_string(),
+            command: GpioRequest::Write { logic: true },
+        })?;
+        assert!(matches!(res, Ok(Response::Gpio(GpioResponse::Write))));
+        assert_eq!(debugger.reconnects.get(), 1);
+        assert_eq!(debugger.writes.get(), 2);
+
+        // Data sent on a UART may have been partially transmitted, so the error is reported.
+        debugger.disconnected.set(true);
+        let res = execute(Request::Uart {
+            id: "CONSOLE".to_string(),
+            command: UartRequest::Write {
+                data: b"data".to_vec(),
+            },
+        })?;
+        assert!(res.is_err());
+        assert_eq!(debugger.reconnects.get(), 2);
+        assert_eq!(debugger.writes.get(), 3);
+        Ok(())
+    }
+
+    fn execute(
+        handler: &mut TransportCommandHandler,
+        conn: usize,
+        req: Request,
+    ) -> Result<Result<Response, SerializedError>> {
+        let poll = mio::Poll::new()?;
+        let mut others = NonblockingUartRegistry::new();
+        match handler.execute_cmd(
+            Token(conn),
+            poll.registry(),
+            &mut others,
+            &Message::Req(req),
+        )? {
+            Message::Res(res) => Ok(res),
+            _ => bail!("unexpected message"),
+        }
+    }
+
+    #[test]
+    fn test_jtag_owner() -> Result<()> {
+        let debugger = Rc::new(Debugger::default());
+        let transport = TransportWrapperBuilder::new("flaky".to_string(), false)
+            .build(Box::new(FlakyTransport(Rc::clone(&debugger))))?;
+        let jtag_opts = SessionJtagOpts {
+            openocd: "openocd".into(),
+            max_adapter_speed_khz: 10000,
+        };
+        let mut handler = TransportCommandHandler::new(&transport, jtag_opts)?;
+        let jtag = |command: JtagRequest| Request::Jtag { command };
+        let connect = || {
+            jtag(JtagRequest::Connect {
+                adapter_speed_khz: 1000,
+                tap: JtagTap::RiscvTap,
+            })
+        };
+
+        assert!(execute(&mut handler, 1, connect())?.is_ok());
+        assert!(execute(&mut handler, 1, jtag(JtagRequest::Halt))?.is_ok());
+
+        // Other connections can neither use nor take over the session.
+        assert!(execute(&mut handler, 2, jtag(JtagRequest::Halt))?.is_err());
+        assert!(execute(&mut handler, 2, connect())?.is_err());
+        assert!(execute(&mut handler, 2, jtag(JtagRequest::Disconnect))?.is_err());
+        handler.connection_closed(Token(2));
+        assert_eq!(debugger.jtag_disconnects.get(), 0);
+
+        // The session ends along with the connection which established it.
+        handler.connection_closed(Token(1));
+        assert_eq!(debugger.jtag_disconnects.get(), 1);
+        assert!(execute(&mut handler, 2, jtag(JtagRequest::Halt))?.is_err());
+        assert!(execute(&mut handler, 2, connect())?.is_ok());
+        assert!(execute(&mut handler, 2, jtag(JtagRequest::Halt))?.is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use mio::Token;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::protocol::{LeaseInfo, LeaseKind};
+use crate::transport::proxy::ProxyError;
+use crate::transport::TransportError;
+
+struct Lease {
+    owner: String,
+    kind: LeaseKind,
+    expiry: Option<Instant>,
+}
+
+/// Keeps track of which connections hold leases on the transport.  While any lease is held,
+/// only connections holding a lease may operate the transport.
+#[derive(Default)]
+pub struct LeaseRegistry {
+    leases: HashMap<Token, Lease>,
+}
+
+impl LeaseRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop any leases whose timeout has passed.
+    fn expire(&mut self) {
+        let now = Instant::now();
+        self.leases.retain(|token, lease| match lease.expiry {
+            Some(expiry) if expiry <= now => {
+                log::info!(
+                    "Lease of connection {:#X} ({}) expired",
+                    token.0,
+                    lease.owner
+                );
+                false
+            }
+            _ => true,
+        });
+    }
+
+    /// Describe leases held by connections other than `conn_token`, which `kind` of lease would
+    /// conflict with.
+    fn conflicts(&self, conn_token: Token, kind: Option<LeaseKind>) -> Vec<String> {
+        self.leases
+            .iter()
+            .filter(|(token, lease)| {
+                **token != conn_token
+                    && (kind.is_none()
+                        || kind == Some(LeaseKind::Exclusive)
+                        || lease.kind == LeaseKind::Exclusive)
+            })
+            .map(|(_, lease)| format!("{} ({:?})", lease.owner, lease.kind))
+            .collect()
+    }
+
+    /// Acquire a lease for the given connection, or renew/replace the one it already holds.
+    pub fn acquire(
+        &mut self,
+        conn_token: Token,
+        kind: LeaseKind,
+        timeout: Option<Duration>,
+        owner: &str,
+    ) -> Result<()> {
+        self.expire();
+        let conflicts = self.conflicts(conn_token, Some(kind));
+        if !conflicts.is_empty() {
+            return Err(ProxyError::Leased(conflicts.join(", ")).into());
+        }
+        log::info!(
+            "Connection {:#X} ({}) acquired {:?} lease",
+            conn_token.0,
+            owner,
+            kind
+        );
+        self.leases.insert(
+            conn_token,
+            Lease {
+                owner: owner.to_string(),
+                kind,
+                expiry: timeout.map(|timeout| Instant::now() + timeout),
+            },
+        );
+        Ok(())
+    }
+
+    /// Release the lease held by the given connection.
+    pub fn release(&mut self, conn_token: Token) -> Result<()> {
+        self.expire();
+        self.leases
+            .remove(&conn_token)
+            .ok_or(TransportError::InvalidOperation)?;
+        log::info!("Connection {:#X} released its lease", conn_token.0);
+        Ok(())
+    }
+
+    /// Release any lease held by a connection which has been closed.
+    pub fn connection_closed(&mut self, conn_token: Token) {
+        if let Some(lease) = self.leases.remove(&conn_token) {
+            log::info!(
+                "Lease of connection {:#X} ({}) released on disconnect",
+                conn_token.0,
+                lease.owner
+            );
+        }
+    }
+
+    /// Verify that the given connection is allowed to operate the transport.
+    pub fn check(&mut self, conn_token: Token) -> Result<()> {
+        self.expire();
+        if self.leases.is_empty() || self.leases.contains_key(&conn_token) {
+            return Ok(());
+        }
+        Err(ProxyError::Leased(self.conflicts(conn_token, None).join(", ")).into())
+    }
+
+    /// List all current leases.
+    pub fn list(&mut self, conn_token: Token) -> Vec<LeaseInfo> {
+        self.expire();
+        let now = Instant::now();
+        self.leases
+            .iter()
+            .map(|(token, lease)| LeaseInfo {
+                owner: lease.owner.clone(),
+                kind: lease.kind,
+                expires_in: lease.expiry.map(|expiry| expiry - now),
+                own: *token == conn_token,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALICE: Token = Token(1);
+    const BOB: Token = Token(2);
+    const CAROL: Token = Token(3);
+
+    #[test]
+    fn test_exclusive() -> Result<()> {
+        let mut leases = LeaseRegistry::new();
+        leases.acquire(ALICE, LeaseKind::Exclusive, None, "alice")?;
+        assert!(leases.check(ALICE).is_ok());
+        let err = leases.check(BOB).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Transport is leased by another client: alice (Exclusive)"
+        );
+        assert!(leases.acquire(BOB, LeaseKind::Shared, None, "bob").is_err());
+        leases.connection_closed(ALICE);
+        assert!(leases.check(BOB).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared() -> Result<()> {
+        let mut leases = LeaseRegistry::new();
+        leases.acquire(ALICE, LeaseKind::Shared, None, "alice")?;
+        leases.acquire(BOB, LeaseKind::Shared, None, "bob")?;
+        assert!(leases.check(ALICE).is_ok());
+        assert!(leases.check(BOB).is_ok());
+        assert!(leases.check(CAROL).is_err());
+        assert!(leases
+            .acquire(CAROL, LeaseKind::Exclusive, None, "carol")
+            .is_err());
+        // Upgrading is only possible once other holders have released their leases.
+        assert!(leases
+            .acquire(ALICE, LeaseKind::Exclusive, None, "alice")
+            .is_err());
+        leases.release(BOB)?;
+        leases.acquire(ALICE, LeaseKind::Exclusive, None, "alice")?;
+        assert_eq!(leases.list(ALICE).len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_timeout() -> Result<()> {
+        let mut leases = LeaseRegistry::new();
+        leases.acquire(ALICE, LeaseKind::Exclusive, Some(Duration::ZERO), "alice")?;
+        assert!(leases.check(BOB).is_ok());
+        assert!(leases.release(ALICE).is_err());
+        Ok(())
+    }
+}
@@ -2,24 +2,35 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{bail, Result};
-use handler::TransportCommandHandler;
+use anyhow::{bail, ensure, Context, Result};
+use clap::Args;
 use mio::event::Event;
-use mio::net::TcpListener;
+use mio::net::{TcpListener, UnixListener};
 use mio::{Registry, Token};
 use nonblocking_uart::NonblockingUartRegistry;
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
 use protocol::Message;
-use socket_server::{Connection, JsonSocketServer};
-use std::collections::HashMap;
+use socket_server::{Connection, JsonSocketServer, Listener, StdioStream};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
 use std::net::SocketAddr;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use targets::TargetCommandHandler;
 
 use crate::app::TransportWrapper;
 
+mod binary;
 pub mod errors;
 mod handler;
+mod lease;
 mod nonblocking_uart;
 pub mod protocol;
 mod socket_server;
+mod targets;
 
 /// Interface for handlers of protocol messages, responding to each message with a single
 /// instance of the same protocol message.
@@ -32,6 +43,9 @@ pub trait CommandHandler<Msg, E: ExtraEventHandler> {
         msg: &Msg,
     ) -> Result<Msg>;
 
+    /// Called when a connection has been closed, for releasing any resources held on its behalf.
+    fn connection_closed(&mut self, _conn_token: Token) {}
+
     fn register_nonblocking_help(
         &self,
         _registry: &mio::Registry,
@@ -53,15 +67,93 @@ pub trait ExtraEventHandler {
     ) -> Result<bool>;
 }
 
+/// Options for protecting the session proxy from eavesdropping and unauthorized clients.
+#[derive(Debug, Default, Args)]
+pub struct SessionSecurityOpts {
+    /// PEM file with the certificate chain of the server, enables TLS (requires --tls-key).
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of the server certificate.
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM file with CA certificates, clients will be required to present a certificate signed
+    /// by one of these.
+    #[arg(long)]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Pre-shared token, which clients must present before issuing any requests.
+    #[arg(long, env = "OPENTITANSESSION_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+impl SessionSecurityOpts {
+    fn tls_acceptor(&self) -> Result<Option<SslAcceptor>> {
+        let (cert, key) = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => {
+                ensure!(
+                    self.tls_client_ca.is_none(),
+                    "--tls-client-ca requires --tls-cert"
+                );
+                return Ok(None);
+            }
+            _ => bail!("--tls-cert and --tls-key must be given together"),
+        };
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server())?;
+        builder
+            .set_certificate_chain_file(cert)
+            .with_context(|| format!("loading {cert:?}"))?;
+        builder
+            .set_private_key_file(key, SslFiletype::PEM)
+            .with_context(|| format!("loading {key:?}"))?;
+        builder.check_private_key()?;
+        if let Some(ca) = &self.tls_client_ca {
+            builder
+                .set_ca_file(ca)
+                .with_context(|| format!("loading {ca:?}"))?;
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+        Ok(Some(builder.build()))
+    }
+}
+
+/// Server-side configuration of JTAG access offered to clients.  Clients only select the TAP
+/// and the adapter speed, as the session would otherwise run whatever OpenOCD binary a client
+/// names.
+#[derive(Clone, Debug, Args)]
+pub struct SessionJtagOpts {
+    /// OpenOCD binary to run on behalf of clients.
+    #[arg(long, default_value = "openocd")]
+    pub openocd: PathBuf,
+
+    /// Highest JTAG adapter speed clients may request.
+    #[arg(long, default_value = "10000")]
+    pub max_adapter_speed_khz: u64,
+}
+
 /// This is the main entry point for the session proxy.  This struct will either bind on a
 /// specified port, or find an available port from a range, before entering an event loop.
+/// Alternatively, it can listen on a Unix domain socket, or serve a single connection over
+/// standard input and output.
 pub struct SessionHandler<'a> {
-    port: u16,
-    socket_server: JsonSocketServer<Message, TransportCommandHandler<'a>, NonblockingUartRegistry>,
+    port: Option<u16>,
+    /// Unix domain socket to be removed when the session ends.
+    unix_socket: Option<PathBuf>,
+    socket_server: JsonSocketServer<Message, TargetCommandHandler<'a>, NonblockingUartRegistry>,
 }
 
 impl<'a> SessionHandler<'a> {
-    pub fn init(transport: &'a TransportWrapper, listen_port: Option<u16>) -> Result<Self> {
+    /// Serves a number of transports, among which each client selects one by name.  If the map
+    /// holds a single transport, clients need not select it.
+    pub fn init(
+        transports: &'a BTreeMap<String, TransportWrapper>,
+        listen_port: Option<u16>,
+        security: &SessionSecurityOpts,
+        jtag: &SessionJtagOpts,
+    ) -> Result<Self> {
+        let tls_acceptor = security.tls_acceptor()?;
         let mut port = listen_port.unwrap_or(9900);
         let limit = listen_port.unwrap_or(9999);
         // Find a suitable port to bind to.
@@ -73,18 +165,80 @@ impl<'a> SessionHandler<'a> {
                 Err(_) => port += 1,
             }
         };
-        let socket_server = JsonSocketServer::new(
-            TransportCommandHandler::new(transport)?,
+        let mut session = Self::new(transports, Listener::Tcp(socket), security, jtag)?;
+        if let Some(tls_acceptor) = tls_acceptor {
+            session.socket_server.set_tls_acceptor(tls_acceptor);
+        }
+        session.port = Some(port);
+        Ok(session)
+    }
+
+    /// Like `init()`, but listens on a Unix domain socket at `path`, accessible according to
+    /// the given permission bits.  A stale socket left behind by a previous session is
+    /// replaced.
+    pub fn init_unix(
+        transports: &'a BTreeMap<String, TransportWrapper>,
+        path: &Path,
+        mode: u32,
+        security: &SessionSecurityOpts,
+        jtag: &SessionJtagOpts,
+    ) -> Result<Self> {
+        ensure!(
+            security.tls_cert.is_none(),
+            "TLS is only supported for TCP connections"
+        );
+        // Remember the absolute path, in case the working directory changes.
+        let path = env::current_dir()?.join(path);
+        remove_stale_socket(&path)?;
+        let socket = UnixListener::bind(&path).with_context(|| format!("binding {path:?}"))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        let mut session = Self::new(transports, Listener::Unix(socket), security, jtag)?;
+        session.unix_socket = Some(path);
+        Ok(session)
+    }
+
+    /// Like `init()`, but serves a single connection over standard input and output, with
+    /// `run_loop()` returning once it has been closed.
+    pub fn init_stdio(
+        transports: &'a BTreeMap<String, TransportWrapper>,
+        security: &SessionSecurityOpts,
+        jtag: &SessionJtagOpts,
+    ) -> Result<Self> {
+        ensure!(
+            security.tls_cert.is_none(),
+            "TLS is only supported for TCP connections"
+        );
+        Self::new(
+            transports,
+            Listener::Stdio(StdioStream::new()?),
+            security,
+            jtag,
+        )
+    }
+
+    fn new(
+        transports: &'a BTreeMap<String, TransportWrapper>,
+        listener: Listener,
+        security: &SessionSecurityOpts,
+        jtag: &SessionJtagOpts,
+    ) -> Result<Self> {
+        let mut socket_server = JsonSocketServer::new(
+            TargetCommandHandler::new(transports, jtag)?,
             NonblockingUartRegistry::new(),
-            socket,
+            listener,
         )?;
+        if let Some(token) = &security.token {
+            socket_server.set_token(token.clone());
+        }
         Ok(Self {
-            port,
+            port: None,
+            unix_socket: None,
             socket_server,
         })
     }
 
-    pub fn get_port(&self) -> u16 {
+    /// The TCP port being listened on, if any.
+    pub fn get_port(&self) -> Option<u16> {
         self.port
     }
 
@@ -92,3 +246,30 @@ impl<'a> SessionHandler<'a> {
         self.socket_server.run_loop()
     }
 }
+
+impl Drop for SessionHandler<'_> {
+    fn drop(&mut self) {
+        if let Some(path) = &self.unix_socket {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Remove a Unix domain socket at `path`, unless another process is listening on it.
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) => {
+            ensure!(
+                metadata.file_type().is_socket(),
+                "{path:?} exists, and is not a socket"
+            );
+            match UnixStream::connect(path) {
+                Ok(_) => bail!("Another session is listening on {path:?}"),
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => Ok(fs::remove_file(path)?),
+                Err(e) => Err(e).with_context(|| format!("probing {path:?}")),
+            }
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("{path:?}")),
+    }
+}
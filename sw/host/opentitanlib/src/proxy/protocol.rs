@@ -2,17 +2,25 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Duration;
 
 use crate::bootstrap::BootstrapOptions;
+use crate::dif::lc_ctrl::LcCtrlReg;
 use crate::io::emu::{EmuState, EmuValue};
 use crate::io::gpio::{
     ClockNature, MonitoringReadResponse, MonitoringStartResponse, PinMode, PullMode,
 };
 use crate::io::i2c::DeviceStatus;
+use crate::io::jtag::{JtagTap, RiscvReg};
 use crate::io::spi::{MaxSizes, TransferMode};
 use crate::io::uart::Parity;
+use crate::proxy::binary;
 use crate::proxy::errors::SerializedError;
 use crate::transport::Capabilities;
 use crate::util::voltage::Voltage;
@@ -28,9 +36,115 @@ pub enum Message {
     Async { channel: u32, msg: AsyncMessage },
 }
 
+/// First message sent by the client, if the server requires a pre-shared token.  No `Message`
+/// will be processed until the server has responded with `AuthResponse::Accepted`.
+#[derive(Serialize, Deserialize)]
+pub struct AuthRequest {
+    pub token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum AuthResponse {
+    Accepted,
+    Rejected,
+}
+
+/// Encoding of the messages exchanged on a connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    /// One JSON document per line.  Used until another encoding has been negotiated.
+    #[default]
+    Json,
+    /// Each message in the encoding of `crate::proxy::binary`, preceded by its length as a
+    /// 32-bit little-endian number.
+    Binary,
+}
+
+/// Largest message accepted in the binary encoding, guarding against a corrupt or hostile
+/// length prefix making the receiver buffer an unbounded amount of data.  Comfortably larger
+/// than any flash image sent for bootstrapping.
+pub const MAX_BINARY_MESSAGE_SIZE: usize = 64 << 20;
+
+impl Encoding {
+    /// All encodings supported by this implementation.
+    pub const ALL: [Encoding; 2] = [Encoding::Json, Encoding::Binary];
+
+    /// Appends `msg` to `buf`, framed such that the receiver can tell where it ends.
+    pub fn encode<T: Serialize>(self, buf: &mut Vec<u8>, msg: &T) -> Result<()> {
+        match self {
+            Encoding::Json => {
+                serde_json::to_writer(&mut *buf, msg)?;
+                buf.push(b'\n');
+            }
+            Encoding::Binary => {
+                let start = buf.len();
+                buf.extend_from_slice(&[0u8; 4]);
+                binary::to_writer(buf, msg)?;
+                let len = u32::try_from(buf.len() - start - 4)?;
+                buf[start..start + 4].copy_from_slice(&len.to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    /// Locates the first complete message in `buf`, returning the range holding its encoding,
+    /// and the length of the entire frame.  For JSON, the first `searched` bytes are known not
+    /// to contain the end of a message.  Fails if a binary message is announced to be larger
+    /// than `MAX_BINARY_MESSAGE_SIZE`.
+    pub fn find_message(
+        self,
+        buf: &[u8],
+        searched: usize,
+    ) -> Result<Option<(Range<usize>, usize)>> {
+        match self {
+            Encoding::Json => Ok(buf[searched..]
+                .iter()
+                .position(|c| *c == b'\n')
+                .map(|n| (0..searched + n, searched + n + 1))),
+            Encoding::Binary => {
+                let Some(prefix) = buf.get(..4) else {
+                    return Ok(None);
+                };
+                let len = u32::from_le_bytes(prefix.try_into().unwrap()) as usize;
+                if len > MAX_BINARY_MESSAGE_SIZE {
+                    bail!(binary::BinaryError::MessageTooLarge(len));
+                }
+                Ok((buf.len() >= 4 + len).then_some((4..4 + len, 4 + len)))
+            }
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, data: &[u8]) -> Result<T> {
+        Ok(match self {
+            Encoding::Json => serde_json::from_slice(data)?,
+            Encoding::Binary => binary::from_slice(data)?,
+        })
+    }
+}
+
+/// Optionally sent by the client in place of a JSON `Message`, listing the encodings it
+/// supports, in order of preference.  The server responds with the one to be used by both
+/// sides for all further messages.  Both the request and response are in JSON.
+///
+/// Servers predating this message close the connection when receiving it, so clients only
+/// send it after the server has listed encodings other than JSON in its response to
+/// `ProxyRequest::Provides`.
+#[derive(Serialize, Deserialize)]
+pub struct EncodingRequest {
+    pub encodings: Vec<Encoding>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EncodingResponse {
+    pub encoding: Encoding,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum AsyncMessage {
-    UartData { data: Vec<u8> },
+    UartData {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -44,7 +158,9 @@ pub enum Request {
     Spi { id: String, command: SpiRequest },
     I2c { id: String, command: I2cRequest },
     Emu { command: EmuRequest },
+    Jtag { command: JtagRequest },
     Proxy(ProxyRequest),
+    Reconnect,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -58,7 +174,9 @@ pub enum Response {
     Spi(SpiResponse),
     I2c(I2cResponse),
     Emu(EmuResponse),
+    Jtag(JtagResponse),
     Proxy(ProxyResponse),
+    Reconnect,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -111,15 +229,26 @@ pub enum GpioMonResponse {
 
 #[derive(Serialize, Deserialize)]
 pub enum BitbangEntryRequest {
-    Write { data: Vec<u8> },
-    Both { data: Vec<u8> },
-    Delay { clock_ticks: u32 },
+    Write {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    Both {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    Delay {
+        clock_ticks: u32,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum BitbangEntryResponse {
     Write,
-    Both { data: Vec<u8> },
+    Both {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     Delay,
 }
 
@@ -149,35 +278,65 @@ pub enum UartRequest {
         len: u32,
     },
     Write {
+        #[serde(with = "serde_bytes")]
         data: Vec<u8>,
     },
     SupportsNonblockingRead,
     RegisterNonblockingRead,
+    SetFlowControl(bool),
+    SetHardwareFlowControl(bool),
+    SetBreak(bool),
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum UartResponse {
-    GetBaudrate { rate: u32 },
+    GetBaudrate {
+        rate: u32,
+    },
     SetBaudrate,
     SetParity,
-    Read { data: Vec<u8> },
+    Read {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     Write,
-    SupportsNonblockingRead { has_support: bool },
-    RegisterNonblockingRead { channel: u32 },
+    SupportsNonblockingRead {
+        has_support: bool,
+    },
+    RegisterNonblockingRead {
+        channel: u32,
+    },
+    SetFlowControl,
+    SetHardwareFlowControl,
+    SetBreak,
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum SpiTransferRequest {
-    Read { len: u32 },
-    Write { data: Vec<u8> },
-    Both { data: Vec<u8> },
+    Read {
+        len: u32,
+    },
+    Write {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    Both {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum SpiTransferResponse {
-    Read { data: Vec<u8> },
+    Read {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     Write,
-    Both { data: Vec<u8> },
+    Both {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
@@ -251,13 +410,21 @@ pub enum SpiResponse {
 
 #[derive(Serialize, Deserialize)]
 pub enum I2cTransferRequest {
-    Read { len: u32 },
-    Write { data: Vec<u8> },
+    Read {
+        len: u32,
+    },
+    Write {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum I2cTransferResponse {
-    Read { data: Vec<u8> },
+    Read {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
     Write,
 }
 
@@ -279,6 +446,7 @@ pub enum I2cRequest {
         timeout_millis: u32,
     },
     PrepareReadData {
+        #[serde(with = "serde_bytes")]
         data: Vec<u8>,
         sticky: bool,
     },
@@ -318,11 +486,132 @@ pub enum EmuResponse {
     Stop,
 }
 
+#[derive(Serialize, Deserialize)]
+pub enum JtagRequest {
+    /// Starts OpenOCD and connects to `tap`.  The OpenOCD binary and its configuration are
+    /// chosen by the session, the client only gets to pick the adapter speed.  Only the
+    /// connecting client may use the JTAG session, which ends when that client disconnects.
+    Connect {
+        adapter_speed_khz: u64,
+        tap: JtagTap,
+    },
+    Disconnect,
+    ReadLcCtrlReg {
+        reg: LcCtrlReg,
+    },
+    WriteLcCtrlReg {
+        reg: LcCtrlReg,
+        value: u32,
+    },
+    ReadMemory {
+        addr: u32,
+        len: u32,
+    },
+    ReadMemory32 {
+        addr: u32,
+        len: u32,
+    },
+    WriteMemory {
+        addr: u32,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    WriteMemory32 {
+        addr: u32,
+        data: Vec<u32>,
+    },
+    Halt,
+    WaitHalt {
+        timeout_millis: u64,
+    },
+    Resume,
+    ResumeAt {
+        addr: u32,
+    },
+    Step,
+    StepAt {
+        addr: u32,
+    },
+    Reset {
+        run: bool,
+    },
+    ReadRiscvReg {
+        reg: RiscvReg,
+    },
+    WriteRiscvReg {
+        reg: RiscvReg,
+        value: u32,
+    },
+    SetBreakpoint {
+        addr: u32,
+        hw: bool,
+    },
+    RemoveBreakpoint {
+        addr: u32,
+    },
+    RemoveAllBreakpoints,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum JtagResponse {
+    Connect,
+    Disconnect,
+    ReadLcCtrlReg {
+        value: u32,
+    },
+    WriteLcCtrlReg,
+    ReadMemory {
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+    ReadMemory32 {
+        data: Vec<u32>,
+    },
+    WriteMemory,
+    WriteMemory32,
+    Halt,
+    WaitHalt,
+    Resume,
+    ResumeAt,
+    Step,
+    StepAt,
+    Reset,
+    ReadRiscvReg {
+        value: u32,
+    },
+    WriteRiscvReg,
+    SetBreakpoint,
+    RemoveBreakpoint,
+    RemoveAllBreakpoints,
+}
+
+/// Kind of reservation of the transport held by a session client.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, ValueEnum)]
+pub enum LeaseKind {
+    /// No other client may hold any lease, or issue any requests.
+    Exclusive,
+    /// Other clients may also hold shared leases, clients without a lease may not issue any
+    /// requests.
+    Shared,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeaseInfo {
+    pub owner: String,
+    pub kind: LeaseKind,
+    /// Time until the lease is automatically released, if it was acquired with a timeout.
+    #[serde(with = "humantime_serde")]
+    pub expires_in: Option<Duration>,
+    /// Whether the lease is held by the connection asking.
+    pub own: bool,
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum ProxyRequest {
     Provides,
     Bootstrap {
         options: BootstrapOptions,
+        #[serde(with = "serde_bytes")]
         payload: Vec<u8>,
     },
     ApplyPinStrapping {
@@ -334,15 +623,64 @@ pub enum ProxyRequest {
     ApplyDefaultConfigurationWithStrapping {
         strapping_name: String,
     },
+    AcquireLease {
+        kind: LeaseKind,
+        timeout_millis: Option<u64>,
+        owner: String,
+    },
+    ReleaseLease,
+    GetLeases,
+    ListTargets,
+    SelectTarget {
+        name: String,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum ProxyResponse {
     Provides {
         provides_map: HashMap<String, String>,
+        /// Encodings supported by the server, see `EncodingRequest`.  Absent in responses from
+        /// servers predating the binary encoding.
+        #[serde(default)]
+        encodings: Vec<Encoding>,
     },
     Bootstrap,
     ApplyPinStrapping,
     RemovePinStrapping,
     ApplyDefaultConfigurationWithStrapping,
+    AcquireLease,
+    ReleaseLease,
+    GetLeases {
+        leases: Vec<LeaseInfo>,
+    },
+    ListTargets {
+        targets: Vec<String>,
+    },
+    SelectTarget,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_binary_message() -> Result<()> {
+        let mut buf = Vec::new();
+        Encoding::Binary.encode(&mut buf, &Request::GetCapabilities)?;
+        let frame_len = buf.len();
+        buf.extend_from_slice(&[0u8; 3]);
+        assert_eq!(
+            Encoding::Binary.find_message(&buf, 0)?,
+            Some((4..frame_len, frame_len))
+        );
+        assert_eq!(
+            Encoding::Binary.find_message(&buf[..frame_len - 1], 0)?,
+            None
+        );
+
+        let oversized = u32::try_from(MAX_BINARY_MESSAGE_SIZE + 1)?.to_le_bytes();
+        assert!(Encoding::Binary.find_message(&oversized, 0).is_err());
+        Ok(())
+    }
 }
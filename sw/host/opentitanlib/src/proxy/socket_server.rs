@@ -3,34 +3,53 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{bail, Result};
-use mio::event::Event;
-use mio::net::TcpListener;
-use mio::net::TcpStream;
+use mio::event::{Event, Source};
+use mio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use mio::unix::SourceFd;
 use mio::{Events, Interest, Poll, Registry, Token};
 use mio_signals::{Signal, SignalSet, Signals};
+use openssl::ssl::{ErrorCode, Ssl, SslAcceptor, SslStream};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::HashMap;
-use std::io::{ErrorKind, Read, Write};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
 use std::marker::PhantomData;
+use std::ops::Range;
+use std::os::fd::{AsFd, AsRawFd};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use super::protocol::{AuthRequest, AuthResponse, Encoding, EncodingRequest, EncodingResponse};
 use super::CommandHandler;
 use super::ExtraEventHandler;
 
 const BUFFER_SIZE: usize = 8192;
-const EOL_CODE: u8 = b'\n';
 
 pub fn get_next_token() -> Token {
     static TOCKEN_COUNTER: AtomicUsize = AtomicUsize::new(0);
     Token(TOCKEN_COUNTER.fetch_add(1, Ordering::Relaxed))
 }
 
-/// This struct listens on a TCP socket, and maintains a number of concurrent connections,
-/// receiving serialized JSON representations of `Msg`, passing them to the given
+/// Source of the connections served by a `JsonSocketServer`.
+pub enum Listener {
+    Tcp(TcpListener),
+    /// Access is controlled by the filesystem permissions of the socket.
+    Unix(UnixListener),
+    /// A single connection, over the standard input and output of the process, e.g. when run
+    /// through `ssh`.  The server exits once the connection is closed.
+    Stdio(StdioStream),
+}
+
+/// This struct listens on a TCP or Unix domain socket, and maintains a number of concurrent
+/// connections, receiving serialized JSON representations of `Msg`, passing them to the given
 /// `CommandHandler` to obtain responses to be sent as socket flow contol permits.  Note that
 /// this implementaion is not specific to (and does not refer to) any particular protocol.
+///
+/// TCP connections can optionally be encrypted using TLS, and clients can be required to
+/// present a pre-shared token, in an `AuthRequest` message, before any of their messages are
+/// processed.  Clients can negotiate a more compact encoding than JSON, by means of an
+/// `EncodingRequest` message.
 pub struct JsonSocketServer<
     Msg: DeserializeOwned + Serialize,
     T: CommandHandler<Msg, E>,
@@ -39,12 +58,14 @@ pub struct JsonSocketServer<
     command_handler: T,
     extra_event_handler: E,
     poll: Poll,
-    socket: TcpListener,
+    listener: Listener,
     socket_token: Token,
     signals: Signals,
     signal_token: Token,
     nonblocking_help_token: Token,
     connection_map: HashMap<Token, Connection>,
+    tls_acceptor: Option<SslAcceptor>,
+    token: Option<String>,
     exit_requested: bool,
     phantom: PhantomData<Msg>,
 }
@@ -52,15 +73,21 @@ pub struct JsonSocketServer<
 impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEventHandler>
     JsonSocketServer<Msg, T, E>
 {
-    pub fn new(
-        command_handler: T,
-        extra_event_handler: E,
-        mut socket: TcpListener,
-    ) -> Result<Self> {
+    pub fn new(command_handler: T, extra_event_handler: E, mut listener: Listener) -> Result<Self> {
         let poll = Poll::new()?;
         let socket_token = get_next_token();
-        poll.registry()
-            .register(&mut socket, socket_token, Interest::READABLE)?;
+        match listener {
+            Listener::Tcp(ref mut socket) => {
+                poll.registry()
+                    .register(socket, socket_token, Interest::READABLE)?;
+            }
+            Listener::Unix(ref mut socket) => {
+                poll.registry()
+                    .register(socket, socket_token, Interest::READABLE)?;
+            }
+            // The connection is established in `run_loop()`, once all settings are in place.
+            Listener::Stdio(_) => (),
+        }
         // Create a `Signals` instance that will catch given set of signals for us.
         let signals: SignalSet = Signal::Terminate | Signal::Interrupt;
         let mut signals = Signals::new(signals)?;
@@ -74,18 +101,35 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
             command_handler,
             extra_event_handler,
             poll,
-            socket,
+            listener,
             socket_token,
             signals,
             signal_token,
             nonblocking_help_token,
             connection_map: HashMap::new(),
+            tls_acceptor: None,
+            token: None,
             exit_requested: false,
             phantom: PhantomData,
         })
     }
 
+    /// Require TLS on all subsequently accepted TCP connections.
+    pub fn set_tls_acceptor(&mut self, tls_acceptor: SslAcceptor) {
+        self.tls_acceptor = Some(tls_acceptor);
+    }
+
+    /// Require all subsequently accepted connections to present the given token before any
+    /// other messages.
+    pub fn set_token(&mut self, token: String) {
+        self.token = Some(token);
+    }
+
     pub fn run_loop(&mut self) -> Result<()> {
+        if let Listener::Stdio(ref stdio) = self.listener {
+            let stream = Stream::Stdio(stdio.try_clone()?);
+            self.add_connection(stream)?;
+        }
         let mut events = Events::with_capacity(1024);
         while !self.exit_requested {
             match self.poll.poll(&mut events, None) {
@@ -127,33 +171,53 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
     /// Accept new socket connections, creating new Connection objects.
     fn process_new_connection(&mut self) -> Result<()> {
         loop {
-            match self.socket.accept() {
-                Ok((mut conn_socket, _addres)) => {
-                    let token = get_next_token();
-                    log::info!("New connection id:{:#X}", token.0);
-                    match self.connection_map.entry(token) {
-                        Vacant(entry) => {
-                            self.poll.registry().register(
-                                &mut conn_socket,
-                                token,
-                                Interest::READABLE | Interest::WRITABLE,
-                            )?;
-                            entry.insert(Connection::new(conn_socket));
-                        }
-                        Occupied(_) => {
-                            panic!("JsonSocketServer error: token colision");
-                        }
-                    };
-                }
+            let accepted = match self.listener {
+                Listener::Tcp(ref socket) => socket.accept().and_then(|(conn_socket, _address)| {
+                    Ok(match self.tls_acceptor {
+                        Some(ref acceptor) => Stream::Tls(
+                            Ssl::new(acceptor.context())
+                                .and_then(|ssl| SslStream::new(ssl, conn_socket))
+                                .map_err(io::Error::other)?,
+                        ),
+                        None => Stream::Plain(conn_socket),
+                    })
+                }),
+                Listener::Unix(ref socket) => socket
+                    .accept()
+                    .map(|(conn_socket, _address)| Stream::Unix(conn_socket)),
+                Listener::Stdio(_) => return Ok(()),
+            };
+            match accepted {
+                Ok(stream) => self.add_connection(stream)?,
                 Err(err) if err.kind() == ErrorKind::WouldBlock => {
                     // No more connections ready to accept (or spurious poll event).
                     return Ok(());
                 }
-                Err(err) => bail!("Error accepting TCP connection: {}", err),
+                Err(err) => bail!("Error accepting connection: {}", err),
             }
         }
     }
 
+    /// Register a newly established connection with the event loop.
+    fn add_connection(&mut self, mut stream: Stream) -> Result<()> {
+        let token = get_next_token();
+        log::info!("New connection id:{:#X}", token.0);
+        match self.connection_map.entry(token) {
+            Vacant(entry) => {
+                self.poll.registry().register(
+                    &mut stream,
+                    token,
+                    Interest::READABLE | Interest::WRITABLE,
+                )?;
+                entry.insert(Connection::new(stream, self.token.is_none()));
+            }
+            Occupied(_) => {
+                panic!("JsonSocketServer error: token colision");
+            }
+        };
+        Ok(())
+    }
+
     fn process_signals(&mut self) -> Result<()> {
         loop {
             match self.signals.receive()? {
@@ -177,18 +241,30 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
     fn process_connection(&mut self, event: &Event) -> Result<bool> {
         match self.connection_map.get_mut(&event.token()) {
             Some(conn) => {
-                if event.is_writable() {
+                let handshake_pending = conn.tls_handshake_pending;
+                if !conn.handshake()? {
+                    // TLS handshake still in progress, wait for more data from the client.
+                    return Ok(false);
+                }
+                // The event completing the TLS handshake may have been for either direction, and
+                // there will be no further event to indicate that the other is also ready.
+                if event.is_writable() || handshake_pending {
                     conn.write()?;
                 }
-                if event.is_readable() {
+                if event.is_readable() || handshake_pending {
                     conn.read()?;
-                    Self::process_any_requests(
-                        conn,
-                        &mut self.command_handler,
-                        event.token(),
-                        self.poll.registry(),
-                        &mut self.extra_event_handler,
-                    )?;
+                    if !conn.authenticated {
+                        Self::authenticate(conn, self.token.as_deref().unwrap_or_default())?;
+                    }
+                    if conn.authenticated {
+                        Self::process_any_requests(
+                            conn,
+                            &mut self.command_handler,
+                            event.token(),
+                            self.poll.registry(),
+                            &mut self.extra_event_handler,
+                        )?;
+                    }
                 }
                 // Return whether this connection object should be dropped.
                 Ok((conn.rx_eof && (conn.tx_buf.is_empty())) || conn.broken)
@@ -204,27 +280,89 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
             .connection_map
             .remove(&event.token())
             .expect("Missing connection this should never happend!!!");
-        self.poll.registry().deregister(&mut conn.socket)?;
+        self.poll.registry().deregister(&mut conn.stream)?;
+        self.command_handler.connection_closed(event.token());
+        if let Listener::Stdio(_) = self.listener {
+            // There will be no other connections.
+            self.exit_requested = true;
+        }
         // As `conn` runs out of scope here, its `drop()` method will close the OS handle, which
         // in turn causes TCP/IP connection shutdown to be signalled to the remote end.
         Ok(())
     }
 
-    /// Check if the buffer contains at least one full JSON request.  If so, remove it from the
-    /// buffer, decode and return it.
+    /// Check whether the client has sent its `AuthRequest`, and if so, mark the connection as
+    /// authenticated if the token matches, or have it shut down if not.
+    fn authenticate(conn: &mut Connection, token: &str) -> Result<()> {
+        let Some(request) = Self::get_complete_message::<AuthRequest>(conn)? else {
+            return Ok(());
+        };
+        if constant_time_eq(request.token.as_bytes(), token.as_bytes()) {
+            conn.authenticated = true;
+            conn.transmit_outgoing_msg(AuthResponse::Accepted)
+        } else {
+            log::warn!("Connection presented an invalid token");
+            // Discard anything else the client may have sent, and close the connection once the
+            // response has been sent.
+            conn.rx_buf.clear();
+            conn.rx_eof = true;
+            conn.transmit_outgoing_msg(AuthResponse::Rejected)
+        }
+    }
+
+    /// Check whether a JSON message, which could not be decoded as a request, is an
+    /// `EncodingRequest`, and if so, respond with the encoding to be used for all further
+    /// messages.  Returns whether the message has been consumed.
+    fn negotiate_encoding(conn: &mut Connection, range: Range<usize>, len: usize) -> Result<bool> {
+        if conn.encoding != Encoding::Json {
+            return Ok(false);
+        }
+        let Ok(request) = serde_json::from_slice::<EncodingRequest>(&conn.rx_buf[range]) else {
+            return Ok(false);
+        };
+        conn.consume(len);
+        // Go with the first preference of the client which is supported.
+        let encoding = request
+            .encodings
+            .into_iter()
+            .find(|encoding| Encoding::ALL.contains(encoding))
+            .unwrap_or_default();
+        log::info!("Connection using {:?} encoding", encoding);
+        conn.transmit_outgoing_msg(EncodingResponse { encoding })?;
+        conn.encoding = encoding;
+        Ok(true)
+    }
+
+    /// Check if the buffer contains at least one full request.  If so, remove it from the
+    /// buffer, decode and return it.  Any `EncodingRequest` found along the way is handled.
     fn get_complete_request(conn: &mut Connection) -> Result<Option<Msg>> {
-        if let Some(n) = conn.rx_buf.iter().position(|c| *c == EOL_CODE) {
-            let res = serde_json::from_slice::<Msg>(&conn.rx_buf[..n])?;
-            if n + 1 < conn.rx_buf.len() {
-                // Shuffling bytes around in a Vec is expensive, but realistically, as the
-                // clients would be waiting for response to each request before sending the next
-                // request, this code will rarely if ever execute.
-                conn.rx_buf.rotate_left(n + 1);
+        loop {
+            let Some((range, len)) = conn.encoding.find_message(&conn.rx_buf, 0)? else {
+                return Ok(None);
+            };
+            match conn.encoding.decode::<Msg>(&conn.rx_buf[range.clone()]) {
+                Ok(res) => {
+                    conn.consume(len);
+                    return Ok(Some(res));
+                }
+                Err(e) => {
+                    if !Self::negotiate_encoding(conn, range, len)? {
+                        return Err(e);
+                    }
+                }
             }
-            conn.rx_buf.resize(conn.rx_buf.len() - n - 1, 0);
-            return Ok(Some(res));
         }
-        Ok(None)
+    }
+
+    /// Check if the buffer contains at least one full message of type `M`, in the encoding of
+    /// the connection.  If so, remove it from the buffer, decode and return it.
+    fn get_complete_message<M: DeserializeOwned>(conn: &mut Connection) -> Result<Option<M>> {
+        let Some((range, len)) = conn.encoding.find_message(&conn.rx_buf, 0)? else {
+            return Ok(None);
+        };
+        let res = conn.encoding.decode::<M>(&conn.rx_buf[range])?;
+        conn.consume(len);
+        Ok(Some(res))
     }
 
     // Look for any completely received requests in the rx_buf, and handle them one by one.
@@ -245,9 +383,139 @@ impl<Msg: DeserializeOwned + Serialize, T: CommandHandler<Msg, E>, E: ExtraEvent
     }
 }
 
+/// Compare two byte strings, in time independent of where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// The standard input and output of the process, serving as one connection.  Both must be
+/// pollable, e.g. pipes or a terminal, rather than regular files.
+pub struct StdioStream {
+    input: File,
+    output: File,
+}
+
+impl StdioStream {
+    /// Duplicate the standard input and output file descriptors, and switch them to
+    /// nonblocking mode.  The process may redirect its own standard output afterwards.
+    pub fn new() -> Result<Self> {
+        let input = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+        let output = File::from(io::stdout().as_fd().try_clone_to_owned()?);
+        rustix::io::ioctl_fionbio(&input, true)?;
+        rustix::io::ioctl_fionbio(&output, true)?;
+        Ok(Self { input, output })
+    }
+
+    fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            input: self.input.try_clone()?,
+            output: self.output.try_clone()?,
+        })
+    }
+}
+
+/// The socket of a connection, possibly wrapped in a TLS session.
+enum Stream {
+    Plain(TcpStream),
+    Tls(SslStream<TcpStream>),
+    Unix(UnixStream),
+    Stdio(StdioStream),
+}
+
+impl Source for Stream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.register(registry, token, interests),
+            Stream::Tls(stream) => stream.get_mut().register(registry, token, interests),
+            Stream::Unix(socket) => socket.register(registry, token, interests),
+            Stream::Stdio(stdio) => {
+                // Events for either direction are reported under the token of the connection.
+                SourceFd(&stdio.input.as_raw_fd()).register(registry, token, Interest::READABLE)?;
+                SourceFd(&stdio.output.as_raw_fd()).register(registry, token, Interest::WRITABLE)
+            }
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.reregister(registry, token, interests),
+            Stream::Tls(stream) => stream.get_mut().reregister(registry, token, interests),
+            Stream::Unix(socket) => socket.reregister(registry, token, interests),
+            Stream::Stdio(stdio) => {
+                SourceFd(&stdio.input.as_raw_fd()).reregister(
+                    registry,
+                    token,
+                    Interest::READABLE,
+                )?;
+                SourceFd(&stdio.output.as_raw_fd()).reregister(registry, token, Interest::WRITABLE)
+            }
+        }
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.deregister(registry),
+            Stream::Tls(stream) => stream.get_mut().deregister(registry),
+            Stream::Unix(socket) => socket.deregister(registry),
+            Stream::Stdio(stdio) => {
+                SourceFd(&stdio.input.as_raw_fd()).deregister(registry)?;
+                SourceFd(&stdio.output.as_raw_fd()).deregister(registry)
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+            Stream::Unix(socket) => socket.read(buf),
+            Stream::Stdio(stdio) => stdio.input.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+            Stream::Unix(socket) => socket.write(buf),
+            Stream::Stdio(stdio) => stdio.output.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.flush(),
+            Stream::Tls(stream) => stream.flush(),
+            Stream::Unix(socket) => socket.flush(),
+            Stream::Stdio(stdio) => stdio.output.flush(),
+        }
+    }
+}
+
 /// Represents one connection with a remote OpenTitan tool invocation.
 pub struct Connection {
-    socket: TcpStream,
+    stream: Stream,
+    /// The server side of the TLS handshake has not yet completed.
+    tls_handshake_pending: bool,
+    /// The client has presented the expected token, or none is required.  Until then, no
+    /// messages are passed to the `CommandHandler`.
+    authenticated: bool,
+    /// Encoding of messages in both directions, other than those for authentication.
+    encoding: Encoding,
     /// Outgoing data waiting to be written when the socket permits.
     tx_buf: Vec<u8>,
     /// Data received from the remote end, but not yet decoded into `Msg`.
@@ -261,9 +529,12 @@ pub struct Connection {
 }
 
 impl Connection {
-    fn new(soc: TcpStream) -> Self {
+    fn new(stream: Stream, authenticated: bool) -> Self {
         Self {
-            socket: soc,
+            tls_handshake_pending: matches!(stream, Stream::Tls(_)),
+            stream,
+            authenticated,
+            encoding: Encoding::Json,
             tx_buf: Vec::new(),
             rx_buf: Vec::new(),
             rx_eof: false,
@@ -273,20 +544,49 @@ impl Connection {
 
     pub fn transmit_outgoing_msg<T: Serialize>(&mut self, msg: T) -> Result<()> {
         // Encode response into tx_buf.
-        serde_json::to_writer(&mut self.tx_buf, &msg)?;
-        self.tx_buf.push(EOL_CODE);
+        self.encoding.encode(&mut self.tx_buf, &msg)?;
         // Transmit as much as possible without blocking, leaving any remnant in
         // tx_buf.  poll() will tell us when more can be written.
         self.write()?;
         Ok(())
     }
 
+    /// Remove the first `len` bytes, holding a message which has been processed, from `rx_buf`.
+    fn consume(&mut self, len: usize) {
+        if len < self.rx_buf.len() {
+            // Shuffling bytes around in a Vec is expensive, but realistically, as the clients
+            // would be waiting for response to each request before sending the next request,
+            // this code will rarely if ever execute.
+            self.rx_buf.rotate_left(len);
+        }
+        self.rx_buf.resize(self.rx_buf.len() - len, 0);
+    }
+
+    /// Advance any ongoing TLS handshake as far as possible without blocking.  Returns whether
+    /// the connection is ready for exchanging messages.
+    fn handshake(&mut self) -> Result<bool> {
+        if !self.tls_handshake_pending {
+            return Ok(true);
+        }
+        let Stream::Tls(ref mut stream) = self.stream else {
+            unreachable!();
+        };
+        match stream.accept() {
+            Ok(()) => {
+                self.tls_handshake_pending = false;
+                Ok(true)
+            }
+            Err(e) if matches!(e.code(), ErrorCode::WANT_READ | ErrorCode::WANT_WRITE) => Ok(false),
+            Err(e) => bail!("TLS handshake: {}", e),
+        }
+    }
+
     // Fill rx_buf with as much data as is available on the socket.
     fn read(&mut self) -> Result<()> {
         let mut rx_buf_len: usize = self.rx_buf.len();
         loop {
             self.rx_buf.resize(rx_buf_len + BUFFER_SIZE, 0);
-            match self.socket.read(&mut self.rx_buf[rx_buf_len..]) {
+            match self.stream.read(&mut self.rx_buf[rx_buf_len..]) {
                 Ok(0) => {
                     self.rx_eof = true;
                     break;
@@ -309,7 +609,7 @@ impl Connection {
     // Transmit as much data out of tx_buf as socket will allow.
     fn write(&mut self) -> Result<()> {
         while !self.tx_buf.is_empty() {
-            match self.socket.write(&self.tx_buf) {
+            match self.stream.write(&self.tx_buf) {
                 Ok(n) => {
                     if n < self.tx_buf.len() {
                         // Shuffling bytes around in a Vec is expensive, but realistically, as
@@ -0,0 +1,140 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Result};
+use mio::{Registry, Token};
+use std::collections::{BTreeMap, HashMap};
+
+use super::errors::SerializedError;
+use super::handler::TransportCommandHandler;
+use super::protocol::{Encoding, Message, ProxyRequest, ProxyResponse, Request, Response};
+use super::{CommandHandler, SessionJtagOpts};
+use crate::app::TransportWrapper;
+use crate::proxy::nonblocking_uart::NonblockingUartRegistry;
+use crate::transport::proxy::ProxyError;
+use crate::transport::{Capabilities, Capability};
+
+/// Dispatches protocol requests among a number of named transports served by the same session
+/// process.  Each connection selects the transport it wants to operate once, after which all
+/// of its requests are forwarded to the `TransportCommandHandler` of that transport.  If only a
+/// single transport is served, connections are bound to it without having to select it.
+pub struct TargetCommandHandler<'a> {
+    targets: BTreeMap<String, TransportCommandHandler<'a>>,
+    selected: HashMap<Token, String>,
+}
+
+impl<'a> TargetCommandHandler<'a> {
+    pub fn new(
+        transports: &'a BTreeMap<String, TransportWrapper>,
+        jtag_opts: &SessionJtagOpts,
+    ) -> Result<Self> {
+        let mut targets = BTreeMap::new();
+        for (name, transport) in transports {
+            targets.insert(
+                name.clone(),
+                TransportCommandHandler::new(transport, jtag_opts.clone())?,
+            );
+        }
+        Ok(Self {
+            targets,
+            selected: HashMap::new(),
+        })
+    }
+
+    /// Name of the target that requests from the given connection should go to, if any.
+    fn target_name(&self, conn_token: Token) -> Option<&str> {
+        if let Some(name) = self.selected.get(&conn_token) {
+            return Some(name);
+        }
+        match self.targets.keys().collect::<Vec<_>>()[..] {
+            [name] => Some(name),
+            _ => None,
+        }
+    }
+
+    fn target_names(&self) -> Vec<String> {
+        self.targets.keys().cloned().collect()
+    }
+
+    /// Handles requests from a connection which has yet to select one of several targets.
+    /// Enough is answered for the client to be able to construct its `TransportWrapper`, and
+    /// list and select targets.
+    fn do_execute_unselected_cmd(&self, req: &Request) -> Result<Response> {
+        match req {
+            Request::GetCapabilities => Ok(Response::GetCapabilities(Capabilities::new(
+                Capability::NONE,
+            ))),
+            Request::Proxy(ProxyRequest::Provides) => {
+                Ok(Response::Proxy(ProxyResponse::Provides {
+                    provides_map: HashMap::new(),
+                    encodings: Encoding::ALL.to_vec(),
+                }))
+            }
+            _ => bail!(ProxyError::NoTargetSelected(self.target_names().join(", "))),
+        }
+    }
+}
+
+impl<'a> CommandHandler<Message, NonblockingUartRegistry> for TargetCommandHandler<'a> {
+    fn execute_cmd(
+        &mut self,
+        conn_token: Token,
+        registry: &Registry,
+        others: &mut NonblockingUartRegistry,
+        msg: &Message,
+    ) -> Result<Message> {
+        let Message::Req(req) = msg else {
+            bail!("Client sent non-Request to server!!!");
+        };
+        let res = match req {
+            Request::Proxy(ProxyRequest::ListTargets) => {
+                Ok(Response::Proxy(ProxyResponse::ListTargets {
+                    targets: self.target_names(),
+                }))
+            }
+            Request::Proxy(ProxyRequest::SelectTarget { name }) => {
+                if self.targets.contains_key(name) {
+                    // Any lease held on the previously selected target is given up.
+                    if let Some(previous) = self.selected.insert(conn_token, name.clone()) {
+                        if let Some(handler) = self.targets.get_mut(&previous) {
+                            handler.connection_closed(conn_token);
+                        }
+                    }
+                    Ok(Response::Proxy(ProxyResponse::SelectTarget))
+                } else {
+                    Err(ProxyError::UnknownTarget(name.clone()).into())
+                }
+            }
+            _ => match self.target_name(conn_token).map(str::to_string) {
+                Some(name) => {
+                    let handler = self.targets.get_mut(&name).unwrap();
+                    return handler.execute_cmd(conn_token, registry, others, msg);
+                }
+                None => self.do_execute_unselected_cmd(req),
+            },
+        };
+        Ok(Message::Res(res.map_err(SerializedError::from)))
+    }
+
+    fn connection_closed(&mut self, conn_token: Token) {
+        self.selected.remove(&conn_token);
+        for handler in self.targets.values_mut() {
+            handler.connection_closed(conn_token);
+        }
+    }
+
+    fn register_nonblocking_help(&self, registry: &mio::Registry, token: mio::Token) -> Result<()> {
+        for handler in self.targets.values() {
+            handler.register_nonblocking_help(registry, token)?;
+        }
+        Ok(())
+    }
+
+    fn nonblocking_help(&self) -> Result<()> {
+        for handler in self.targets.values() {
+            handler.nonblocking_help()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rescue over USB, using the DFU class requests.
+//!
+//! Experimental: the ROM_EXT does not implement this protocol yet, and the `SET_MODE` request
+//! below is a proposal which may change once the device side lands.
+//!
+//! While in rescue mode, the ROM_EXT enumerates as a DFU device.  The rescue mode is selected
+//! with the vendor-specific `SET_MODE` request carrying the four mode bytes, data is sent to
+//! the chip as DFU_DNLOAD blocks concluded by a zero-length block, and received from the chip
+//! as DFU_UPLOAD blocks until a short block.
+
+use anyhow::{anyhow, bail, Result};
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use crate::app::TransportWrapper;
+use crate::rescue::{RescueError, RescueMode, RescueProtocol};
+use crate::transport::hyperdebug::dfu::{
+    get_status, wait_for_idle, DfuStatus, DFU_STATE_DFU_IDLE, DFU_STATUS_OK, USB_CLASS_APP,
+    USB_DFU_DNLOAD, USB_SUBCLASS_DFU,
+};
+use crate::transport::hyperdebug::VID_GOOGLE;
+use crate::util::usb::UsbBackend;
+
+/// An open DFU interface of the chip.
+struct DfuDevice {
+    usb: UsbBackend,
+    interface: u8,
+    xfer_size: usize,
+}
+
+pub struct RescueUsbDfu {
+    usb_vid: u16,
+    usb_pid: Option<u16>,
+    usb_serial: Option<String>,
+    device: RefCell<Option<DfuDevice>>,
+    reset_delay: Duration,
+    enter_delay: Duration,
+}
+
+impl RescueUsbDfu {
+    const USB_PROTOCOL_DFU_MODE: u8 = 0x02;
+    const USB_DFU_UPLOAD: u8 = 2;
+    pub const SET_MODE: u8 = 0x0a;
+    /// Transfer size used if the DFU functional descriptor cannot be found.
+    const DEFAULT_XFER_SIZE: usize = 64;
+
+    pub fn new(usb_vid: Option<u16>, usb_pid: Option<u16>, usb_serial: Option<String>) -> Self {
+        RescueUsbDfu {
+            usb_vid: usb_vid.unwrap_or(VID_GOOGLE),
+            usb_pid,
+            usb_serial,
+            device: RefCell::new(None),
+            reset_delay: Duration::from_millis(50),
+            enter_delay: Duration::from_secs(5),
+        }
+    }
+
+    /// Waits up to `timeout` for the chip to enumerate, and claims its DFU interface.
+    fn connect(&self, timeout: Duration) -> Result<()> {
+        let usb_pid = self
+            .usb_pid
+            .ok_or_else(|| anyhow!("USB DFU rescue requires the product ID (--dfu-pid)"))?;
+        let deadline = Instant::now() + timeout;
+        let mut usb = loop {
+            match UsbBackend::new(self.usb_vid, usb_pid, self.usb_serial.as_deref()) {
+                Ok(usb) => break usb,
+                Err(e) if Instant::now() >= deadline => return Err(e),
+                Err(_) => std::thread::sleep(Duration::from_millis(100)),
+            }
+        };
+        let mut dfu = None;
+        for interface in usb.active_config_descriptor()?.interfaces() {
+            for desc in interface.descriptors() {
+                if desc.class_code() == USB_CLASS_APP
+                    && desc.sub_class_code() == USB_SUBCLASS_DFU
+                    && desc.protocol_code() == Self::USB_PROTOCOL_DFU_MODE
+                {
+                    // The DFU functional descriptor carries wTransferSize at offset 5.
+                    let extra = desc.extra();
+                    let xfer_size = match extra.len() {
+                        9.. => u16::from_le_bytes([extra[5], extra[6]]) as usize,
+                        _ => 0,
+                    };
+                    let xfer_size = if xfer_size == 0 {
+                        Self::DEFAULT_XFER_SIZE
+                    } else {
+                        xfer_size
+                    };
+                    dfu = Some((interface.number(), xfer_size));
+                }
+            }
+        }
+        let Some((interface, xfer_size)) = dfu else {
+            bail!(RescueError::UnsupportedProtocol(
+                "no DFU interface found".into()
+            ));
+        };
+        usb.claim_interface(interface)?;
+        *self.device.borrow_mut() = Some(DfuDevice {
+            usb,
+            interface,
+            xfer_size,
+        });
+        Ok(())
+    }
+
+    /// Issues the vendor-specific request selecting the rescue `mode`.
+    fn write_mode(dev: &DfuDevice, mode: [u8; 4]) -> Result<()> {
+        dev.usb.write_control(
+            rusb::request_type(
+                rusb::Direction::Out,
+                rusb::RequestType::Vendor,
+                rusb::Recipient::Interface,
+            ),
+            Self::SET_MODE,
+            0,
+            dev.interface as u16,
+            &mode,
+        )?;
+        Ok(())
+    }
+
+    /// Runs `f` on the DFU interface, connecting to it first if necessary.
+    fn with_device<T>(&self, f: impl FnOnce(&DfuDevice) -> Result<T>) -> Result<T> {
+        if self.device.borrow().is_none() {
+            self.connect(Duration::ZERO)?;
+        }
+        f(self.device.borrow().as_ref().unwrap())
+    }
+}
+
+impl RescueProtocol for RescueUsbDfu {
+    fn enter(&self, transport: &TransportWrapper) -> Result<()> {
+        log::info!("Applying RESCUE strapping to trigger rescue mode.");
+        let strapping = transport.pin_strapping("RESCUE")?;
+        strapping.apply()?;
+        // Any previous connection becomes invalid as the chip resets.
+        self.device.borrow_mut().take();
+        transport.reset_target(self.reset_delay, /*clear_uart*=*/ false)?;
+        let result = self.connect(self.enter_delay);
+        strapping.remove()?;
+        result
+    }
+
+    fn reenter(&self, transport: &TransportWrapper) -> Result<()> {
+        let strapping = transport.pin_strapping("RESCUE")?;
+        strapping.apply()?;
+        let result = self.reboot().and_then(|_| {
+            // Give the chip time to drop off the bus before looking for it again.
+            std::thread::sleep(self.reset_delay);
+            self.connect(self.enter_delay)
+        });
+        strapping.remove()?;
+        result
+    }
+
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
+        self.with_device(|dev| {
+            Self::write_mode(dev, mode)?;
+            wait_for_idle(&dev.usb, dev.interface)?;
+            Ok(())
+        })
+    }
+
+    fn reboot(&self) -> Result<()> {
+        // The chip resets as soon as it accepts the mode and drops off the bus, so neither wait
+        // for it to become idle nor keep the stale connection.
+        self.with_device(|dev| Self::write_mode(dev, RescueMode::REBOOT))?;
+        self.device.borrow_mut().take();
+        Ok(())
+    }
+
+    fn send(&self, data: &[u8]) -> Result<()> {
+        self.with_device(|dev| {
+            let request_type = rusb::request_type(
+                rusb::Direction::Out,
+                rusb::RequestType::Class,
+                rusb::Recipient::Interface,
+            );
+            let mut block = 0u16;
+            for chunk in data.chunks(dev.xfer_size) {
+                dev.usb.write_control(
+                    request_type,
+                    USB_DFU_DNLOAD,
+                    block,
+                    dev.interface as u16,
+                    chunk,
+                )?;
+                wait_for_idle(&dev.usb, dev.interface)?;
+                block = block.wrapping_add(1);
+            }
+            // A zero-length block ends the download, and the chip then processes the data.
+            dev.usb.write_control(
+                request_type,
+                USB_DFU_DNLOAD,
+                block,
+                dev.interface as u16,
+                &[],
+            )?;
+            loop {
+                let DfuStatus {
+                    status,
+                    poll_timeout,
+                    state,
+                } = get_status(&dev.usb, dev.interface)?;
+                if status != DFU_STATUS_OK {
+                    bail!(RescueError::DeviceError(format!("DFU status {status}")));
+                }
+                if state == DFU_STATE_DFU_IDLE {
+                    return Ok(());
+                }
+                std::thread::sleep(poll_timeout);
+            }
+        })
+    }
+
+    fn recv(&self) -> Result<Vec<u8>> {
+        self.with_device(|dev| {
+            let mut data = Vec::new();
+            let mut buf = vec![0u8; dev.xfer_size];
+            let mut block = 0u16;
+            loop {
+                let n = dev.usb.read_control(
+                    rusb::request_type(
+                        rusb::Direction::In,
+                        rusb::RequestType::Class,
+                        rusb::Recipient::Interface,
+                    ),
+                    Self::USB_DFU_UPLOAD,
+                    block,
+                    dev.interface as u16,
+                    &mut buf,
+                )?;
+                data.extend_from_slice(&buf[..n]);
+                // A short block marks the end of the upload.
+                if n < buf.len() {
+                    return Ok(data);
+                }
+                block = block.wrapping_add(1);
+            }
+        })
+    }
+}
@@ -2,13 +2,246 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use anyhow::{bail, Result};
+use clap::{Args, ValueEnum};
 use thiserror::Error;
 
+use crate::app::TransportWrapper;
+use crate::chip::boot_log::BootLog;
+use crate::chip::boot_svc::{
+    BootSlot, BootSvc, BootSvcKind, OwnershipActivateRequest, OwnershipUnlockRequest,
+};
+use crate::io::spi::{SpiParams, TransferMode};
+use crate::io::uart::UartParams;
+use crate::util::parse_int::ParseInt;
+use crate::util::voltage::Voltage;
+
+pub mod dfu;
 pub mod serial;
+pub mod spidfu;
 pub mod xmodem;
 
 #[derive(Debug, Error)]
 pub enum RescueError {
     #[error("bad mode: {0}")]
     BadMode(String),
+    #[error("unsupported rescue protocol: {0}")]
+    UnsupportedProtocol(String),
+    #[error("device error: {0}")]
+    DeviceError(String),
+    #[error("unexpected boot services response: expected {0}, but found {1}")]
+    UnexpectedResponse(BootSvcKind, BootSvcKind),
+    #[error("boot services request {0} failed with status {1:#x}")]
+    RequestFailed(BootSvcKind, u32),
+}
+
+/// Rescue mode selectors.  Every protocol transmits these as the four ASCII bytes.
+pub struct RescueMode;
+
+impl RescueMode {
+    pub const RESCUE: [u8; 4] = *b"RESQ";
+    pub const REBOOT: [u8; 4] = *b"REBO";
+    pub const BOOT_LOG: [u8; 4] = *b"BLOG";
+    pub const BOOT_SVC_REQ: [u8; 4] = *b"BREQ";
+    pub const BOOT_SVC_RSP: [u8; 4] = *b"BRSP";
+    pub const OWNER_BLOCK: [u8; 4] = *b"OWNR";
+}
+
+/// A transport-specific way of talking to the ROM_EXT rescue module.  Implementations only
+/// provide entry, mode selection and the raw data transfers, the rescue commands are built on
+/// top of those.
+pub trait RescueProtocol {
+    /// Resets the chip into rescue mode.
+    fn enter(&self, transport: &TransportWrapper) -> Result<()>;
+    /// Selects the rescue `mode`, which determines the meaning of subsequent transfers.
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()>;
+    /// Sends `data` to the chip in the current mode.
+    fn send(&self, data: &[u8]) -> Result<()>;
+    /// Receives data from the chip in the current mode.
+    fn recv(&self) -> Result<Vec<u8>>;
+    /// Reboots the chip and returns to rescue mode without resetting it, so that the contents
+    /// of the retention RAM (e.g. a boot services response) are preserved.
+    fn reenter(&self, transport: &TransportWrapper) -> Result<()>;
+
+    fn reboot(&self) -> Result<()> {
+        self.set_mode(RescueMode::REBOOT)
+    }
+
+    fn update_firmware(&self, image: &[u8]) -> Result<()> {
+        self.set_mode(RescueMode::RESCUE)?;
+        self.send(image)
+    }
+
+    fn get_boot_log_raw(&self) -> Result<Vec<u8>> {
+        self.set_mode(RescueMode::BOOT_LOG)?;
+        self.recv()
+    }
+
+    fn get_boot_log(&self) -> Result<BootLog> {
+        let blog = self.get_boot_log_raw()?;
+        Ok(BootLog::try_from(blog.as_slice())?)
+    }
+
+    fn get_boot_svc_raw(&self) -> Result<Vec<u8>> {
+        self.set_mode(RescueMode::BOOT_SVC_RSP)?;
+        self.recv()
+    }
+
+    fn get_boot_svc(&self) -> Result<BootSvc> {
+        let bsvc = self.get_boot_svc_raw()?;
+        Ok(BootSvc::try_from(bsvc.as_slice())?)
+    }
+
+    fn set_boot_svc_raw(&self, data: &[u8]) -> Result<()> {
+        self.set_mode(RescueMode::BOOT_SVC_REQ)?;
+        self.send(data)
+    }
+
+    fn clear_boot_svc(&self) -> Result<()> {
+        let message = BootSvc::empty(&[]);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    fn set_min_bl0_sec_ver(&self, ver: u32) -> Result<()> {
+        let message = BootSvc::min_bl0_sec_ver(ver);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    fn set_next_bl0_slot(&self, slot: BootSlot) -> Result<()> {
+        let message = BootSvc::next_boot_bl0_slot(slot);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    fn set_primary_bl0_slot(&self, slot: BootSlot) -> Result<()> {
+        let message = BootSvc::primary_bl0_slot(slot);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    fn ownership_unlock(&self, unlock: OwnershipUnlockRequest) -> Result<()> {
+        let message = BootSvc::ownership_unlock(unlock);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    fn ownership_activate(&self, activate: OwnershipActivateRequest) -> Result<()> {
+        let message = BootSvc::ownership_activate(activate);
+        let data = message.to_bytes()?;
+        self.set_boot_svc_raw(&data)
+    }
+
+    /// Reboots the chip so that the ROM_EXT services the pending `request`, then fetches the
+    /// response and checks that it answers `request` and reports success.  Requests without a
+    /// response of their own (i.e. `Empty`) are left in place by the ROM_EXT.
+    fn wait_boot_svc_response(
+        &self,
+        transport: &TransportWrapper,
+        request: BootSvcKind,
+    ) -> Result<BootSvc> {
+        self.reenter(transport)?;
+        let response = self.get_boot_svc()?;
+        let expected = request.response().unwrap_or(request);
+        if response.header.kind != expected {
+            bail!(RescueError::UnexpectedResponse(
+                expected,
+                response.header.kind
+            ));
+        }
+        match response.status() {
+            Some(status) if status != BootSvc::STATUS_OK => {
+                bail!(RescueError::RequestFailed(request, status))
+            }
+            _ => Ok(response),
+        }
+    }
+
+    fn set_owner_config(&self, data: &[u8]) -> Result<()> {
+        self.set_mode(RescueMode::OWNER_BLOCK)?;
+        self.send(data)
+    }
+}
+
+/// Interface over which to perform the rescue protocol.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RescueProtocolKind {
+    /// XMODEM over the console UART, as implemented by the ROM_EXT.
+    Xmodem,
+    /// Experimental: emulated SPI flash, not implemented by the ROM_EXT yet.
+    SpiDfu,
+    /// Experimental: USB DFU, not implemented by the ROM_EXT yet.
+    UsbDfu,
+}
+
+/// Options selecting the rescue protocol and the interface to perform it over.
+#[derive(Clone, Debug, Args)]
+pub struct RescueParams {
+    /// Rescue protocol to use.
+    #[arg(long, value_enum, ignore_case = true, default_value = "xmodem")]
+    pub protocol: RescueProtocolKind,
+    #[command(flatten)]
+    pub uart: UartParams,
+    /// SPI instance (SPI rescue only).
+    #[arg(long)]
+    pub spi_bus: Option<String>,
+    /// SPI bus speed (SPI rescue only).
+    #[arg(long)]
+    pub spi_speed: Option<u32>,
+    /// SPI chip select pin (SPI rescue only).
+    #[arg(long)]
+    pub spi_chip_select: Option<String>,
+    /// SPI bus voltage (SPI rescue only).
+    #[arg(long)]
+    pub spi_voltage: Option<Voltage>,
+    /// SPI polarity/phase mode (SPI rescue only).
+    #[arg(long)]
+    pub spi_mode: Option<TransferMode>,
+    /// USB vendor ID of the chip in rescue mode (USB DFU only).
+    #[arg(long, value_parser = u16::from_str)]
+    pub dfu_vid: Option<u16>,
+    /// USB product ID of the chip in rescue mode (USB DFU only).
+    #[arg(long, value_parser = u16::from_str)]
+    pub dfu_pid: Option<u16>,
+    /// USB serial number of the chip in rescue mode (USB DFU only).
+    #[arg(long)]
+    pub dfu_serial: Option<String>,
+}
+
+impl RescueParams {
+    /// The SPI options, which carry a `spi-` prefix so as not to clash with the options of the
+    /// individual rescue commands (e.g. `--mode` of ownership unlock).
+    fn spi_params(&self) -> SpiParams {
+        SpiParams {
+            bus: self.spi_bus.clone(),
+            speed: self.spi_speed,
+            chip_select: self.spi_chip_select.clone(),
+            voltage: self.spi_voltage,
+            mode: self.spi_mode,
+        }
+    }
+
+    /// Creates the rescue protocol selected by these parameters.
+    pub fn create(&self, transport: &TransportWrapper) -> Result<Box<dyn RescueProtocol>> {
+        if self.protocol != RescueProtocolKind::Xmodem {
+            log::warn!(
+                "The {:?} rescue protocol is experimental and not implemented by the ROM_EXT",
+                self.protocol
+            );
+        }
+        Ok(match self.protocol {
+            RescueProtocolKind::Xmodem => {
+                Box::new(serial::RescueSerial::new(self.uart.create(transport)?))
+            }
+            RescueProtocolKind::SpiDfu => Box::new(spidfu::RescueSpiDfu::new(
+                self.spi_params().create(transport, "BOOTSTRAP")?,
+            )),
+            RescueProtocolKind::UsbDfu => Box::new(dfu::RescueUsbDfu::new(
+                self.dfu_vid,
+                self.dfu_pid,
+                self.dfu_serial.clone(),
+            )),
+        })
+    }
 }
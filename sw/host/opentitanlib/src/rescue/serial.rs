@@ -7,11 +7,9 @@ use std::rc::Rc;
 use std::time::Duration;
 
 use crate::app::TransportWrapper;
-use crate::chip::boot_log::BootLog;
-use crate::chip::boot_svc::{BootSlot, BootSvc, OwnershipActivateRequest, OwnershipUnlockRequest};
 use crate::io::uart::Uart;
 use crate::rescue::xmodem::Xmodem;
-use crate::rescue::RescueError;
+use crate::rescue::{RescueError, RescueProtocol};
 use crate::uart::console::UartConsole;
 
 pub struct RescueSerial {
@@ -22,12 +20,6 @@ pub struct RescueSerial {
 
 impl RescueSerial {
     const ONE_SECOND: Duration = Duration::from_secs(1);
-    pub const RESCUE: [u8; 4] = *b"RESQ";
-    pub const REBOOT: [u8; 4] = *b"REBO";
-    pub const BOOT_LOG: [u8; 4] = *b"BLOG";
-    pub const BOOT_SVC_REQ: [u8; 4] = *b"BREQ";
-    pub const BOOT_SVC_RSP: [u8; 4] = *b"BRSP";
-    pub const OWNER_BLOCK: [u8; 4] = *b"OWNR";
 
     pub fn new(uart: Rc<dyn Uart>) -> Self {
         RescueSerial {
@@ -37,10 +29,8 @@ impl RescueSerial {
         }
     }
 
-    pub fn enter(&self, transport: &TransportWrapper) -> Result<()> {
-        log::info!("Setting serial break to trigger rescue mode.");
-        self.uart.set_break(true)?;
-        transport.reset_target(self.reset_delay, /*clear_uart*=*/ true)?;
+    /// Waits for the rescue banner while serial break is held, then releases it.
+    fn wait_for_rescue(&self) -> Result<()> {
         UartConsole::wait_for(&*self.uart, r"rescue:.*\r\n", self.enter_delay)?;
         log::info!("Rescue triggered. clearing serial break.");
         self.uart.set_break(false)?;
@@ -49,8 +39,24 @@ impl RescueSerial {
         let _ = UartConsole::wait_for(&*self.uart, r"(ok|error):.*\r\n", Self::ONE_SECOND);
         Ok(())
     }
+}
+
+impl RescueProtocol for RescueSerial {
+    fn enter(&self, transport: &TransportWrapper) -> Result<()> {
+        log::info!("Setting serial break to trigger rescue mode.");
+        self.uart.set_break(true)?;
+        transport.reset_target(self.reset_delay, /*clear_uart*=*/ true)?;
+        self.wait_for_rescue()
+    }
+
+    fn reenter(&self, _transport: &TransportWrapper) -> Result<()> {
+        self.reboot()?;
+        log::info!("Setting serial break to return to rescue mode.");
+        self.uart.set_break(true)?;
+        self.wait_for_rescue()
+    }
 
-    pub fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
         self.uart.write(&mode)?;
         let enter = b'\r';
         self.uart.write(std::slice::from_ref(&enter))?;
@@ -61,79 +67,16 @@ impl RescueSerial {
         Ok(())
     }
 
-    pub fn reboot(&self) -> Result<()> {
-        self.set_mode(Self::REBOOT)?;
-        Ok(())
-    }
-
-    pub fn update_firmware(&self, image: &[u8]) -> Result<()> {
-        self.set_mode(Self::RESCUE)?;
-        let xm = Xmodem::new();
-        xm.send(&*self.uart, image)?;
-        Ok(())
-    }
-
-    pub fn get_boot_log_raw(&self) -> Result<Vec<u8>> {
-        self.set_mode(Self::BOOT_LOG)?;
-        let mut blog = Vec::new();
-        let xm = Xmodem::new();
-        xm.receive(&*self.uart, &mut blog)?;
-        Ok(blog)
-    }
-
-    pub fn get_boot_log(&self) -> Result<BootLog> {
-        let blog = self.get_boot_log_raw()?;
-        Ok(BootLog::try_from(blog.as_slice())?)
-    }
-
-    pub fn get_boot_svc_raw(&self) -> Result<Vec<u8>> {
-        self.set_mode(Self::BOOT_SVC_RSP)?;
-        let mut bsvc = Vec::new();
-        let xm = Xmodem::new();
-        xm.receive(&*self.uart, &mut bsvc)?;
-        Ok(bsvc)
-    }
-
-    pub fn get_boot_svc(&self) -> Result<BootSvc> {
-        let bsvc = self.get_boot_svc_raw()?;
-        Ok(BootSvc::try_from(bsvc.as_slice())?)
-    }
-
-    pub fn set_boot_svc_raw(&self, data: &[u8]) -> Result<()> {
-        self.set_mode(Self::BOOT_SVC_REQ)?;
+    fn send(&self, data: &[u8]) -> Result<()> {
         let xm = Xmodem::new();
         xm.send(&*self.uart, data)?;
         Ok(())
     }
 
-    pub fn set_next_bl0_slot(&self, slot: BootSlot) -> Result<()> {
-        let message = BootSvc::next_boot_bl0_slot(slot);
-        let data = message.to_bytes()?;
-        self.set_boot_svc_raw(&data)
-    }
-
-    pub fn set_primary_bl0_slot(&self, slot: BootSlot) -> Result<()> {
-        let message = BootSvc::primary_bl0_slot(slot);
-        let data = message.to_bytes()?;
-        self.set_boot_svc_raw(&data)
-    }
-
-    pub fn ownership_unlock(&self, unlock: OwnershipUnlockRequest) -> Result<()> {
-        let message = BootSvc::ownership_unlock(unlock);
-        let data = message.to_bytes()?;
-        self.set_boot_svc_raw(&data)
-    }
-
-    pub fn ownership_activate(&self, activate: OwnershipActivateRequest) -> Result<()> {
-        let message = BootSvc::ownership_activate(activate);
-        let data = message.to_bytes()?;
-        self.set_boot_svc_raw(&data)
-    }
-
-    pub fn set_owner_config(&self, data: &[u8]) -> Result<()> {
-        self.set_mode(Self::OWNER_BLOCK)?;
+    fn recv(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
         let xm = Xmodem::new();
-        xm.send(&*self.uart, data)?;
-        Ok(())
+        xm.receive(&*self.uart, &mut data)?;
+        Ok(data)
     }
 }
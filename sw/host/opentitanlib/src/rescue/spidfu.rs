@@ -0,0 +1,117 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Rescue over the SPI device, for chips without a usable UART.
+//!
+//! Experimental: the ROM_EXT does not implement this protocol yet, and the commands below are
+//! a proposal which may change once the device side lands.
+//!
+//! The ROM_EXT presents the rescue module as an emulated SPI flash, and the host drives it
+//! with ordinary EEPROM transactions:
+//! - `SET_MODE` followed by the four mode bytes selects the rescue mode;
+//! - data is sent to the chip with `PAGE_PROGRAM` at increasing addresses, waiting for the
+//!   busy bit to clear after each page, and the transfer is concluded with `END_TRANSFER`;
+//! - data is received from the chip with `READ` starting at address zero.
+
+use anyhow::Result;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::app::TransportWrapper;
+use crate::io::eeprom::{AddressMode, Transaction, MODE_111};
+use crate::io::spi::Target;
+use crate::rescue::{RescueMode, RescueProtocol};
+use crate::spiflash::SpiFlash;
+
+pub struct RescueSpiDfu {
+    spi: Rc<dyn Target>,
+    reset_delay: Duration,
+    enter_delay: Duration,
+}
+
+impl RescueSpiDfu {
+    pub const SET_MODE: u8 = 0xb1;
+    pub const END_TRANSFER: u8 = 0xb2;
+    /// Size of the rescue buffer, the maximum amount of data returned by `recv()`.
+    pub const BUFFER_SIZE: usize = 2048;
+
+    pub fn new(spi: Rc<dyn Target>) -> Self {
+        RescueSpiDfu {
+            spi,
+            reset_delay: Duration::from_millis(50),
+            enter_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RescueProtocol for RescueSpiDfu {
+    fn enter(&self, transport: &TransportWrapper) -> Result<()> {
+        log::info!("Applying RESCUE strapping to trigger rescue mode.");
+        let strapping = transport.pin_strapping("RESCUE")?;
+        strapping.apply()?;
+        transport.reset_target(self.reset_delay, /*clear_uart*=*/ false)?;
+        std::thread::sleep(self.enter_delay);
+        strapping.remove()?;
+        Ok(())
+    }
+
+    fn reenter(&self, transport: &TransportWrapper) -> Result<()> {
+        let strapping = transport.pin_strapping("RESCUE")?;
+        strapping.apply()?;
+        let result = self.reboot();
+        std::thread::sleep(self.enter_delay);
+        strapping.remove()?;
+        result
+    }
+
+    fn set_mode(&self, mode: [u8; 4]) -> Result<()> {
+        self.spi.run_eeprom_transactions(&mut [
+            Transaction::Write(MODE_111.cmd(Self::SET_MODE), &mode),
+            Transaction::WaitForBusyClear,
+        ])
+    }
+
+    fn reboot(&self) -> Result<()> {
+        // The chip resets as soon as it accepts the mode, so there is no busy bit to wait for.
+        self.spi.run_eeprom_transactions(&mut [Transaction::Write(
+            MODE_111.cmd(Self::SET_MODE),
+            &RescueMode::REBOOT,
+        )])
+    }
+
+    fn send(&self, data: &[u8]) -> Result<()> {
+        let chunk_size = std::cmp::min(
+            SpiFlash::LEGACY_PAGE_SIZE as usize,
+            self.spi.get_eeprom_max_transfer_sizes()?.write,
+        );
+        for (i, chunk) in data.chunks(chunk_size).enumerate() {
+            let address = (i * chunk_size) as u32;
+            self.spi.run_eeprom_transactions(&mut [
+                Transaction::Command(MODE_111.cmd(SpiFlash::WRITE_ENABLE)),
+                Transaction::Write(
+                    MODE_111.cmd_addr(SpiFlash::PAGE_PROGRAM, address, AddressMode::Mode3b),
+                    chunk,
+                ),
+                Transaction::WaitForBusyClear,
+            ])?;
+        }
+        self.spi.run_eeprom_transactions(&mut [
+            Transaction::Command(MODE_111.cmd(Self::END_TRANSFER)),
+            Transaction::WaitForBusyClear,
+        ])
+    }
+
+    fn recv(&self) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; Self::BUFFER_SIZE];
+        let chunk_size = self.spi.get_eeprom_max_transfer_sizes()?.read;
+        for (i, chunk) in data.chunks_mut(chunk_size).enumerate() {
+            let address = (i * chunk_size) as u32;
+            self.spi.run_eeprom_transactions(&mut [Transaction::Read(
+                MODE_111.cmd_addr(SpiFlash::READ, address, AddressMode::Mode3b),
+                chunk,
+            )])?;
+        }
+        Ok(data)
+    }
+}
@@ -4,6 +4,7 @@
 
 use crate::io::uart::Uart;
 use anyhow::Result;
+use clap::ValueEnum;
 use std::io::{Read, Write};
 use thiserror::Error;
 
@@ -17,13 +18,22 @@ pub enum XmodemError {
     UnsupportedMode(String),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, ValueEnum)]
 #[repr(usize)]
 pub enum XmodemBlock {
+    #[value(name = "128")]
     Block128 = 128,
+    #[value(name = "1k")]
     Block1k = 1024,
 }
 
+/// The integrity check used for each block, as requested by the receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Checksum {
+    Crc16,
+    Sum8,
+}
+
 #[derive(Debug)]
 pub struct Xmodem {
     pub max_errors: usize,
@@ -39,13 +49,13 @@ impl Default for Xmodem {
 
 impl Xmodem {
     const POLYNOMIAL: u16 = 0x1021;
-    const CRC: u8 = 0x43;
-    const SOH: u8 = 0x01;
-    const STX: u8 = 0x02;
-    const EOF: u8 = 0x04;
-    const ACK: u8 = 0x06;
-    const NAK: u8 = 0x15;
-    const CAN: u8 = 0x18;
+    pub(crate) const CRC: u8 = 0x43;
+    pub(crate) const SOH: u8 = 0x01;
+    pub(crate) const STX: u8 = 0x02;
+    pub(crate) const EOF: u8 = 0x04;
+    pub(crate) const ACK: u8 = 0x06;
+    pub(crate) const NAK: u8 = 0x15;
+    pub(crate) const CAN: u8 = 0x18;
 
     pub fn new() -> Self {
         Xmodem {
@@ -55,7 +65,7 @@ impl Xmodem {
         }
     }
 
-    fn crc16(buf: &[u8]) -> u16 {
+    pub(crate) fn crc16(buf: &[u8]) -> u16 {
         let mut crc = 0u16;
         for byte in buf {
             crc ^= (*byte as u16) << 8;
@@ -71,24 +81,53 @@ impl Xmodem {
     }
 
     pub fn send(&self, uart: &dyn Uart, data: impl Read) -> Result<()> {
-        self.send_start(uart)?;
-        self.send_data(uart, data)?;
+        let checksum = self.send_start(uart)?;
+        self.send_data(uart, data, checksum)?;
         self.send_finish(uart)?;
         Ok(())
     }
 
-    fn send_start(&self, uart: &dyn Uart) -> Result<()> {
+    /// Sends a batch of `files` (name and contents) using the YMODEM protocol.
+    pub fn send_batch(&self, uart: &dyn Uart, files: &[(&str, &[u8])]) -> Result<()> {
+        for (name, data) in files {
+            self.send_ymodem_header(uart, &format!("{name}\0{}", data.len()))?;
+            let checksum = self.send_start(uart)?;
+            self.send_data(uart, *data, checksum)?;
+            self.send_finish(uart)?;
+        }
+        // An empty header block terminates the batch.
+        self.send_ymodem_header(uart, "")
+    }
+
+    fn send_ymodem_header(&self, uart: &dyn Uart, header: &str) -> Result<()> {
+        if self.send_start(uart)? != Checksum::Crc16 {
+            return Err(
+                XmodemError::UnsupportedMode("YMODEM with standard checksums".into()).into(),
+            );
+        }
+        let len = match header.len() {
+            n if n <= XmodemBlock::Block128 as usize => XmodemBlock::Block128,
+            n if n <= XmodemBlock::Block1k as usize => XmodemBlock::Block1k,
+            _ => return Err(XmodemError::UnsupportedMode("YMODEM header too long".into()).into()),
+        };
+        let mut errors = 0usize;
+        // The header is block zero and is padded with NULs.
+        let block = Self::block(0, header.as_bytes(), len, 0, Checksum::Crc16);
+        self.send_block(uart, &block, &mut errors)
+    }
+
+    fn send_start(&self, uart: &dyn Uart) -> Result<Checksum> {
         let mut ch = 0u8;
         let mut cancels = 0usize;
-        // Wait for the XMODEM CRC start sequence.
+        // Wait for the XMODEM start sequence, which also selects the checksum type.
         loop {
             uart.read(std::slice::from_mut(&mut ch))?;
             match ch {
                 Self::CRC => {
-                    return Ok(());
+                    return Ok(Checksum::Crc16);
                 }
                 Self::NAK => {
-                    return Err(XmodemError::UnsupportedMode("standard checksums".into()).into());
+                    return Ok(Checksum::Sum8);
                 }
                 Self::CAN => {
                     cancels += 1;
@@ -103,64 +142,111 @@ impl Xmodem {
         }
     }
 
-    fn send_data(&self, uart: &dyn Uart, mut data: impl Read) -> Result<()> {
+    /// Builds a block with the given block number, padding `data` to `len` with `pad`.
+    fn block(num: u8, data: &[u8], len: XmodemBlock, pad: u8, checksum: Checksum) -> Vec<u8> {
+        let mut buf = vec![pad; len as usize + 3];
+        buf[0] = match len {
+            XmodemBlock::Block128 => Self::SOH,
+            XmodemBlock::Block1k => Self::STX,
+        };
+        buf[1] = num;
+        buf[2] = 255 - num;
+        buf[3..3 + data.len()].copy_from_slice(data);
+        match checksum {
+            Checksum::Crc16 => {
+                let crc = Self::crc16(&buf[3..]);
+                buf.push((crc >> 8) as u8);
+                buf.push((crc & 0xFF) as u8);
+            }
+            Checksum::Sum8 => {
+                let sum = buf[3..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+                buf.push(sum);
+            }
+        }
+        buf
+    }
+
+    /// Sends `block`, retrying until it is acknowledged.
+    fn send_block(&self, uart: &dyn Uart, block: &[u8], errors: &mut usize) -> Result<()> {
+        let mut cancels = 0usize;
+        loop {
+            uart.write(block)?;
+            let mut ch = 0u8;
+            uart.read(std::slice::from_mut(&mut ch))?;
+            match ch {
+                Self::ACK => return Ok(()),
+                Self::NAK => {
+                    log::info!("XMODEM send got NAK.  Retrying.");
+                    *errors += 1;
+                }
+                Self::CAN => {
+                    cancels += 1;
+                    if cancels >= 2 {
+                        return Err(XmodemError::Cancelled.into());
+                    }
+                }
+                _ => {
+                    log::info!("Expected ACK. Got {ch:#x}.");
+                    *errors += 1;
+                }
+            }
+            if *errors >= self.max_errors {
+                return Err(XmodemError::ExhaustedRetries(*errors).into());
+            }
+        }
+    }
+
+    fn send_data(&self, uart: &dyn Uart, mut data: impl Read, checksum: Checksum) -> Result<()> {
+        // XMODEM-1K requires CRCs; receivers asking for checksums only understand 128-byte
+        // blocks.
+        let block_len = match checksum {
+            Checksum::Crc16 => self.block_len,
+            Checksum::Sum8 => XmodemBlock::Block128,
+        };
         let mut block = 0usize;
         let mut errors = 0usize;
+        let mut buf = vec![0u8; block_len as usize];
         loop {
             block += 1;
-            let mut buf = vec![self.pad_byte; self.block_len as usize + 3];
-            let n = data.read(&mut buf[3..])?;
+            let mut n = 0;
+            while n < buf.len() {
+                let len = data.read(&mut buf[n..])?;
+                if len == 0 {
+                    break;
+                }
+                n += len;
+            }
             if n == 0 {
                 break;
             }
-
-            buf[0] = match self.block_len {
-                XmodemBlock::Block128 => Self::SOH,
-                XmodemBlock::Block1k => Self::STX,
+            // Like other XMODEM-1K senders, use a short block for a short remainder to
+            // save sending padding.
+            let len = if n <= XmodemBlock::Block128 as usize {
+                XmodemBlock::Block128
+            } else {
+                block_len
             };
-            buf[1] = block as u8;
-            buf[2] = 255 - buf[1];
-            let crc = Self::crc16(&buf[3..]);
-            buf.push((crc >> 8) as u8);
-            buf.push((crc & 0xFF) as u8);
             log::info!("Sending block {block}");
-
-            let mut cancels = 0usize;
-            loop {
-                uart.write(&buf)?;
-                let mut ch = 0u8;
-                uart.read(std::slice::from_mut(&mut ch))?;
-                match ch {
-                    Self::ACK => break,
-                    Self::NAK => {
-                        log::info!("XMODEM send got NAK.  Retrying.");
-                        errors += 1;
-                    }
-                    Self::CAN => {
-                        cancels += 1;
-                        if cancels >= 2 {
-                            return Err(XmodemError::Cancelled.into());
-                        }
-                    }
-                    _ => {
-                        log::info!("Expected ACK. Got {ch:#x}.");
-                        errors += 1;
-                    }
-                }
-                if errors >= self.max_errors {
-                    return Err(XmodemError::ExhaustedRetries(errors).into());
-                }
-            }
+            let buf = Self::block(block as u8, &buf[..n], len, self.pad_byte, checksum);
+            self.send_block(uart, &buf, &mut errors)?;
         }
         Ok(())
     }
 
     fn send_finish(&self, uart: &dyn Uart) -> Result<()> {
-        uart.write(&[Self::EOF])?;
-        let mut ch = 0u8;
-        uart.read(std::slice::from_mut(&mut ch))?;
-        if ch != Self::ACK {
-            log::info!("Expected ACK. Got {ch:#x}.");
+        // Some receivers NAK the first EOF to make sure it wasn't line noise.
+        for _ in 0..self.max_errors {
+            uart.write(&[Self::EOF])?;
+            let mut ch = 0u8;
+            uart.read(std::slice::from_mut(&mut ch))?;
+            match ch {
+                Self::ACK => break,
+                Self::NAK => continue,
+                _ => {
+                    log::info!("Expected ACK. Got {ch:#x}.");
+                    break;
+                }
+            }
         }
         Ok(())
     }
@@ -315,11 +401,35 @@ November 19, 1863
         let child = ChildUart::spawn(&["rx", &filename])?;
         let xmodem = Xmodem::new();
         let gettysburg = GETTYSBURG.as_bytes();
-        let result = xmodem.send(&child, gettysburg);
-        assert_eq!(child.wait()?.success(), false);
-        assert!(result.is_err());
-        let err = result.unwrap_err().downcast::<XmodemError>().unwrap();
-        assert_eq!(err.to_string(), "Unsupported mode: standard checksums");
+        xmodem.send(&child, gettysburg)?;
+        assert!(child.wait()?.success());
+        let result = std::fs::read(&filename)?;
+        // Checksum mode falls back to 128-byte blocks.
+        assert_eq!(result.len() % 128, 0);
+        assert!(result.len() < gettysburg.len() + 128);
+        assert_eq!(&result[..gettysburg.len()], gettysburg);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ymodem_send() -> Result<()> {
+        let dirname = tmpfilename("test_ymodem_send");
+        std::fs::create_dir_all(&dirname)?;
+        // The YMODEM receiver writes files named by the header into its working directory.
+        let child = ChildUart::spawn(&["sh", "-c", &format!("cd {dirname} && exec rb")])?;
+        let xmodem = Xmodem::new();
+        let gettysburg = GETTYSBURG.as_bytes();
+        let short = &gettysburg[..200];
+        xmodem.send_batch(
+            &child,
+            &[("gettysburg.txt", gettysburg), ("short.txt", short)],
+        )?;
+        assert!(child.wait()?.success());
+        // The header carries the file size, so the received files are not padded.
+        let result = std::fs::read(format!("{dirname}/gettysburg.txt"))?;
+        assert_eq!(result, gettysburg);
+        let result = std::fs::read(format!("{dirname}/short.txt"))?;
+        assert_eq!(result, short);
         Ok(())
     }
 
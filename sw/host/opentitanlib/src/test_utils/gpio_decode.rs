@@ -0,0 +1,750 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decoding of UART, SPI, I2C and JTAG traffic from edges captured by `GpioMonitoring`, allowing
+//! bus issues to be debugged without an external logic analyzer.
+
+use anyhow::{bail, ensure, Result};
+use serde::{Deserialize, Serialize};
+use serde_annotate::Annotate;
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::impl_serializable_error;
+use crate::io::gpio::{Edge, MonitoringEvent};
+use crate::io::spi::{ClockPhase, ClockPolarity, TransferMode};
+use crate::test_utils::bitbanging::i2c;
+
+/// Errors related to decoding of captured signals.
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum DecodeError {
+    #[error("Unknown signal \"{0}\"")]
+    UnknownSignal(String),
+    #[error("Invalid VCD file: {0}")]
+    InvalidVcd(String),
+    #[error("Timestamp resolution of the capture is unknown")]
+    UnknownResolution,
+    #[error("I2C transfer at {0} too long to decode")]
+    I2cTransferTooLong(u64),
+}
+impl_serializable_error!(DecodeError);
+
+/// Edges captured on a set of signals, either by `GpioMonitoring`, or read from a VCD file.
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    /// Names of the signals, `MonitoringEvent::signal_index` being an index into this list.
+    pub signals: Vec<String>,
+    /// Number of timestamp units per second, if known.
+    pub resolution: Option<u64>,
+    pub initial_timestamp: u64,
+    pub initial_levels: Vec<bool>,
+    pub events: Vec<MonitoringEvent>,
+}
+
+/// A byte received by a UART.
+#[derive(Debug, Serialize, Annotate)]
+pub struct UartByte {
+    pub timestamp: u64,
+    #[annotate(format = hex)]
+    pub data: u8,
+    /// Whether the stop bit was low.
+    pub framing_error: bool,
+}
+
+/// Data exchanged while chip select was asserted.
+#[derive(Debug, Serialize, Annotate)]
+pub struct SpiTransaction {
+    pub timestamp: u64,
+    /// Number of clock cycles.  Any final partial byte is padded with zeros in its least
+    /// significant bits.
+    pub bits: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[annotate(format = hex)]
+    pub copi: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[annotate(format = hex)]
+    pub cipo: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Serialize, Annotate)]
+pub enum I2cSymbol {
+    Start,
+    Stop,
+    Addr {
+        #[annotate(format = hex)]
+        addr: u8,
+        read: bool,
+        nack: bool,
+    },
+    Bytes {
+        #[annotate(format = hex)]
+        data: Vec<u8>,
+        nack: bool,
+    },
+    /// Fewer than nine clock cycles between start/stop conditions.
+    Broken {
+        bits: String,
+    },
+}
+
+/// Symbols from one start condition up to and including the following stop condition.
+#[derive(Debug, Serialize, Annotate)]
+pub struct I2cTransaction {
+    pub timestamp: u64,
+    pub symbols: Vec<I2cSymbol>,
+}
+
+/// States of the JTAG TAP controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum TapState {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDrScan,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIrScan,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+impl TapState {
+    /// State following a rising edge of TCK with the given level of TMS.
+    pub fn next(self, tms: bool) -> Self {
+        use TapState::*;
+        match (self, tms) {
+            (TestLogicReset, false) => RunTestIdle,
+            (TestLogicReset, true) => TestLogicReset,
+            (RunTestIdle, false) => RunTestIdle,
+            (RunTestIdle, true) => SelectDrScan,
+            (SelectDrScan, false) => CaptureDr,
+            (SelectDrScan, true) => SelectIrScan,
+            (CaptureDr, false) | (ShiftDr, false) | (Exit2Dr, false) => ShiftDr,
+            (CaptureDr, true) | (ShiftDr, true) => Exit1Dr,
+            (Exit1Dr, false) | (PauseDr, false) => PauseDr,
+            (Exit1Dr, true) | (Exit2Dr, true) => UpdateDr,
+            (PauseDr, true) => Exit2Dr,
+            (SelectIrScan, false) => CaptureIr,
+            (SelectIrScan, true) => TestLogicReset,
+            (CaptureIr, false) | (ShiftIr, false) | (Exit2Ir, false) => ShiftIr,
+            (CaptureIr, true) | (ShiftIr, true) => Exit1Ir,
+            (Exit1Ir, false) | (PauseIr, false) => PauseIr,
+            (Exit1Ir, true) | (Exit2Ir, true) => UpdateIr,
+            (PauseIr, true) => Exit2Ir,
+            (UpdateDr, false) | (UpdateIr, false) => RunTestIdle,
+            (UpdateDr, true) | (UpdateIr, true) => SelectDrScan,
+        }
+    }
+}
+
+/// A change of state of the JTAG TAP controller.
+#[derive(Debug, Serialize, Annotate)]
+pub struct JtagTransition {
+    pub timestamp: u64,
+    pub state: TapState,
+    /// Bits shifted in through TDI in the Shift-DR/IR state just left, in the order shifted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tdi: Option<String>,
+    /// Bits shifted out through TDO in the Shift-DR/IR state just left, in the order shifted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tdo: Option<String>,
+}
+
+/// Accumulates bits, most significant first, into bytes.
+#[derive(Default)]
+struct BitAccumulator {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitAccumulator {
+    fn push(&mut self, bit: bool) {
+        if self.bits / 8 == self.bytes.len() {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+        }
+        self.bits += 1;
+    }
+}
+
+fn bit_string(bits: &[bool]) -> String {
+    bits.iter()
+        .map(|bit| if *bit { '1' } else { '0' })
+        .collect()
+}
+
+/// Parses e.g. "1ns" or "1000000ps", returning the number of time units per second.
+fn parse_timescale(timescale: &str) -> Result<u64> {
+    let split = timescale
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(timescale.len());
+    let (number, unit) = timescale.split_at(split);
+    let units_per_second: u64 = match unit {
+        "s" => 1,
+        "ms" => 1_000,
+        "us" => 1_000_000,
+        "ns" => 1_000_000_000,
+        "ps" => 1_000_000_000_000,
+        "fs" => 1_000_000_000_000_000,
+        _ => bail!(DecodeError::InvalidVcd(format!("timescale {}", timescale))),
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 => Ok(units_per_second / number),
+        _ => bail!(DecodeError::InvalidVcd(format!("timescale {}", timescale))),
+    }
+}
+
+impl Capture {
+    /// Reads single-bit signals from a VCD file, such as written by `opentitantool gpio
+    /// monitoring vcd` or `GpioMon::dump_vcd()`.  The first value of each signal is taken as
+    /// its initial level.
+    pub fn from_vcd(vcd: &str) -> Result<Self> {
+        let mut capture = Capture::default();
+        let mut ids = HashMap::new();
+        let mut first_levels: Vec<Option<bool>> = Vec::new();
+        let mut levels: Vec<Option<bool>> = Vec::new();
+        let mut timestamp = None;
+        let mut tokens = vcd.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token {
+                "$timescale" => {
+                    let timescale: String = tokens.by_ref().take_while(|t| *t != "$end").collect();
+                    capture.resolution = Some(parse_timescale(&timescale)?);
+                }
+                "$var" => {
+                    // Type, width, identifier and name, possibly followed by a bit range.
+                    let fields: Vec<&str> = tokens.by_ref().take_while(|t| *t != "$end").collect();
+                    ensure!(
+                        fields.len() >= 4 && fields[1] == "1",
+                        DecodeError::InvalidVcd(format!("$var {}", fields.join(" ")))
+                    );
+                    ids.insert(fields[2], capture.signals.len());
+                    capture.signals.push(fields[3].to_string());
+                    first_levels.push(None);
+                    levels.push(None);
+                }
+                // Value changes within these sections are processed as any others.
+                "$dumpvars" | "$dumpall" | "$dumpon" | "$dumpoff" | "$end" => (),
+                t if t.starts_with('$') => {
+                    tokens.by_ref().find(|t| *t == "$end");
+                }
+                t if t.starts_with('#') => {
+                    let Ok(value) = t[1..].parse() else {
+                        bail!(DecodeError::InvalidVcd(format!("timestamp {}", t)));
+                    };
+                    if timestamp.is_none() {
+                        capture.initial_timestamp = value;
+                    }
+                    timestamp = Some(value);
+                }
+                t => {
+                    let (value, id) = t.split_at(1);
+                    let Some(&index) = ids.get(id) else {
+                        bail!(DecodeError::InvalidVcd(format!("value change {}", t)));
+                    };
+                    let level = match value {
+                        "0" => false,
+                        "1" => true,
+                        // Unknown and high impedance values are disregarded.
+                        "x" | "X" | "z" | "Z" => continue,
+                        _ => bail!(DecodeError::InvalidVcd(format!("value change {}", t))),
+                    };
+                    match levels[index] {
+                        None => {
+                            first_levels[index] = Some(level);
+                            levels[index] = Some(level);
+                        }
+                        Some(previous) if previous == level => (),
+                        Some(_) => {
+                            levels[index] = Some(level);
+                            capture.events.push(MonitoringEvent {
+                                signal_index: index as u8,
+                                edge: if level { Edge::Rising } else { Edge::Falling },
+                                timestamp: timestamp.unwrap_or_default(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        capture.initial_levels = first_levels
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect();
+        Ok(capture)
+    }
+
+    fn signal(&self, name: &str) -> Result<usize> {
+        match self
+            .signals
+            .iter()
+            .position(|signal| signal.eq_ignore_ascii_case(name))
+        {
+            Some(index) => Ok(index),
+            None => bail!(DecodeError::UnknownSignal(name.to_string())),
+        }
+    }
+
+    /// Looks up an optional signal, absent ones being represented by an index never matching
+    /// any event.
+    fn optional_signal(&self, name: Option<&str>) -> Result<usize> {
+        match name {
+            Some(name) => self.signal(name),
+            None => Ok(usize::MAX),
+        }
+    }
+
+    /// Returns the initial levels of the given signals, with bit `i` representing the level of
+    /// `signals[i]`, followed by the timestamp and levels after each edge on any of them.
+    fn states(&self, signals: &[usize]) -> (u8, Vec<(u64, u8)>) {
+        let mut state = 0u8;
+        for (bit, &index) in signals.iter().enumerate() {
+            let level = self.initial_levels.get(index).copied().unwrap_or_default();
+            state |= (level as u8) << bit;
+        }
+        let initial = state;
+        let mut result = Vec::new();
+        for event in &self.events {
+            let Some(bit) = signals
+                .iter()
+                .position(|&index| index == event.signal_index as usize)
+            else {
+                continue;
+            };
+            match event.edge {
+                Edge::Rising => state |= 1 << bit,
+                Edge::Falling => state &= !(1 << bit),
+            }
+            result.push((event.timestamp, state));
+        }
+        (initial, result)
+    }
+
+    /// Decodes bytes received with 8 data bits, no parity and one stop bit.
+    pub fn decode_uart(&self, rx: &str, baudrate: u32) -> Result<Vec<UartByte>> {
+        let resolution = self.resolution.ok_or(DecodeError::UnknownResolution)?;
+        let (initial, states) = self.states(&[self.signal(rx)?]);
+        let bit_time = resolution as f64 / baudrate as f64;
+        let level_at =
+            |time: f64| match states.partition_point(|&(timestamp, _)| timestamp as f64 <= time) {
+                0 => initial != 0,
+                n => states[n - 1].1 != 0,
+            };
+        let mut result = Vec::new();
+        let mut idle_from = 0.0;
+        for &(timestamp, level) in &states {
+            // Look for the falling edge of a start bit.
+            if level != 0 || (timestamp as f64) < idle_from {
+                continue;
+            }
+            let start = timestamp as f64;
+            let mut data = 0u8;
+            for bit in 0..8 {
+                if level_at(start + (1.5 + bit as f64) * bit_time) {
+                    data |= 1 << bit;
+                }
+            }
+            let stop = start + 9.5 * bit_time;
+            result.push(UartByte {
+                timestamp,
+                data,
+                framing_error: !level_at(stop),
+            });
+            idle_from = stop;
+        }
+        Ok(result)
+    }
+
+    /// Decodes SPI transactions, sampling data on the clock edges given by `mode`.
+    pub fn decode_spi(
+        &self,
+        sck: &str,
+        cs: &str,
+        copi: Option<&str>,
+        cipo: Option<&str>,
+        mode: TransferMode,
+    ) -> Result<Vec<SpiTransaction>> {
+        const SCK: u8 = 1 << 0;
+        const CS: u8 = 1 << 1;
+        const COPI: u8 = 1 << 2;
+        const CIPO: u8 = 1 << 3;
+        let (initial, states) = self.states(&[
+            self.signal(sck)?,
+            self.signal(cs)?,
+            self.optional_signal(copi)?,
+            self.optional_signal(cipo)?,
+        ]);
+        let idle_high = matches!(mode.polarity(), ClockPolarity::IdleHigh);
+        let sample_leading = matches!(mode.phase(), ClockPhase::SampleLeading);
+
+        let new_transaction = |timestamp| {
+            (
+                timestamp,
+                BitAccumulator::default(),
+                BitAccumulator::default(),
+            )
+        };
+        let finish = |(timestamp, copi_bits, cipo_bits): (u64, BitAccumulator, BitAccumulator)| {
+            SpiTransaction {
+                timestamp,
+                bits: copi_bits.bits,
+                copi: copi.map(|_| copi_bits.bytes),
+                cipo: cipo.map(|_| cipo_bits.bytes),
+            }
+        };
+        let mut result = Vec::new();
+        let mut current = (initial & CS == 0).then(|| new_transaction(self.initial_timestamp));
+        let mut previous = initial;
+        for &(timestamp, state) in &states {
+            let changed = previous ^ state;
+            if changed & CS != 0 {
+                if state & CS == 0 {
+                    current = Some(new_transaction(timestamp));
+                } else if let Some(transaction) = current.take() {
+                    result.push(finish(transaction));
+                }
+            }
+            if changed & SCK != 0 {
+                let leading = (state & SCK != 0) != idle_high;
+                if let Some((_, copi_bits, cipo_bits)) = current.as_mut() {
+                    if leading == sample_leading {
+                        // Data levels as they were just before the clock edge.
+                        copi_bits.push(previous & COPI != 0);
+                        cipo_bits.push(previous & CIPO != 0);
+                    }
+                }
+            }
+            previous = state;
+        }
+        if let Some(transaction) = current {
+            result.push(finish(transaction));
+        }
+        Ok(result)
+    }
+
+    /// Decodes I2C transfers, using the decoder for bitbanged I2C.
+    pub fn decode_i2c(&self, scl: &str, sda: &str) -> Result<Vec<I2cTransaction>> {
+        const SDA: u8 = 1 << 0;
+        const SCL: u8 = 1 << 1;
+        // Samples of the decoder are limited to the data bytes fitting in its buffer.
+        const MAX_CLOCK_CYCLES: usize = 9 * 255;
+        let (initial, states) = self.states(&[self.signal(sda)?, self.signal(scl)?]);
+
+        // Split the capture at each stop condition, decoding each part separately.
+        let mut result = Vec::new();
+        let mut samples = vec![initial];
+        let mut start: Option<u64> = None;
+        let mut clock_cycles = 0;
+        let mut previous = initial;
+        for &(timestamp, state) in &states {
+            samples.push(state);
+            if previous & SCL == 0 && state & SCL != 0 {
+                clock_cycles += 1;
+            }
+            if previous & SCL != 0 && state & SCL != 0 {
+                if previous & SDA != 0 && state & SDA == 0 {
+                    start.get_or_insert(timestamp);
+                } else if previous & SDA == 0 && state & SDA != 0 {
+                    if let Some(start) = start.take() {
+                        ensure!(
+                            clock_cycles <= MAX_CLOCK_CYCLES,
+                            DecodeError::I2cTransferTooLong(start)
+                        );
+                        result.push(I2cTransaction {
+                            timestamp: start,
+                            symbols: Self::decode_i2c_samples(samples)?,
+                        });
+                    }
+                    samples = vec![state];
+                    clock_cycles = 0;
+                }
+            }
+            previous = state;
+        }
+        if let Some(start) = start {
+            ensure!(
+                clock_cycles <= MAX_CLOCK_CYCLES,
+                DecodeError::I2cTransferTooLong(start)
+            );
+            result.push(I2cTransaction {
+                timestamp: start,
+                symbols: Self::decode_i2c_samples(samples)?,
+            });
+        }
+        Ok(result)
+    }
+
+    fn decode_i2c_samples(samples: Vec<u8>) -> Result<Vec<I2cSymbol>> {
+        let mut decoder = i2c::decoder::Decoder::<0, 1> { buffer: [0u8; 256] };
+        Ok(decoder
+            .run(samples)?
+            .into_iter()
+            .map(|transfer| match transfer {
+                i2c::decoder::Transfer::Start => I2cSymbol::Start,
+                i2c::decoder::Transfer::Stop => I2cSymbol::Stop,
+                i2c::decoder::Transfer::Addr { addr, read, nack } => {
+                    I2cSymbol::Addr { addr, read, nack }
+                }
+                i2c::decoder::Transfer::Bytes { data, nack } => I2cSymbol::Bytes {
+                    data: data.to_vec(),
+                    nack,
+                },
+                i2c::decoder::Transfer::Broken(bits) => I2cSymbol::Broken {
+                    bits: bit_string(
+                        &bits
+                            .iter()
+                            .map(|bit| *bit == i2c::Bit::High)
+                            .collect::<Vec<_>>(),
+                    ),
+                },
+            })
+            .collect())
+    }
+
+    /// Decodes state transitions of the JTAG TAP controller, and the bits shifted while in the
+    /// Shift-DR/IR states.  As the initial state is unknown, decoding starts once the controller
+    /// has been reset by five rising edges of TCK with TMS high.
+    pub fn decode_jtag(
+        &self,
+        tck: &str,
+        tms: &str,
+        tdi: Option<&str>,
+        tdo: Option<&str>,
+    ) -> Result<Vec<JtagTransition>> {
+        const TCK: u8 = 1 << 0;
+        const TMS: u8 = 1 << 1;
+        const TDI: u8 = 1 << 2;
+        const TDO: u8 = 1 << 3;
+        let (mut previous, states) = self.states(&[
+            self.signal(tck)?,
+            self.signal(tms)?,
+            self.optional_signal(tdi)?,
+            self.optional_signal(tdo)?,
+        ]);
+
+        let mut result = Vec::new();
+        let mut state: Option<TapState> = None;
+        let mut tms_high_count = 0;
+        let mut tdi_bits = Vec::new();
+        let mut tdo_bits = Vec::new();
+        for &(timestamp, levels) in &states {
+            let rising = previous & TCK == 0 && levels & TCK != 0;
+            let (tms_level, tdi_level, tdo_level) = (
+                previous & TMS != 0,
+                previous & TDI != 0,
+                previous & TDO != 0,
+            );
+            previous = levels;
+            if !rising {
+                continue;
+            }
+            let Some(current) = state else {
+                // Synchronize by waiting for the controller to be reset.
+                tms_high_count = if tms_level { tms_high_count + 1 } else { 0 };
+                if tms_high_count == 5 {
+                    state = Some(TapState::TestLogicReset);
+                    result.push(JtagTransition {
+                        timestamp,
+                        state: TapState::TestLogicReset,
+                        tdi: None,
+                        tdo: None,
+                    });
+                }
+                continue;
+            };
+            let shifting = matches!(current, TapState::ShiftDr | TapState::ShiftIr);
+            if shifting {
+                tdi_bits.push(tdi_level);
+                tdo_bits.push(tdo_level);
+            }
+            let next = current.next(tms_level);
+            if next == current {
+                continue;
+            }
+            state = Some(next);
+            let (tdi_shifted, tdo_shifted) = if shifting {
+                (
+                    tdi.map(|_| bit_string(&tdi_bits)),
+                    tdo.map(|_| bit_string(&tdo_bits)),
+                )
+            } else {
+                (None, None)
+            };
+            tdi_bits.clear();
+            tdo_bits.clear();
+            result.push(JtagTransition {
+                timestamp,
+                state: next,
+                tdi: tdi_shifted,
+                tdo: tdo_shifted,
+            });
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Builds a capture from samples taken at every timestamp, with bit `i` of each sample being
+    /// the level of the `i`th signal.
+    fn capture_from_samples(signals: &[&str], samples: &[u8]) -> Capture {
+        let mut events = Vec::new();
+        for (timestamp, pair) in samples.windows(2).enumerate() {
+            for index in 0..signals.len() {
+                let (before, after) = (pair[0] >> index & 1, pair[1] >> index & 1);
+                if before != after {
+                    events.push(MonitoringEvent {
+                        signal_index: index as u8,
+                        edge: if after != 0 {
+                            Edge::Rising
+                        } else {
+                            Edge::Falling
+                        },
+                        timestamp: timestamp as u64 + 1,
+                    });
+                }
+            }
+        }
+        Capture {
+            signals: signals.iter().map(|s| s.to_string()).collect(),
+            resolution: Some(1_000_000),
+            initial_timestamp: 0,
+            initial_levels: (0..signals.len())
+                .map(|index| samples[0] >> index & 1 != 0)
+                .collect(),
+            events,
+        }
+    }
+
+    #[test]
+    fn test_vcd() -> Result<()> {
+        let capture = Capture::from_vcd(
+            "$timescale 1000000ps $end\n\
+             $scope module logic $end\n\
+             $var wire 1 '0 RX $end\n\
+             $var wire 1 '1 TX $end\n\
+             $upscope $end\n\
+             $enddefinitions $end\n\
+             #100\n1'0\n0'1\n\
+             #150\n0'0\n\
+             #170\n1'1\n",
+        )?;
+        assert_eq!(capture.signals, ["RX", "TX"]);
+        assert_eq!(capture.resolution, Some(1_000_000));
+        assert_eq!(capture.initial_timestamp, 100);
+        assert_eq!(capture.initial_levels, [true, false]);
+        assert_eq!(capture.events.len(), 2);
+        assert_eq!(capture.events[1].signal_index, 1);
+        assert_eq!(capture.events[1].edge, Edge::Rising);
+        assert_eq!(capture.events[1].timestamp, 170);
+        Ok(())
+    }
+
+    #[test]
+    fn test_uart() -> Result<()> {
+        // One sample per bit: idle, start, 0x4B (LSB first), stop, idle, start, 0x00, missing
+        // stop bit.
+        let mut samples = vec![1, 1, 0, 1, 1, 0, 1, 0, 0, 1, 0, 1, 1, 0];
+        samples.extend([0; 9]);
+        let capture = capture_from_samples(&["RX"], &samples);
+        let bytes = capture.decode_uart("rx", 1_000_000)?;
+        assert_eq!(bytes.len(), 2);
+        assert_eq!((bytes[0].data, bytes[0].framing_error), (0x4B, false));
+        assert_eq!((bytes[1].data, bytes[1].framing_error), (0x00, true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_spi() -> Result<()> {
+        // Mode 0: CS low, then eight clock cycles with COPI = 0xA5 and CIPO = 0x3C.
+        let mut samples = vec![0b1110, 0b1100];
+        for bit in (0..8).rev() {
+            let data = (0xA5 >> bit & 1) << 2 | (0x3C >> bit & 1) << 3;
+            samples.extend([data, data | 1]);
+        }
+        samples.extend([0b0000, 0b0010]);
+        let capture = capture_from_samples(&["SCK", "CS", "COPI", "CIPO"], &samples);
+        let transactions =
+            capture.decode_spi("SCK", "CS", Some("COPI"), Some("CIPO"), TransferMode::Mode0)?;
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].timestamp, 1);
+        assert_eq!(transactions[0].bits, 8);
+        assert_eq!(transactions[0].copi, Some(vec![0xA5]));
+        assert_eq!(transactions[0].cipo, Some(vec![0x3C]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_i2c() -> Result<()> {
+        use i2c::encoder::{Encoder, Transfer};
+        let samples = Encoder::<0, 1> {}.run(&[
+            Transfer::Start,
+            Transfer::Addr {
+                addr: 0x50,
+                read: false,
+                nack: false,
+            },
+            Transfer::Write(&[0x12, 0x34]),
+            Transfer::Stop,
+        ]);
+        let mut samples_with_idle = vec![0b11];
+        samples_with_idle.extend(samples);
+        let capture = capture_from_samples(&["SDA", "SCL"], &samples_with_idle);
+        let transactions = capture.decode_i2c("SCL", "SDA")?;
+        assert_eq!(transactions.len(), 1);
+        let symbols = &transactions[0].symbols;
+        assert!(matches!(symbols[0], I2cSymbol::Start));
+        assert!(matches!(
+            symbols[1],
+            I2cSymbol::Addr {
+                addr: 0x50,
+                read: false,
+                ..
+            }
+        ));
+        assert!(matches!(symbols.last(), Some(I2cSymbol::Stop)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_jtag() -> Result<()> {
+        // Reset, then Run-Test/Idle, Select-DR-Scan, Capture-DR, Shift-DR, shifting two bits on
+        // the way to Exit1-DR.
+        let tms = [1, 1, 1, 1, 1, 0, 1, 0, 0, 0, 1];
+        let tdi = [0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1];
+        let mut samples = Vec::new();
+        for (tms, tdi) in tms.iter().zip(tdi) {
+            let data = tms << 1 | tdi << 2;
+            samples.extend([data, data | 1]);
+        }
+        let capture = capture_from_samples(&["TCK", "TMS", "TDI"], &samples);
+        let transitions = capture.decode_jtag("TCK", "TMS", Some("TDI"), None)?;
+        let states: Vec<TapState> = transitions.iter().map(|t| t.state).collect();
+        assert_eq!(
+            states,
+            [
+                TapState::TestLogicReset,
+                TapState::RunTestIdle,
+                TapState::SelectDrScan,
+                TapState::CaptureDr,
+                TapState::ShiftDr,
+                TapState::Exit1Dr,
+            ]
+        );
+        assert_eq!(transitions[5].tdi.as_deref(), Some("11"));
+        assert_eq!(transitions[5].tdo, None);
+        Ok(())
+    }
+}
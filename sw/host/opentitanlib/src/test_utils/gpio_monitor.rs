@@ -9,6 +9,7 @@ use std::rc::Rc;
 
 use crate::app::TransportWrapper;
 use crate::io::gpio::{ClockNature, Edge, GpioMonitoring, MonitoringEvent};
+use crate::test_utils::gpio_decode::Capture;
 
 // This structure makes it easier to monitor GPIOs and supports dumping a trace
 // in the VCD format for further examination. In addition, for easier debugging
@@ -110,6 +111,17 @@ impl<'a> GpioMon<'a> {
         Ok(events.events)
     }
 
+    /// Returns the trace since the beginning, for decoding using `gpio_decode`.
+    pub fn capture(&self) -> Capture {
+        Capture {
+            signals: self.pin_names.clone(),
+            resolution: Some(self.resolution),
+            initial_timestamp: self.initial_timestamp,
+            initial_levels: self.initial_levels.clone(),
+            events: self.events.clone(),
+        }
+    }
+
     pub fn dump_vcd(&self) -> String {
         const SYMBOLS: &[char] = &['!', '#', '$', '%', '&', '(', ')'];
         assert!(self.pins.len() < SYMBOLS.len());
@@ -11,6 +11,7 @@ pub mod epmp;
 #[cfg(not(feature = "english_breakfast"))]
 pub mod extclk;
 pub mod gpio;
+pub mod gpio_decode;
 pub mod gpio_monitor;
 pub mod i2c_target;
 pub mod init;
@@ -16,6 +16,8 @@ use crate::uart::console::{ExitStatus, UartConsole};
 // Bring in the auto-generated sources.
 include!(env!("ottf"));
 
+/// Sends ujson RPC messages over a console, which may be a UART or, on parts where the UART is
+/// not available, a `SpiConsoleDevice` with experimental writes enabled.
 pub trait UartSend {
     fn send(&self, uart: &dyn Uart) -> Result<()>;
 
@@ -41,6 +43,7 @@ impl<T: Serialize> UartSend for T {
     }
 }
 
+/// Receives ujson RPC responses over a console, see `UartSend`.
 pub trait UartRecv {
     fn recv(uart: &dyn Uart, timeout: Duration, quiet: bool) -> Result<Self>
     where
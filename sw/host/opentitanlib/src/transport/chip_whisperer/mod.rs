@@ -10,6 +10,7 @@ use std::cell::RefCell;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::time::Duration;
 
 use crate::io::gpio::GpioPin;
 use crate::io::spi::Target;
@@ -27,11 +28,14 @@ pub mod gpio;
 pub mod spi;
 pub mod usb;
 
+/// How long to wait for the board to reappear on the USB bus, when reconnecting.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Default)]
 struct Inner {
     spi: Option<Rc<dyn Target>>,
     gpio: HashMap<String, Rc<dyn GpioPin>>,
-    uart: HashMap<u32, Rc<dyn Uart>>,
+    uart: HashMap<u32, Rc<SerialPortUart>>,
 }
 
 pub struct ChipWhisperer<B: Board> {
@@ -58,6 +62,11 @@ impl<B: Board> ChipWhisperer<B> {
     }
 
     fn open_uart(&self, instance: u32) -> Result<SerialPortUart> {
+        SerialPortUart::open(&self.uart_port_name(instance)?, B::UART_BAUD)
+    }
+
+    /// Returns the name of the serial device of the given UART instance.
+    fn uart_port_name(&self, instance: u32) -> Result<String> {
         if self.uart_override.is_empty() {
             let usb = self.device.borrow();
             let serial_number = usb.get_serial_number();
@@ -79,14 +88,14 @@ impl<B: Board> ChipWhisperer<B> {
             let port = ports.get(instance as usize).ok_or_else(|| {
                 TransportError::InvalidInstance(TransportInterfaceType::Uart, instance.to_string())
             })?;
-            SerialPortUart::open(&port.port_name, B::UART_BAUD)
+            Ok(port.port_name.clone())
         } else {
             let instance = instance as usize;
             ensure!(
                 instance < self.uart_override.len(),
                 TransportError::InvalidInstance(TransportInterfaceType::Uart, instance.to_string())
             );
-            SerialPortUart::open(&self.uart_override[instance], B::UART_BAUD)
+            Ok(self.uart_override[instance].clone())
         }
     }
 }
@@ -110,7 +119,20 @@ impl<B: Board + 'static> Transport for ChipWhisperer<B> {
             }
             Entry::Occupied(o) => Rc::clone(o.get()),
         };
-        Ok(uart)
+        Ok(uart as Rc<dyn Uart>)
+    }
+
+    fn reconnect(&self) -> Result<()> {
+        self.device.borrow_mut().reconnect(RECONNECT_TIMEOUT)?;
+        let inner = self.inner.borrow();
+        // Serial devices may have been renamed, switch existing UART instances to the new ones.
+        for (&instance, uart) in &inner.uart {
+            uart.reopen(&self.uart_port_name(instance)?, B::UART_BAUD)?;
+        }
+        if inner.spi.is_some() {
+            self.device.borrow().spi1_enable(true)?;
+        }
+        Ok(())
     }
 
     fn gpio_pin(&self, pinname: &str) -> Result<Rc<dyn GpioPin>> {
@@ -3,4 +3,5 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod fpga;
+pub mod spi;
 pub mod uart;
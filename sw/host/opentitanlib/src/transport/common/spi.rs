@@ -0,0 +1,538 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{ensure, Result};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::io::eeprom::{self, DataWidth, Switch};
+use crate::io::gpio::{BitbangEntry, GpioBitbanging, GpioPin, PinMode, PullMode};
+use crate::io::spi::{
+    AssertChipSelect, ClockPhase, ClockPolarity, MaxSizes, SpiError, Target, TargetChipDeassert,
+    Transfer, TransferMode,
+};
+
+/// Sequence of samples making up a SPI transaction, for use with `GpioBitbanging`.  Bit 0 of each
+/// sample is the serial clock, bit 1 the chip select, and bits 2-5 the data lanes IO0-IO3.  (In
+/// single-lane mode, IO0 is COPI and IO1 is CIPO.)  Each clock cycle is made up of two samples.
+///
+/// The samples can be inspected and altered before being run by `BitbangSpi::run_waveform()`,
+/// allowing for testing of a device with deliberately malformed transactions.
+pub struct SpiWaveform {
+    mode: TransferMode,
+    /// Chip select level outside of the transaction, zero if held asserted by `assert_cs()`.
+    cs_idle: u8,
+    samples: Vec<u8>,
+    /// Index of the first sample to decode, and the lane width, of each byte read.
+    reads: Vec<(usize, u32)>,
+    /// Largest number of data lanes used by any phase.
+    lanes: u32,
+}
+
+impl SpiWaveform {
+    pub const SCK: u8 = 1 << 0;
+    pub const CS: u8 = 1 << 1;
+    pub const DATA_SHIFT: u32 = 2;
+    /// All data lanes at high level, that is released in case of open drain.
+    const DATA_RELEASED: u8 = 0x0F;
+
+    /// Starts a new waveform, asserting chip select unless `cs_held` indicates that it is already
+    /// asserted.
+    pub fn new(mode: TransferMode, cs_held: bool) -> Self {
+        let mut result = Self {
+            mode,
+            cs_idle: if cs_held { 0 } else { Self::CS },
+            samples: Vec::new(),
+            reads: Vec::new(),
+            lanes: 1,
+        };
+        let idle = result.clock_idle() | Self::DATA_RELEASED << Self::DATA_SHIFT;
+        result.samples.extend([result.cs_idle | idle, idle]);
+        result
+    }
+
+    fn clock_idle(&self) -> u8 {
+        match self.mode.polarity() {
+            ClockPolarity::IdleLow => 0,
+            ClockPolarity::IdleHigh => Self::SCK,
+        }
+    }
+
+    fn lanes(&mut self, width: DataWidth) -> Result<u32> {
+        let lanes = match width {
+            DataWidth::Single => 1,
+            DataWidth::Dual => 2,
+            DataWidth::Quad => 4,
+            DataWidth::Octo => return Err(SpiError::InvalidDataWidth(width).into()),
+        };
+        self.lanes = std::cmp::max(self.lanes, lanes);
+        Ok(lanes)
+    }
+
+    /// Appends one clock cycle, with the given levels on the data lanes.  The data is set up
+    /// before the edge at which the device samples it, the device driving its data in turn on
+    /// the opposite edge.
+    fn cycle(&mut self, data: u8) {
+        let idle = self.clock_idle() | data << Self::DATA_SHIFT;
+        let active = idle ^ Self::SCK;
+        match self.mode.phase() {
+            ClockPhase::SampleLeading => self.samples.extend([idle, active]),
+            ClockPhase::SampleTrailing => self.samples.extend([active, idle]),
+        }
+    }
+
+    /// Appends clock cycles sending the given data, most significant bit first, using the given
+    /// number of data lanes.  Lanes not used are kept high.
+    pub fn write(&mut self, data: &[u8], width: DataWidth) -> Result<()> {
+        let lanes = self.lanes(width)?;
+        let mask = (1u8 << lanes) - 1;
+        for byte in data {
+            for shift in (0..8 / lanes).rev() {
+                self.cycle(byte >> (shift * lanes) & mask | Self::DATA_RELEASED & !mask);
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends clock cycles receiving `len` bytes using the given number of data lanes, the
+    /// lanes being released by the host.  In single-lane mode data is received on IO1.
+    pub fn read(&mut self, len: usize, width: DataWidth) -> Result<()> {
+        let lanes = self.lanes(width)?;
+        for _ in 0..len {
+            // The level sampled just before applying the second sample of a cycle is the one
+            // at the edge at which the host samples.
+            self.reads.push((self.samples.len() + 1, lanes));
+            for _ in 0..8 / lanes {
+                self.cycle(Self::DATA_RELEASED);
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends clock cycles simultaneously sending and receiving data in single-lane mode.
+    pub fn both(&mut self, data: &[u8]) {
+        for byte in data {
+            self.reads.push((self.samples.len() + 1, 1));
+            for shift in (0..8).rev() {
+                self.cycle(byte >> shift & 1 | Self::DATA_RELEASED & !1);
+            }
+        }
+    }
+
+    /// Appends clock cycles with all data lanes released.
+    pub fn dummy_cycles(&mut self, count: u8) {
+        for _ in 0..count {
+            self.cycle(Self::DATA_RELEASED);
+        }
+    }
+
+    /// Returns the clock to its idle level, and deasserts chip select unless held.
+    pub fn end(&mut self) {
+        let idle = self.clock_idle() | Self::DATA_RELEASED << Self::DATA_SHIFT;
+        self.samples.extend([idle, self.cs_idle | idle]);
+    }
+
+    pub fn samples(&self) -> &[u8] {
+        &self.samples
+    }
+
+    pub fn samples_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.samples
+    }
+
+    /// Extracts the bytes received by `read()` and `both()` from the levels sampled while
+    /// running the waveform.
+    pub fn decode(&self, sampled: &[u8]) -> Result<Vec<u8>> {
+        let mut result = Vec::with_capacity(self.reads.len());
+        for &(start, lanes) in &self.reads {
+            let mask = (1u8 << lanes) - 1;
+            let end = start + 2 * (8 / lanes as usize - 1);
+            ensure!(
+                end < sampled.len(),
+                SpiError::InvalidDataLength(sampled.len())
+            );
+            let mut byte = 0u8;
+            for index in (start..=end).step_by(2) {
+                let data = sampled[index] >> Self::DATA_SHIFT;
+                let bits = if lanes == 1 {
+                    data >> 1 & 1
+                } else {
+                    data & mask
+                };
+                byte = byte << lanes | bits;
+            }
+            result.push(byte);
+        }
+        Ok(result)
+    }
+}
+
+struct Pins {
+    serial_clock: Rc<dyn GpioPin>,
+    chip_select: Rc<dyn GpioPin>,
+    /// Data lanes IO0-IO3, the latter two only needed for quad mode.
+    data: [Option<Rc<dyn GpioPin>>; 4],
+}
+
+/// Software-defined SPI host, generating SPI transactions as `GpioBitbanging` waveforms on
+/// arbitrary GPIO pins.  Supports all four SPI transfer modes, as well as dual and quad data
+/// phases in `run_eeprom_transactions()`.
+///
+/// In single-lane mode, COPI is driven push-pull and CIPO is an input.  When using more than one
+/// lane, all data pins are put in open drain mode with pull-up, such that the device can drive
+/// them whenever the host is not.
+pub struct BitbangSpi {
+    bitbanging: Rc<dyn GpioBitbanging>,
+    pins: RefCell<Pins>,
+    mode: Cell<TransferMode>,
+    max_speed: Cell<u32>,
+    /// Whether pins have been configured for multi-lane use, `None` if not yet configured.
+    multi_lane: Cell<Option<bool>>,
+    cs_asserted_count: Cell<u32>,
+}
+
+impl BitbangSpi {
+    const DEFAULT_SPEED: u32 = 100_000;
+    const MAX_TRANSFER_SIZE: usize = 4096;
+
+    pub fn new(
+        bitbanging: Rc<dyn GpioBitbanging>,
+        serial_clock: Rc<dyn GpioPin>,
+        host_out_device_in: Rc<dyn GpioPin>,
+        host_in_device_out: Rc<dyn GpioPin>,
+        chip_select: Rc<dyn GpioPin>,
+        io2: Option<Rc<dyn GpioPin>>,
+        io3: Option<Rc<dyn GpioPin>>,
+    ) -> Self {
+        Self {
+            bitbanging,
+            pins: RefCell::new(Pins {
+                serial_clock,
+                chip_select,
+                data: [Some(host_out_device_in), Some(host_in_device_out), io2, io3],
+            }),
+            mode: Cell::new(TransferMode::Mode0),
+            max_speed: Cell::new(Self::DEFAULT_SPEED),
+            multi_lane: Cell::new(None),
+            cs_asserted_count: Cell::new(0),
+        }
+    }
+
+    /// Starts a new waveform in the current transfer mode.
+    pub fn waveform(&self) -> SpiWaveform {
+        SpiWaveform::new(self.mode.get(), self.cs_asserted_count.get() > 0)
+    }
+
+    fn configure_pins(&self, multi_lane: bool) -> Result<()> {
+        if self.multi_lane.get() == Some(multi_lane) {
+            return Ok(());
+        }
+        let pins = self.pins.borrow();
+        let clock_idle = matches!(self.mode.get().polarity(), ClockPolarity::IdleHigh);
+        pins.serial_clock
+            .set(Some(PinMode::PushPull), Some(clock_idle), None, None)?;
+        pins.chip_select.set(
+            Some(PinMode::PushPull),
+            Some(self.cs_asserted_count.get() == 0),
+            None,
+            None,
+        )?;
+        for (lane, pin) in pins.data.iter().enumerate() {
+            let Some(pin) = pin else {
+                continue;
+            };
+            if multi_lane {
+                pin.set(
+                    Some(PinMode::OpenDrain),
+                    Some(true),
+                    Some(PullMode::PullUp),
+                    None,
+                )?;
+            } else if lane == 1 {
+                pin.set(Some(PinMode::Input), None, None, None)?;
+            } else {
+                pin.set(Some(PinMode::PushPull), Some(true), None, None)?;
+            }
+        }
+        self.multi_lane.set(Some(multi_lane));
+        Ok(())
+    }
+
+    /// Runs the given waveform on the SPI pins, returning the bytes received.
+    pub fn run_waveform(&self, waveform: &SpiWaveform) -> Result<Vec<u8>> {
+        ensure!(
+            waveform.lanes <= 2 || self.pins.borrow().data.iter().all(Option::is_some),
+            SpiError::InvalidDataWidth(DataWidth::Quad)
+        );
+        self.configure_pins(waveform.lanes > 1)?;
+        let pins = self.pins.borrow();
+        let mut pin_list: Vec<&dyn GpioPin> =
+            vec![pins.serial_clock.as_ref(), pins.chip_select.as_ref()];
+        pin_list.extend(pins.data.iter().map_while(|pin| pin.as_deref()));
+        // Samples may not refer to pins beyond those given.
+        let mask = ((1u32 << pin_list.len()) - 1) as u8;
+        let samples: Vec<u8> = waveform.samples().iter().map(|s| s & mask).collect();
+        let mut sampled = vec![0u8; samples.len()];
+        self.bitbanging.run(
+            &pin_list,
+            Duration::from_nanos(500_000_000 / self.max_speed.get() as u64),
+            &mut [BitbangEntry::Both(&samples, &mut sampled)],
+        )?;
+        waveform.decode(&sampled)
+    }
+
+    fn run_eeprom_transaction(
+        &self,
+        cmd: &eeprom::Cmd,
+        write: Option<&[u8]>,
+        read: Option<&mut [u8]>,
+    ) -> Result<()> {
+        ensure!(
+            !cmd.get_double_transfer_rate(),
+            SpiError::InvalidDoubleTransferRate()
+        );
+        let (opcode_width, addr_width, data_width) = match cmd.get_switch() {
+            Switch::Mode111 => (DataWidth::Single, DataWidth::Single, DataWidth::Single),
+            Switch::Mode11N => (DataWidth::Single, DataWidth::Single, cmd.get_width()),
+            Switch::Mode1NN => (DataWidth::Single, cmd.get_width(), cmd.get_width()),
+            Switch::ModeNNN => (cmd.get_width(), cmd.get_width(), cmd.get_width()),
+        };
+        let addr_len = cmd.get_address_len() as usize;
+        let mut waveform = self.waveform();
+        waveform.write(cmd.get_opcode(), opcode_width)?;
+        waveform.write(&cmd.get_address().to_be_bytes()[4 - addr_len..], addr_width)?;
+        waveform.dummy_cycles(cmd.get_dummy_cycles());
+        if let Some(wbuf) = write {
+            waveform.write(wbuf, data_width)?;
+        }
+        if let Some(rbuf) = &read {
+            waveform.read(rbuf.len(), data_width)?;
+        }
+        waveform.end();
+        let data = self.run_waveform(&waveform)?;
+        if let Some(rbuf) = read {
+            rbuf.copy_from_slice(&data);
+        }
+        Ok(())
+    }
+
+    fn do_assert_cs(&self, assert: bool) -> Result<()> {
+        let mut count = self.cs_asserted_count.get();
+        if assert {
+            if count == 0 {
+                self.configure_pins(self.multi_lane.get().unwrap_or(false))?;
+                self.pins.borrow().chip_select.write(false)?;
+            }
+            count += 1;
+        } else {
+            if count == 1 {
+                self.pins.borrow().chip_select.write(true)?;
+            }
+            count -= 1;
+        }
+        self.cs_asserted_count.set(count);
+        Ok(())
+    }
+}
+
+impl Target for BitbangSpi {
+    fn get_transfer_mode(&self) -> Result<TransferMode> {
+        Ok(self.mode.get())
+    }
+    fn set_transfer_mode(&self, mode: TransferMode) -> Result<()> {
+        if mode != self.mode.get() {
+            self.mode.set(mode);
+            // Clock idle level may have changed.
+            self.multi_lane.set(None);
+        }
+        Ok(())
+    }
+
+    fn get_bits_per_word(&self) -> Result<u32> {
+        Ok(8)
+    }
+    fn set_bits_per_word(&self, bits_per_word: u32) -> Result<()> {
+        match bits_per_word {
+            8 => Ok(()),
+            _ => Err(SpiError::InvalidWordSize(bits_per_word).into()),
+        }
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        Ok(self.max_speed.get())
+    }
+    fn set_max_speed(&self, max_speed: u32) -> Result<()> {
+        ensure!(max_speed > 0, SpiError::InvalidSpeed(max_speed));
+        self.max_speed.set(max_speed);
+        Ok(())
+    }
+
+    fn supports_bidirectional_transfer(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn set_pins(
+        &self,
+        serial_clock: Option<&Rc<dyn GpioPin>>,
+        host_out_device_in: Option<&Rc<dyn GpioPin>>,
+        host_in_device_out: Option<&Rc<dyn GpioPin>>,
+        chip_select: Option<&Rc<dyn GpioPin>>,
+    ) -> Result<()> {
+        let mut pins = self.pins.borrow_mut();
+        if let Some(pin) = serial_clock {
+            pins.serial_clock = Rc::clone(pin);
+        }
+        if let Some(pin) = host_out_device_in {
+            pins.data[0] = Some(Rc::clone(pin));
+        }
+        if let Some(pin) = host_in_device_out {
+            pins.data[1] = Some(Rc::clone(pin));
+        }
+        if let Some(pin) = chip_select {
+            pins.chip_select = Rc::clone(pin);
+        }
+        self.multi_lane.set(None);
+        Ok(())
+    }
+
+    fn get_max_transfer_count(&self) -> Result<usize> {
+        Ok(usize::MAX)
+    }
+
+    fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        Ok(MaxSizes {
+            read: Self::MAX_TRANSFER_SIZE,
+            write: Self::MAX_TRANSFER_SIZE,
+        })
+    }
+
+    fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+        let mut waveform = self.waveform();
+        for transfer in transaction.iter() {
+            match transfer {
+                Transfer::Read(rbuf) => waveform.read(rbuf.len(), DataWidth::Single)?,
+                Transfer::Write(wbuf) => waveform.write(wbuf, DataWidth::Single)?,
+                Transfer::Both(wbuf, rbuf) => {
+                    ensure!(
+                        wbuf.len() == rbuf.len(),
+                        SpiError::MismatchedDataLength(wbuf.len(), rbuf.len())
+                    );
+                    waveform.both(wbuf);
+                }
+            }
+        }
+        waveform.end();
+        let data = self.run_waveform(&waveform)?;
+        let mut data = data.as_slice();
+        for transfer in transaction.iter_mut() {
+            match transfer {
+                Transfer::Read(rbuf) | Transfer::Both(_, rbuf) => {
+                    let (head, tail) = data.split_at(rbuf.len());
+                    rbuf.copy_from_slice(head);
+                    data = tail;
+                }
+                Transfer::Write(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn run_eeprom_transactions(&self, transactions: &mut [eeprom::Transaction]) -> Result<()> {
+        for transaction in transactions {
+            match transaction {
+                eeprom::Transaction::Command(cmd) => {
+                    self.run_eeprom_transaction(cmd, None, None)?
+                }
+                eeprom::Transaction::Read(cmd, rbuf) => {
+                    self.run_eeprom_transaction(cmd, None, Some(&mut rbuf[..]))?
+                }
+                eeprom::Transaction::Write(cmd, wbuf) => {
+                    self.run_eeprom_transaction(cmd, Some(*wbuf), None)?
+                }
+                eeprom::Transaction::WaitForBusyClear => {
+                    let mut status = eeprom::STATUS_WIP;
+                    while status & eeprom::STATUS_WIP != 0 {
+                        self.run_transaction(&mut [
+                            Transfer::Write(&[eeprom::READ_STATUS]),
+                            Transfer::Read(std::slice::from_mut(&mut status)),
+                        ])?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+        self.do_assert_cs(true)?;
+        Ok(AssertChipSelect::new(self))
+    }
+}
+
+impl TargetChipDeassert for BitbangSpi {
+    fn deassert_cs(&self) {
+        // We cannot propagate errors through `Drop::drop()`, so panic on any error.
+        self.do_assert_cs(false)
+            .expect("Error while deasserting CS");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mode0_write() -> Result<()> {
+        let mut waveform = SpiWaveform::new(TransferMode::Mode0, false);
+        waveform.write(&[0xA0], DataWidth::Single)?;
+        waveform.end();
+        // Clock low when idle, COPI set up before each rising edge.
+        let expected: Vec<u8> = [0x3E, 0x3C]
+            .into_iter()
+            .chain([1, 0, 1, 0, 0, 0, 0, 0].into_iter().flat_map(|bit| {
+                let data = (0x0E | bit) << 2;
+                [data, data | 0x01]
+            }))
+            .chain([0x3C, 0x3E])
+            .collect();
+        assert_eq!(waveform.samples(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mode3_read() -> Result<()> {
+        let mut waveform = SpiWaveform::new(TransferMode::Mode3, true);
+        waveform.read(1, DataWidth::Single)?;
+        waveform.end();
+        // Clock high when idle, falling edge first, chip select held asserted throughout.
+        assert_eq!(waveform.samples()[..4], [0x3D, 0x3D, 0x3C, 0x3D]);
+        assert_eq!(waveform.samples()[18..], [0x3D, 0x3D]);
+        // Device drives 0x5A on CIPO (IO1, bit 3), sampled before the rising edges.
+        let sampled: Vec<u8> = (0..waveform.samples().len())
+            .map(|i| match i {
+                3..=17 if i % 2 == 1 => (0x5A >> ((17 - i) / 2) & 1) << 3,
+                _ => 0,
+            })
+            .collect();
+        assert_eq!(waveform.decode(&sampled)?, [0x5A]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quad_read() -> Result<()> {
+        let mut waveform = SpiWaveform::new(TransferMode::Mode0, false);
+        waveform.read(2, DataWidth::Quad)?;
+        waveform.end();
+        assert_eq!(waveform.lanes, 4);
+        // Four cycles, each receiving a nibble on IO0-IO3.
+        let mut sampled = vec![0u8; waveform.samples().len()];
+        for (cycle, nibble) in [0x1, 0x2, 0xB, 0xC].into_iter().enumerate() {
+            sampled[3 + 2 * cycle] = nibble << 2;
+        }
+        assert_eq!(waveform.decode(&sampled)?, [0x12, 0xBC]);
+        assert!(waveform.write(&[0], DataWidth::Octo).is_err());
+        Ok(())
+    }
+}
@@ -19,6 +19,7 @@ use crate::util;
 /// Implementation of the `Uart` trait on top of a serial device, such as `/dev/ttyUSB0`.
 pub struct SerialPortUart {
     flow_control: Cell<FlowControl>,
+    hardware_flow_control: Cell<bool>,
     port: RefCell<TTYPort>,
     rxbuf: RefCell<VecDeque<u8>>,
 }
@@ -38,11 +39,46 @@ impl SerialPortUart {
         flock_serial(&port, port_name)?;
         Ok(SerialPortUart {
             flow_control: Cell::new(FlowControl::None),
+            hardware_flow_control: Cell::new(false),
             port: RefCell::new(port),
             rxbuf: RefCell::default(),
         })
     }
 
+    /// Replaces the underlying serial device with the given one, e.g. after the USB device
+    /// providing it has re-enumerated.  Any data received but not yet read is discarded.
+    /// Registrations for nonblocking operation do not carry over, and must be made again, while
+    /// flow control settings are re-applied to the new device.
+    pub fn reopen(&self, port_name: &str, baud: u32) -> Result<()> {
+        let mut port = self.port.borrow_mut();
+        // The new device may well be the same TTY as the old one, give up exclusive access to
+        // the old one first, or opening and locking the new one would fail.
+        release_serial(&mut port);
+        let new_port = TTYPort::open(&serialport::new(port_name, baud).flow_control(
+            Self::serialport_flow_control(self.hardware_flow_control.get()),
+        ))
+        .map_err(|e| UartError::OpenError(e.to_string()).into())
+        .and_then(|new_port| flock_serial(&new_port, port_name).map(|_| new_port));
+        match new_port {
+            Ok(new_port) => *port = new_port,
+            Err(e) => {
+                // Keep holding the old device, in case it is still usable.
+                let _ = port.set_exclusive(true);
+                let _ = flock_serial(&port, port_name);
+                return Err(e);
+            }
+        }
+        self.rxbuf.borrow_mut().clear();
+        Ok(())
+    }
+
+    fn serialport_flow_control(hardware_flow_control: bool) -> serialport::FlowControl {
+        match hardware_flow_control {
+            false => serialport::FlowControl::None,
+            true => serialport::FlowControl::Hardware,
+        }
+    }
+
     fn read_worker(&self, timeout: Duration) -> Result<()> {
         let mut buf = [0u8; 256];
         let mut port = self.port.borrow_mut();
@@ -184,6 +220,15 @@ impl Uart for SerialPortUart {
         Ok(())
     }
 
+    fn set_hardware_flow_control(&self, enable: bool) -> Result<()> {
+        self.port
+            .borrow_mut()
+            .set_flow_control(Self::serialport_flow_control(enable))
+            .context("setting flow control")?;
+        self.hardware_flow_control.set(enable);
+        Ok(())
+    }
+
     fn set_break(&self, enable: bool) -> Result<()> {
         let port = self.port.borrow_mut();
         if enable {
@@ -231,3 +276,37 @@ pub fn flock_serial(port: &TTYPort, port_name: &str) -> Result<()> {
     })?;
     Ok(())
 }
+
+/// Release the lock taken by `flock_serial()`, as well as the exclusive mode in which
+/// `TTYPort::open()` puts the device, while keeping it open.
+fn release_serial(port: &mut TTYPort) {
+    let _ = port.set_exclusive(false);
+    // SAFETY: `fd` is owned by `port` and is valid.
+    let fd = unsafe { BorrowedFd::borrow_raw(port.as_raw_fd()) };
+    let _ = rustix::fs::flock(fd, rustix::fs::FlockOperation::Unlock);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::os::fd::FromRawFd;
+
+    #[test]
+    fn test_reopen_same_port() -> Result<()> {
+        let pty = nix::pty::openpty(None, None)?;
+        // SAFETY: `openpty()` returned newly opened file descriptors, not owned elsewhere.
+        let (_master, slave) =
+            unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        let path = nix::unistd::ttyname(slave.as_raw_fd())?;
+        let path = path.to_str().unwrap();
+        let uart = SerialPortUart::open(path, 115200)?;
+        // Reconnecting without the device having re-enumerated finds the same TTY.
+        uart.reopen(path, 115200)?;
+        // The lock is still held, now on the new file descriptor.
+        assert!(SerialPortUart::open(path, 115200).is_err());
+        drop(uart);
+        SerialPortUart::open(path, 115200)?;
+        Ok(())
+    }
+}
@@ -43,6 +43,10 @@ pub enum TransportError {
     PllProgramFailed(String),
     #[error("Invalid pin strapping name \"{0}\"")]
     InvalidStrappingName(String),
+    #[error("Invalid sequence name \"{0}\"")]
+    InvalidSequenceName(String),
+    #[error("Sequence \"{0}\" defined differently in multiple configuration files")]
+    InconsistentSequenceConf(String),
     #[error("Invalid IO expander name \"{0}\"")]
     InvalidIoExpanderName(String),
     #[error("Invalid pin {1} for IO expander \"{0}\"")]
@@ -69,13 +73,42 @@ pub enum TransportError {
     InvalidConfStrapAlias(String, String),
     #[error("Strapping \"{0}\" pin \"{1}\" cannot declare \"invert\"")]
     InvalidConfStrapInvert(String, String),
-    #[error("Expected value \"{1}\" for key \"{0}\", found \"{2}\"")]
-    RequiresUnequal(String, String, String),
-    #[error("Expected value \"{1}\" for key \"{0}\", found none")]
-    RequiresMissing(String, String),
+    #[error("Configuration file {0} applies to interfaces {2:?}, not \"{1}\"")]
+    ConfInterfaceMismatch(String, String, Vec<String>),
+    #[error("Requirements of configuration files not satisfied:{}", requires_diff(.0))]
+    RequiresUnsatisfied(Vec<RequiresMismatch>),
 }
 impl_serializable_error!(TransportError);
 
+/// An entry of the `requires` map of a configuration file, which is not satisfied by the merged
+/// `provides` maps.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RequiresMismatch {
+    pub key: String,
+    pub required: String,
+    pub required_by: String,
+    /// The provided value, and the source providing it, if any.
+    pub provided: Option<(String, String)>,
+}
+
+/// Renders the mismatches as a diff from the required to the provided values.
+fn requires_diff(mismatches: &[RequiresMismatch]) -> String {
+    let mut diff = String::new();
+    for m in mismatches {
+        diff += &format!(
+            "\n- {} = \"{}\" (required by {})",
+            m.key, m.required, m.required_by
+        );
+        diff += &match &m.provided {
+            Some((value, source)) => {
+                format!("\n+ {} = \"{}\" (provided by {})", m.key, value, source)
+            }
+            None => format!("\n+ {} not provided", m.key),
+        };
+    }
+    diff
+}
+
 /// Enum value used by `TransportError::InvalidInstance`.
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub enum TransportInterfaceType {
@@ -94,29 +94,30 @@ impl Transport for HyperdebugDfu {
                 &update_firmware_action.firmware,
                 update_firmware_action.progress.as_ref(),
                 update_firmware_action.force,
-            )
+            )?;
+            Ok(None)
         } else {
             bail!(TransportError::UnsupportedOperation)
         }
     }
 }
 
-const USB_CLASS_APP: u8 = 0xFE;
-const USB_SUBCLASS_DFU: u8 = 0x01;
+pub(crate) const USB_CLASS_APP: u8 = 0xFE;
+pub(crate) const USB_SUBCLASS_DFU: u8 = 0x01;
 
 const DFUSE_ERASE_PAGE: u8 = 0x41;
 const DFUSE_PROGRAM_PAGE: u8 = 0x21;
 
-const DFU_STATUS_OK: u8 = 0x00;
+pub(crate) const DFU_STATUS_OK: u8 = 0x00;
 
 const DFU_STATE_APP_IDLE: u8 = 0x00;
-const DFU_STATE_DFU_IDLE: u8 = 0x02;
-const DFU_STATE_DOWNLOAD_BUSY: u8 = 0x04;
+pub(crate) const DFU_STATE_DFU_IDLE: u8 = 0x02;
+pub(crate) const DFU_STATE_DOWNLOAD_BUSY: u8 = 0x04;
 const DFU_STATE_DOWNLOAD_IDLE: u8 = 0x05;
 
 const USB_DFU_DETACH: u8 = 0;
-const USB_DFU_DNLOAD: u8 = 1;
-const USB_DFU_GETSTATUS: u8 = 3;
+pub(crate) const USB_DFU_DNLOAD: u8 = 1;
+pub(crate) const USB_DFU_GETSTATUS: u8 = 3;
 
 #[cfg(not(feature = "include_hyperdebug_firmware"))]
 const OFFICIAL_FIRMWARE: Option<&'static [u8]> = None;
@@ -160,14 +161,16 @@ fn get_hyperdebug_firmware_version(firmware: &[u8]) -> Result<&str> {
 }
 
 /// Helper method to perform flash programming using ST's DfuSe variant of the DFU protocol.
-/// This method is used both by the `Hyperdebug` and the `HyperdebugDfu` structs.
+/// This method is used both by the `Hyperdebug` and the `HyperdebugDfu` structs.  Returns
+/// whether the device was flashed, and has consequently restarted and re-enumerated, as opposed
+/// to already running the requested firmware version.
 pub fn update_firmware(
     usb_device: &mut UsbBackend,
     current_firmware_version: Option<&str>,
     firmware: &Option<Vec<u8>>,
     progress: &dyn ProgressIndicator,
     force: bool,
-) -> Result<Option<Box<dyn Annotate>>> {
+) -> Result<bool> {
     let firmware: &[u8] = if let Some(vec) = firmware.as_ref() {
         validate_firmware_image(vec)?;
         vec
@@ -183,7 +186,7 @@ pub fn update_firmware(
                     "HyperDebug already running firmware version {}.  Consider --force.",
                     new_version,
                 );
-                return Ok(None);
+                return Ok(false);
             }
         }
     }
@@ -196,7 +199,7 @@ pub fn update_firmware(
     if wait_for_idle(usb_device, dfu_desc.dfu_interface)? != DFU_STATE_APP_IDLE {
         // Device is already running DFU bootloader, proceed to firmware transfer.
         do_update_firmware(usb_device, dfu_desc, firmware, progress)?;
-        return Ok(None);
+        return Ok(true);
     }
 
     // Device is running the HyperDebug firmware, not DFU bootloader.  Ask for switch to
@@ -245,7 +248,7 @@ pub fn update_firmware(
         Some(usb_device.get_serial_number()),
     )
     .context("Unable to establish connection after flashing.  Possibly bad image.")?;
-    Ok(None)
+    Ok(true)
 }
 
 fn do_update_firmware(
@@ -419,45 +422,63 @@ fn scan_usb_descriptor(usb_device: &UsbBackend) -> Result<DfuDescriptor> {
     })
 }
 
+/// Response to the DFU GETSTATUS request.
+pub(crate) struct DfuStatus {
+    pub status: u8,
+    pub poll_timeout: std::time::Duration,
+    pub state: u8,
+}
+
+/// Issue a single DFU GETSTATUS request.
+pub(crate) fn get_status(dfu_device: &UsbBackend, dfu_interface: u8) -> Result<DfuStatus> {
+    let mut response = [0u8; 6];
+    let rc = dfu_device.read_control(
+        rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        ),
+        USB_DFU_GETSTATUS,
+        0,
+        dfu_interface as u16,
+        &mut response,
+    )?;
+    if rc != response.len() {
+        bail!(TransportError::FirmwareProgramFailed("".to_string()));
+    }
+    let poll_timeout_ms = u32::from_le_bytes([response[1], response[2], response[3], 0]);
+    Ok(DfuStatus {
+        status: response[0],
+        poll_timeout: std::time::Duration::from_millis(poll_timeout_ms as u64),
+        state: response[4],
+    })
+}
+
 /// Poll the bootloader using GETSTATUS request, until it leaves the "busy" state.
-fn wait_for_idle(dfu_device: &UsbBackend, dfu_interface: u8) -> Result<u8> {
+pub(crate) fn wait_for_idle(dfu_device: &UsbBackend, dfu_interface: u8) -> Result<u8> {
     loop {
-        let mut response = [0u8; 6];
-        let rc = dfu_device.read_control(
-            rusb::request_type(
-                rusb::Direction::In,
-                rusb::RequestType::Class,
-                rusb::Recipient::Interface,
-            ),
-            USB_DFU_GETSTATUS,
-            0,
-            dfu_interface as u16,
-            &mut response,
-        )?;
-        if rc != response.len() {
-            bail!(TransportError::FirmwareProgramFailed("".to_string()));
-        }
-        let command_status = response[0];
-        let minimum_delay_ms =
-            u64::from_le_bytes([response[1], response[2], response[3], 0, 0, 0, 0, 0]);
-        let device_state = response[4];
-        if command_status != DFU_STATUS_OK {
+        let DfuStatus {
+            status,
+            poll_timeout,
+            state,
+        } = get_status(dfu_device, dfu_interface)?;
+        if status != DFU_STATUS_OK {
             bail!(TransportError::FirmwareProgramFailed(format!(
                 "Unexpected DFU status {}",
-                response[0]
+                status
             )));
         }
-        if device_state == DFU_STATE_APP_IDLE
-            || device_state == DFU_STATE_DFU_IDLE
-            || device_state == DFU_STATE_DOWNLOAD_IDLE
+        if state == DFU_STATE_APP_IDLE
+            || state == DFU_STATE_DFU_IDLE
+            || state == DFU_STATE_DOWNLOAD_IDLE
         {
-            return Ok(device_state);
-        } else if device_state == DFU_STATE_DOWNLOAD_BUSY {
-            std::thread::sleep(std::time::Duration::from_millis(minimum_delay_ms));
+            return Ok(state);
+        } else if state == DFU_STATE_DOWNLOAD_BUSY {
+            std::thread::sleep(poll_timeout);
         } else {
             bail!(TransportError::FirmwareProgramFailed(format!(
                 "Unexpected DFU state {}",
-                response[4]
+                state
             )));
         }
     }
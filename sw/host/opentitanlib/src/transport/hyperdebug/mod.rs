@@ -18,6 +18,7 @@ use std::io::Write;
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use crate::debug::openocd::OpenOcdJtagChain;
 use crate::io::gpio::{GpioBitbanging, GpioMonitoring, GpioPin};
@@ -54,9 +55,9 @@ pub struct Hyperdebug<T: Flavor> {
     spi_interface: BulkInterface,
     i2c_interface: Option<BulkInterface>,
     cmsis_interface: Option<BulkInterface>,
-    uart_interfaces: HashMap<String, UartInterface>,
+    uart_interfaces: RefCell<HashMap<String, UartInterface>>,
     inner: Rc<Inner>,
-    current_firmware_version: Option<String>,
+    current_firmware_version: RefCell<Option<String>>,
     cmsis_google_capabilities: Cell<Option<u16>>,
     phantom: PhantomData<T>,
 }
@@ -95,7 +96,7 @@ pub const PID_HYPERDEBUG: u16 = 0x520e;
 
 /// Index of a single USB "interface", with its associated IN and OUT
 /// endpoints.  Used to instantiate e.g. SPI trait.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct BulkInterface {
     interface: u8,
     in_endpoint: u8,
@@ -113,6 +114,19 @@ impl UartInterface {
     }
 }
 
+/// USB interfaces found by `Hyperdebug::discover_interfaces()`.
+struct Interfaces {
+    firmware_version: Option<String>,
+    console_tty: PathBuf,
+    spi_interface: BulkInterface,
+    i2c_interface: Option<BulkInterface>,
+    cmsis_interface: Option<BulkInterface>,
+    uart_interfaces: HashMap<String, UartInterface>,
+}
+
+/// How long to wait for HyperDebug to reappear on the USB bus, when reconnecting.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
 impl<T: Flavor> Hyperdebug<T> {
     const USB_CLASS_VENDOR: u8 = 255;
     const USB_SUBCLASS_UART: u8 = 80;
@@ -146,7 +160,45 @@ impl<T: Flavor> Hyperdebug<T> {
             usb_pid.unwrap_or_else(T::get_default_usb_pid),
             usb_serial,
         )?;
+        let interfaces = Self::discover_interfaces(&mut device)?;
+        if let Some(current_firmware_version) = &interfaces.firmware_version {
+            if let Some(released_firmware_version) = dfu::official_firmware_version()? {
+                if T::perform_initial_fw_check()
+                    && *current_firmware_version != released_firmware_version
+                {
+                    log::warn!(
+                        "Current HyperDebug firmware version is {}, newest release is {}, Consider running `opentitantool transport update-firmware`",
+                        current_firmware_version,
+                        released_firmware_version,
+                    );
+                }
+            }
+        }
+        let result = Hyperdebug::<T> {
+            spi_interface: interfaces.spi_interface,
+            i2c_interface: interfaces.i2c_interface,
+            cmsis_interface: interfaces.cmsis_interface,
+            uart_interfaces: RefCell::new(interfaces.uart_interfaces),
+            inner: Rc::new(Inner {
+                console_tty: RefCell::new(interfaces.console_tty),
+                usb_device: RefCell::new(device),
+                gpio: Default::default(),
+                spis: Default::default(),
+                selected_spi: Cell::new(0),
+                i2cs_by_name: Default::default(),
+                i2cs_by_index: Default::default(),
+                uarts: Default::default(),
+            }),
+            current_firmware_version: RefCell::new(interfaces.firmware_version),
+            cmsis_google_capabilities: Cell::new(None),
+            phantom: PhantomData,
+        };
+        Ok(result)
+    }
 
+    /// Enumerates the USB interfaces of HyperDebug, and locates the TTY devices of the serial
+    /// interfaces among them.
+    fn discover_interfaces(device: &mut UsbBackend) -> Result<Interfaces> {
         let path = PathBuf::from("/sys/bus/usb/devices");
 
         let mut console_tty: Option<PathBuf> = None;
@@ -156,26 +208,9 @@ impl<T: Flavor> Hyperdebug<T> {
         let mut uart_interfaces: HashMap<String, UartInterface> = HashMap::new();
 
         let config_desc = device.active_config_descriptor()?;
-        let current_firmware_version = if let Some(idx) = config_desc.description_string_index() {
-            if let Ok(current_firmware_version) = device.read_string_descriptor_ascii(idx) {
-                if let Some(released_firmware_version) = dfu::official_firmware_version()? {
-                    if T::perform_initial_fw_check()
-                        && current_firmware_version != released_firmware_version
-                    {
-                        log::warn!(
-                            "Current HyperDebug firmware version is {}, newest release is {}, Consider running `opentitantool transport update-firmware`",
-                            current_firmware_version,
-                            released_firmware_version,
-                        );
-                    }
-                }
-                Some(current_firmware_version)
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        let firmware_version = config_desc
+            .description_string_index()
+            .and_then(|idx| device.read_string_descriptor_ascii(idx).ok());
         // Iterate through each USB interface, discovering e.g. supported UARTs.
         for interface in config_desc.interfaces() {
             for interface_desc in interface.descriptors() {
@@ -280,30 +315,18 @@ impl<T: Flavor> Hyperdebug<T> {
                 }
             }
         }
-        let result = Hyperdebug::<T> {
+        Ok(Interfaces {
+            firmware_version,
+            console_tty: console_tty.ok_or_else(|| {
+                TransportError::CommunicationError("Missing console interface".to_string())
+            })?,
             spi_interface: spi_interface.ok_or_else(|| {
                 TransportError::CommunicationError("Missing SPI interface".to_string())
             })?,
             i2c_interface,
             cmsis_interface,
             uart_interfaces,
-            inner: Rc::new(Inner {
-                console_tty: console_tty.ok_or_else(|| {
-                    TransportError::CommunicationError("Missing console interface".to_string())
-                })?,
-                usb_device: RefCell::new(device),
-                gpio: Default::default(),
-                spis: Default::default(),
-                selected_spi: Cell::new(0),
-                i2cs_by_name: Default::default(),
-                i2cs_by_index: Default::default(),
-                uarts: Default::default(),
-            }),
-            current_firmware_version,
-            cmsis_google_capabilities: Cell::new(None),
-            phantom: PhantomData,
-        };
-        Ok(result)
+        })
     }
 
     /// Locates the /dev/ttyUSBn node corresponding to a given interface in the sys directory
@@ -416,14 +439,14 @@ impl<T: Flavor> Hyperdebug<T> {
 /// Spi and Uart sub-structs can all refer to this shared data, which is guaranteed to live on,
 /// even if the caller lets the outer Hyperdebug struct run out of scope.
 pub struct Inner {
-    console_tty: PathBuf,
+    console_tty: RefCell<PathBuf>,
     usb_device: RefCell<UsbBackend>,
     gpio: RefCell<HashMap<String, Rc<dyn GpioPin>>>,
     spis: RefCell<HashMap<u8, Rc<dyn Target>>>,
     selected_spi: Cell<u8>,
     i2cs_by_name: RefCell<HashMap<String, Rc<dyn Bus>>>,
     i2cs_by_index: RefCell<HashMap<u8, Rc<dyn Bus>>>,
-    uarts: RefCell<HashMap<PathBuf, Rc<dyn Uart>>>,
+    uarts: RefCell<HashMap<u8, Rc<uart::HyperdebugUart>>>,
 }
 
 impl Inner {
@@ -497,8 +520,8 @@ impl Inner {
 
     /// Send a command to HyperDebug firmware, with a callback to receive any output.
     fn execute_command(&self, cmd: &str, mut callback: impl FnMut(&str)) -> Result<()> {
-        let port_name = self
-            .console_tty
+        let console_tty = self.console_tty.borrow();
+        let port_name = console_tty
             .to_str()
             .ok_or(TransportError::UnicodePathError)?;
         let mut port = TTYPort::open(
@@ -592,6 +615,46 @@ impl<T: Flavor> Transport for Hyperdebug<T> {
         self.inner.cmd_no_output("reinit")
     }
 
+    fn reconnect(&self) -> Result<()> {
+        let deadline = Instant::now() + RECONNECT_TIMEOUT;
+        let mut usb_device = self.inner.usb_device.borrow_mut();
+        usb_device.reconnect(RECONNECT_TIMEOUT)?;
+        // TTY devices appear some time after the USB device.
+        let interfaces = loop {
+            match Self::discover_interfaces(&mut usb_device) {
+                Ok(interfaces) => break interfaces,
+                Err(_) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(100))
+                }
+                Err(e) => return Err(e),
+            }
+        };
+        drop(usb_device);
+        // The interface numbers are recorded in existing SPI and I2C instances.
+        ensure!(
+            interfaces.spi_interface == self.spi_interface
+                && interfaces.i2c_interface == self.i2c_interface
+                && interfaces.cmsis_interface == self.cmsis_interface,
+            TransportError::CommunicationError(
+                "USB interfaces changed after reconnecting".to_string()
+            )
+        );
+        // TTY devices may have been renamed, switch existing UART instances to the new ones.
+        for uart_interface in interfaces.uart_interfaces.values() {
+            if let Some(uart) = self.inner.uarts.borrow().get(&uart_interface.interface) {
+                uart.reopen(&uart_interface.tty)?;
+            }
+        }
+        *self.uart_interfaces.borrow_mut() = interfaces.uart_interfaces;
+        *self.inner.console_tty.borrow_mut() = interfaces.console_tty;
+        *self.current_firmware_version.borrow_mut() = interfaces.firmware_version;
+        self.cmsis_google_capabilities.set(None);
+        // HyperDebug has forgotten which SPI bus was selected, make the next SPI transaction
+        // select it again.
+        self.inner.selected_spi.set(u8::MAX);
+        Ok(())
+    }
+
     // Create SPI Target instance, or return one from a cache of previously created instances.
     fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
         let (enable_cmd, idx) = T::spi_index(&self.inner, instance)?;
@@ -666,14 +729,14 @@ impl<T: Flavor> Transport for Hyperdebug<T> {
 
     // Create Uart instance, or return one from a cache of previously created instances.
     fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
-        match self.uart_interfaces.get(instance) {
+        match self.uart_interfaces.borrow().get(instance) {
             Some(uart_interface) => {
-                if let Some(instance) = self.inner.uarts.borrow().get(&uart_interface.tty) {
-                    return Ok(Rc::clone(instance));
+                if let Some(instance) = self.inner.uarts.borrow().get(&uart_interface.interface) {
+                    return Ok(Rc::clone(instance) as Rc<dyn Uart>);
                 }
                 let supports_clearing_queues =
                     self.get_cmsis_google_capabilities()? & Self::GOOGLE_CAP_UART_QUEUE_CLEAR != 0;
-                let instance: Rc<dyn Uart> = Rc::new(uart::HyperdebugUart::open(
+                let instance = Rc::new(uart::HyperdebugUart::open(
                     &self.inner,
                     uart_interface,
                     supports_clearing_queues,
@@ -681,8 +744,8 @@ impl<T: Flavor> Transport for Hyperdebug<T> {
                 self.inner
                     .uarts
                     .borrow_mut()
-                    .insert(uart_interface.tty.clone(), Rc::clone(&instance));
-                Ok(instance)
+                    .insert(uart_interface.interface, Rc::clone(&instance));
+                Ok(instance as Rc<dyn Uart>)
             }
             _ => Err(TransportError::InvalidInstance(
                 TransportInterfaceType::Uart,
@@ -745,13 +808,19 @@ impl<T: Flavor> Transport for Hyperdebug<T> {
 
     fn dispatch(&self, action: &dyn Any) -> Result<Option<Box<dyn Annotate>>> {
         if let Some(update_firmware_action) = action.downcast_ref::<UpdateFirmware>() {
-            dfu::update_firmware(
+            let flashed = dfu::update_firmware(
                 &mut self.inner.usb_device.borrow_mut(),
-                self.current_firmware_version.as_deref(),
+                self.current_firmware_version.borrow().as_deref(),
                 &update_firmware_action.firmware,
                 update_firmware_action.progress.as_ref(),
                 update_firmware_action.force,
-            )
+            )?;
+            if flashed {
+                // HyperDebug has restarted with the new firmware, establish new connection, such
+                // that this object remains usable.
+                self.reconnect()?;
+            }
+            Ok(None)
         } else if let Some(fpga_program) = action.downcast_ref::<FpgaProgram>() {
             T::load_bitstream(fpga_program).map(|_| None)
         } else if let Some(clear) = action.downcast_ref::<ClearBitstream>() {
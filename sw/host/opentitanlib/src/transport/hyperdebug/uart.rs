@@ -2,6 +2,7 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::path::Path;
 use std::rc::Rc;
 use std::time::Duration;
 
@@ -63,6 +64,16 @@ impl HyperdebugUart {
     }
 }
 
+impl HyperdebugUart {
+    /// Switches to the given TTY device, after HyperDebug has re-enumerated.
+    pub fn reopen(&self, tty: &Path) -> Result<()> {
+        self.serial_port.reopen(
+            tty.to_str().ok_or(TransportError::UnicodePathError)?,
+            UART_BAUD,
+        )
+    }
+}
+
 impl Uart for HyperdebugUart {
     fn get_baudrate(&self) -> Result<u32> {
         let usb_handle = self.inner.usb_device.borrow();
@@ -0,0 +1,291 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Context, Result};
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::fs::File;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use zerocopy::FromZeroes;
+
+use super::{find_device, open_device};
+use crate::io::gpio::{GpioError, GpioPin, PinMode, PullMode};
+use crate::util::parse_int::ParseInt;
+
+// Definitions from the version 2 GPIO character device API, see `include/uapi/linux/gpio.h`
+// in the Linux source tree.
+const GPIO_MAX_NAME_SIZE: usize = 32;
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+const GPIO_V2_LINE_FLAG_OPEN_DRAIN: u64 = 1 << 6;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+const GPIO_V2_LINE_FLAG_BIAS_DISABLED: u64 = 1 << 10;
+
+const GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+
+#[repr(C)]
+#[derive(FromZeroes)]
+struct GpioChipInfo {
+    name: [u8; GPIO_MAX_NAME_SIZE],
+    label: [u8; GPIO_MAX_NAME_SIZE],
+    lines: u32,
+}
+
+#[repr(C)]
+#[derive(FromZeroes)]
+struct GpioV2LineAttribute {
+    id: u32,
+    padding: u32,
+    // Union of `flags`, `values` and `debounce_period_us`.
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(FromZeroes)]
+struct GpioV2LineConfigAttribute {
+    attr: GpioV2LineAttribute,
+    mask: u64,
+}
+
+#[repr(C)]
+#[derive(FromZeroes)]
+struct GpioV2LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    padding: [u32; 5],
+    attrs: [GpioV2LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+#[derive(FromZeroes)]
+struct GpioV2LineRequest {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    config: GpioV2LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    padding: [u32; 5],
+    fd: i32,
+}
+
+#[repr(C)]
+#[derive(FromZeroes)]
+struct GpioV2LineInfo {
+    name: [u8; GPIO_MAX_NAME_SIZE],
+    consumer: [u8; GPIO_MAX_NAME_SIZE],
+    offset: u32,
+    num_attrs: u32,
+    flags: u64,
+    attrs: [GpioV2LineAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+    padding: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(FromZeroes)]
+struct GpioV2LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+nix::ioctl_read!(gpio_get_chipinfo, 0xB4, 0x01, GpioChipInfo);
+nix::ioctl_readwrite!(gpio_v2_get_lineinfo, 0xB4, 0x05, GpioV2LineInfo);
+nix::ioctl_readwrite!(gpio_v2_get_line, 0xB4, 0x07, GpioV2LineRequest);
+nix::ioctl_readwrite!(gpio_v2_line_set_config, 0xB4, 0x0D, GpioV2LineConfig);
+nix::ioctl_readwrite!(gpio_v2_line_get_values, 0xB4, 0x0E, GpioV2LineValues);
+nix::ioctl_readwrite!(gpio_v2_line_set_values, 0xB4, 0x0F, GpioV2LineValues);
+
+/// Interprets a NUL-padded name from the kernel.
+fn c_name(name: &[u8]) -> &str {
+    CStr::from_bytes_until_nul(name)
+        .ok()
+        .and_then(|name| name.to_str().ok())
+        .unwrap_or("")
+}
+
+fn line_info(chip: &File, offset: u32) -> Result<GpioV2LineInfo> {
+    let mut info = GpioV2LineInfo::new_zeroed();
+    info.offset = offset;
+    unsafe { gpio_v2_get_lineinfo(chip.as_raw_fd(), &mut info) }?;
+    Ok(info)
+}
+
+/// Locates a line by its `gpiochip<n>:<offset>` designation, or by its name, returning the
+/// open GPIO chip, and the offset of the line within it.
+fn find_line(dev_dir: &Path, instance: &str) -> Result<(File, u32)> {
+    if let Some((chip, offset)) = instance.split_once(':') {
+        let offset = u32::from_str(offset)
+            .with_context(|| format!("invalid line offset in {instance:?}"))?;
+        return Ok((open_device(&find_device(&dev_dir.join(chip)))?, offset));
+    }
+    let mut chips: Vec<PathBuf> = std::fs::read_dir(dev_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("gpiochip"))
+        .map(|entry| entry.path())
+        .collect();
+    chips.sort();
+    for path in chips {
+        let chip = open_device(&path)?;
+        let mut info = GpioChipInfo::new_zeroed();
+        unsafe { gpio_get_chipinfo(chip.as_raw_fd(), &mut info) }?;
+        for offset in 0..info.lines {
+            if c_name(&line_info(&chip, offset)?.name).eq_ignore_ascii_case(instance) {
+                return Ok((chip, offset));
+            }
+        }
+    }
+    bail!(GpioError::InvalidPinName(instance.to_string()))
+}
+
+/// A single line of a GPIO chip, requested through the GPIO character device for as long as
+/// this object exists.
+pub struct LinuxGpioPin {
+    name: String,
+    request: File,
+    mode: Cell<PinMode>,
+    // `None` if the bias of the line is unknown, and has not been set.
+    pull: Cell<Option<PullMode>>,
+    // Level to drive while in output mode.  Retained while the pin is an input.
+    value: Cell<bool>,
+}
+
+impl LinuxGpioPin {
+    pub fn open(dev_dir: &Path, instance: &str) -> Result<Self> {
+        let (chip, offset) = find_line(dev_dir, instance)?;
+        let info = line_info(&chip, offset)?;
+        let mode = if info.flags & GPIO_V2_LINE_FLAG_OUTPUT == 0 {
+            PinMode::Input
+        } else if info.flags & GPIO_V2_LINE_FLAG_OPEN_DRAIN != 0 {
+            PinMode::OpenDrain
+        } else {
+            PinMode::PushPull
+        };
+        let pull = if info.flags & GPIO_V2_LINE_FLAG_BIAS_PULL_UP != 0 {
+            Some(PullMode::PullUp)
+        } else if info.flags & GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN != 0 {
+            Some(PullMode::PullDown)
+        } else if info.flags & GPIO_V2_LINE_FLAG_BIAS_DISABLED != 0 {
+            Some(PullMode::None)
+        } else {
+            None
+        };
+
+        // Request the line without any flags, leaving its configuration as it is, until
+        // changed through this object.
+        let mut req = GpioV2LineRequest::new_zeroed();
+        req.offsets[0] = offset;
+        req.num_lines = 1;
+        let consumer = b"opentitanlib";
+        req.consumer[..consumer.len()].copy_from_slice(consumer);
+        unsafe { gpio_v2_get_line(chip.as_raw_fd(), &mut req) }
+            .with_context(|| format!("requesting GPIO line {instance:?}"))?;
+        let pin = Self {
+            name: instance.to_string(),
+            request: unsafe { File::from_raw_fd(req.fd) },
+            mode: Cell::new(mode),
+            pull: Cell::new(pull),
+            value: Cell::new(false),
+        };
+        pin.value.set(pin.read()?);
+        Ok(pin)
+    }
+
+    /// Reconfigures the line according to `mode`, `pull` and `value`, in one operation.
+    fn configure(&self, mode: PinMode, pull: Option<PullMode>, value: bool) -> Result<()> {
+        let mut config = GpioV2LineConfig::new_zeroed();
+        config.flags = match mode {
+            PinMode::Input => GPIO_V2_LINE_FLAG_INPUT,
+            PinMode::PushPull => GPIO_V2_LINE_FLAG_OUTPUT,
+            PinMode::OpenDrain => GPIO_V2_LINE_FLAG_OUTPUT | GPIO_V2_LINE_FLAG_OPEN_DRAIN,
+            _ => bail!(GpioError::UnsupportedPinMode(mode)),
+        };
+        config.flags |= match pull {
+            None => 0,
+            Some(PullMode::None) => GPIO_V2_LINE_FLAG_BIAS_DISABLED,
+            Some(PullMode::PullUp) => GPIO_V2_LINE_FLAG_BIAS_PULL_UP,
+            Some(PullMode::PullDown) => GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN,
+        };
+        if mode != PinMode::Input {
+            config.num_attrs = 1;
+            config.attrs[0].attr.id = GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES;
+            config.attrs[0].attr.value = value as u64;
+            config.attrs[0].mask = 1;
+        }
+        unsafe { gpio_v2_line_set_config(self.request.as_raw_fd(), &mut config) }
+            .with_context(|| format!("configuring GPIO line {:?}", self.name))?;
+        self.mode.set(mode);
+        self.pull.set(pull);
+        self.value.set(value);
+        Ok(())
+    }
+}
+
+impl GpioPin for LinuxGpioPin {
+    fn read(&self) -> Result<bool> {
+        let mut values = GpioV2LineValues { bits: 0, mask: 1 };
+        unsafe { gpio_v2_line_get_values(self.request.as_raw_fd(), &mut values) }?;
+        Ok(values.bits & 1 != 0)
+    }
+
+    /// Sets the level driven by the pin, which takes effect once in output mode, if currently
+    /// an input.
+    fn write(&self, value: bool) -> Result<()> {
+        if self.mode.get() != PinMode::Input {
+            let mut values = GpioV2LineValues {
+                bits: value as u64,
+                mask: 1,
+            };
+            unsafe { gpio_v2_line_set_values(self.request.as_raw_fd(), &mut values) }?;
+        }
+        self.value.set(value);
+        Ok(())
+    }
+
+    fn set_mode(&self, mode: PinMode) -> Result<()> {
+        self.configure(mode, self.pull.get(), self.value.get())
+    }
+
+    fn set_pull_mode(&self, mode: PullMode) -> Result<()> {
+        self.configure(self.mode.get(), Some(mode), self.value.get())
+    }
+
+    fn set(
+        &self,
+        mode: Option<PinMode>,
+        value: Option<bool>,
+        pull: Option<PullMode>,
+        analog_value: Option<f32>,
+    ) -> Result<()> {
+        if analog_value.is_some() {
+            bail!(GpioError::UnsupportedPinMode(PinMode::AnalogOutput));
+        }
+        self.configure(
+            mode.unwrap_or(self.mode.get()),
+            pull.or(self.pull.get()),
+            value.unwrap_or(self.value.get()),
+        )
+    }
+
+    fn get_internal_pin_name(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_struct_layout() {
+        // Must match the structures of the kernel.
+        assert_eq!(std::mem::size_of::<GpioChipInfo>(), 68);
+        assert_eq!(std::mem::size_of::<GpioV2LineConfig>(), 272);
+        assert_eq!(std::mem::size_of::<GpioV2LineRequest>(), 592);
+        assert_eq!(std::mem::size_of::<GpioV2LineInfo>(), 256);
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Context, Result};
+use std::cell::Cell;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use super::open_device;
+use crate::io::i2c::{Bus, I2cError, Transfer};
+use crate::transport::TransportError;
+
+// Definitions from `include/uapi/linux/i2c.h` and `include/uapi/linux/i2c-dev.h` in the Linux
+// source tree.
+const I2C_M_RD: u16 = 0x0001;
+const I2C_RDWR_IOCTL_MAX_MSGS: usize = 42;
+
+#[repr(C)]
+struct I2cMsg {
+    addr: u16,
+    flags: u16,
+    len: u16,
+    buf: *mut u8,
+}
+
+#[repr(C)]
+struct I2cRdwrIoctlData {
+    msgs: *mut I2cMsg,
+    nmsgs: u32,
+}
+
+nix::ioctl_write_ptr_bad!(i2c_rdwr, 0x0707, I2cRdwrIoctlData);
+
+/// I2C bus accessed through i2c-dev, such as `/dev/i2c-1`.
+pub struct LinuxI2c {
+    device: File,
+    // Devicetree property holding the bus frequency, if any.
+    clock_frequency: PathBuf,
+    default_addr: Cell<Option<u8>>,
+}
+
+impl LinuxI2c {
+    pub fn open(path: &Path) -> Result<Self> {
+        let name = path.file_name().unwrap_or_default();
+        Ok(Self {
+            device: open_device(path)?,
+            clock_frequency: Path::new("/sys/class/i2c-adapter")
+                .join(name)
+                .join("of_node/clock-frequency"),
+            default_addr: Cell::new(None),
+        })
+    }
+}
+
+impl Bus for LinuxI2c {
+    /// Reports the bus frequency configured in the devicetree, i2c-dev offers no means of
+    /// querying or changing it.
+    fn get_max_speed(&self) -> Result<u32> {
+        let Ok(data) = std::fs::read(&self.clock_frequency) else {
+            bail!(TransportError::UnsupportedOperation);
+        };
+        let data: [u8; 4] = data
+            .as_slice()
+            .try_into()
+            .with_context(|| format!("reading {}", self.clock_frequency.display()))?;
+        Ok(u32::from_be_bytes(data))
+    }
+
+    /// Succeeds only if the bus is known to run no faster than `max_speed`.
+    fn set_max_speed(&self, max_speed: u32) -> Result<()> {
+        ensure!(
+            self.get_max_speed()? <= max_speed,
+            TransportError::UnsupportedOperation
+        );
+        Ok(())
+    }
+
+    fn set_default_address(&self, addr: u8) -> Result<()> {
+        self.default_addr.set(Some(addr));
+        Ok(())
+    }
+
+    fn run_transaction(&self, addr: Option<u8>, transaction: &mut [Transfer]) -> Result<()> {
+        let addr = addr
+            .or(self.default_addr.get())
+            .ok_or(I2cError::MissingAddress)?;
+        // The kernel limits the number of messages in one combined transaction, splitting it
+        // would put stop conditions where the caller expects repeated starts.
+        ensure!(
+            transaction.len() <= I2C_RDWR_IOCTL_MAX_MSGS,
+            I2cError::Generic(format!(
+                "At most {I2C_RDWR_IOCTL_MAX_MSGS} transfers per transaction"
+            ))
+        );
+        let mut msgs = Vec::with_capacity(transaction.len());
+        for transfer in transaction.iter_mut() {
+            let (flags, buf, len) = match transfer {
+                Transfer::Read(rbuf) => (I2C_M_RD, rbuf.as_mut_ptr(), rbuf.len()),
+                // The kernel does not write to the buffer of write messages.
+                Transfer::Write(wbuf) => (0, wbuf.as_ptr() as *mut u8, wbuf.len()),
+            };
+            msgs.push(I2cMsg {
+                addr: addr as u16,
+                flags,
+                len: u16::try_from(len).map_err(|_| I2cError::InvalidDataLength(len))?,
+                buf,
+            });
+        }
+        let data = I2cRdwrIoctlData {
+            msgs: msgs.as_mut_ptr(),
+            nmsgs: msgs.len() as u32,
+        };
+        unsafe { i2c_rdwr(self.device.as_raw_fd(), &data) }.context("I2C transaction")?;
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transport driving the target through the peripherals of the Linux machine running
+//! opentitantool, as found on Raspberry Pi-class hosts.  SPI, I2C, GPIO and UART instances are
+//! named by their device nodes:
+//!
+//! - SPI: `spidev<bus>.<cs>`, using the spidev driver.
+//! - I2C: `i2c-<bus>`, using the i2c-dev driver.
+//! - GPIO: `gpiochip<n>:<offset>`, or the name of a line of any GPIO chip, using the GPIO
+//!   character device.
+//! - UART: `ttyUSB0`, `ttyAMA0`, etc.
+//!
+//! Names starting with `/` are taken to be the full path of the device node, which
+//! `TransportWrapper` passes on unchanged.  Other names are converted to uppercase by
+//! `TransportWrapper`, so the device node is looked up in `/dev` without regard to case, as are
+//! GPIO line names.  Configuration files can assign meaningful names to these instances using
+//! aliases.
+
+use anyhow::{ensure, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::io::gpio::GpioPin;
+use crate::io::i2c::Bus;
+use crate::io::spi::Target;
+use crate::io::uart::Uart;
+use crate::transport::common::uart::SerialPortUart;
+use crate::transport::{
+    Capabilities, Capability, Transport, TransportError, TransportInterfaceType,
+};
+
+mod gpio;
+mod i2c;
+mod spi;
+
+use crate::transport::linux::gpio::LinuxGpioPin;
+use crate::transport::linux::i2c::LinuxI2c;
+use crate::transport::linux::spi::LinuxSpi;
+
+/// Options for the Linux transport.
+pub struct Options {
+    /// Directory in which device nodes are looked up.
+    pub dev_dir: PathBuf,
+    /// Initial baud rate of UARTs.
+    pub uart_baud: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            dev_dir: PathBuf::from("/dev"),
+            uart_baud: 115200,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    spi: HashMap<PathBuf, Rc<dyn Target>>,
+    i2c: HashMap<PathBuf, Rc<dyn Bus>>,
+    uart: HashMap<PathBuf, Rc<dyn Uart>>,
+    gpio: HashMap<String, Rc<dyn GpioPin>>,
+}
+
+pub struct Linux {
+    options: Options,
+    inner: RefCell<Inner>,
+}
+
+impl Linux {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            inner: RefCell::default(),
+        }
+    }
+
+    /// Returns the path of the device node of the given instance, after verifying that the
+    /// name of the device node starts with `prefix`.
+    fn device_path(
+        &self,
+        interface: TransportInterfaceType,
+        instance: &str,
+        prefix: &str,
+    ) -> Result<PathBuf> {
+        let path = find_device(&self.options.dev_dir.join(instance));
+        let name = path.file_name().and_then(|name| name.to_str());
+        ensure!(
+            !instance.is_empty()
+                && name.map_or(false, |name| name.to_lowercase().starts_with(prefix)),
+            TransportError::InvalidInstance(interface, instance.to_string())
+        );
+        Ok(path)
+    }
+}
+
+/// Returns the path of the device node matching `path` without regard to case, or `path` itself
+/// if there is none.
+fn find_device(path: &Path) -> PathBuf {
+    if path.exists() {
+        return path.to_path_buf();
+    }
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return path.to_path_buf();
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return path.to_path_buf();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .find(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .eq_ignore_ascii_case(name)
+        })
+        .map_or_else(|| path.to_path_buf(), |entry| entry.path())
+}
+
+/// Opens a device node for reading and writing, reporting which one failed to open.
+fn open_device(path: &Path) -> Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| TransportError::OpenError(path.display().to_string(), e.to_string()).into())
+}
+
+impl Transport for Linux {
+    fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::new(
+            Capability::UART | Capability::GPIO | Capability::SPI | Capability::I2C,
+        ))
+    }
+
+    fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+        let path = self.device_path(TransportInterfaceType::Spi, instance, "spidev")?;
+        let mut inner = self.inner.borrow_mut();
+        if let Some(spi) = inner.spi.get(&path) {
+            return Ok(Rc::clone(spi));
+        }
+        let spi: Rc<dyn Target> = Rc::new(LinuxSpi::open(&path)?);
+        inner.spi.insert(path, Rc::clone(&spi));
+        Ok(spi)
+    }
+
+    fn i2c(&self, instance: &str) -> Result<Rc<dyn Bus>> {
+        let path = self.device_path(TransportInterfaceType::I2c, instance, "i2c-")?;
+        let mut inner = self.inner.borrow_mut();
+        if let Some(i2c) = inner.i2c.get(&path) {
+            return Ok(Rc::clone(i2c));
+        }
+        let i2c: Rc<dyn Bus> = Rc::new(LinuxI2c::open(&path)?);
+        inner.i2c.insert(path, Rc::clone(&i2c));
+        Ok(i2c)
+    }
+
+    fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
+        let path = self.device_path(TransportInterfaceType::Uart, instance, "tty")?;
+        let mut inner = self.inner.borrow_mut();
+        if let Some(uart) = inner.uart.get(&path) {
+            return Ok(Rc::clone(uart));
+        }
+        let port_name = path.to_str().ok_or(TransportError::UnicodePathError)?;
+        let uart: Rc<dyn Uart> = Rc::new(SerialPortUart::open(port_name, self.options.uart_baud)?);
+        inner.uart.insert(path, Rc::clone(&uart));
+        Ok(uart)
+    }
+
+    fn gpio_pin(&self, instance: &str) -> Result<Rc<dyn GpioPin>> {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(pin) = inner.gpio.get(instance) {
+            return Ok(Rc::clone(pin));
+        }
+        let pin: Rc<dyn GpioPin> = Rc::new(LinuxGpioPin::open(&self.options.dev_dir, instance)?);
+        inner.gpio.insert(instance.to_string(), Rc::clone(&pin));
+        Ok(pin)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_device_path() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("linux_dev_test_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Nodes"))?;
+        for name in ["spidev0.0", "ttyUSB0", "Nodes/ttyACM0"] {
+            std::fs::write(dir.join(name), b"")?;
+        }
+        let linux = Linux::new(Options {
+            dev_dir: dir.clone(),
+            ..Default::default()
+        });
+        let spi =
+            |instance: &str| linux.device_path(TransportInterfaceType::Spi, instance, "spidev");
+        let uart =
+            |instance: &str| linux.device_path(TransportInterfaceType::Uart, instance, "tty");
+
+        // Names as converted to uppercase by `TransportWrapper`.
+        assert_eq!(spi("SPIDEV0.0")?, dir.join("spidev0.0"));
+        assert_eq!(uart("TTYUSB0")?, dir.join("ttyUSB0"));
+        assert!(uart("SPIDEV0.0").is_err());
+        // Absolute paths, which are passed on unchanged.
+        let absolute = dir.join("Nodes/ttyACM0");
+        assert_eq!(uart(absolute.to_str().unwrap())?, absolute);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
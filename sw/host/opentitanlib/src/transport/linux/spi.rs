@@ -0,0 +1,240 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{ensure, Context, Result};
+use std::cell::Cell;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::rc::Rc;
+use zerocopy::FromZeroes;
+
+use super::open_device;
+use crate::io::spi::{
+    AssertChipSelect, MaxSizes, SpiError, Target, TargetChipDeassert, Transfer, TransferMode,
+};
+
+// Definitions from `include/uapi/linux/spi/spidev.h` in the Linux source tree.
+const SPI_CPHA: u8 = 0x01;
+const SPI_CPOL: u8 = 0x02;
+
+#[repr(C)]
+#[derive(FromZeroes)]
+struct SpiIocTransfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+nix::ioctl_write_buf!(spi_ioc_message, b'k', 0, SpiIocTransfer);
+nix::ioctl_read!(spi_ioc_rd_mode, b'k', 1, u8);
+nix::ioctl_write_ptr!(spi_ioc_wr_mode, b'k', 1, u8);
+nix::ioctl_read!(spi_ioc_rd_bits_per_word, b'k', 3, u8);
+nix::ioctl_read!(spi_ioc_rd_max_speed_hz, b'k', 4, u32);
+nix::ioctl_write_ptr!(spi_ioc_wr_max_speed_hz, b'k', 4, u32);
+
+/// The ioctl request encodes the size of the array of transfers in 14 bits.
+const MAX_TRANSFER_COUNT: usize = ((1 << 14) - 1) / std::mem::size_of::<SpiIocTransfer>();
+
+/// Default of the `bufsiz` parameter of the spidev module, limiting the total size of data
+/// in each direction of a transaction.
+const DEFAULT_BUFSIZ: usize = 4096;
+
+/// SPI bus accessed through spidev, such as `/dev/spidev0.0`.
+pub struct LinuxSpi {
+    device: File,
+    bufsiz: usize,
+    // Number of outstanding `AssertChipSelect` objects.
+    cs_asserted: Cell<usize>,
+}
+
+impl LinuxSpi {
+    pub fn open(path: &Path) -> Result<Self> {
+        let bufsiz = match std::fs::read_to_string("/sys/module/spidev/parameters/bufsiz") {
+            Ok(bufsiz) => bufsiz
+                .trim()
+                .parse()
+                .context("parsing spidev bufsiz parameter")?,
+            Err(_) => DEFAULT_BUFSIZ,
+        };
+        Ok(Self {
+            device: open_device(path)?,
+            bufsiz,
+            cs_asserted: Cell::new(0),
+        })
+    }
+
+    /// Submits the transfers as one message.  Chip select is kept asserted between the
+    /// transfers, and, if `keep_cs` is set, after the last one.
+    fn submit(&self, transfers: &mut [SpiIocTransfer], keep_cs: bool) -> Result<()> {
+        if let Some(last) = transfers.last_mut() {
+            // On the last transfer of a message, `cs_change` instructs the driver to leave chip
+            // select asserted until the next message.
+            last.cs_change = keep_cs as u8;
+        }
+        unsafe { spi_ioc_message(self.device.as_raw_fd(), transfers) }.context("SPI transfer")?;
+        Ok(())
+    }
+}
+
+impl Target for LinuxSpi {
+    fn get_transfer_mode(&self) -> Result<TransferMode> {
+        let mut mode = 0u8;
+        unsafe { spi_ioc_rd_mode(self.device.as_raw_fd(), &mut mode) }?;
+        Ok(match mode & (SPI_CPOL | SPI_CPHA) {
+            0 => TransferMode::Mode0,
+            SPI_CPHA => TransferMode::Mode1,
+            SPI_CPOL => TransferMode::Mode2,
+            _ => TransferMode::Mode3,
+        })
+    }
+
+    fn set_transfer_mode(&self, mode: TransferMode) -> Result<()> {
+        let mut value = 0u8;
+        unsafe { spi_ioc_rd_mode(self.device.as_raw_fd(), &mut value) }?;
+        value &= !(SPI_CPOL | SPI_CPHA);
+        value |= match mode {
+            TransferMode::Mode0 => 0,
+            TransferMode::Mode1 => SPI_CPHA,
+            TransferMode::Mode2 => SPI_CPOL,
+            TransferMode::Mode3 => SPI_CPOL | SPI_CPHA,
+        };
+        unsafe { spi_ioc_wr_mode(self.device.as_raw_fd(), &value) }?;
+        Ok(())
+    }
+
+    fn get_bits_per_word(&self) -> Result<u32> {
+        let mut bits_per_word = 0u8;
+        unsafe { spi_ioc_rd_bits_per_word(self.device.as_raw_fd(), &mut bits_per_word) }?;
+        // Zero means the default of eight bits.
+        Ok(match bits_per_word {
+            0 => 8,
+            n => n as u32,
+        })
+    }
+
+    fn set_bits_per_word(&self, bits_per_word: u32) -> Result<()> {
+        match bits_per_word {
+            8 => Ok(()),
+            _ => Err(SpiError::InvalidWordSize(bits_per_word).into()),
+        }
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        let mut speed = 0u32;
+        unsafe { spi_ioc_rd_max_speed_hz(self.device.as_raw_fd(), &mut speed) }?;
+        Ok(speed)
+    }
+
+    fn set_max_speed(&self, max_speed: u32) -> Result<()> {
+        unsafe { spi_ioc_wr_max_speed_hz(self.device.as_raw_fd(), &max_speed) }
+            .map_err(|_| SpiError::InvalidSpeed(max_speed))?;
+        Ok(())
+    }
+
+    fn supports_bidirectional_transfer(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn get_max_transfer_count(&self) -> Result<usize> {
+        Ok(MAX_TRANSFER_COUNT)
+    }
+
+    fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        Ok(MaxSizes {
+            read: self.bufsiz,
+            write: self.bufsiz,
+        })
+    }
+
+    fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+        ensure!(
+            transaction.len() <= MAX_TRANSFER_COUNT,
+            SpiError::InvalidOption(format!("more than {MAX_TRANSFER_COUNT} transfers"))
+        );
+        let mut transfers = Vec::with_capacity(transaction.len());
+        let (mut read_len, mut write_len) = (0, 0);
+        for transfer in transaction.iter_mut() {
+            let mut xfer = SpiIocTransfer::new_zeroed();
+            match transfer {
+                Transfer::Read(rbuf) => {
+                    xfer.rx_buf = rbuf.as_mut_ptr() as u64;
+                    xfer.len = rbuf.len() as u32;
+                    read_len += rbuf.len();
+                }
+                Transfer::Write(wbuf) => {
+                    xfer.tx_buf = wbuf.as_ptr() as u64;
+                    xfer.len = wbuf.len() as u32;
+                    write_len += wbuf.len();
+                }
+                Transfer::Both(wbuf, rbuf) => {
+                    ensure!(
+                        rbuf.len() == wbuf.len(),
+                        SpiError::MismatchedDataLength(wbuf.len(), rbuf.len())
+                    );
+                    xfer.tx_buf = wbuf.as_ptr() as u64;
+                    xfer.rx_buf = rbuf.as_mut_ptr() as u64;
+                    xfer.len = wbuf.len() as u32;
+                    read_len += rbuf.len();
+                    write_len += wbuf.len();
+                }
+            }
+            transfers.push(xfer);
+        }
+        ensure!(
+            read_len <= self.bufsiz,
+            SpiError::InvalidDataLength(read_len)
+        );
+        ensure!(
+            write_len <= self.bufsiz,
+            SpiError::InvalidDataLength(write_len)
+        );
+        if transfers.is_empty() {
+            return Ok(());
+        }
+        self.submit(&mut transfers, self.cs_asserted.get() > 0)
+    }
+
+    fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+        // Chip select is asserted along with the first transaction, and kept asserted after
+        // each transaction as long as any `AssertChipSelect` object exists.
+        self.cs_asserted.set(self.cs_asserted.get() + 1);
+        Ok(AssertChipSelect::new(self))
+    }
+}
+
+impl TargetChipDeassert for LinuxSpi {
+    fn deassert_cs(&self) {
+        let count = self.cs_asserted.get() - 1;
+        self.cs_asserted.set(count);
+        if count == 0 {
+            // spidev offers no way of deasserting chip select by itself, submit an empty
+            // transfer, after which it is deasserted.
+            let mut transfers = [SpiIocTransfer::new_zeroed()];
+            if let Err(e) = self.submit(&mut transfers, false) {
+                log::error!("Error deasserting chip select: {e:?}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transfer_layout() {
+        // Must match `struct spi_ioc_transfer` of the kernel.
+        assert_eq!(std::mem::size_of::<SpiIocTransfer>(), 32);
+        assert_eq!(MAX_TRANSFER_COUNT, 511);
+    }
+}
@@ -9,6 +9,7 @@ use std::any::Any;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::Duration;
 
 use crate::bootstrap::BootstrapOptions;
 use crate::io::emu::Emulator;
@@ -18,20 +19,24 @@ use crate::io::jtag::{JtagChain, JtagParams};
 use crate::io::nonblocking_help::{NoNonblockingHelp, NonblockingHelp};
 use crate::io::spi::Target;
 use crate::io::uart::Uart;
+use crate::proxy::protocol::{LeaseInfo, LeaseKind};
 
 pub mod chip_whisperer;
 pub mod common;
 pub mod dediprog;
 pub mod hyperdebug;
 pub mod ioexpander;
+pub mod linux;
 pub mod proxy;
+pub mod record;
+pub mod sim;
 pub mod ti50emulator;
 pub mod ultradebug;
 pub mod verilator;
 
 // Export custom error types
 mod errors;
-pub use errors::{TransportError, TransportInterfaceType};
+pub use errors::{RequiresMismatch, TransportError, TransportInterfaceType};
 
 bitflags! {
     /// A bitmap of capabilities which may be provided by a transport.
@@ -63,7 +68,7 @@ pub struct Capabilities {
 impl Capabilities {
     /// Create a new Capabilities object representing a provider of
     /// capabilities specified by `cap`.
-    fn new(cap: Capability) -> Self {
+    pub(crate) fn new(cap: Capability) -> Self {
         Self { capabilities: cap }
     }
 
@@ -112,6 +117,13 @@ pub trait Transport {
         Ok(())
     }
 
+    /// Reestablishes the connection to the debugger, e.g. after it has reset and re-enumerated on
+    /// the USB bus.  Instances previously returned by this transport remain usable, but the
+    /// debugger will have lost any configuration, see `TransportWrapper::reconnect()`.
+    fn reconnect(&self) -> Result<()> {
+        Err(TransportError::UnsupportedOperation.into())
+    }
+
     /// Returns a [`JtagChain`] implementation.
     fn jtag(&self, _opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
         Err(TransportError::InvalidInterface(TransportInterfaceType::Jtag).into())
@@ -178,6 +190,18 @@ pub trait ProxyOps {
 
     /// Applies the default transport init configuration expect with the specify strap applied.
     fn apply_default_configuration_with_strap(&self, strapping_name: &str) -> Result<()>;
+
+    /// Reserves the remote transport until released, the connection is closed, or the optional
+    /// `timeout` passes.  While any lease is held, connections without one cannot operate the
+    /// transport.  `owner` is used to identify the holder to other clients.
+    fn acquire_lease(&self, kind: LeaseKind, timeout: Option<Duration>, owner: &str) -> Result<()>;
+    fn release_lease(&self) -> Result<()>;
+    /// Lists the leases currently held by any client.
+    fn leases(&self) -> Result<Vec<LeaseInfo>>;
+
+    /// Lists the names of the transports served by the session process, any of which can be
+    /// selected when connecting.
+    fn targets(&self) -> Result<Vec<String>>;
 }
 
 /// Used by Transport implementations dealing with emulated OpenTitan
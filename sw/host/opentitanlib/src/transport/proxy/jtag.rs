@@ -0,0 +1,257 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Result};
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::ProxyError;
+use crate::debug::openocd::OpenOcd;
+use crate::dif::lc_ctrl::LcCtrlReg;
+use crate::io::jtag::{Jtag, JtagChain, JtagParams, JtagTap, RiscvReg};
+use crate::proxy::protocol::{JtagRequest, JtagResponse, Request, Response};
+use crate::transport::proxy::{Inner, Proxy};
+use crate::transport::TransportError;
+
+/// JTAG chain of the remote transport.  OpenOCD is started by the session process, once a TAP
+/// is chosen, using the session's own OpenOCD binary and configuration.  Only the adapter speed
+/// of `params` is passed on.
+pub struct ProxyJtagChain {
+    inner: Rc<Inner>,
+    params: JtagParams,
+}
+
+impl ProxyJtagChain {
+    pub fn open(proxy: &Proxy, params: &JtagParams) -> Result<Self> {
+        Ok(Self {
+            inner: Rc::clone(&proxy.inner),
+            params: params.clone(),
+        })
+    }
+}
+
+impl JtagChain for ProxyJtagChain {
+    fn connect(self: Box<Self>, tap: JtagTap) -> Result<Box<dyn Jtag>> {
+        let mut jtag = Box::new(ProxyJtag {
+            inner: self.inner,
+            tap,
+            connected: false,
+        });
+        match jtag.execute_command(JtagRequest::Connect {
+            adapter_speed_khz: self.params.adapter_speed_khz,
+            tap,
+        })? {
+            JtagResponse::Connect => {
+                jtag.connected = true;
+                Ok(jtag)
+            }
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+        // The OpenOCD instance lives on the other side of the session connection.
+        Err(TransportError::UnsupportedOperation.into())
+    }
+}
+
+pub struct ProxyJtag {
+    inner: Rc<Inner>,
+    tap: JtagTap,
+    connected: bool,
+}
+
+impl ProxyJtag {
+    // Convenience method for issuing JTAG commands via proxy protocol.
+    fn execute_command(&self, command: JtagRequest) -> Result<JtagResponse> {
+        match self.inner.execute_command(Request::Jtag { command })? {
+            Response::Jtag(resp) => Ok(resp),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn do_disconnect(&mut self) -> Result<()> {
+        self.connected = false;
+        match self.execute_command(JtagRequest::Disconnect)? {
+            JtagResponse::Disconnect => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+}
+
+impl Drop for ProxyJtag {
+    fn drop(&mut self) {
+        // Make sure the session process does not keep OpenOCD running on our behalf.
+        if self.connected {
+            let _ = self.do_disconnect();
+        }
+    }
+}
+
+impl Jtag for ProxyJtag {
+    fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+        Err(TransportError::UnsupportedOperation.into())
+    }
+
+    fn as_raw(&mut self) -> Result<&mut OpenOcd> {
+        Err(TransportError::UnsupportedOperation.into())
+    }
+
+    fn disconnect(mut self: Box<Self>) -> Result<()> {
+        self.do_disconnect()
+    }
+
+    fn tap(&self) -> JtagTap {
+        self.tap
+    }
+
+    fn read_lc_ctrl_reg(&mut self, reg: &LcCtrlReg) -> Result<u32> {
+        match self.execute_command(JtagRequest::ReadLcCtrlReg { reg: reg.clone() })? {
+            JtagResponse::ReadLcCtrlReg { value } => Ok(value),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn write_lc_ctrl_reg(&mut self, reg: &LcCtrlReg, value: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::WriteLcCtrlReg {
+            reg: reg.clone(),
+            value,
+        })? {
+            JtagResponse::WriteLcCtrlReg => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn read_memory(&mut self, addr: u32, buf: &mut [u8]) -> Result<usize> {
+        match self.execute_command(JtagRequest::ReadMemory {
+            addr,
+            len: buf.len() as u32,
+        })? {
+            JtagResponse::ReadMemory { data } if data.len() <= buf.len() => {
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn read_memory32(&mut self, addr: u32, buf: &mut [u32]) -> Result<usize> {
+        match self.execute_command(JtagRequest::ReadMemory32 {
+            addr,
+            len: buf.len() as u32,
+        })? {
+            JtagResponse::ReadMemory32 { data } if data.len() <= buf.len() => {
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn write_memory(&mut self, addr: u32, buf: &[u8]) -> Result<()> {
+        match self.execute_command(JtagRequest::WriteMemory {
+            addr,
+            data: buf.to_vec(),
+        })? {
+            JtagResponse::WriteMemory => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn write_memory32(&mut self, addr: u32, buf: &[u32]) -> Result<()> {
+        match self.execute_command(JtagRequest::WriteMemory32 {
+            addr,
+            data: buf.to_vec(),
+        })? {
+            JtagResponse::WriteMemory32 => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        match self.execute_command(JtagRequest::Halt)? {
+            JtagResponse::Halt => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn wait_halt(&mut self, timeout: Duration) -> Result<()> {
+        match self.execute_command(JtagRequest::WaitHalt {
+            timeout_millis: timeout.as_millis() as u64,
+        })? {
+            JtagResponse::WaitHalt => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        match self.execute_command(JtagRequest::Resume)? {
+            JtagResponse::Resume => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn resume_at(&mut self, addr: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::ResumeAt { addr })? {
+            JtagResponse::ResumeAt => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn step(&mut self) -> Result<()> {
+        match self.execute_command(JtagRequest::Step)? {
+            JtagResponse::Step => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn step_at(&mut self, addr: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::StepAt { addr })? {
+            JtagResponse::StepAt => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn reset(&mut self, run: bool) -> Result<()> {
+        match self.execute_command(JtagRequest::Reset { run })? {
+            JtagResponse::Reset => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn read_riscv_reg(&mut self, reg: &RiscvReg) -> Result<u32> {
+        match self.execute_command(JtagRequest::ReadRiscvReg { reg: *reg })? {
+            JtagResponse::ReadRiscvReg { value } => Ok(value),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn write_riscv_reg(&mut self, reg: &RiscvReg, value: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::WriteRiscvReg { reg: *reg, value })? {
+            JtagResponse::WriteRiscvReg => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn set_breakpoint(&mut self, addr: u32, hw: bool) -> Result<()> {
+        match self.execute_command(JtagRequest::SetBreakpoint { addr, hw })? {
+            JtagResponse::SetBreakpoint => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn remove_breakpoint(&mut self, addr: u32) -> Result<()> {
+        match self.execute_command(JtagRequest::RemoveBreakpoint { addr })? {
+            JtagResponse::RemoveBreakpoint => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn remove_all_breakpoints(&mut self) -> Result<()> {
+        match self.execute_command(JtagRequest::RemoveAllBreakpoints)? {
+            JtagResponse::RemoveAllBreakpoints => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+}
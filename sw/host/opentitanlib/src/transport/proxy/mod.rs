@@ -2,14 +2,19 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslStream};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::io;
-use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::net::{TcpStream, ToSocketAddrs};
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
 use std::rc::Rc;
 use std::time::Duration;
 use thiserror::Error;
@@ -19,20 +24,26 @@ use crate::impl_serializable_error;
 use crate::io::emu::Emulator;
 use crate::io::gpio::{GpioBitbanging, GpioMonitoring, GpioPin};
 use crate::io::i2c::Bus;
+use crate::io::jtag::{JtagChain, JtagParams};
 use crate::io::nonblocking_help::NonblockingHelp;
 use crate::io::spi::Target;
 use crate::io::uart::Uart;
 use crate::proxy::protocol::{
-    AsyncMessage, Message, ProxyRequest, ProxyResponse, Request, Response,
+    AsyncMessage, AuthRequest, AuthResponse, Encoding, EncodingRequest, EncodingResponse,
+    LeaseInfo, LeaseKind, Message, ProxyRequest, ProxyResponse, Request, Response,
 };
 use crate::transport::{Capabilities, Capability, ProxyOps, Transport, TransportError};
 
 mod emu;
 mod gpio;
 mod i2c;
+mod jtag;
+mod replay;
 mod spi;
 mod uart;
 
+pub use replay::{Replay, ReplayError};
+
 #[derive(Debug, Error, Serialize, Deserialize)]
 pub enum ProxyError {
     #[error("Unexpected reply")]
@@ -41,9 +52,55 @@ pub enum ProxyError {
     JsonEncoding(String),
     #[error("JSON decoding: {0}")]
     JsonDecoding(String),
+    #[error("Transport is leased by another client: {0}")]
+    Leased(String),
+    #[error("No target named \"{0}\" is served by the session")]
+    UnknownTarget(String),
+    #[error("No target selected, the session serves: {0}")]
+    NoTargetSelected(String),
 }
 impl_serializable_error!(ProxyError);
 
+/// Optional measures for securing the connection to the session process, which must match
+/// those the session process was started with.
+#[derive(Default)]
+pub struct ProxySecurity {
+    /// Encrypt the connection using TLS.
+    pub tls: bool,
+    /// PEM file with CA certificates for verifying the server, instead of the system defaults.
+    pub tls_ca: Option<PathBuf>,
+    /// PEM files with client certificate chain and private key, if required by the server.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Pre-shared token to present to the server.
+    pub token: Option<String>,
+}
+
+impl ProxySecurity {
+    fn tls_connector(&self) -> Result<SslConnector> {
+        let mut builder = SslConnector::builder(SslMethod::tls_client())?;
+        if let Some(ca) = &self.tls_ca {
+            builder
+                .set_ca_file(ca)
+                .with_context(|| format!("loading {ca:?}"))?;
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                builder
+                    .set_certificate_chain_file(cert)
+                    .with_context(|| format!("loading {cert:?}"))?;
+                builder
+                    .set_private_key_file(key, SslFiletype::PEM)
+                    .with_context(|| format!("loading {key:?}"))?;
+                builder.check_private_key()?;
+            }
+            (None, None) => (),
+            _ => bail!("TLS client certificate and key must be given together"),
+        }
+        Ok(builder.build())
+    }
+}
+
 /// Implementation of the Transport trait backed by connection to a remote OpenTitan tool
 /// session process.
 pub struct Proxy {
@@ -51,8 +108,14 @@ pub struct Proxy {
 }
 
 impl Proxy {
-    /// Establish connection with a running session process.
-    pub fn open(host: Option<&str>, port: u16) -> Result<Self> {
+    /// Establish connection with a running session process, operating the transport served
+    /// under the name `target`, if the session serves more than one.
+    pub fn open(
+        host: Option<&str>,
+        port: u16,
+        security: &ProxySecurity,
+        target: Option<&str>,
+    ) -> Result<Self> {
         let host = host.unwrap_or("localhost");
         let addr = ToSocketAddrs::to_socket_addrs(&(host, port))
             .map_err(|e| TransportError::ProxyLookupError(host.to_string(), e.to_string()))?
@@ -60,18 +123,225 @@ impl Proxy {
             .unwrap();
         let conn = TcpStream::connect(addr)
             .map_err(|e| TransportError::ProxyConnectError(addr.to_string(), e.to_string()))?;
+        let conn = if security.tls {
+            let stream = security
+                .tls_connector()?
+                .connect(host, conn)
+                .map_err(|e| TransportError::ProxyConnectError(addr.to_string(), e.to_string()))?;
+            ProxyStream::Tls(stream)
+        } else {
+            ProxyStream::Plain(conn)
+        };
+        Self::connect(conn, &addr.to_string(), security, target)
+    }
+
+    /// Like `open()`, but connects to a session process listening on a Unix domain socket.
+    pub fn open_unix(path: &Path, security: &ProxySecurity, target: Option<&str>) -> Result<Self> {
+        let name = path.display().to_string();
+        ensure!(
+            !security.tls,
+            TransportError::ProxyConnectError(name, "TLS is only supported over TCP".to_string())
+        );
+        let conn = UnixStream::connect(path)
+            .map_err(|e| TransportError::ProxyConnectError(name.clone(), e.to_string()))?;
+        Self::connect(ProxyStream::Unix(conn), &name, security, target)
+    }
+
+    /// Like `open()`, but runs a shell command, whose standard input and output are connected
+    /// to a session process, typically `ssh <host> opentitansession --stdio ...`.
+    pub fn open_command(
+        command: &str,
+        security: &ProxySecurity,
+        target: Option<&str>,
+    ) -> Result<Self> {
+        ensure!(
+            !security.tls,
+            TransportError::ProxyConnectError(
+                command.to_string(),
+                "TLS is only supported over TCP".to_string()
+            )
+        );
+        let conn = CommandStream::spawn(command)
+            .map_err(|e| TransportError::ProxyConnectError(command.to_string(), e.to_string()))?;
+        Self::connect(ProxyStream::Command(conn), command, security, target)
+    }
+
+    /// Authenticate and select a target on a newly established connection, switching to the
+    /// binary encoding if the session process supports it.
+    fn connect(
+        conn: ProxyStream,
+        address: &str,
+        security: &ProxySecurity,
+        target: Option<&str>,
+    ) -> Result<Self> {
+        let inner = Self::authenticate(conn, address, security)?;
+        inner.negotiate_encoding()?;
+        if let Some(name) = target {
+            match inner.execute_command(Request::Proxy(ProxyRequest::SelectTarget {
+                name: name.to_string(),
+            }))? {
+                Response::Proxy(ProxyResponse::SelectTarget) => (),
+                _ => bail!(ProxyError::UnexpectedReply()),
+            }
+        }
+        Ok(Self {
+            inner: Rc::new(inner),
+        })
+    }
+
+    fn authenticate(conn: ProxyStream, address: &str, security: &ProxySecurity) -> Result<Inner> {
+        let inner = Inner::new(conn);
+        if let Some(token) = &security.token {
+            inner.send_message(&AuthRequest {
+                token: token.clone(),
+            })?;
+            match inner.recv_message::<AuthResponse>()? {
+                AuthResponse::Accepted => (),
+                AuthResponse::Rejected => bail!(TransportError::ProxyConnectError(
+                    address.to_string(),
+                    "token rejected".to_string()
+                )),
+            }
+        }
+        Ok(inner)
+    }
+}
+
+/// Standard input and output of a child process relaying the connection to the session
+/// process, e.g. `ssh`.
+struct CommandStream {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    read_timeout: Option<Duration>,
+}
+
+impl CommandStream {
+    fn spawn(command: &str) -> io::Result<Self> {
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
         Ok(Self {
-            inner: Rc::new(Inner {
-                conn: RefCell::new(conn),
-                uarts: RefCell::new(HashMap::new()),
-                uart_channel_map: RefCell::new(HashMap::new()),
-                recv_buf: RefCell::new(Vec::new()),
-                nonblocking_help_enabled: Cell::new(false),
-            }),
+            stdin: child.stdin.take().unwrap(),
+            stdout: child.stdout.take().unwrap(),
+            child,
+            read_timeout: None,
         })
     }
 }
 
+impl Read for CommandStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Pipes have no notion of a read timeout, wait for data explicitly.  As with sockets,
+        // expiry is reported as `WouldBlock`.
+        if let Some(timeout) = self.read_timeout {
+            let timeout = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+            let mut pfd = [rustix::event::PollFd::new(
+                &self.stdout,
+                rustix::event::PollFlags::IN,
+            )];
+            if rustix::event::poll(&mut pfd, timeout)? == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+        }
+        self.stdout.read(buf)
+    }
+}
+
+impl Drop for CommandStream {
+    fn drop(&mut self) {
+        // The session process at the other end exits when the relay goes away.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Connection to the session process, possibly wrapped in a TLS session, or to a trace being
+/// replayed in place of a session process.
+enum ProxyStream {
+    Plain(TcpStream),
+    Tls(SslStream<TcpStream>),
+    Unix(UnixStream),
+    Command(CommandStream),
+    Replay(replay::TraceServer),
+}
+
+impl ProxyStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        match self {
+            ProxyStream::Plain(socket) => socket.set_read_timeout(timeout)?,
+            ProxyStream::Tls(stream) => stream.get_ref().set_read_timeout(timeout)?,
+            ProxyStream::Unix(socket) => socket.set_read_timeout(timeout)?,
+            ProxyStream::Command(command) => command.read_timeout = timeout,
+            // Nonblocking operation is never recorded, see `RecordingUart`.
+            ProxyStream::Replay(_) => bail!(TransportError::UnsupportedOperation),
+        }
+        Ok(())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        match self {
+            ProxyStream::Plain(socket) => socket.set_nonblocking(nonblocking)?,
+            ProxyStream::Tls(stream) => stream.get_ref().set_nonblocking(nonblocking)?,
+            ProxyStream::Unix(socket) => socket.set_nonblocking(nonblocking)?,
+            ProxyStream::Command(command) => {
+                rustix::io::ioctl_fionbio(&command.stdout, nonblocking)?
+            }
+            ProxyStream::Replay(_) => bail!(TransportError::UnsupportedOperation),
+        }
+        Ok(())
+    }
+
+    /// File descriptor on which incoming data can be awaited.
+    fn as_raw_fd(&self) -> Result<RawFd> {
+        match self {
+            ProxyStream::Plain(socket) => Ok(socket.as_raw_fd()),
+            ProxyStream::Tls(stream) => Ok(stream.get_ref().as_raw_fd()),
+            ProxyStream::Unix(socket) => Ok(socket.as_raw_fd()),
+            ProxyStream::Command(command) => Ok(command.stdout.as_raw_fd()),
+            ProxyStream::Replay(_) => bail!(TransportError::UnsupportedOperation),
+        }
+    }
+}
+
+impl Read for ProxyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ProxyStream::Plain(socket) => socket.read(buf),
+            ProxyStream::Tls(stream) => stream.read(buf),
+            ProxyStream::Unix(socket) => socket.read(buf),
+            ProxyStream::Command(command) => command.read(buf),
+            ProxyStream::Replay(server) => server.read(buf),
+        }
+    }
+}
+
+impl Write for ProxyStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ProxyStream::Plain(socket) => socket.write(buf),
+            ProxyStream::Tls(stream) => stream.write(buf),
+            ProxyStream::Unix(socket) => socket.write(buf),
+            ProxyStream::Command(command) => command.stdin.write(buf),
+            ProxyStream::Replay(server) => server.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ProxyStream::Plain(socket) => socket.flush(),
+            ProxyStream::Tls(stream) => stream.flush(),
+            ProxyStream::Unix(socket) => socket.flush(),
+            ProxyStream::Command(command) => command.stdin.flush(),
+            ProxyStream::Replay(server) => server.flush(),
+        }
+    }
+}
+
 struct UartRecord {
     pub uart: Rc<dyn Uart>,
     pub pipe_sender: mio::unix::pipe::Sender,
@@ -79,20 +349,56 @@ struct UartRecord {
 }
 
 struct Inner {
-    conn: RefCell<TcpStream>,
+    conn: RefCell<ProxyStream>,
     pub uarts: RefCell<HashMap<String, UartRecord>>,
     uart_channel_map: RefCell<HashMap<u32, String>>,
     recv_buf: RefCell<Vec<u8>>,
+    encoding: Cell<Encoding>,
     nonblocking_help_enabled: Cell<bool>,
 }
 
 impl Inner {
-    /// Helper method for sending one JSON request and receiving the response.  Called as part
-    /// of the implementation of every method of the sub-traits (gpio, uart, spi, i2c).
+    fn new(conn: ProxyStream) -> Self {
+        Self {
+            conn: RefCell::new(conn),
+            uarts: RefCell::new(HashMap::new()),
+            uart_channel_map: RefCell::new(HashMap::new()),
+            recv_buf: RefCell::new(Vec::new()),
+            encoding: Cell::new(Encoding::Json),
+            nonblocking_help_enabled: Cell::new(false),
+        }
+    }
+
+    /// Switches the connection to the binary encoding, if the session process lists it among
+    /// the encodings it supports in its response to `ProxyRequest::Provides`.  Session
+    /// processes predating the binary encoding list none, and the connection stays in JSON.
+    fn negotiate_encoding(&self) -> Result<()> {
+        match self.execute_command(Request::Proxy(ProxyRequest::Provides))? {
+            Response::Proxy(ProxyResponse::Provides { encodings, .. }) => {
+                if !encodings.contains(&Encoding::Binary) {
+                    log::info!("Session does not support binary encoding, using JSON");
+                    return Ok(());
+                }
+            }
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+        self.send_message(&EncodingRequest {
+            encodings: vec![Encoding::Binary, Encoding::Json],
+        })?;
+        let response = self.recv_message::<EncodingResponse>()?;
+        self.encoding.set(response.encoding);
+        Ok(())
+    }
+
+    /// Helper method for sending one request and receiving the response.  Called as part of
+    /// the implementation of every method of the sub-traits (gpio, uart, spi, i2c).
     fn execute_command(&self, req: Request) -> Result<Response> {
-        self.send_json_request(req).context("json encoding")?;
+        self.send_request(req).context("request encoding")?;
         loop {
-            match self.recv_json_response().context("json decoding")? {
+            match self
+                .recv_message::<Message>()
+                .context("response decoding")?
+            {
                 Message::Res(res) => match res {
                     Ok(value) => return Ok(value),
                     Err(e) => return Err(anyhow::Error::from(e)),
@@ -109,7 +415,7 @@ impl Inner {
         } else {
             self.recv_with_timeout(timeout)?;
         }
-        while let Some(msg) = self.dequeue_json_response()? {
+        while let Some(msg) = self.dequeue_message::<Message>()? {
             match msg {
                 Message::Async { channel, msg } => self.process_async_data(channel, msg)?,
                 _ => bail!(ProxyError::UnexpectedReply()),
@@ -131,26 +437,34 @@ impl Inner {
         Ok(())
     }
 
-    /// Send a one-line JSON encoded requests, terminated with one newline.
-    fn send_json_request(&self, req: Request) -> Result<()> {
-        let conn: &mut std::net::TcpStream = &mut self.conn.borrow_mut();
-        let mut writer = BufWriter::new(conn);
-        serde_json::to_writer(&mut writer, &Message::Req(req))?;
-        writer.write_all(&[b'\n'])?;
-        writer.flush()?;
+    /// Send one request, in the encoding of the connection.
+    fn send_request(&self, req: Request) -> Result<()> {
+        self.send_message(&Message::Req(req))
+    }
+
+    /// Send one message, in the encoding of the connection.
+    fn send_message<T: Serialize>(&self, msg: &T) -> Result<()> {
+        let mut data = Vec::new();
+        self.encoding.get().encode(&mut data, msg)?;
+        let mut conn = self.conn.borrow_mut();
+        conn.write_all(&data)?;
+        conn.flush()?;
         Ok(())
     }
 
-    /// Decode one JSON response, possibly waiting for more network data.
-    fn recv_json_response(&self) -> Result<Message> {
-        if let Some(msg) = self.dequeue_json_response()? {
+    /// Decode one message, possibly waiting for more network data.
+    fn recv_message<T: DeserializeOwned>(&self) -> Result<T> {
+        if let Some(msg) = self.dequeue_message()? {
             return Ok(msg);
         }
+        let encoding = self.encoding.get();
         let mut conn = self.conn.borrow_mut();
         let mut buf = self.recv_buf.borrow_mut();
         let mut idx: usize = buf.len();
         loop {
-            buf.resize(idx + 2048, 0);
+            // Grow the buffer along with the message being received, such that large messages
+            // do not require an excessive number of system calls.
+            buf.resize(idx + idx.max(2048), 0);
             let rc = conn.read(&mut buf[idx..])?;
             if rc == 0 {
                 anyhow::bail!(io::Error::new(
@@ -159,12 +473,12 @@ impl Inner {
                 ))
             }
             idx += rc;
-            let Some(newline_pos) = buf[idx - rc..idx].iter().position(|b| *b == b'\n') else {
+            let Some((range, len)) = encoding.find_message(&buf[..idx], idx - rc)? else {
                 continue;
             };
-            let result = serde_json::from_slice::<Message>(&buf[..idx - rc + newline_pos])?;
+            let result = encoding.decode::<T>(&buf[range])?;
             buf.resize(idx, 0u8);
-            buf.drain(..idx - rc + newline_pos + 1);
+            buf.drain(..len);
             return Ok(result);
         }
     }
@@ -215,13 +529,14 @@ impl Inner {
         Ok(())
     }
 
-    fn dequeue_json_response(&self) -> Result<Option<Message>> {
+    fn dequeue_message<T: DeserializeOwned>(&self) -> Result<Option<T>> {
+        let encoding = self.encoding.get();
         let mut buf = self.recv_buf.borrow_mut();
-        let Some(newline_pos) = buf.iter().position(|b| *b == b'\n') else {
+        let Some((range, len)) = encoding.find_message(&buf, 0)? else {
             return Ok(None);
         };
-        let result = serde_json::from_slice::<Message>(&buf[..newline_pos])?;
-        buf.drain(..newline_pos + 1);
+        let result = encoding.decode::<T>(&buf[range])?;
+        buf.drain(..len);
         Ok(Some(result))
     }
 }
@@ -249,7 +564,7 @@ impl ProxyOpsImpl {
 impl ProxyOps for ProxyOpsImpl {
     fn provides_map(&self) -> Result<HashMap<String, String>> {
         match self.execute_command(ProxyRequest::Provides {})? {
-            ProxyResponse::Provides { provides_map } => Ok(provides_map),
+            ProxyResponse::Provides { provides_map, .. } => Ok(provides_map),
             _ => bail!(ProxyError::UnexpectedReply()),
         }
     }
@@ -290,6 +605,38 @@ impl ProxyOps for ProxyOpsImpl {
             _ => bail!(ProxyError::UnexpectedReply()),
         }
     }
+
+    fn acquire_lease(&self, kind: LeaseKind, timeout: Option<Duration>, owner: &str) -> Result<()> {
+        match self.execute_command(ProxyRequest::AcquireLease {
+            kind,
+            timeout_millis: timeout.map(|timeout| timeout.as_millis() as u64),
+            owner: owner.to_string(),
+        })? {
+            ProxyResponse::AcquireLease => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn release_lease(&self) -> Result<()> {
+        match self.execute_command(ProxyRequest::ReleaseLease)? {
+            ProxyResponse::ReleaseLease => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn leases(&self) -> Result<Vec<LeaseInfo>> {
+        match self.execute_command(ProxyRequest::GetLeases)? {
+            ProxyResponse::GetLeases { leases } => Ok(leases),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn targets(&self) -> Result<Vec<String>> {
+        match self.execute_command(ProxyRequest::ListTargets)? {
+            ProxyResponse::ListTargets { targets } => Ok(targets),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
 }
 
 impl Transport for Proxy {
@@ -310,6 +657,13 @@ impl Transport for Proxy {
         }
     }
 
+    fn reconnect(&self) -> Result<()> {
+        match self.inner.execute_command(Request::Reconnect)? {
+            Response::Reconnect => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
     // Create SPI Target instance, or return one from a cache of previously created instances.
     fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
         Ok(Rc::new(spi::ProxySpi::open(self, instance)?))
@@ -353,6 +707,11 @@ impl Transport for Proxy {
         Ok(Rc::new(gpio::GpioBitbangingImpl::new(self)?))
     }
 
+    // Create JtagChain instance, OpenOCD will be launched by the session process.
+    fn jtag(&self, opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
+        Ok(Box::new(jtag::ProxyJtagChain::open(self, opts)?))
+    }
+
     // Create Emulator instance, or return one from a cache of previously created instances.
     fn emulator(&self) -> Result<Rc<dyn Emulator>> {
         Ok(Rc::new(emu::ProxyEmu::open(self)?))
@@ -376,9 +735,9 @@ pub struct ProxyNonblockingHelp {
 
 impl NonblockingHelp for ProxyNonblockingHelp {
     fn register_nonblocking_help(&self, registry: &mio::Registry, token: mio::Token) -> Result<()> {
-        let conn: &mut std::net::TcpStream = &mut self.inner.conn.borrow_mut();
+        let conn = self.inner.conn.borrow();
         registry.register(
-            &mut mio::unix::SourceFd(&conn.as_raw_fd()),
+            &mut mio::unix::SourceFd(&conn.as_raw_fd()?),
             token,
             mio::Interest::READABLE,
         )?;
@@ -0,0 +1,250 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use thiserror::Error;
+
+use super::{Inner, Proxy, ProxyError, ProxyStream};
+use crate::impl_serializable_error;
+use crate::io::gpio::GpioPin;
+use crate::io::i2c::Bus;
+use crate::io::jtag::{JtagChain, JtagParams};
+use crate::io::spi::Target;
+use crate::io::uart::Uart;
+use crate::proxy::errors::SerializedError;
+use crate::proxy::protocol::{Message, Request, Response};
+use crate::transport::record::TraceEntry;
+use crate::transport::{Capabilities, Transport};
+
+#[derive(Debug, Error, Serialize, Deserialize)]
+pub enum ReplayError {
+    #[error("Call {index} diverges from the trace, expected {expected}, got {actual}")]
+    Divergence {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
+    #[error("Call {0} goes beyond the end of the trace: {1}")]
+    EndOfTrace(usize, String),
+    #[error("Replay stopped at call {0}, which diverged from the trace")]
+    Diverged(usize),
+}
+impl_serializable_error!(ReplayError);
+
+/// Stands in for a session process, answering each request with the response recorded for it
+/// in a trace, after verifying that the request is identical to the recorded one.  Once a
+/// request has diverged from the trace, all further requests fail.
+pub struct TraceServer {
+    entries: VecDeque<TraceEntry>,
+    index: usize,
+    diverged: Option<usize>,
+    request_buf: Vec<u8>,
+    response_buf: VecDeque<u8>,
+}
+
+impl TraceServer {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("opening trace {path:?}"))?;
+        Self::from_reader(BufReader::new(file)).with_context(|| format!("reading trace {path:?}"))
+    }
+
+    fn from_reader(reader: impl BufRead) -> Result<Self> {
+        let mut entries = VecDeque::new();
+        for (lineno, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push_back(
+                serde_json::from_str(&line).with_context(|| format!("line {}", lineno + 1))?,
+            );
+        }
+        Ok(Self {
+            entries,
+            index: 0,
+            diverged: None,
+            request_buf: Vec::new(),
+            response_buf: VecDeque::new(),
+        })
+    }
+
+    fn execute_cmd(&mut self, req: &Request) -> Result<Response> {
+        let index = self.index;
+        self.index += 1;
+        if let Some(diverged) = self.diverged {
+            bail!(ReplayError::Diverged(diverged));
+        }
+        let actual = serde_json::to_value(req)?;
+        let Some(entry) = self.entries.pop_front() else {
+            self.diverged = Some(index);
+            bail!(ReplayError::EndOfTrace(index, actual.to_string()));
+        };
+        if entry.request != actual {
+            self.diverged = Some(index);
+            bail!(ReplayError::Divergence {
+                index,
+                expected: entry.request.to_string(),
+                actual: actual.to_string(),
+            });
+        }
+        Ok(entry.response?)
+    }
+
+    /// Processes one line received from the client.
+    fn process_line(&mut self, line: &[u8]) -> Result<()> {
+        let Message::Req(req) = serde_json::from_slice(line)? else {
+            bail!(ProxyError::UnexpectedReply());
+        };
+        let res = self.execute_cmd(&req).map_err(SerializedError::from);
+        serde_json::to_writer(&mut self.response_buf, &Message::Res(res))?;
+        self.response_buf.push_back(b'\n');
+        Ok(())
+    }
+}
+
+impl Read for TraceServer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Every request has been answered by the time it is written, so running out of
+        // responses means that the client waits for something that will never come.
+        self.response_buf.read(buf)
+    }
+}
+
+impl Write for TraceServer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.request_buf.extend_from_slice(buf);
+        while let Some(newline_pos) = self.request_buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.request_buf.drain(..=newline_pos).collect();
+            self.process_line(&line[..newline_pos])
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TraceServer {
+    fn drop(&mut self) {
+        if self.diverged.is_none() && !self.entries.is_empty() {
+            log::warn!(
+                "Replay ended after {} calls, {} recorded calls were not replayed",
+                self.index,
+                self.entries.len()
+            );
+        }
+    }
+}
+
+/// Transport serving the responses of a trace recorded by
+/// [`crate::transport::record::RecordingTransport`].  The interfaces of the transport are those
+/// of `Proxy`, with the trace in place of a session process.
+pub struct Replay {
+    proxy: Proxy,
+}
+
+impl Replay {
+    pub fn open(trace: &Path) -> Result<Self> {
+        let server = TraceServer::open(trace)?;
+        Ok(Self {
+            proxy: Proxy {
+                inner: Rc::new(Inner::new(ProxyStream::Replay(server))),
+            },
+        })
+    }
+}
+
+impl Transport for Replay {
+    fn capabilities(&self) -> Result<Capabilities> {
+        // Unlike `Proxy`, the proxy-only operations are not offered.
+        match self.proxy.inner.execute_command(Request::GetCapabilities)? {
+            Response::GetCapabilities(capabilities) => Ok(capabilities),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn apply_default_configuration(&self) -> Result<()> {
+        self.proxy.apply_default_configuration()
+    }
+
+    fn reconnect(&self) -> Result<()> {
+        self.proxy.reconnect()
+    }
+
+    fn jtag(&self, opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
+        self.proxy.jtag(opts)
+    }
+
+    fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+        self.proxy.spi(instance)
+    }
+
+    fn i2c(&self, instance: &str) -> Result<Rc<dyn Bus>> {
+        self.proxy.i2c(instance)
+    }
+
+    fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
+        self.proxy.uart(instance)
+    }
+
+    fn gpio_pin(&self, instance: &str) -> Result<Rc<dyn GpioPin>> {
+        self.proxy.gpio_pin(instance)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    const TRACE: &str = r#"
+{"request":{"Gpio":{"id":"RESET","command":{"Write":{"logic":false}}}},"response":{"Ok":{"Gpio":"Write"}}}
+{"request":{"Gpio":{"id":"RESET","command":"Read"}},"response":{"Ok":{"Gpio":{"Read":{"value":true}}}}}
+"#;
+
+    fn replay(trace: &str) -> Result<Proxy> {
+        let server = TraceServer::from_reader(Cursor::new(trace))?;
+        Ok(Proxy {
+            inner: Rc::new(Inner::new(ProxyStream::Replay(server))),
+        })
+    }
+
+    fn replay_error(result: Result<impl std::fmt::Debug>) -> ReplayError {
+        result.unwrap_err().downcast::<ReplayError>().unwrap()
+    }
+
+    #[test]
+    fn test_replay() -> Result<()> {
+        let proxy = replay(TRACE)?;
+        let pin = proxy.gpio_pin("RESET")?;
+        pin.write(false)?;
+        assert!(pin.read()?);
+        assert!(matches!(
+            replay_error(pin.read()),
+            ReplayError::EndOfTrace(2, _)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_divergence() -> Result<()> {
+        let proxy = replay(TRACE)?;
+        let pin = proxy.gpio_pin("RESET")?;
+        assert!(matches!(
+            replay_error(pin.write(true)),
+            ReplayError::Divergence { index: 0, .. }
+        ));
+        // Requests after the divergence fail, even if they match the trace.
+        assert!(matches!(replay_error(pin.read()), ReplayError::Diverged(0)));
+        Ok(())
+    }
+}
@@ -80,6 +80,20 @@ impl Uart for ProxyUart {
         }
     }
 
+    fn set_flow_control(&self, flow_control: bool) -> Result<()> {
+        match self.execute_command(UartRequest::SetFlowControl(flow_control))? {
+            UartResponse::SetFlowControl => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
+    fn set_hardware_flow_control(&self, enable: bool) -> Result<()> {
+        match self.execute_command(UartRequest::SetHardwareFlowControl(enable))? {
+            UartResponse::SetHardwareFlowControl => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
     fn set_parity(&self, parity: Parity) -> Result<()> {
         match self.execute_command(UartRequest::SetParity(parity))? {
             UartResponse::SetParity => Ok(()),
@@ -87,6 +101,13 @@ impl Uart for ProxyUart {
         }
     }
 
+    fn set_break(&self, enable: bool) -> Result<()> {
+        match self.execute_command(UartRequest::SetBreak(enable))? {
+            UartResponse::SetBreak => Ok(()),
+            _ => bail!(ProxyError::UnexpectedReply()),
+        }
+    }
+
     /// Reads UART receive data into `buf`, returning the number of bytes read.
     /// This function _may_ block.
     fn read(&self, buf: &mut [u8]) -> Result<usize> {
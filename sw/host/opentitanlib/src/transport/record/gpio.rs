@@ -0,0 +1,109 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::rc::Rc;
+
+use super::Recorder;
+use crate::io::gpio::{GpioPin, PinMode, PullMode};
+use crate::proxy::protocol::{GpioRequest, GpioResponse, Request, Response};
+
+pub struct RecordingGpioPin {
+    inner: Rc<dyn GpioPin>,
+    recorder: Rc<Recorder>,
+    pinname: String,
+}
+
+impl RecordingGpioPin {
+    pub fn new(inner: Rc<dyn GpioPin>, recorder: &Rc<Recorder>, pinname: &str) -> Self {
+        Self {
+            inner,
+            recorder: Rc::clone(recorder),
+            pinname: pinname.to_string(),
+        }
+    }
+
+    fn record<T>(
+        &self,
+        command: GpioRequest,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> GpioResponse,
+    ) -> Result<T> {
+        self.recorder.record(
+            Request::Gpio {
+                id: self.pinname.clone(),
+                command,
+            },
+            result,
+            |value| Response::Gpio(response(value)),
+        )
+    }
+}
+
+impl GpioPin for RecordingGpioPin {
+    fn read(&self) -> Result<bool> {
+        self.record(GpioRequest::Read, self.inner.read(), |value| {
+            GpioResponse::Read { value: *value }
+        })
+    }
+
+    fn write(&self, value: bool) -> Result<()> {
+        self.record(
+            GpioRequest::Write { logic: value },
+            self.inner.write(value),
+            |_| GpioResponse::Write,
+        )
+    }
+
+    fn set_mode(&self, mode: PinMode) -> Result<()> {
+        self.record(
+            GpioRequest::SetMode { mode },
+            self.inner.set_mode(mode),
+            |_| GpioResponse::SetMode,
+        )
+    }
+
+    fn set_pull_mode(&self, pull: PullMode) -> Result<()> {
+        self.record(
+            GpioRequest::SetPullMode { pull },
+            self.inner.set_pull_mode(pull),
+            |_| GpioResponse::SetPullMode,
+        )
+    }
+
+    fn analog_read(&self) -> Result<f32> {
+        // Not expressible in the session protocol.
+        self.inner.analog_read()
+    }
+
+    fn analog_write(&self, volts: f32) -> Result<()> {
+        // Not expressible in the session protocol.
+        self.inner.analog_write(volts)
+    }
+
+    fn set(
+        &self,
+        mode: Option<PinMode>,
+        value: Option<bool>,
+        pull: Option<PullMode>,
+        analog_value: Option<f32>,
+    ) -> Result<()> {
+        self.record(
+            GpioRequest::MultiSet {
+                mode,
+                value,
+                pull,
+                analog_value,
+            },
+            self.inner.set(mode, value, pull, analog_value),
+            |_| GpioResponse::MultiSet,
+        )
+    }
+
+    fn get_internal_pin_name(&self) -> Option<&str> {
+        // Other interfaces of the underlying transport, e.g. `Target::set_pins()`, may need to
+        // identify the pin.
+        self.inner.get_internal_pin_name()
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::Recorder;
+use crate::io::i2c::{Bus, DeviceStatus, Mode, Transfer};
+use crate::proxy::protocol::{
+    I2cRequest, I2cResponse, I2cTransferRequest, I2cTransferResponse, Request, Response,
+};
+
+pub struct RecordingI2c {
+    inner: Rc<dyn Bus>,
+    recorder: Rc<Recorder>,
+    instance: String,
+    // The session protocol has no notion of a default address, it is resolved on the client
+    // side, and must be resolved here in the same way.
+    default_address: Cell<Option<u8>>,
+}
+
+impl RecordingI2c {
+    pub fn new(inner: Rc<dyn Bus>, recorder: &Rc<Recorder>, instance: &str) -> Self {
+        Self {
+            inner,
+            recorder: Rc::clone(recorder),
+            instance: instance.to_string(),
+            default_address: Cell::new(None),
+        }
+    }
+
+    fn record<T>(
+        &self,
+        command: I2cRequest,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> I2cResponse,
+    ) -> Result<T> {
+        self.recorder.record(
+            Request::I2c {
+                id: self.instance.clone(),
+                command,
+            },
+            result,
+            |value| Response::I2c(response(value)),
+        )
+    }
+}
+
+impl Bus for RecordingI2c {
+    fn set_mode(&self, mode: Mode) -> Result<()> {
+        match mode {
+            Mode::Host => self.record(
+                I2cRequest::SetModeHost,
+                self.inner.set_mode(Mode::Host),
+                |_| I2cResponse::SetModeHost,
+            ),
+            Mode::Device(addr) => self.record(
+                I2cRequest::SetModeDevice { addr },
+                self.inner.set_mode(Mode::Device(addr)),
+                |_| I2cResponse::SetModeDevice,
+            ),
+        }
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        self.record(
+            I2cRequest::GetMaxSpeed,
+            self.inner.get_max_speed(),
+            |speed| I2cResponse::GetMaxSpeed { speed: *speed },
+        )
+    }
+    fn set_max_speed(&self, value: u32) -> Result<()> {
+        self.record(
+            I2cRequest::SetMaxSpeed { value },
+            self.inner.set_max_speed(value),
+            |_| I2cResponse::SetMaxSpeed,
+        )
+    }
+
+    fn set_default_address(&self, addr: u8) -> Result<()> {
+        self.default_address.set(Some(addr));
+        self.inner.set_default_address(addr)
+    }
+
+    fn run_transaction(&self, address: Option<u8>, transaction: &mut [Transfer]) -> Result<()> {
+        let mut req: Vec<I2cTransferRequest> = Vec::new();
+        for transfer in &*transaction {
+            match transfer {
+                Transfer::Read(rbuf) => req.push(I2cTransferRequest::Read {
+                    len: rbuf.len() as u32,
+                }),
+                Transfer::Write(wbuf) => req.push(I2cTransferRequest::Write {
+                    data: wbuf.to_vec(),
+                }),
+            }
+        }
+        let result = self.inner.run_transaction(address, transaction);
+        self.record(
+            I2cRequest::RunTransaction {
+                address: address.or(self.default_address.get()),
+                transaction: req,
+            },
+            result,
+            |_| I2cResponse::RunTransaction {
+                transaction: transaction
+                    .iter()
+                    .map(|transfer| match transfer {
+                        Transfer::Read(rbuf) => I2cTransferResponse::Read {
+                            data: rbuf.to_vec(),
+                        },
+                        Transfer::Write(_) => I2cTransferResponse::Write,
+                    })
+                    .collect(),
+            },
+        )
+    }
+
+    fn get_device_status(&self, timeout: Duration) -> Result<DeviceStatus> {
+        self.record(
+            I2cRequest::GetDeviceStatus {
+                timeout_millis: timeout.as_millis() as u32,
+            },
+            self.inner.get_device_status(timeout),
+            |status| I2cResponse::GetDeviceStatus {
+                status: status.clone(),
+            },
+        )
+    }
+
+    fn prepare_read_data(&self, data: &[u8], sticky: bool) -> Result<()> {
+        self.record(
+            I2cRequest::PrepareReadData {
+                data: data.to_vec(),
+                sticky,
+            },
+            self.inner.prepare_read_data(data, sticky),
+            |_| I2cResponse::PrepareReadData,
+        )
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::Recorder;
+use crate::debug::openocd::OpenOcd;
+use crate::dif::lc_ctrl::LcCtrlReg;
+use crate::io::jtag::{Jtag, JtagChain, JtagParams, JtagTap, RiscvReg};
+use crate::proxy::protocol::{JtagRequest, JtagResponse, Request, Response};
+
+pub struct RecordingJtagChain<'a> {
+    inner: Box<dyn JtagChain + 'a>,
+    recorder: Rc<Recorder>,
+    params: JtagParams,
+}
+
+impl<'a> RecordingJtagChain<'a> {
+    pub fn new(
+        inner: Box<dyn JtagChain + 'a>,
+        recorder: &Rc<Recorder>,
+        params: &JtagParams,
+    ) -> Self {
+        Self {
+            inner,
+            recorder: Rc::clone(recorder),
+            params: params.clone(),
+        }
+    }
+}
+
+impl<'a> JtagChain for RecordingJtagChain<'a> {
+    fn connect(self: Box<Self>, tap: JtagTap) -> Result<Box<dyn Jtag>> {
+        let Self {
+            inner,
+            recorder,
+            params,
+        } = *self;
+        let inner = recorder.record(
+            Request::Jtag {
+                command: JtagRequest::Connect {
+                    adapter_speed_khz: params.adapter_speed_khz,
+                    tap,
+                },
+            },
+            inner.connect(tap),
+            |_| Response::Jtag(JtagResponse::Connect),
+        )?;
+        Ok(Box::new(RecordingJtag {
+            inner: Some(inner),
+            recorder,
+        }))
+    }
+
+    fn into_raw(self: Box<Self>) -> Result<OpenOcd> {
+        // Not expressible in the session protocol.
+        self.inner.into_raw()
+    }
+}
+
+pub struct RecordingJtag {
+    // Only `None` after having been disconnected.
+    inner: Option<Box<dyn Jtag>>,
+    recorder: Rc<Recorder>,
+}
+
+impl RecordingJtag {
+    fn inner(&mut self) -> &mut Box<dyn Jtag> {
+        self.inner.as_mut().unwrap()
+    }
+
+    fn record<T>(
+        &mut self,
+        command: JtagRequest,
+        call: impl FnOnce(&mut Box<dyn Jtag>) -> Result<T>,
+        response: impl FnOnce(&T) -> JtagResponse,
+    ) -> Result<T> {
+        let result = call(self.inner());
+        self.recorder
+            .record(Request::Jtag { command }, result, |value| {
+                Response::Jtag(response(value))
+            })
+    }
+}
+
+impl Drop for RecordingJtag {
+    fn drop(&mut self) {
+        // The replaying client disconnects when dropped, and so does the underlying `Jtag`.
+        if self.inner.take().is_some() {
+            let _ = self.recorder.record(
+                Request::Jtag {
+                    command: JtagRequest::Disconnect,
+                },
+                Ok(()),
+                |_| Response::Jtag(JtagResponse::Disconnect),
+            );
+        }
+    }
+}
+
+impl Jtag for RecordingJtag {
+    fn into_raw(mut self: Box<Self>) -> Result<OpenOcd> {
+        // Not expressible in the session protocol.
+        self.inner.take().unwrap().into_raw()
+    }
+
+    fn as_raw(&mut self) -> Result<&mut OpenOcd> {
+        // Not expressible in the session protocol.
+        self.inner().as_raw()
+    }
+
+    fn disconnect(mut self: Box<Self>) -> Result<()> {
+        let inner = self.inner.take().unwrap();
+        self.recorder.record(
+            Request::Jtag {
+                command: JtagRequest::Disconnect,
+            },
+            inner.disconnect(),
+            |_| Response::Jtag(JtagResponse::Disconnect),
+        )
+    }
+
+    fn tap(&self) -> JtagTap {
+        self.inner.as_ref().unwrap().tap()
+    }
+
+    fn read_lc_ctrl_reg(&mut self, reg: &LcCtrlReg) -> Result<u32> {
+        self.record(
+            JtagRequest::ReadLcCtrlReg { reg: reg.clone() },
+            |jtag| jtag.read_lc_ctrl_reg(reg),
+            |value| JtagResponse::ReadLcCtrlReg { value: *value },
+        )
+    }
+
+    fn write_lc_ctrl_reg(&mut self, reg: &LcCtrlReg, value: u32) -> Result<()> {
+        self.record(
+            JtagRequest::WriteLcCtrlReg {
+                reg: reg.clone(),
+                value,
+            },
+            |jtag| jtag.write_lc_ctrl_reg(reg, value),
+            |_| JtagResponse::WriteLcCtrlReg,
+        )
+    }
+
+    fn read_memory(&mut self, addr: u32, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len() as u32;
+        let result = self.inner().read_memory(addr, buf);
+        self.recorder.record(
+            Request::Jtag {
+                command: JtagRequest::ReadMemory { addr, len },
+            },
+            result,
+            |count| {
+                Response::Jtag(JtagResponse::ReadMemory {
+                    data: buf[..*count].to_vec(),
+                })
+            },
+        )
+    }
+
+    fn read_memory32(&mut self, addr: u32, buf: &mut [u32]) -> Result<usize> {
+        let len = buf.len() as u32;
+        let result = self.inner().read_memory32(addr, buf);
+        self.recorder.record(
+            Request::Jtag {
+                command: JtagRequest::ReadMemory32 { addr, len },
+            },
+            result,
+            |count| {
+                Response::Jtag(JtagResponse::ReadMemory32 {
+                    data: buf[..*count].to_vec(),
+                })
+            },
+        )
+    }
+
+    fn write_memory(&mut self, addr: u32, buf: &[u8]) -> Result<()> {
+        self.record(
+            JtagRequest::WriteMemory {
+                addr,
+                data: buf.to_vec(),
+            },
+            |jtag| jtag.write_memory(addr, buf),
+            |_| JtagResponse::WriteMemory,
+        )
+    }
+
+    fn write_memory32(&mut self, addr: u32, buf: &[u32]) -> Result<()> {
+        self.record(
+            JtagRequest::WriteMemory32 {
+                addr,
+                data: buf.to_vec(),
+            },
+            |jtag| jtag.write_memory32(addr, buf),
+            |_| JtagResponse::WriteMemory32,
+        )
+    }
+
+    fn halt(&mut self) -> Result<()> {
+        self.record(
+            JtagRequest::Halt,
+            |jtag| jtag.halt(),
+            |_| JtagResponse::Halt,
+        )
+    }
+
+    fn wait_halt(&mut self, timeout: Duration) -> Result<()> {
+        self.record(
+            JtagRequest::WaitHalt {
+                timeout_millis: timeout.as_millis() as u64,
+            },
+            |jtag| jtag.wait_halt(timeout),
+            |_| JtagResponse::WaitHalt,
+        )
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.record(
+            JtagRequest::Resume,
+            |jtag| jtag.resume(),
+            |_| JtagResponse::Resume,
+        )
+    }
+
+    fn resume_at(&mut self, addr: u32) -> Result<()> {
+        self.record(
+            JtagRequest::ResumeAt { addr },
+            |jtag| jtag.resume_at(addr),
+            |_| JtagResponse::ResumeAt,
+        )
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.record(
+            JtagRequest::Step,
+            |jtag| jtag.step(),
+            |_| JtagResponse::Step,
+        )
+    }
+
+    fn step_at(&mut self, addr: u32) -> Result<()> {
+        self.record(
+            JtagRequest::StepAt { addr },
+            |jtag| jtag.step_at(addr),
+            |_| JtagResponse::StepAt,
+        )
+    }
+
+    fn reset(&mut self, run: bool) -> Result<()> {
+        self.record(
+            JtagRequest::Reset { run },
+            |jtag| jtag.reset(run),
+            |_| JtagResponse::Reset,
+        )
+    }
+
+    fn read_riscv_reg(&mut self, reg: &RiscvReg) -> Result<u32> {
+        self.record(
+            JtagRequest::ReadRiscvReg { reg: *reg },
+            |jtag| jtag.read_riscv_reg(reg),
+            |value| JtagResponse::ReadRiscvReg { value: *value },
+        )
+    }
+
+    fn write_riscv_reg(&mut self, reg: &RiscvReg, value: u32) -> Result<()> {
+        self.record(
+            JtagRequest::WriteRiscvReg { reg: *reg, value },
+            |jtag| jtag.write_riscv_reg(reg, value),
+            |_| JtagResponse::WriteRiscvReg,
+        )
+    }
+
+    fn set_breakpoint(&mut self, addr: u32, hw: bool) -> Result<()> {
+        self.record(
+            JtagRequest::SetBreakpoint { addr, hw },
+            |jtag| jtag.set_breakpoint(addr, hw),
+            |_| JtagResponse::SetBreakpoint,
+        )
+    }
+
+    fn remove_breakpoint(&mut self, addr: u32) -> Result<()> {
+        self.record(
+            JtagRequest::RemoveBreakpoint { addr },
+            |jtag| jtag.remove_breakpoint(addr),
+            |_| JtagResponse::RemoveBreakpoint,
+        )
+    }
+
+    fn remove_all_breakpoints(&mut self) -> Result<()> {
+        self.record(
+            JtagRequest::RemoveAllBreakpoints,
+            |jtag| jtag.remove_all_breakpoints(),
+            |_| JtagResponse::RemoveAllBreakpoints,
+        )
+    }
+}
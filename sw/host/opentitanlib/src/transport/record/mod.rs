@@ -0,0 +1,264 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording of the operations performed on a transport, for later replay by means of
+//! [`crate::transport::proxy::Replay`].
+//!
+//! Each operation on a `Uart`, `Target`, `Bus`, `GpioPin` or `Jtag` is recorded in the form of
+//! the request and response that would have been exchanged with a session process, had the
+//! transport been accessed through `opentitansession`.  This way, the replaying transport can
+//! reuse the client side of the session protocol, and produce exactly the same requests as were
+//! recorded, as long as the host-side logic is deterministic.
+//!
+//! Trait methods with default implementations which the session protocol does not forward
+//! (such as `Uart::clear_rx_buffer()`) are left to their default, so that they are recorded as
+//! the more primitive operations they are composed of.  Operations which cannot be expressed in
+//! the session protocol at all (such as raw OpenOCD access) are passed through without being
+//! recorded, and will fail when replayed.
+//!
+//! The proxy-only operations (`ProxyOps`) are not offered, even if the underlying transport is a
+//! session process, such that host-side logic takes the same path when recording as when
+//! replaying, e.g. applying pin strappings one pin at a time.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::io::emu::Emulator;
+use crate::io::gpio::{GpioBitbanging, GpioMonitoring, GpioPin};
+use crate::io::i2c::Bus;
+use crate::io::jtag::{JtagChain, JtagParams};
+use crate::io::nonblocking_help::NonblockingHelp;
+use crate::io::spi::Target;
+use crate::io::uart::Uart;
+use crate::proxy::errors::SerializedError;
+use crate::proxy::protocol::{Request, Response};
+use crate::transport::{Capabilities, Capability, Transport};
+
+mod gpio;
+mod i2c;
+mod jtag;
+mod spi;
+mod uart;
+
+/// One line of a trace file, as read back for replay.  The request is kept in its generic JSON
+/// form, for comparison with the requests of the replaying client.
+#[derive(Deserialize)]
+pub struct TraceEntry {
+    pub request: serde_json::Value,
+    pub response: Result<Response, SerializedError>,
+}
+
+/// One line of a trace file, as written during recording.
+#[derive(Serialize)]
+struct TraceEntryRef<'a> {
+    request: &'a Request,
+    response: Result<&'a Response, &'a SerializedError>,
+}
+
+/// Writer of a trace file, consisting of one JSON encoded `TraceEntry` per line.
+pub struct Recorder {
+    writer: RefCell<BufWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("creating trace {path:?}"))?;
+        Ok(Self {
+            writer: RefCell::new(BufWriter::new(file)),
+        })
+    }
+
+    /// Records `request` together with the outcome of having performed it, `response` being
+    /// used to express any successful result in protocol terms.  The result is passed on to the
+    /// caller, any error having been converted to and from its serialized form, in the same way
+    /// as it would have been by the session protocol.
+    fn record<T>(
+        &self,
+        request: Request,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> Response,
+    ) -> Result<T> {
+        match result {
+            Ok(value) => {
+                self.write(&request, Ok(&response(&value)))?;
+                Ok(value)
+            }
+            Err(e) => {
+                let e = SerializedError::from(e);
+                self.write(&request, Err(&e))?;
+                Err(e.into())
+            }
+        }
+    }
+
+    fn write(
+        &self,
+        request: &Request,
+        response: Result<&Response, &SerializedError>,
+    ) -> Result<()> {
+        let mut writer = self.writer.borrow_mut();
+        serde_json::to_writer(&mut *writer, &TraceEntryRef { request, response })?;
+        // Flush after every entry, such that the trace is complete even if the process is
+        // killed.
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Transport decorator recording every operation on the interfaces of the underlying transport
+/// into a trace file.
+pub struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    recorder: Rc<Recorder>,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Box<dyn Transport>, trace: &Path) -> Result<Self> {
+        Ok(Self {
+            inner,
+            recorder: Rc::new(Recorder::create(trace)?),
+        })
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn capabilities(&self) -> Result<Capabilities> {
+        // Neither the recording nor the replaying transport offer the proxy-only operations.
+        let capabilities = self.inner.capabilities()?.capabilities & !Capability::PROXY;
+        self.recorder.record(
+            Request::GetCapabilities,
+            Ok(Capabilities::new(capabilities)),
+            |_| Response::GetCapabilities(Capabilities::new(capabilities)),
+        )
+    }
+
+    fn apply_default_configuration(&self) -> Result<()> {
+        self.recorder.record(
+            Request::ApplyDefaultConfiguration,
+            self.inner.apply_default_configuration(),
+            |_| Response::ApplyDefaultConfiguration,
+        )
+    }
+
+    fn reconnect(&self) -> Result<()> {
+        self.recorder
+            .record(Request::Reconnect, self.inner.reconnect(), |_| {
+                Response::Reconnect
+            })
+    }
+
+    fn jtag(&self, opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
+        Ok(Box::new(jtag::RecordingJtagChain::new(
+            self.inner.jtag(opts)?,
+            &self.recorder,
+            opts,
+        )))
+    }
+
+    fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+        Ok(Rc::new(spi::RecordingSpi::new(
+            self.inner.spi(instance)?,
+            &self.recorder,
+            instance,
+        )))
+    }
+
+    fn i2c(&self, instance: &str) -> Result<Rc<dyn Bus>> {
+        Ok(Rc::new(i2c::RecordingI2c::new(
+            self.inner.i2c(instance)?,
+            &self.recorder,
+            instance,
+        )))
+    }
+
+    fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
+        Ok(Rc::new(uart::RecordingUart::new(
+            self.inner.uart(instance)?,
+            &self.recorder,
+            instance,
+        )))
+    }
+
+    fn gpio_pin(&self, instance: &str) -> Result<Rc<dyn GpioPin>> {
+        Ok(Rc::new(gpio::RecordingGpioPin::new(
+            self.inner.gpio_pin(instance)?,
+            &self.recorder,
+            instance,
+        )))
+    }
+
+    // The remaining interfaces are not recorded.
+
+    fn gpio_monitoring(&self) -> Result<Rc<dyn GpioMonitoring>> {
+        self.inner.gpio_monitoring()
+    }
+
+    fn gpio_bitbanging(&self) -> Result<Rc<dyn GpioBitbanging>> {
+        self.inner.gpio_bitbanging()
+    }
+
+    fn emulator(&self) -> Result<Rc<dyn Emulator>> {
+        self.inner.emulator()
+    }
+
+    fn dispatch(&self, action: &dyn Any) -> Result<Option<Box<dyn serde_annotate::Annotate>>> {
+        self.inner.dispatch(action)
+    }
+
+    fn nonblocking_help(&self) -> Result<Rc<dyn NonblockingHelp>> {
+        self.inner.nonblocking_help()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::proxy::Replay;
+    use crate::transport::sim::{Options, Sim};
+
+    /// Stands in for a session process, which offers the proxy-only operations.
+    struct Session(Sim);
+
+    impl Transport for Session {
+        fn capabilities(&self) -> Result<Capabilities> {
+            Ok(self.0.capabilities()?.add(Capability::PROXY))
+        }
+
+        fn reconnect(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
+            self.0.uart(instance)
+        }
+    }
+
+    fn exercise(transport: &dyn Transport) -> Result<()> {
+        // Both recording and replay must steer clear of the unrecorded proxy-only operations.
+        let capabilities = transport.capabilities()?;
+        assert!(capabilities.request(Capability::PROXY).ok().is_err());
+        transport.reconnect()?;
+        let uart = transport.uart("0")?;
+        uart.set_break(true)?;
+        uart.set_break(false)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_replay() -> Result<()> {
+        let trace = std::env::temp_dir().join(format!("record_test_{}.jsonl", std::process::id()));
+        let session = Session(Sim::new(Options::default())?);
+        exercise(&RecordingTransport::new(Box::new(session), &trace)?)?;
+        let result = exercise(&Replay::open(&trace)?);
+        std::fs::remove_file(&trace)?;
+        result
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, ensure, Result};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::Recorder;
+use crate::io::gpio;
+use crate::io::spi::{
+    AssertChipSelect, MaxSizes, SpiError, Target, TargetChipDeassert, Transfer, TransferMode,
+};
+use crate::proxy::protocol::{
+    Request, Response, SpiRequest, SpiResponse, SpiTransferRequest, SpiTransferResponse,
+};
+use crate::util::voltage::Voltage;
+
+pub struct RecordingSpi {
+    inner: Rc<dyn Target>,
+    recorder: Rc<Recorder>,
+    instance: String,
+    // Chip select assertions of the underlying target, one per `AssertChipSelect` handed out.
+    chip_select: RefCell<Vec<AssertChipSelect>>,
+}
+
+impl RecordingSpi {
+    pub fn new(inner: Rc<dyn Target>, recorder: &Rc<Recorder>, instance: &str) -> Self {
+        Self {
+            inner,
+            recorder: Rc::clone(recorder),
+            instance: instance.to_string(),
+            chip_select: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn record<T>(
+        &self,
+        command: SpiRequest,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> SpiResponse,
+    ) -> Result<T> {
+        self.recorder.record(
+            Request::Spi {
+                id: self.instance.clone(),
+                command,
+            },
+            result,
+            |value| Response::Spi(response(value)),
+        )
+    }
+}
+
+fn spi_pin_name(pin: Option<&Rc<dyn gpio::GpioPin>>) -> Result<Option<String>> {
+    if let Some(pin) = pin {
+        let Some(name) = pin.get_internal_pin_name() else {
+            bail!(SpiError::InvalidPin)
+        };
+        Ok(Some(name.to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+impl Target for RecordingSpi {
+    fn get_transfer_mode(&self) -> Result<TransferMode> {
+        self.record(
+            SpiRequest::GetTransferMode,
+            self.inner.get_transfer_mode(),
+            |mode| SpiResponse::GetTransferMode { mode: *mode },
+        )
+    }
+
+    fn set_transfer_mode(&self, mode: TransferMode) -> Result<()> {
+        self.record(
+            SpiRequest::SetTransferMode { mode },
+            self.inner.set_transfer_mode(mode),
+            |_| SpiResponse::SetTransferMode,
+        )
+    }
+
+    fn get_bits_per_word(&self) -> Result<u32> {
+        self.record(
+            SpiRequest::GetBitsPerWord,
+            self.inner.get_bits_per_word(),
+            |bits_per_word| SpiResponse::GetBitsPerWord {
+                bits_per_word: *bits_per_word,
+            },
+        )
+    }
+    fn set_bits_per_word(&self, bits_per_word: u32) -> Result<()> {
+        self.record(
+            SpiRequest::SetBitsPerWord { bits_per_word },
+            self.inner.set_bits_per_word(bits_per_word),
+            |_| SpiResponse::SetBitsPerWord,
+        )
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        self.record(
+            SpiRequest::GetMaxSpeed,
+            self.inner.get_max_speed(),
+            |speed| SpiResponse::GetMaxSpeed { speed: *speed },
+        )
+    }
+    fn set_max_speed(&self, value: u32) -> Result<()> {
+        self.record(
+            SpiRequest::SetMaxSpeed { value },
+            self.inner.set_max_speed(value),
+            |_| SpiResponse::SetMaxSpeed,
+        )
+    }
+
+    fn supports_bidirectional_transfer(&self) -> Result<bool> {
+        self.record(
+            SpiRequest::SupportsBidirectionalTransfer,
+            self.inner.supports_bidirectional_transfer(),
+            |has_support| SpiResponse::SupportsBidirectionalTransfer {
+                has_support: *has_support,
+            },
+        )
+    }
+
+    fn set_pins(
+        &self,
+        serial_clock: Option<&Rc<dyn gpio::GpioPin>>,
+        host_out_device_in: Option<&Rc<dyn gpio::GpioPin>>,
+        host_in_device_out: Option<&Rc<dyn gpio::GpioPin>>,
+        chip_select: Option<&Rc<dyn gpio::GpioPin>>,
+    ) -> Result<()> {
+        let command = SpiRequest::SetPins {
+            serial_clock: spi_pin_name(serial_clock)?,
+            host_out_device_in: spi_pin_name(host_out_device_in)?,
+            host_in_device_out: spi_pin_name(host_in_device_out)?,
+            chip_select: spi_pin_name(chip_select)?,
+        };
+        self.record(
+            command,
+            self.inner.set_pins(
+                serial_clock,
+                host_out_device_in,
+                host_in_device_out,
+                chip_select,
+            ),
+            |_| SpiResponse::SetPins,
+        )
+    }
+
+    fn get_max_transfer_count(&self) -> Result<usize> {
+        self.record(
+            SpiRequest::GetMaxTransferCount,
+            self.inner.get_max_transfer_count(),
+            |number| SpiResponse::GetMaxTransferCount { number: *number },
+        )
+    }
+
+    fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        self.record(
+            SpiRequest::GetMaxTransferSizes,
+            self.inner.get_max_transfer_sizes(),
+            |sizes| SpiResponse::GetMaxTransferSizes { sizes: *sizes },
+        )
+    }
+
+    fn get_eeprom_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        self.record(
+            SpiRequest::GetEepromMaxTransferSizes,
+            self.inner.get_eeprom_max_transfer_sizes(),
+            |sizes| SpiResponse::GetEepromMaxTransferSizes { sizes: *sizes },
+        )
+    }
+
+    fn set_voltage(&self, voltage: Voltage) -> Result<()> {
+        self.record(
+            SpiRequest::SetVoltage { voltage },
+            self.inner.set_voltage(voltage),
+            |_| SpiResponse::SetVoltage,
+        )
+    }
+
+    fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+        let mut req: Vec<SpiTransferRequest> = Vec::new();
+        for transfer in transaction.iter() {
+            match transfer {
+                Transfer::Read(rbuf) => req.push(SpiTransferRequest::Read {
+                    len: rbuf.len() as u32,
+                }),
+                Transfer::Write(wbuf) => req.push(SpiTransferRequest::Write {
+                    data: wbuf.to_vec(),
+                }),
+                Transfer::Both(wbuf, rbuf) => {
+                    ensure!(
+                        rbuf.len() == wbuf.len(),
+                        SpiError::MismatchedDataLength(wbuf.len(), rbuf.len())
+                    );
+                    req.push(SpiTransferRequest::Both {
+                        data: wbuf.to_vec(),
+                    })
+                }
+            }
+        }
+        let result = self.inner.run_transaction(transaction);
+        self.record(
+            SpiRequest::RunTransaction { transaction: req },
+            result,
+            |_| SpiResponse::RunTransaction {
+                transaction: transaction
+                    .iter()
+                    .map(|transfer| match transfer {
+                        Transfer::Read(rbuf) => SpiTransferResponse::Read {
+                            data: rbuf.to_vec(),
+                        },
+                        Transfer::Write(_) => SpiTransferResponse::Write,
+                        Transfer::Both(_, rbuf) => SpiTransferResponse::Both {
+                            data: rbuf.to_vec(),
+                        },
+                    })
+                    .collect(),
+            },
+        )
+    }
+
+    fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+        let result = Rc::clone(&self.inner).assert_cs();
+        self.record(SpiRequest::AssertChipSelect, result, |_| {
+            SpiResponse::AssertChipSelect
+        })
+        .map(|assertion| {
+            self.chip_select.borrow_mut().push(assertion);
+            AssertChipSelect::new(self)
+        })
+    }
+}
+
+impl TargetChipDeassert for RecordingSpi {
+    fn deassert_cs(&self) {
+        // Dropping the assertion of the underlying target deasserts chip select there.
+        // `Drop::drop()` cannot propagate errors, so the deassertion is always recorded as
+        // successful.
+        self.chip_select.borrow_mut().pop();
+        self.record(SpiRequest::DeassertChipSelect, Ok(()), |_| {
+            SpiResponse::DeassertChipSelect
+        })
+        .expect("Error recording chip select");
+    }
+}
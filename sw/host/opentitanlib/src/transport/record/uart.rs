@@ -0,0 +1,143 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::Recorder;
+use crate::io::uart::{Parity, Uart};
+use crate::proxy::protocol::{Request, Response, UartRequest, UartResponse};
+
+pub struct RecordingUart {
+    inner: Rc<dyn Uart>,
+    recorder: Rc<Recorder>,
+    instance: String,
+}
+
+impl RecordingUart {
+    pub fn new(inner: Rc<dyn Uart>, recorder: &Rc<Recorder>, instance: &str) -> Self {
+        Self {
+            inner,
+            recorder: Rc::clone(recorder),
+            instance: instance.to_string(),
+        }
+    }
+
+    fn record<T>(
+        &self,
+        command: UartRequest,
+        result: Result<T>,
+        response: impl FnOnce(&T) -> UartResponse,
+    ) -> Result<T> {
+        self.recorder.record(
+            Request::Uart {
+                id: self.instance.clone(),
+                command,
+            },
+            result,
+            |value| Response::Uart(response(value)),
+        )
+    }
+}
+
+impl Uart for RecordingUart {
+    fn get_baudrate(&self) -> Result<u32> {
+        self.record(
+            UartRequest::GetBaudrate,
+            self.inner.get_baudrate(),
+            |rate| UartResponse::GetBaudrate { rate: *rate },
+        )
+    }
+
+    fn set_baudrate(&self, rate: u32) -> Result<()> {
+        self.record(
+            UartRequest::SetBaudrate { rate },
+            self.inner.set_baudrate(rate),
+            |_| UartResponse::SetBaudrate,
+        )
+    }
+
+    fn set_flow_control(&self, flow_control: bool) -> Result<()> {
+        self.record(
+            UartRequest::SetFlowControl(flow_control),
+            self.inner.set_flow_control(flow_control),
+            |_| UartResponse::SetFlowControl,
+        )
+    }
+
+    fn set_hardware_flow_control(&self, enable: bool) -> Result<()> {
+        self.record(
+            UartRequest::SetHardwareFlowControl(enable),
+            self.inner.set_hardware_flow_control(enable),
+            |_| UartResponse::SetHardwareFlowControl,
+        )
+    }
+
+    fn set_parity(&self, parity: Parity) -> Result<()> {
+        self.record(
+            UartRequest::SetParity(parity),
+            self.inner.set_parity(parity),
+            |_| UartResponse::SetParity,
+        )
+    }
+
+    fn set_break(&self, enable: bool) -> Result<()> {
+        self.record(
+            UartRequest::SetBreak(enable),
+            self.inner.set_break(enable),
+            |_| UartResponse::SetBreak,
+        )
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len() as u32;
+        let result = self.inner.read(buf);
+        self.record(
+            UartRequest::Read {
+                timeout_millis: None,
+                len,
+            },
+            result,
+            |n| UartResponse::Read {
+                data: buf[..*n].to_vec(),
+            },
+        )
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let len = buf.len() as u32;
+        let result = self.inner.read_timeout(buf, timeout);
+        self.record(
+            UartRequest::Read {
+                timeout_millis: Some(timeout.as_millis() as u32),
+                len,
+            },
+            result,
+            |n| UartResponse::Read {
+                data: buf[..*n].to_vec(),
+            },
+        )
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        self.record(
+            UartRequest::Write { data: buf.to_vec() },
+            self.inner.write(buf),
+            |_| UartResponse::Write,
+        )
+    }
+
+    fn supports_nonblocking_read(&self) -> Result<bool> {
+        // Nonblocking reads happen at the discretion of the event loop, which would make the
+        // trace depend on timing.  Make users fall back to ordinary reads instead.
+        self.record(
+            UartRequest::SupportsNonblockingRead,
+            Ok(false),
+            |has_support| UartResponse::SupportsNonblockingRead {
+                has_support: *has_support,
+            },
+        )
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::io::gpio::{GpioError, GpioPin, PinMode, PullMode};
+use crate::transport::sim::Inner;
+use crate::transport::TransportError;
+
+/// State of a single pin of the simulated chip, combining what the host drives onto it with
+/// the level driven by the chip itself.
+pub(crate) struct PinState {
+    mode: PinMode,
+    level: bool,
+    pull: PullMode,
+    target_level: bool,
+}
+
+impl PinState {
+    pub fn new(target_level: bool) -> Self {
+        PinState {
+            mode: PinMode::Input,
+            level: false,
+            pull: PullMode::None,
+            target_level,
+        }
+    }
+
+    /// Returns the effective logic level on the wire.
+    pub fn level(&self) -> bool {
+        match (self.mode, self.level, self.pull) {
+            (PinMode::PushPull, level, _) => level,
+            (PinMode::OpenDrain, false, _) => false,
+            (_, _, PullMode::PullUp) => true,
+            (_, _, PullMode::PullDown) => false,
+            _ => self.target_level,
+        }
+    }
+
+    pub fn set_target_level(&mut self, level: bool) {
+        self.target_level = level;
+    }
+
+    /// Stops the host from driving the pin.
+    pub fn release(&mut self) {
+        self.mode = PinMode::Input;
+        self.level = false;
+        self.pull = PullMode::None;
+    }
+}
+
+/// Host-side view of a pin of the simulated chip.
+pub struct SimGpioPin {
+    inner: Rc<RefCell<Inner>>,
+    pinname: String,
+}
+
+impl SimGpioPin {
+    pub(crate) fn new(inner: Rc<RefCell<Inner>>, pinname: String) -> Self {
+        SimGpioPin { inner, pinname }
+    }
+}
+
+impl GpioPin for SimGpioPin {
+    fn read(&self) -> Result<bool> {
+        Ok(self.inner.borrow().pin_level(&self.pinname))
+    }
+
+    fn write(&self, value: bool) -> Result<()> {
+        self.set(None, Some(value), None, None)
+    }
+
+    fn set_mode(&self, mode: PinMode) -> Result<()> {
+        self.set(Some(mode), None, None, None)
+    }
+
+    fn set_pull_mode(&self, mode: PullMode) -> Result<()> {
+        self.set(None, None, Some(mode), None)
+    }
+
+    /// Atomically sets mode, value, and weak pull, and lets the simulated chip react to the
+    /// resulting level.
+    fn set(
+        &self,
+        mode: Option<PinMode>,
+        value: Option<bool>,
+        pull: Option<PullMode>,
+        analog_value: Option<f32>,
+    ) -> Result<()> {
+        if analog_value.is_some() {
+            return Err(TransportError::UnsupportedOperation.into());
+        }
+        let mut inner = self.inner.borrow_mut();
+        let pin = inner.pin_mut(&self.pinname);
+        if let Some(mode) = mode {
+            match mode {
+                PinMode::Input | PinMode::PushPull | PinMode::OpenDrain => pin.mode = mode,
+                _ => return Err(GpioError::UnsupportedPinMode(mode).into()),
+            }
+        }
+        if let Some(value) = value {
+            pin.level = value;
+        }
+        if let Some(pull) = pull {
+            pin.pull = pull;
+        }
+        inner.update_reset();
+        Ok(())
+    }
+}
@@ -0,0 +1,475 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! In-process model of an OpenTitan target, for exercising host-side logic (bootstrapping,
+//! rescue, console interaction and reset sequencing) without any hardware attached.
+
+use anyhow::{ensure, Result};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::io::gpio::GpioPin;
+use crate::io::spi::Target;
+use crate::io::uart::Uart;
+use crate::transport::{
+    Capabilities, Capability, Transport, TransportError, TransportInterfaceType,
+};
+
+mod gpio;
+mod spi;
+mod uart;
+
+pub use uart::{ConsoleResponse, ConsoleScript};
+
+use crate::transport::sim::gpio::{PinState, SimGpioPin};
+use crate::transport::sim::spi::{FlashState, SimSpi};
+use crate::transport::sim::uart::{ConsoleState, SimUart};
+
+/// Name of the pin which holds the simulated chip in reset while low.
+const RESET_PIN: &str = "RESET";
+/// Pins sampled when the simulated chip comes out of reset, all three high selects bootstrap.
+const SW_STRAP_PINS: [&str; 3] = ["IOC0", "IOC1", "IOC2"];
+/// Strap levels selecting rescue, matching the `RESCUE` strapping of `opentitan_sim.json`.
+const RESCUE_STRAPS: [bool; 3] = [true, false, true];
+
+/// Startup options for the simulated target.
+pub struct Options {
+    /// Size of the simulated flash in bytes.
+    pub flash_size: usize,
+    /// Initial content of the simulated flash, the remainder is erased (0xff).
+    pub flash_image: Vec<u8>,
+    /// Whether bytes written by the host to the console UART are echoed back.
+    pub uart_loopback: bool,
+    /// Scripted console behavior.
+    pub console_script: ConsoleScript,
+    /// Whether the SPI flash follows the semantics of the ROM bootstrap: the first erase command
+    /// erases the whole flash, programming is ignored before that, read commands are not served
+    /// and the JEDEC ID has the ROM bootstrap bit set.
+    pub rom_bootstrap: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            flash_size: 1024 * 1024,
+            flash_image: Vec::new(),
+            uart_loopback: false,
+            console_script: ConsoleScript::default(),
+            rom_bootstrap: false,
+        }
+    }
+}
+
+/// The mode in which the simulated chip booted when last released from reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BootMode {
+    /// Regular boot, the console emits the boot banner and serves scripted responses.  Data
+    /// sent by the host as SPI console frames is received as if sent on the console UART, and
+    /// console output not yet read from the UART is served to the host as SPI console frames.
+    Normal,
+    /// ROM bootstrap, the SPI flash accepts EEPROM erase/program commands.
+    Bootstrap,
+    /// Rescue mode, entered by holding UART break or the rescue straps while releasing reset.
+    /// Rescue is served both on the console UART and on the SPI EEPROM interface.
+    Rescue,
+}
+
+/// Internal state of the simulated chip, shared by the individual interface objects.
+pub(crate) struct Inner {
+    pins: HashMap<String, PinState>,
+    in_reset: bool,
+    boot_mode: BootMode,
+    boot_count: usize,
+    console: ConsoleState,
+    flash: FlashState,
+}
+
+impl Inner {
+    fn pin_level(&self, name: &str) -> bool {
+        match self.pins.get(name) {
+            Some(pin) => pin.level(),
+            None => PinState::new(name == RESET_PIN).level(),
+        }
+    }
+
+    fn pin_mut(&mut self, name: &str) -> &mut PinState {
+        self.pins
+            .entry(name.to_string())
+            .or_insert_with(|| PinState::new(name == RESET_PIN))
+    }
+
+    /// Inspects the level of the reset pin, entering reset or booting as appropriate.
+    fn update_reset(&mut self) {
+        let reset_released = self.pin_level(RESET_PIN);
+        if !self.in_reset && !reset_released {
+            log::debug!("Simulated target entering reset");
+            self.in_reset = true;
+            self.console.reset();
+            self.flash.reset();
+        } else if self.in_reset && reset_released {
+            self.in_reset = false;
+            self.boot();
+        }
+    }
+
+    /// Samples straps and UART break, and performs the boot of the simulated chip.
+    fn boot(&mut self) {
+        self.boot_count += 1;
+        let straps = SW_STRAP_PINS.map(|pin| self.pin_level(pin));
+        self.boot_mode = if straps == [true; 3] {
+            BootMode::Bootstrap
+        } else if straps == RESCUE_STRAPS || self.console.break_asserted() {
+            BootMode::Rescue
+        } else {
+            BootMode::Normal
+        };
+        log::debug!("Simulated target booting in {:?} mode", self.boot_mode);
+        self.flash.set_mode(self.boot_mode);
+        self.console.boot(self.boot_mode);
+    }
+
+    /// Resets the simulated chip from within, without involving the reset pin.
+    fn reboot(&mut self) {
+        log::debug!("Simulated target reset via SPI");
+        self.console.reset();
+        self.flash.reset();
+        self.boot();
+    }
+}
+
+/// Handle for inspecting and manipulating the simulated chip "from the other side", that is,
+/// from the perspective of the device rather than the host.  Typically used by tests to verify
+/// the effects of host-side logic.
+#[derive(Clone)]
+pub struct SimChip {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl SimChip {
+    /// Returns a copy of the entire content of the simulated flash.
+    pub fn flash(&self) -> Vec<u8> {
+        self.inner.borrow().flash.data().to_vec()
+    }
+
+    /// Overwrites part of the simulated flash, bypassing the EEPROM protocol.
+    pub fn write_flash(&self, offset: usize, data: &[u8]) -> Result<()> {
+        self.inner.borrow_mut().flash.write(offset, data)
+    }
+
+    /// Queues data as if transmitted by the simulated chip on its console UART.
+    pub fn console_write(&self, data: &[u8]) {
+        self.inner.borrow_mut().console.emit(data)
+    }
+
+    /// Returns (and forgets) all data received by the simulated chip on its console UART.
+    pub fn console_received(&self) -> Vec<u8> {
+        self.inner.borrow_mut().console.take_received()
+    }
+
+    /// Returns the data most recently uploaded via rescue in the given mode (e.g. "RESQ").
+    pub fn rescue_upload(&self, mode: &str) -> Option<Vec<u8>> {
+        self.inner.borrow().console.rescue_upload(mode)
+    }
+
+    /// Sets the data to be served via rescue in the given mode (e.g. "BLOG").
+    pub fn set_rescue_download(&self, mode: &str, data: Vec<u8>) {
+        self.inner
+            .borrow_mut()
+            .console
+            .set_rescue_download(mode, data)
+    }
+
+    /// Returns the logic level of the given pin, as seen by the simulated chip.
+    pub fn pin_level(&self, name: &str) -> bool {
+        self.inner.borrow().pin_level(&name.to_uppercase())
+    }
+
+    /// Sets the level which the simulated chip drives onto the given pin, visible to the host
+    /// when it does not itself drive the pin.
+    pub fn set_pin_level(&self, name: &str, level: bool) {
+        let mut inner = self.inner.borrow_mut();
+        inner.pin_mut(&name.to_uppercase()).set_target_level(level);
+        inner.update_reset();
+    }
+
+    /// Returns whether the simulated chip is currently held in reset.
+    pub fn in_reset(&self) -> bool {
+        self.inner.borrow().in_reset
+    }
+
+    /// Returns the mode of the most recent boot.
+    pub fn boot_mode(&self) -> BootMode {
+        self.inner.borrow().boot_mode
+    }
+
+    /// Returns the number of times the simulated chip has been released from reset.
+    pub fn boot_count(&self) -> usize {
+        self.inner.borrow().boot_count
+    }
+}
+
+/// Represents the simulated transport object.
+pub struct Sim {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl Sim {
+    /// Creates a simulated target according to `options`.
+    pub fn new(options: Options) -> Result<Self> {
+        let mut flash = FlashState::new(options.flash_size, options.rom_bootstrap);
+        flash.write(0, &options.flash_image)?;
+        flash.set_mode(BootMode::Normal);
+        let console = ConsoleState::new(options.uart_loopback, &options.console_script)?;
+        Ok(Sim {
+            inner: Rc::new(RefCell::new(Inner {
+                pins: HashMap::new(),
+                in_reset: false,
+                boot_mode: BootMode::Normal,
+                boot_count: 0,
+                console,
+                flash,
+            })),
+        })
+    }
+
+    /// Returns a handle for observing the simulated chip.
+    pub fn chip(&self) -> SimChip {
+        SimChip {
+            inner: Rc::clone(&self.inner),
+        }
+    }
+}
+
+impl Transport for Sim {
+    fn capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::new(
+            Capability::UART | Capability::GPIO | Capability::SPI,
+        ))
+    }
+
+    fn apply_default_configuration(&self) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        for pin in inner.pins.values_mut() {
+            pin.release();
+        }
+        inner.update_reset();
+        Ok(())
+    }
+
+    fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
+        ensure!(
+            instance == "0",
+            TransportError::InvalidInstance(TransportInterfaceType::Uart, instance.to_string())
+        );
+        Ok(Rc::new(SimUart::new(Rc::clone(&self.inner))))
+    }
+
+    fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+        ensure!(
+            instance == "0",
+            TransportError::InvalidInstance(TransportInterfaceType::Spi, instance.to_string())
+        );
+        Ok(Rc::new(SimSpi::new(Rc::clone(&self.inner))))
+    }
+
+    fn gpio_pin(&self, instance: &str) -> Result<Rc<dyn GpioPin>> {
+        Ok(Rc::new(SimGpioPin::new(
+            Rc::clone(&self.inner),
+            instance.to_uppercase(),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::config::process_config_file;
+    use crate::app::{TransportWrapper, TransportWrapperBuilder};
+    use crate::bootstrap::{Bootstrap, BootstrapError, BootstrapOptions};
+    use crate::rescue::serial::RescueSerial;
+    use crate::rescue::spidfu::RescueSpiDfu;
+    use crate::rescue::RescueProtocol;
+    use crate::uart::console::UartConsole;
+    use clap::Parser;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[derive(Parser)]
+    struct Opts {
+        #[command(flatten)]
+        bootstrap: BootstrapOptions,
+    }
+
+    fn sim_transport(options: Options) -> Result<(TransportWrapper, SimChip)> {
+        let sim = Sim::new(options)?;
+        let chip = sim.chip();
+        let mut builder = TransportWrapperBuilder::new("sim".to_string(), false);
+        process_config_file(&mut builder, Path::new("/__builtin__/opentitan_sim.json"))?;
+        let transport = builder.build(Box::new(sim))?;
+        transport.apply_default_configuration(None)?;
+        Ok((transport, chip))
+    }
+
+    #[test]
+    fn test_reset_emits_banner() -> Result<()> {
+        let (transport, chip) = sim_transport(Options {
+            console_script: ConsoleScript {
+                boot_banner: "Hello from sim\r\n".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        })?;
+        let boots = chip.boot_count();
+        transport.reset_target(Duration::from_millis(1), true)?;
+        assert_eq!(chip.boot_count(), boots + 1);
+        assert_eq!(chip.boot_mode(), BootMode::Normal);
+        let uart = transport.uart("console")?;
+        UartConsole::wait_for(&*uart, r"Hello from sim", Duration::from_millis(100))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scripted_console() -> Result<()> {
+        let (transport, chip) = sim_transport(Options {
+            console_script: ConsoleScript {
+                responses: vec![ConsoleResponse {
+                    expect: r"ping (\w+)\r".to_string(),
+                    reply: "pong $1\r\n".to_string(),
+                }],
+                ..Default::default()
+            },
+            ..Default::default()
+        })?;
+        let uart = transport.uart("console")?;
+        uart.write(b"ping abc\r")?;
+        let result = UartConsole::wait_for(&*uart, r"pong (\w+)", Duration::from_millis(100))?;
+        assert_eq!(result[1], "abc");
+        assert_eq!(chip.console_received(), b"ping abc\r");
+        Ok(())
+    }
+
+    #[test]
+    fn test_bootstrap() -> Result<()> {
+        let (transport, chip) = sim_transport(Options::default())?;
+        chip.write_flash(0, &[0u8; 8192])?;
+        let payload = (0..5000).map(|i| i as u8).collect::<Vec<u8>>();
+        let opts = Opts::parse_from(["test", "--reset-delay=1ms"]);
+        Bootstrap::update(&transport, &opts.bootstrap, &payload)?;
+        let flash = chip.flash();
+        assert_eq!(&flash[..payload.len()], &payload[..]);
+        // The chip erase must have cleared the remainder of the previous content.
+        assert!(flash[payload.len()..].iter().all(|&b| b == 0xff));
+        assert_eq!(chip.boot_mode(), BootMode::Normal);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bootstrap_incremental() -> Result<()> {
+        let (transport, chip) = sim_transport(Options::default())?;
+        let old = (0..12288).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        chip.write_flash(0, &old)?;
+        // Content beyond the last sector of the payload is left alone in incremental mode.
+        chip.write_flash(0x10000, b"marker")?;
+        let mut payload = old.clone();
+        payload[5000] ^= 0x55;
+        let opts = Opts::parse_from(["test", "--reset-delay=1ms", "--incremental", "--verify"]);
+        Bootstrap::update(&transport, &opts.bootstrap, &payload)?;
+        let flash = chip.flash();
+        assert_eq!(&flash[..payload.len()], &payload[..]);
+        assert_eq!(&flash[0x10000..0x10006], b"marker");
+        Ok(())
+    }
+
+    #[test]
+    fn test_bootstrap_rom_semantics() -> Result<()> {
+        let (transport, chip) = sim_transport(Options {
+            rom_bootstrap: true,
+            ..Default::default()
+        })?;
+        chip.write_flash(0, &[0u8; 8192])?;
+        let payload = (0..5000).map(|i| i as u8).collect::<Vec<u8>>();
+        let opts = Opts::parse_from(["test", "--reset-delay=1ms"]);
+        Bootstrap::update(&transport, &opts.bootstrap, &payload)?;
+        let flash = chip.flash();
+        assert_eq!(&flash[..payload.len()], &payload[..]);
+        assert!(flash[payload.len()..].iter().all(|&b| b == 0xff));
+
+        // Incremental mode would leave all but the changed sectors erased, and verification
+        // relies on READ, so both must be refused before touching the flash.
+        let mut update = payload.clone();
+        update[100] ^= 0x55;
+        for option in ["--incremental", "--verify"] {
+            let opts = Opts::parse_from(["test", "--reset-delay=1ms", option]);
+            let err = Bootstrap::update(&transport, &opts.bootstrap, &update).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<BootstrapError>(),
+                Some(BootstrapError::UnsupportedByRom(_))
+            ));
+            assert_eq!(chip.flash(), flash);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_bootstrap_unsupported_options() -> Result<()> {
+        let (transport, chip) = sim_transport(Options::default())?;
+        let boots = chip.boot_count();
+        for protocol in ["primitive", "legacy", "legacy-rescue"] {
+            for option in ["--verify", "--incremental", "--base-image=/dev/null"] {
+                let protocol = format!("--protocol={}", protocol);
+                let opts = Opts::parse_from(["test", "--reset-delay=1ms", &protocol, option]);
+                let err = Bootstrap::update(&transport, &opts.bootstrap, &[0u8; 16]).unwrap_err();
+                assert!(matches!(
+                    err.downcast_ref::<BootstrapError>(),
+                    Some(BootstrapError::UnsupportedOption(_, _))
+                ));
+            }
+        }
+        // The options are refused before the target is touched.
+        assert_eq!(chip.boot_count(), boots);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rescue() -> Result<()> {
+        let (transport, chip) = sim_transport(Options::default())?;
+        let uart = transport.uart("console")?;
+        let rescue = RescueSerial::new(Rc::clone(&uart));
+        rescue.enter(&transport)?;
+        assert_eq!(chip.boot_mode(), BootMode::Rescue);
+        let image = (0..3000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        rescue.update_firmware(&image)?;
+        let upload = chip.rescue_upload("RESQ").unwrap();
+        assert_eq!(&upload[..image.len()], &image[..]);
+
+        chip.set_rescue_download("BLOG", b"boot log".to_vec());
+        let blog = rescue.get_boot_log_raw()?;
+        assert_eq!(&blog[..8], b"boot log");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rescue_spi() -> Result<()> {
+        let (transport, chip) = sim_transport(Options::default())?;
+        let rescue = RescueSpiDfu::new(transport.spi("BOOTSTRAP")?);
+        rescue.enter(&transport)?;
+        assert_eq!(chip.boot_mode(), BootMode::Rescue);
+        let image = (0..3000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+        rescue.update_firmware(&image)?;
+        assert_eq!(chip.rescue_upload("RESQ").unwrap(), image);
+
+        chip.set_rescue_download("BLOG", b"boot log".to_vec());
+        let blog = rescue.get_boot_log_raw()?;
+        assert_eq!(&blog[..8], b"boot log");
+
+        // Rebooting while the strapping is held must land in rescue again.
+        let boots = chip.boot_count();
+        rescue.reenter(&transport)?;
+        assert_eq!(chip.boot_count(), boots + 1);
+        assert_eq!(chip.boot_mode(), BootMode::Rescue);
+        Ok(())
+    }
+}
@@ -0,0 +1,556 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{ensure, Result};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::io::spi::{
+    AssertChipSelect, MaxSizes, SpiError, Target, TargetChipDeassert, Transfer, TransferMode,
+};
+use crate::rescue::spidfu::RescueSpiDfu;
+use crate::rescue::RescueMode;
+use crate::spiflash::SpiFlash;
+use crate::transport::sim::{BootMode, Inner};
+
+/// Size of the programming page, page program wraps around within a page.
+const PAGE_SIZE: usize = 256;
+/// Largest flash addressable with three address bytes.
+const MAX_3B_SIZE: usize = 1 << 24;
+/// Value returned by READ_ID.
+const JEDEC_ID: [u8; 3] = [0xef, 0x40, 0x14];
+/// Value returned by READ_ID by the ROM bootstrap: lowRISC's manufacturer ID (in bank 13) and a
+/// device ID with the ROM bootstrap bit set and the log2 of the flash size.
+const ROM_JEDEC_ID: [u8; 15] = [
+    0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0xef, 0x08, 20,
+];
+/// Maximum data size per transfer accepted by the simulated SPI host.
+const MAX_TRANSFER_SIZE: usize = 2048;
+/// Size of the header of SPI console frames, see `crate::console::spi`.
+const CONSOLE_FRAME_HEADER_SIZE: usize = 8;
+/// Maximum data length of SPI console frames sent to the host.
+const CONSOLE_MAX_DATA_LENGTH: usize = 2032;
+/// Opcode byte with which the host starts reading a console frame, followed by three more.
+const CONSOLE_READ: u8 = 0xff;
+
+/// Outcome of a command received on the SPI EEPROM interface.
+pub(crate) enum FlashEvent {
+    None,
+    /// The chip resets.
+    Reset,
+    /// Rescue has been asked to switch to the given mode.
+    RescueMode(String),
+    /// Rescue has received data from the host in the given mode.
+    RescueUpload(String, Vec<u8>),
+    /// The console has received data from the host.
+    ConsoleData(Vec<u8>),
+}
+
+/// State of the SPI EEPROM interface of the simulated chip.  Its role depends on the boot mode:
+/// the flash in bootstrap mode, the rescue buffer in rescue mode, and the console otherwise.
+pub(crate) struct FlashState {
+    data: Vec<u8>,
+    /// Mode of the current boot, `None` while in reset.
+    mode: Option<BootMode>,
+    write_enabled: bool,
+    four_byte: bool,
+    reset_enabled: bool,
+    /// All bytes received since CS was asserted.
+    command: Vec<u8>,
+    cs_asserted_count: u32,
+    transfer_mode: TransferMode,
+    max_speed: u32,
+    /// Whether to mimic the ROM bootstrap rather than a regular SPI flash.
+    rom_bootstrap: bool,
+    /// Whether the ROM bootstrap has received its initial erase command.
+    rom_erased: bool,
+    rescue_mode: String,
+    /// Data being received from the host, or to be read by the host, depending on the mode.
+    rescue_data: Vec<u8>,
+    /// Number of the next console frame sent to the host.
+    console_frame_number: u32,
+    /// Remainder of the console frame being read by the host.
+    console_out: VecDeque<u8>,
+}
+
+impl FlashState {
+    pub fn new(size: usize, rom_bootstrap: bool) -> Self {
+        FlashState {
+            data: vec![0xff; size],
+            mode: None,
+            write_enabled: false,
+            four_byte: size > MAX_3B_SIZE,
+            reset_enabled: false,
+            command: Vec::new(),
+            cs_asserted_count: 0,
+            transfer_mode: TransferMode::Mode0,
+            max_speed: 1_000_000,
+            rom_bootstrap,
+            rom_erased: false,
+            rescue_mode: String::new(),
+            rescue_data: Vec::new(),
+            console_frame_number: 0,
+            console_out: VecDeque::new(),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        ensure!(
+            offset + data.len() <= self.data.len(),
+            SpiError::InvalidDataLength(offset + data.len())
+        );
+        self.data[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn reset(&mut self) {
+        self.mode = None;
+        self.write_enabled = false;
+        self.four_byte = self.data.len() > MAX_3B_SIZE;
+        self.reset_enabled = false;
+        self.rom_erased = false;
+        self.rescue_mode.clear();
+        self.rescue_data.clear();
+        self.command.clear();
+        self.console_frame_number = 0;
+        self.console_out.clear();
+    }
+
+    pub fn set_mode(&mut self, mode: BootMode) {
+        self.mode = Some(mode);
+    }
+
+    /// Sets the data to be read by the host in the current rescue mode.
+    pub fn set_rescue_data(&mut self, data: Vec<u8>) {
+        self.rescue_data = data;
+    }
+
+    /// Whether a new console frame should be queued for the host, that is, when booted normally
+    /// with the previous frame read completely, and a console read about to start with
+    /// `first_byte`.
+    fn wants_console_frame(&self, first_byte: Option<u8>) -> bool {
+        self.mode == Some(BootMode::Normal)
+            && self.command.is_empty()
+            && first_byte == Some(CONSOLE_READ)
+            && self.console_out.is_empty()
+    }
+
+    /// Queues a console frame carrying `data` for reading by the host, see `crate::console::spi`.
+    fn queue_console_frame(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            // Without a frame, the host reads all 0xff, which it discards as junk.
+            return;
+        }
+        self.console_out
+            .extend(self.console_frame_number.to_le_bytes());
+        self.console_out.extend((data.len() as u32).to_le_bytes());
+        self.console_out.extend(data);
+        self.console_out
+            .extend(std::iter::repeat(0xff).take((4 - data.len() % 4) % 4));
+        self.console_frame_number = self.console_frame_number.wrapping_add(1);
+    }
+
+    /// Returns the number of address bytes and dummy bytes following the given opcode, or `None`
+    /// if the opcode is not followed by a data phase to be served by the simulated flash.
+    fn read_header(&self, opcode: u8) -> Option<(usize, usize)> {
+        let addr_len = if self.four_byte { 4 } else { 3 };
+        match opcode {
+            SpiFlash::READ => Some((addr_len, 0)),
+            SpiFlash::FAST_READ => Some((addr_len, 1)),
+            SpiFlash::READ_4B => Some((4, 0)),
+            SpiFlash::FAST_READ_4B => Some((4, 1)),
+            SpiFlash::READ_SFDP => Some((3, 1)),
+            SpiFlash::READ_STATUS
+            | SpiFlash::READ_STATUS2
+            | SpiFlash::READ_STATUS3
+            | SpiFlash::READ_ID => Some((0, 0)),
+            _ => None,
+        }
+    }
+
+    fn address(&self, addr_len: usize) -> usize {
+        self.command[1..1 + addr_len]
+            .iter()
+            .fold(0usize, |acc, &b| (acc << 8) | b as usize)
+    }
+
+    /// Processes one byte from the host, returning the byte clocked out by the simulated flash.
+    fn clock(&mut self, byte: u8) -> u8 {
+        let index = self.command.len();
+        self.command.push(byte);
+        let Some(mode) = self.mode else {
+            return 0xff;
+        };
+        if index == 0 {
+            return 0xff;
+        }
+        let opcode = self.command[0];
+        if mode == BootMode::Normal && opcode == CONSOLE_READ {
+            // The host clocks out four 0xff bytes, then reads the header or data of a frame.
+            return match index {
+                1..=3 => 0xff,
+                _ => self.console_out.pop_front().unwrap_or(0xff),
+            };
+        }
+        let Some((addr_len, dummy_len)) = self.read_header(opcode) else {
+            return 0xff;
+        };
+        let header_len = 1 + addr_len + dummy_len;
+        if index < header_len {
+            return 0xff;
+        }
+        let offset = index - header_len;
+        match opcode {
+            SpiFlash::READ_STATUS => {
+                if self.write_enabled {
+                    SpiFlash::STATUS_WEL
+                } else {
+                    0
+                }
+            }
+            SpiFlash::READ_STATUS2 | SpiFlash::READ_STATUS3 => 0,
+            SpiFlash::READ_ID if self.rom_bootstrap => {
+                ROM_JEDEC_ID.get(offset).copied().unwrap_or(0xff)
+            }
+            SpiFlash::READ_ID => JEDEC_ID.get(offset).copied().unwrap_or(0xff),
+            SpiFlash::READ_SFDP => self
+                .sfdp()
+                .get(self.address(addr_len) + offset)
+                .copied()
+                .unwrap_or(0xff),
+            _ if mode == BootMode::Rescue => self
+                .rescue_data
+                .get(self.address(addr_len) + offset)
+                .copied()
+                .unwrap_or(0xff),
+            // Neither the console nor the ROM bootstrap serve reads of the flash.
+            _ if mode == BootMode::Normal || self.rom_bootstrap => 0xff,
+            _ => self.data[(self.address(addr_len) + offset) % self.data.len()],
+        }
+    }
+
+    /// Acts on the command accumulated while CS was asserted.
+    fn finish(&mut self) -> FlashEvent {
+        let command = std::mem::take(&mut self.command);
+        let Some(mode) = self.mode else {
+            return FlashEvent::None;
+        };
+        if command.is_empty() {
+            return FlashEvent::None;
+        }
+        match mode {
+            BootMode::Normal => return self.finish_console(&command),
+            BootMode::Rescue => return self.finish_rescue(&command),
+            BootMode::Bootstrap => (),
+        }
+        let opcode = command[0];
+        let addr_len = match opcode {
+            SpiFlash::SECTOR_ERASE_4B
+            | SpiFlash::BLOCK_ERASE_32K_4B
+            | SpiFlash::BLOCK_ERASE_64K_4B => 4,
+            _ if self.four_byte => 4,
+            _ => 3,
+        };
+        let address = || {
+            command
+                .get(1..1 + addr_len)
+                .map(|a| a.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize))
+        };
+        let reset_enabled = std::mem::take(&mut self.reset_enabled);
+        if self.rom_bootstrap && !self.rom_erased && opcode != SpiFlash::WRITE_ENABLE {
+            // Until the first erase, the ROM bootstrap ignores everything else, and then erases
+            // the whole flash regardless of the kind of erase command.
+            if self.write_enabled
+                && (opcode == SpiFlash::SECTOR_ERASE || opcode == SpiFlash::CHIP_ERASE)
+            {
+                self.data.fill(0xff);
+                self.rom_erased = true;
+            }
+            self.write_enabled = false;
+            return FlashEvent::None;
+        }
+        match opcode {
+            SpiFlash::WRITE_ENABLE => self.write_enabled = true,
+            SpiFlash::WRITE_DISABLE => self.write_enabled = false,
+            SpiFlash::ENTER_4B => self.four_byte = true,
+            SpiFlash::EXIT_4B => self.four_byte = self.data.len() > MAX_3B_SIZE,
+            SpiFlash::RESET_ENABLE => self.reset_enabled = true,
+            SpiFlash::RESET if reset_enabled => return FlashEvent::Reset,
+            SpiFlash::PAGE_PROGRAM if self.write_enabled => {
+                if let Some(address) = address() {
+                    let page = address - address % PAGE_SIZE;
+                    for (i, &byte) in command[1 + addr_len..].iter().enumerate() {
+                        let pos = (page + (address + i) % PAGE_SIZE) % self.data.len();
+                        // NOR flash programming can only clear bits.
+                        self.data[pos] &= byte;
+                    }
+                }
+                self.write_enabled = false;
+            }
+            SpiFlash::SECTOR_ERASE
+            | SpiFlash::SECTOR_ERASE_4B
+            | SpiFlash::BLOCK_ERASE_32K
+            | SpiFlash::BLOCK_ERASE_32K_4B
+            | SpiFlash::BLOCK_ERASE_64K
+            | SpiFlash::BLOCK_ERASE_64K_4B
+                if self.write_enabled =>
+            {
+                let size = match opcode {
+                    SpiFlash::SECTOR_ERASE | SpiFlash::SECTOR_ERASE_4B => 4096,
+                    SpiFlash::BLOCK_ERASE_32K | SpiFlash::BLOCK_ERASE_32K_4B => 32768,
+                    _ => 65536,
+                };
+                if let Some(address) = address() {
+                    let start = (address - address % size) % self.data.len();
+                    let end = std::cmp::min(start + size, self.data.len());
+                    self.data[start..end].fill(0xff);
+                }
+                self.write_enabled = false;
+            }
+            SpiFlash::CHIP_ERASE | 0x60 if self.write_enabled => {
+                self.data.fill(0xff);
+                self.write_enabled = false;
+            }
+            _ => {}
+        }
+        FlashEvent::None
+    }
+
+    /// Acts on a command received while booted normally, extracting the data of console frames
+    /// sent by the host, see `crate::console::spi`.  Frames are consumed immediately, so the busy
+    /// bit is never seen set by the host.
+    fn finish_console(&mut self, command: &[u8]) -> FlashEvent {
+        match command[0] {
+            SpiFlash::WRITE_ENABLE => self.write_enabled = true,
+            SpiFlash::PAGE_PROGRAM if self.write_enabled => {
+                self.write_enabled = false;
+                let frame = command.get(4..).unwrap_or_default();
+                if frame.len() < CONSOLE_FRAME_HEADER_SIZE || command[1..4] != [0, 0, 0] {
+                    log::warn!("Simulated target dropping malformed console frame");
+                    return FlashEvent::None;
+                }
+                let len = u32::from_le_bytes(frame[4..8].try_into().unwrap()) as usize;
+                match frame[CONSOLE_FRAME_HEADER_SIZE..].get(..len) {
+                    Some(data) => return FlashEvent::ConsoleData(data.to_vec()),
+                    None => log::warn!("Simulated target dropping truncated console frame"),
+                }
+            }
+            _ => {}
+        }
+        FlashEvent::None
+    }
+
+    /// Acts on a command received while in rescue mode, see `crate::rescue::spidfu`.
+    fn finish_rescue(&mut self, command: &[u8]) -> FlashEvent {
+        match command[0] {
+            RescueSpiDfu::SET_MODE if command.len() == 1 + RescueMode::REBOOT.len() => {
+                self.rescue_mode = String::from_utf8_lossy(&command[1..]).to_string();
+                self.rescue_data.clear();
+                FlashEvent::RescueMode(self.rescue_mode.clone())
+            }
+            RescueSpiDfu::END_TRANSFER => FlashEvent::RescueUpload(
+                self.rescue_mode.clone(),
+                std::mem::take(&mut self.rescue_data),
+            ),
+            SpiFlash::WRITE_ENABLE => {
+                self.write_enabled = true;
+                FlashEvent::None
+            }
+            SpiFlash::PAGE_PROGRAM if self.write_enabled && command.len() >= 4 => {
+                let address = command[1..4]
+                    .iter()
+                    .fold(0usize, |acc, &b| (acc << 8) | b as usize);
+                let data = &command[4..];
+                if self.rescue_data.len() < address + data.len() {
+                    self.rescue_data.resize(address + data.len(), 0xff);
+                }
+                self.rescue_data[address..address + data.len()].copy_from_slice(data);
+                self.write_enabled = false;
+                FlashEvent::None
+            }
+            _ => FlashEvent::None,
+        }
+    }
+
+    /// Synthesizes a minimal SFDP table describing the simulated flash.
+    fn sfdp(&self) -> Vec<u8> {
+        const JEDEC_DWORDS: u8 = 9;
+        const JEDEC_OFFSET: u32 = 0x10;
+        let mut sfdp = Vec::new();
+        // SFDP header: signature, revision 1.0, one parameter header.
+        sfdp.extend_from_slice(b"SFDP");
+        sfdp.extend_from_slice(&[0, 1, 0, 0xff]);
+        // JEDEC basic flash parameter header.
+        sfdp.extend_from_slice(&[0x00, 0, 1, JEDEC_DWORDS]);
+        sfdp.extend_from_slice(&(JEDEC_OFFSET | 0xff00_0000).to_le_bytes());
+        let address_modes = if self.data.len() > MAX_3B_SIZE { 2 } else { 0 };
+        let mut dwords = [0xffff_ffffu32; JEDEC_DWORDS as usize];
+        // 4KiB erase, 64 byte write granularity, 4KiB erase opcode.
+        dwords[0] = 0xff80_0000
+            | (address_modes << 17)
+            | ((SpiFlash::SECTOR_ERASE as u32) << 8)
+            | 0x04
+            | 0x01;
+        dwords[1] = (self.data.len() as u32 * 8) - 1;
+        dwords[2] = 0;
+        dwords[3] = 0;
+        dwords[4] = 0xffff_ffee;
+        dwords[7] = ((SpiFlash::BLOCK_ERASE_64K as u32) << 24)
+            | (16 << 16)
+            | ((SpiFlash::SECTOR_ERASE as u32) << 8)
+            | 12;
+        dwords[8] = 0;
+        for dword in dwords {
+            sfdp.extend_from_slice(&dword.to_le_bytes());
+        }
+        sfdp
+    }
+}
+
+/// SPI port of the simulated chip, connected to its SPI EEPROM bootstrap interface.
+pub struct SimSpi {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl SimSpi {
+    pub(crate) fn new(inner: Rc<RefCell<Inner>>) -> Self {
+        SimSpi { inner }
+    }
+
+    /// Processes the end of a command (CS deasserted), rebooting the chip if requested.
+    fn end_of_command(&self) {
+        let mut inner = self.inner.borrow_mut();
+        if inner.in_reset {
+            return;
+        }
+        match inner.flash.finish() {
+            FlashEvent::None => {}
+            FlashEvent::Reset => inner.reboot(),
+            FlashEvent::RescueMode(mode) if mode.as_bytes() == RescueMode::REBOOT => inner.reboot(),
+            FlashEvent::RescueMode(mode) => {
+                let data = inner.console.rescue_download(&mode);
+                inner.flash.set_rescue_data(data);
+            }
+            FlashEvent::RescueUpload(mode, data) => inner.console.store_rescue_upload(&mode, data),
+            FlashEvent::ConsoleData(data) => {
+                if inner.console.host_write(&data) {
+                    inner.boot();
+                }
+            }
+        }
+    }
+}
+
+impl Target for SimSpi {
+    fn get_transfer_mode(&self) -> Result<TransferMode> {
+        Ok(self.inner.borrow().flash.transfer_mode)
+    }
+    fn set_transfer_mode(&self, mode: TransferMode) -> Result<()> {
+        self.inner.borrow_mut().flash.transfer_mode = mode;
+        Ok(())
+    }
+
+    fn get_bits_per_word(&self) -> Result<u32> {
+        Ok(8)
+    }
+    fn set_bits_per_word(&self, bits_per_word: u32) -> Result<()> {
+        match bits_per_word {
+            8 => Ok(()),
+            _ => Err(SpiError::InvalidWordSize(bits_per_word).into()),
+        }
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        Ok(self.inner.borrow().flash.max_speed)
+    }
+    fn set_max_speed(&self, frequency: u32) -> Result<()> {
+        self.inner.borrow_mut().flash.max_speed = frequency;
+        Ok(())
+    }
+
+    fn supports_bidirectional_transfer(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn get_max_transfer_count(&self) -> Result<usize> {
+        Ok(16)
+    }
+
+    fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        Ok(MaxSizes {
+            read: MAX_TRANSFER_SIZE,
+            write: MAX_TRANSFER_SIZE,
+        })
+    }
+
+    fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+        {
+            let mut inner = self.inner.borrow_mut();
+            // Reads clock out 0xff.
+            let first_byte = match transaction.first() {
+                Some(Transfer::Write(wbuf)) | Some(Transfer::Both(wbuf, _)) => {
+                    wbuf.first().copied()
+                }
+                Some(Transfer::Read(_)) => Some(0xff),
+                None => None,
+            };
+            if inner.flash.wants_console_frame(first_byte) {
+                let data = inner.console.take_tx(CONSOLE_MAX_DATA_LENGTH);
+                inner.flash.queue_console_frame(&data);
+            }
+            let flash = &mut inner.flash;
+            for transfer in transaction.iter_mut() {
+                match transfer {
+                    Transfer::Read(rbuf) => {
+                        for byte in rbuf.iter_mut() {
+                            *byte = flash.clock(0xff);
+                        }
+                    }
+                    Transfer::Write(wbuf) => {
+                        for &byte in wbuf.iter() {
+                            flash.clock(byte);
+                        }
+                    }
+                    Transfer::Both(wbuf, rbuf) => {
+                        ensure!(
+                            wbuf.len() == rbuf.len(),
+                            SpiError::MismatchedDataLength(wbuf.len(), rbuf.len())
+                        );
+                        for (&wbyte, rbyte) in wbuf.iter().zip(rbuf.iter_mut()) {
+                            *rbyte = flash.clock(wbyte);
+                        }
+                    }
+                }
+            }
+            if flash.cs_asserted_count > 0 {
+                return Ok(());
+            }
+        }
+        self.end_of_command();
+        Ok(())
+    }
+
+    fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+        self.inner.borrow_mut().flash.cs_asserted_count += 1;
+        Ok(AssertChipSelect::new(self))
+    }
+}
+
+impl TargetChipDeassert for SimSpi {
+    fn deassert_cs(&self) {
+        let count = {
+            let mut inner = self.inner.borrow_mut();
+            inner.flash.cs_asserted_count -= 1;
+            inner.flash.cs_asserted_count
+        };
+        if count == 0 {
+            self.end_of_command();
+        }
+    }
+}
@@ -0,0 +1,409 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::io::uart::{Parity, Uart, UartError};
+use crate::rescue::xmodem::Xmodem;
+use crate::transport::sim::{BootMode, Inner};
+
+/// Describes how the simulated chip reacts to data on its console UART during normal boot.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConsoleScript {
+    /// Text emitted by the simulated chip each time it boots normally.
+    #[serde(default)]
+    pub boot_banner: String,
+    /// Responses, checked in order each time data is received from the host.
+    #[serde(default)]
+    pub responses: Vec<ConsoleResponse>,
+}
+
+/// A single scripted reaction of the simulated console.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConsoleResponse {
+    /// Regular expression matched against the data received since the last response.
+    pub expect: String,
+    /// Text to emit upon match, `$1` and so on refer to capture groups of `expect`.
+    pub reply: String,
+}
+
+impl ConsoleScript {
+    /// Reads a console script from an HJSON file.
+    pub fn from_file(path: &std::path::Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(serde_annotate::from_str(&data)?)
+    }
+}
+
+/// Device-side state of the rescue protocol.
+enum RescueState {
+    /// Accumulating a four character mode command terminated by `\r`.
+    Command(Vec<u8>),
+    /// Receiving data from the host via XMODEM-CRC.
+    Receive {
+        mode: String,
+        data: Vec<u8>,
+        block: u8,
+        packet: Vec<u8>,
+    },
+    /// Sending data to the host via XMODEM-CRC, `block` is zero until the host has sent the
+    /// initial `C`.
+    Send {
+        data: Vec<u8>,
+        block: usize,
+        eot_sent: bool,
+    },
+}
+
+/// Modes in which rescue expects to receive data from the host.
+const RESCUE_RECEIVE_MODES: [&str; 3] = ["RESQ", "BREQ", "OWNR"];
+/// Modes in which rescue sends data to the host.
+const RESCUE_SEND_MODES: [&str; 2] = ["BLOG", "BRSP"];
+const RESCUE_BLOCK_LEN: usize = 1024;
+
+/// State of the console UART of the simulated chip.
+pub(crate) struct ConsoleState {
+    loopback: bool,
+    boot_banner: String,
+    responses: Vec<(Regex, String)>,
+    /// Data transmitted by the chip, not yet read by the host.
+    tx: VecDeque<u8>,
+    /// All data received from the host.
+    received: Vec<u8>,
+    /// Data received from the host since the last scripted response.
+    input: String,
+    break_asserted: bool,
+    baudrate: u32,
+    mode: BootMode,
+    rescue: RescueState,
+    uploads: HashMap<String, Vec<u8>>,
+    downloads: HashMap<String, Vec<u8>>,
+}
+
+impl ConsoleState {
+    pub fn new(loopback: bool, script: &ConsoleScript) -> Result<Self> {
+        let responses = script
+            .responses
+            .iter()
+            .map(|r| Ok((Regex::new(&r.expect)?, r.reply.clone())))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ConsoleState {
+            loopback,
+            boot_banner: script.boot_banner.clone(),
+            responses,
+            tx: VecDeque::new(),
+            received: Vec::new(),
+            input: String::new(),
+            break_asserted: false,
+            baudrate: 115200,
+            mode: BootMode::Normal,
+            rescue: RescueState::Command(Vec::new()),
+            uploads: HashMap::new(),
+            downloads: HashMap::new(),
+        })
+    }
+
+    pub fn reset(&mut self) {
+        self.input.clear();
+        self.rescue = RescueState::Command(Vec::new());
+    }
+
+    pub fn boot(&mut self, mode: BootMode) {
+        self.mode = mode;
+        match mode {
+            BootMode::Normal => {
+                let banner = self.boot_banner.clone();
+                self.emit(banner.as_bytes());
+            }
+            BootMode::Bootstrap => self.emit(b"bootstrap:1\r\n"),
+            BootMode::Rescue => {
+                self.emit(b"rescue: remember to clear break\r\n");
+                self.emit(b"ok: receive firmware\r\n");
+            }
+        }
+    }
+
+    pub fn emit(&mut self, data: &[u8]) {
+        self.tx.extend(data);
+    }
+
+    /// Removes up to `max` bytes transmitted by the chip, for delivery to the host over an
+    /// interface other than the UART.
+    pub fn take_tx(&mut self, max: usize) -> Vec<u8> {
+        let len = std::cmp::min(max, self.tx.len());
+        self.tx.drain(..len).collect()
+    }
+
+    pub fn take_received(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.received)
+    }
+
+    pub fn break_asserted(&self) -> bool {
+        self.break_asserted
+    }
+
+    pub fn rescue_upload(&self, mode: &str) -> Option<Vec<u8>> {
+        self.uploads.get(mode).cloned()
+    }
+
+    pub fn set_rescue_download(&mut self, mode: &str, data: Vec<u8>) {
+        self.downloads.insert(mode.to_string(), data);
+    }
+
+    /// Returns the data served to the host in the given mode, on any rescue interface.
+    pub fn rescue_download(&self, mode: &str) -> Vec<u8> {
+        self.downloads.get(mode).cloned().unwrap_or_default()
+    }
+
+    /// Records data received from the host in the given mode, on any rescue interface.
+    pub fn store_rescue_upload(&mut self, mode: &str, data: Vec<u8>) {
+        self.uploads.insert(mode.to_string(), data);
+    }
+
+    /// Processes data written by the host, returns `true` if the chip should reboot as a
+    /// consequence.
+    pub fn host_write(&mut self, data: &[u8]) -> bool {
+        self.received.extend_from_slice(data);
+        if self.loopback {
+            self.emit(data);
+        }
+        match self.mode {
+            BootMode::Normal => {
+                self.scripted_response(data);
+                false
+            }
+            BootMode::Bootstrap => false,
+            BootMode::Rescue => data.iter().any(|&byte| self.rescue_byte(byte)),
+        }
+    }
+
+    fn scripted_response(&mut self, data: &[u8]) {
+        self.input.push_str(&String::from_utf8_lossy(data));
+        let reply = self.responses.iter().find_map(|(rx, reply)| {
+            rx.captures(&self.input).map(|caps| {
+                let mut expanded = String::new();
+                caps.expand(reply, &mut expanded);
+                expanded
+            })
+        });
+        if let Some(reply) = reply {
+            self.input.clear();
+            self.emit(reply.as_bytes());
+        }
+    }
+
+    fn rescue_byte(&mut self, byte: u8) -> bool {
+        match &mut self.rescue {
+            RescueState::Command(line) => {
+                if byte != b'\r' {
+                    line.push(byte);
+                    return false;
+                }
+                let command =
+                    String::from_utf8_lossy(&line[line.len().saturating_sub(4)..]).to_string();
+                self.rescue_command(&command)
+            }
+            RescueState::Receive {
+                mode,
+                data,
+                block,
+                packet,
+            } => {
+                if packet.is_empty() {
+                    match byte {
+                        Xmodem::SOH | Xmodem::STX => packet.push(byte),
+                        Xmodem::EOF => {
+                            self.uploads.insert(mode.clone(), std::mem::take(data));
+                            self.rescue = RescueState::Command(Vec::new());
+                            self.emit(&[Xmodem::ACK]);
+                        }
+                        _ if *block == 1 => {
+                            // The host did not start a transfer, interpret as a new command.
+                            self.rescue = RescueState::Command(Vec::new());
+                            return self.rescue_byte(byte);
+                        }
+                        _ => {}
+                    }
+                    return false;
+                }
+                packet.push(byte);
+                let len = if packet[0] == Xmodem::SOH { 128 } else { 1024 };
+                if packet.len() < 3 + len + 2 {
+                    return false;
+                }
+                let payload = &packet[3..3 + len];
+                let crc = u16::from_be_bytes([packet[3 + len], packet[4 + len]]);
+                let reply = if packet[1] == *block
+                    && packet[2] == 255 - packet[1]
+                    && Xmodem::crc16(payload) == crc
+                {
+                    data.extend_from_slice(payload);
+                    *block = block.wrapping_add(1);
+                    Xmodem::ACK
+                } else if packet[1] == block.wrapping_sub(1) {
+                    // Retransmission of a block already received.
+                    Xmodem::ACK
+                } else {
+                    Xmodem::NAK
+                };
+                packet.clear();
+                self.emit(&[reply]);
+                false
+            }
+            RescueState::Send {
+                data,
+                block,
+                eot_sent,
+            } => {
+                match byte {
+                    Xmodem::CRC if *block == 0 => *block = 1,
+                    Xmodem::ACK if *eot_sent => {
+                        self.rescue = RescueState::Command(Vec::new());
+                        return false;
+                    }
+                    Xmodem::ACK if *block > 0 => *block += 1,
+                    Xmodem::NAK if *block > 0 => {}
+                    Xmodem::CAN => {
+                        self.rescue = RescueState::Command(Vec::new());
+                        return false;
+                    }
+                    _ => return false,
+                }
+                let offset = (*block - 1) * RESCUE_BLOCK_LEN;
+                if offset >= data.len() {
+                    *eot_sent = true;
+                    self.emit(&[Xmodem::EOF]);
+                    return false;
+                }
+                let mut packet = vec![Xmodem::STX, *block as u8, 255 - *block as u8];
+                let end = std::cmp::min(offset + RESCUE_BLOCK_LEN, data.len());
+                packet.extend_from_slice(&data[offset..end]);
+                packet.resize(3 + RESCUE_BLOCK_LEN, 0xff);
+                let crc = Xmodem::crc16(&packet[3..]);
+                packet.extend_from_slice(&crc.to_be_bytes());
+                self.emit(&packet);
+                false
+            }
+        }
+    }
+
+    fn rescue_command(&mut self, command: &str) -> bool {
+        self.rescue = RescueState::Command(Vec::new());
+        if command == "REBO" {
+            self.emit(b"ok: reboot\r\n");
+            return true;
+        }
+        if RESCUE_RECEIVE_MODES.contains(&command) {
+            self.emit(format!("ok: receive {command}\r\n").as_bytes());
+            self.rescue = RescueState::Receive {
+                mode: command.to_string(),
+                data: Vec::new(),
+                block: 1,
+                packet: Vec::new(),
+            };
+            self.emit(&[Xmodem::CRC]);
+        } else if RESCUE_SEND_MODES.contains(&command) {
+            self.emit(format!("ok: send {command}\r\n").as_bytes());
+            self.rescue = RescueState::Send {
+                data: self.rescue_download(command),
+                block: 0,
+                eot_sent: false,
+            };
+        } else {
+            self.emit(format!("error: unrecognized mode {command:?}\r\n").as_bytes());
+        }
+        false
+    }
+}
+
+/// Console UART of the simulated chip.
+pub struct SimUart {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl SimUart {
+    pub(crate) fn new(inner: Rc<RefCell<Inner>>) -> Self {
+        SimUart { inner }
+    }
+
+    fn pop(&self, buf: &mut [u8]) -> usize {
+        let mut inner = self.inner.borrow_mut();
+        let tx = &mut inner.console.tx;
+        let len = std::cmp::min(buf.len(), tx.len());
+        for (dst, src) in buf.iter_mut().zip(tx.drain(..len)) {
+            *dst = src;
+        }
+        len
+    }
+}
+
+impl Uart for SimUart {
+    fn get_baudrate(&self) -> Result<u32> {
+        Ok(self.inner.borrow().console.baudrate)
+    }
+
+    fn set_baudrate(&self, baudrate: u32) -> Result<()> {
+        if baudrate == 0 {
+            return Err(UartError::InvalidSpeed(baudrate).into());
+        }
+        self.inner.borrow_mut().console.baudrate = baudrate;
+        Ok(())
+    }
+
+    fn set_flow_control(&self, _flow_control: bool) -> Result<()> {
+        // The simulated chip never overruns, flow control has no effect.
+        Ok(())
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        // Nothing can arrive while the host is blocked, as the simulated chip only produces data
+        // in reaction to the host, so an empty queue would block forever.
+        match self.pop(buf) {
+            0 => Err(
+                UartError::ReadError("no data available from simulated target".to_string()).into(),
+            ),
+            len => Ok(len),
+        }
+    }
+
+    fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        let len = self.pop(buf);
+        if len == 0 {
+            std::thread::sleep(timeout);
+        }
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.in_reset {
+            log::debug!("Simulated target in reset, dropping {} bytes", buf.len());
+            return Ok(());
+        }
+        if inner.console.host_write(buf) {
+            inner.boot();
+        }
+        Ok(())
+    }
+
+    fn clear_rx_buffer(&self) -> Result<()> {
+        self.inner.borrow_mut().console.tx.clear();
+        Ok(())
+    }
+
+    fn set_break(&self, enable: bool) -> Result<()> {
+        self.inner.borrow_mut().console.break_asserted = enable;
+        Ok(())
+    }
+
+    fn set_parity(&self, _parity: Parity) -> Result<()> {
+        Ok(())
+    }
+}
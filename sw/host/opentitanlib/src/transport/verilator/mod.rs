@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod gpio;
+pub mod spi;
 pub mod subprocess;
 pub mod transport;
 
@@ -0,0 +1,184 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{ensure, Context, Result};
+use std::cell::{Cell, RefCell};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::io::spi::{
+    AssertChipSelect, MaxSizes, SpiError, Target, TargetChipDeassert, Transfer, TransferMode,
+};
+use crate::util::file;
+
+/// Largest payload of a single frame understood by the SPI DPI.
+const MAX_FRAME_LEN: usize = 0x7fff;
+/// Flag in the frame header instructing the SPI DPI to keep CS asserted after the frame.
+const FRAME_HOLD_CS: u16 = 0x8000;
+/// Maximum data size per `Read` or `Write` reported to users of the `Target`.
+const MAX_TRANSFER_SIZE: usize = 4096;
+/// Simulation is slow, allow plenty of time for each chunk of response data.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// SPI host connected to the `spi_device` of the simulated chip through the SPI DPI pty.
+///
+/// Each transaction is sent to the DPI as a frame consisting of a two byte big-endian header,
+/// holding the number of payload bytes and the `FRAME_HOLD_CS` flag, followed by the payload.
+/// The DPI clocks out the payload with CS asserted, and sends back one byte for each byte
+/// clocked in.  A frame without payload only changes the state of CS.
+pub struct VerilatorSpi {
+    pty: RefCell<File>,
+    cs_asserted_count: Cell<u32>,
+}
+
+impl VerilatorSpi {
+    pub fn open(path: &str) -> Result<Self> {
+        let pty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("opening SPI DPI {path}"))?;
+        Ok(VerilatorSpi {
+            pty: RefCell::new(pty),
+            cs_asserted_count: Cell::new(0),
+        })
+    }
+
+    /// Sends a single frame, returning the data clocked in from the device.
+    fn frame(&self, data: &[u8], hold_cs: bool) -> Result<Vec<u8>> {
+        ensure!(
+            data.len() <= MAX_FRAME_LEN,
+            SpiError::InvalidDataLength(data.len())
+        );
+        let mut header = data.len() as u16;
+        if hold_cs {
+            header |= FRAME_HOLD_CS;
+        }
+        let mut pty = self.pty.borrow_mut();
+        let mut frame = header.to_be_bytes().to_vec();
+        frame.extend_from_slice(data);
+        pty.write_all(&frame).context("SPI DPI write error")?;
+
+        let mut response = vec![0u8; data.len()];
+        let mut len = 0;
+        while len < response.len() {
+            file::wait_read_timeout(&*pty, RESPONSE_TIMEOUT)
+                .context("waiting for SPI DPI response")?;
+            len += pty
+                .read(&mut response[len..])
+                .context("SPI DPI read error")?;
+        }
+        Ok(response)
+    }
+
+    /// Sends `data`, split into as many frames as necessary, keeping CS asserted between frames,
+    /// and afterwards if `hold_cs` is set.
+    fn exchange(&self, data: &[u8], hold_cs: bool) -> Result<Vec<u8>> {
+        let mut response = Vec::with_capacity(data.len());
+        let mut chunks = data.chunks(MAX_FRAME_LEN).peekable();
+        while let Some(chunk) = chunks.next() {
+            let more = chunks.peek().is_some();
+            response.extend(self.frame(chunk, hold_cs || more)?);
+        }
+        Ok(response)
+    }
+}
+
+impl Target for VerilatorSpi {
+    fn get_transfer_mode(&self) -> Result<TransferMode> {
+        Ok(TransferMode::Mode0)
+    }
+    fn set_transfer_mode(&self, mode: TransferMode) -> Result<()> {
+        // The mode is fixed when the simulation is built.
+        match mode {
+            TransferMode::Mode0 => Ok(()),
+            _ => Err(SpiError::InvalidTransferMode(format!("{mode:?}")).into()),
+        }
+    }
+
+    fn get_bits_per_word(&self) -> Result<u32> {
+        Ok(8)
+    }
+    fn set_bits_per_word(&self, bits_per_word: u32) -> Result<()> {
+        match bits_per_word {
+            8 => Ok(()),
+            _ => Err(SpiError::InvalidWordSize(bits_per_word).into()),
+        }
+    }
+
+    fn get_max_speed(&self) -> Result<u32> {
+        // The DPI clocks SCK at an eighth of the simulated primary clock, the actual rate depends
+        // on simulation performance.
+        Ok(0)
+    }
+    fn set_max_speed(&self, _frequency: u32) -> Result<()> {
+        // The simulation can never outrun the device, nothing to do.
+        Ok(())
+    }
+
+    fn supports_bidirectional_transfer(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn get_max_transfer_count(&self) -> Result<usize> {
+        Ok(usize::MAX)
+    }
+
+    fn get_max_transfer_sizes(&self) -> Result<MaxSizes> {
+        Ok(MaxSizes {
+            read: MAX_TRANSFER_SIZE,
+            write: MAX_TRANSFER_SIZE,
+        })
+    }
+
+    fn run_transaction(&self, transaction: &mut [Transfer]) -> Result<()> {
+        // Concatenate the entire transaction, so that CS stays asserted throughout.
+        let mut data = Vec::new();
+        for transfer in transaction.iter() {
+            match transfer {
+                Transfer::Read(rbuf) => data.resize(data.len() + rbuf.len(), 0xff),
+                Transfer::Write(wbuf) => data.extend_from_slice(wbuf),
+                Transfer::Both(wbuf, rbuf) => {
+                    ensure!(
+                        wbuf.len() == rbuf.len(),
+                        SpiError::MismatchedDataLength(wbuf.len(), rbuf.len())
+                    );
+                    data.extend_from_slice(wbuf);
+                }
+            }
+        }
+        let response = self.exchange(&data, self.cs_asserted_count.get() > 0)?;
+        let mut pos = 0;
+        for transfer in transaction.iter_mut() {
+            match transfer {
+                Transfer::Read(rbuf) | Transfer::Both(_, rbuf) => {
+                    rbuf.copy_from_slice(&response[pos..pos + rbuf.len()]);
+                    pos += rbuf.len();
+                }
+                Transfer::Write(wbuf) => pos += wbuf.len(),
+            }
+        }
+        Ok(())
+    }
+
+    fn assert_cs(self: Rc<Self>) -> Result<AssertChipSelect> {
+        if self.cs_asserted_count.get() == 0 {
+            self.frame(&[], true)?;
+        }
+        self.cs_asserted_count.set(self.cs_asserted_count.get() + 1);
+        Ok(AssertChipSelect::new(self))
+    }
+}
+
+impl TargetChipDeassert for VerilatorSpi {
+    fn deassert_cs(&self) {
+        self.cs_asserted_count.set(self.cs_asserted_count.get() - 1);
+        if self.cs_asserted_count.get() == 0 {
+            // We cannot propagate errors through `Drop::drop()`, so panic on any error.
+            self.frame(&[], false).expect("Error while deasserting CS");
+        }
+    }
+}
@@ -11,10 +11,14 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
+use crate::debug::openocd::OpenOcdJtagChain;
 use crate::io::gpio::{GpioError, GpioPin};
+use crate::io::jtag::{JtagChain, JtagParams};
+use crate::io::spi::Target;
 use crate::io::uart::Uart;
 use crate::transport::common::uart::SerialPortUart;
 use crate::transport::verilator::gpio::{GpioInner, VerilatorGpioPin};
+use crate::transport::verilator::spi::VerilatorSpi;
 use crate::transport::verilator::subprocess::{Options, Subprocess};
 use crate::transport::{
     Capabilities, Capability, Transport, TransportError, TransportInterfaceType,
@@ -22,9 +26,15 @@ use crate::transport::{
 use crate::util::parse_int::ParseInt;
 
 const UART_BAUD: u32 = 40;
+/// The JTAG DPI announces itself during elaboration, alongside the other DPIs, so once those have
+/// been found only a short wait is needed to tell whether the simulation has a JTAG port.
+const JTAG_ANNOUNCE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Simulated RISC-V debug module commands take far longer than OpenOCD expects by default.
+const JTAG_RISCV_COMMAND_TIMEOUT: Duration = Duration::from_secs(120);
 
 pub(crate) struct Inner {
     uart: Option<Rc<dyn Uart>>,
+    spi: Option<Rc<dyn Target>>,
     pub gpio: GpioInner,
 }
 
@@ -35,6 +45,7 @@ pub struct Verilator {
     pub spi_file: String,
     pub gpio_read_file: String,
     pub gpio_write_file: String,
+    pub jtag_port: Option<u16>,
 
     inner: Rc<RefCell<Inner>>,
 }
@@ -52,6 +63,9 @@ impl Verilator {
         static GPIO_WR: Lazy<Regex> = Lazy::new(|| {
             Regex::new(r"GPIO: FIFO pipes created at [^ ]+ \(read\) and ([^ ]+) \(write\) for 32-bit wide GPIO.").unwrap()
         });
+        static JTAG: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(r"JTAG: Virtual JTAG interface [^ ]+ is listening on port (\d+)").unwrap()
+        });
 
         let deadline = Instant::now() + options.timeout;
         let subprocess = Subprocess::from_options(options)?;
@@ -59,6 +73,11 @@ impl Verilator {
         let gpio_wr = subprocess.find(&GPIO_WR, deadline)?;
         let uart = subprocess.find(&UART, deadline)?;
         let spi = subprocess.find(&SPI, deadline)?;
+        // Not every simulation model instantiates the JTAG DPI.
+        let jtag_port = match subprocess.find(&JTAG, Instant::now() + JTAG_ANNOUNCE_TIMEOUT) {
+            Ok(port) => Some(u16::from_str(&port)?),
+            Err(_) => None,
+        };
 
         log::info!("Verilator started with the following interfaces:");
         log::info!("gpio_read = {}", gpio_rd);
@@ -66,6 +85,9 @@ impl Verilator {
         let gpio = GpioInner::new(&gpio_rd, &gpio_wr)?;
         log::info!("uart = {}", uart);
         log::info!("spi = {}", spi);
+        if let Some(port) = jtag_port {
+            log::info!("jtag = localhost:{}", port);
+        }
 
         Ok(Verilator {
             subprocess: Some(subprocess),
@@ -73,7 +95,12 @@ impl Verilator {
             spi_file: spi,
             gpio_read_file: gpio_rd,
             gpio_write_file: gpio_wr,
-            inner: Rc::new(RefCell::new(Inner { uart: None, gpio })),
+            jtag_port,
+            inner: Rc::new(RefCell::new(Inner {
+                uart: None,
+                spi: None,
+                gpio,
+            })),
         })
     }
 
@@ -95,7 +122,11 @@ impl Drop for Verilator {
 
 impl Transport for Verilator {
     fn capabilities(&self) -> Result<Capabilities> {
-        Ok(Capabilities::new(Capability::UART | Capability::GPIO))
+        let mut capabilities = Capability::UART | Capability::GPIO | Capability::SPI;
+        if self.jtag_port.is_some() {
+            capabilities |= Capability::JTAG;
+        }
+        Ok(Capabilities::new(capabilities))
     }
 
     fn uart(&self, instance: &str) -> Result<Rc<dyn Uart>> {
@@ -110,6 +141,18 @@ impl Transport for Verilator {
         Ok(Rc::clone(inner.uart.as_ref().unwrap()))
     }
 
+    fn spi(&self, instance: &str) -> Result<Rc<dyn Target>> {
+        ensure!(
+            instance == "0",
+            TransportError::InvalidInstance(TransportInterfaceType::Spi, instance.to_string())
+        );
+        let mut inner = self.inner.borrow_mut();
+        if inner.spi.is_none() {
+            inner.spi = Some(Rc::new(VerilatorSpi::open(&self.spi_file)?));
+        }
+        Ok(Rc::clone(inner.spi.as_ref().unwrap()))
+    }
+
     fn gpio_pin(&self, instance: &str) -> Result<Rc<dyn GpioPin>> {
         let pin = u8::from_str(instance).with_context(|| format!("can't convert {instance:?}"))?;
         ensure!(pin < 32 || pin == 255, GpioError::InvalidPinNumber(pin));
@@ -119,6 +162,22 @@ impl Transport for Verilator {
         })))
     }
 
+    fn jtag(&self, opts: &JtagParams) -> Result<Box<dyn JtagChain + '_>> {
+        let port = self.jtag_port.ok_or(TransportError::InvalidInterface(
+            TransportInterfaceType::Jtag,
+        ))?;
+        // Tell OpenOCD to talk to the JTAG DPI of the simulation using the remote bitbang
+        // protocol.
+        let mut jtag = OpenOcdJtagChain::new(
+            &format!(
+                "adapter driver remote_bitbang; remote_bitbang_host localhost; remote_bitbang_port {port};"
+            ),
+            opts,
+        )?;
+        jtag.set_riscv_command_timeout(JTAG_RISCV_COMMAND_TIMEOUT);
+        Ok(Box::new(jtag))
+    }
+
     fn dispatch(&self, action: &dyn Any) -> Result<Option<Box<dyn Annotate>>> {
         if let Some(watch) = action.downcast_ref::<Watch>() {
             let subprocess = self.subprocess.as_ref().unwrap();
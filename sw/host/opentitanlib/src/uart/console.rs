@@ -89,8 +89,9 @@ impl UartConsole {
         }
 
         // HACK(nbdd0121): do a nonblocking read because the UART buffer may still have data in it.
-        // If we wait for mio event now, we might be blocking forever.
-        while self.uart_read(device, Duration::from_millis(0), &mut stdout)? {
+        // If we wait for mio event now, we might be blocking forever.  As with the non-mio
+        // implementation, output already in `self.buffer` is matched before waiting for more.
+        loop {
             if self
                 .exit_success
                 .as_ref()
@@ -107,6 +108,9 @@ impl UartConsole {
             {
                 return Ok(ExitStatus::ExitFailure);
             }
+            if !self.uart_read(device, Duration::from_millis(0), &mut stdout)? {
+                break;
+            }
         }
 
         let mut poll = Poll::new()?;
@@ -0,0 +1,384 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Expect-style scripting of console interactions, such that bring-up and manufacturing flows
+//! can be described in HJSON files, rather than written in Rust.
+
+use anyhow::{bail, Result};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::app::TransportWrapper;
+use crate::impl_serializable_error;
+use crate::io::uart::Uart;
+use crate::uart::console::{ExitStatus, UartConsole};
+
+/// Errors related to running console scripts.
+#[derive(Error, Debug, Serialize, Deserialize)]
+pub enum ExpectError {
+    #[error("Label \"{0}\" defined multiple times")]
+    DuplicateLabel(String),
+    #[error("Unknown label \"{0}\"")]
+    UnknownLabel(String),
+    #[error("Undefined variable \"{0}\"")]
+    UndefinedVariable(String),
+    #[error("Timed out waiting for {0:?}")]
+    Timeout(String),
+    #[error("Matched exit_failure expression")]
+    ExitFailure,
+    #[error("Script failed: {0}")]
+    Failed(String),
+}
+impl_serializable_error!(ExpectError);
+
+/// One step of an `ExpectScript`, written as an object with a single key, e.g.
+/// `{ send: "help\r" }` or `{ delay: "10ms" }`.  Text of `send`, as well as regular expressions
+/// of `expect` and `branch`, may refer to variables as `${name}`.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpectStep {
+    /// Marks a position in the script, which other steps may continue at.
+    Label(String),
+    /// Write the given text to the console.
+    Send(String),
+    /// Hold the UART in break condition for the given duration.
+    SendBreak(#[serde(with = "humantime_serde")] Duration),
+    /// Wait for console output matching `regex`, storing any named capture groups as variables.
+    /// Output up to the end of the match is consumed, later output remains for the next `expect`.
+    /// On match, continues at `on_match` if given, otherwise with the next step.  On timeout,
+    /// continues at `on_timeout` if given, otherwise the script fails.
+    Expect {
+        regex: String,
+        #[serde(with = "humantime_serde")]
+        timeout: Duration,
+        #[serde(default)]
+        on_match: Option<String>,
+        #[serde(default)]
+        on_timeout: Option<String>,
+    },
+    /// Continues at label `goto` if the named variable matches `regex`.
+    Branch {
+        variable: String,
+        regex: String,
+        goto: String,
+    },
+    /// Continues at the given label.
+    Goto(String),
+    /// Assigns the given text to the named variable.
+    Set { variable: String, value: String },
+    /// Apply the named strapping.
+    ApplyStrapping(String),
+    /// Return the pins of the named strapping to their default configuration.
+    RemoveStrapping(String),
+    /// Run the named sequence from the configuration files.
+    RunSequence(String),
+    /// Reset the target, holding reset asserted for the given duration.
+    ResetTarget(#[serde(with = "humantime_serde")] Duration),
+    /// Pause for the given duration.
+    Delay(#[serde(with = "humantime_serde")] Duration),
+    /// Ends the script successfully, logging the given message.
+    Pass(String),
+    /// Ends the script unsuccessfully, with the given message.
+    Fail(String),
+}
+
+/// A scripted console interaction, as read from a file such as:
+/// ```hjson
+/// {
+///   variables: { serial: "0000" },
+///   steps: [
+///     { reset_target: "50ms" },
+///     { expect: { regex: "ROM:(?P<rom>[0-9a-f]+)", timeout: "2s" } },
+///     { send: "provision ${serial}\r" },
+///     { expect: { regex: "OK", timeout: "10s", on_timeout: "retry" } },
+///     { pass: "provisioned" },
+///     { label: "retry" },
+///     ...
+///   ]
+/// }
+/// ```
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct ExpectScript {
+    /// Initial values of variables.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    pub steps: Vec<ExpectStep>,
+}
+
+impl ExpectScript {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_annotate::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    /// Returns the index of each label, verifying that all referenced labels exist.
+    fn resolve_labels(&self) -> Result<HashMap<&str, usize>> {
+        let mut labels = HashMap::new();
+        for (index, step) in self.steps.iter().enumerate() {
+            if let ExpectStep::Label(label) = step {
+                if labels.insert(label.as_str(), index).is_some() {
+                    bail!(ExpectError::DuplicateLabel(label.to_string()));
+                }
+            }
+        }
+        for step in &self.steps {
+            let targets = match step {
+                ExpectStep::Expect {
+                    on_match,
+                    on_timeout,
+                    ..
+                } => vec![on_match.as_ref(), on_timeout.as_ref()],
+                ExpectStep::Branch { goto, .. } | ExpectStep::Goto(goto) => vec![Some(goto)],
+                _ => continue,
+            };
+            for label in targets.into_iter().flatten() {
+                if !labels.contains_key(label.as_str()) {
+                    bail!(ExpectError::UnknownLabel(label.to_string()));
+                }
+            }
+        }
+        Ok(labels)
+    }
+
+    /// Runs the script on the given UART, returning the final values of all variables.
+    /// Console output is displayed and logged according to the settings of `console`, whose
+    /// `exit_failure` expression, if any, fails the script whenever matched during `expect`.
+    pub fn run(
+        &self,
+        transport: &TransportWrapper,
+        uart: &dyn Uart,
+        console: &mut UartConsole,
+        mut stdout: Option<&mut dyn Write>,
+    ) -> Result<BTreeMap<String, String>> {
+        let labels = self.resolve_labels()?;
+        let mut variables = self.variables.clone();
+        // Length of the console buffer prefix consumed by the most recent match.  Output read
+        // beyond the match is kept, as subsequent `expect` steps may be looking for it.
+        let mut consumed = 0;
+        let mut pc = 0;
+        while let Some(step) = self.steps.get(pc) {
+            log::debug!("Script step {}: {:?}", pc, step);
+            pc += 1;
+            match step {
+                ExpectStep::Label(_) => (),
+                ExpectStep::Send(text) => uart.write(substitute(text, &variables)?.as_bytes())?,
+                ExpectStep::SendBreak(duration) => {
+                    uart.set_break(true)?;
+                    std::thread::sleep(*duration);
+                    uart.set_break(false)?;
+                }
+                ExpectStep::Expect {
+                    regex,
+                    timeout,
+                    on_match,
+                    on_timeout,
+                } => {
+                    let regex = Regex::new(&substitute(regex, &variables)?)?;
+                    console.buffer.drain(..consumed);
+                    consumed = 0;
+                    console.timeout = Some(*timeout);
+                    console.exit_success = Some(regex.clone());
+                    let status = console.interact(
+                        uart,
+                        None,
+                        stdout.as_mut().map(|out| &mut **out as &mut dyn Write),
+                    )?;
+                    match status {
+                        ExitStatus::ExitSuccess => {
+                            let captures = console.captures(status).unwrap();
+                            store_captures(&regex, &captures, &mut variables);
+                            consumed = captures.get(0).unwrap().end();
+                            if let Some(label) = on_match {
+                                pc = labels[label.as_str()];
+                            }
+                        }
+                        ExitStatus::Timeout => match on_timeout {
+                            Some(label) => pc = labels[label.as_str()],
+                            None => bail!(ExpectError::Timeout(regex.to_string())),
+                        },
+                        ExitStatus::ExitFailure => bail!(ExpectError::ExitFailure),
+                        _ => bail!("Unexpected console status: {:?}", status),
+                    }
+                }
+                ExpectStep::Branch {
+                    variable,
+                    regex,
+                    goto,
+                } => {
+                    let Some(value) = variables.get(variable) else {
+                        bail!(ExpectError::UndefinedVariable(variable.to_string()));
+                    };
+                    if Regex::new(&substitute(regex, &variables)?)?.is_match(value) {
+                        pc = labels[goto.as_str()];
+                    }
+                }
+                ExpectStep::Goto(label) => pc = labels[label.as_str()],
+                ExpectStep::Set { variable, value } => {
+                    let value = substitute(value, &variables)?;
+                    variables.insert(variable.to_string(), value);
+                }
+                ExpectStep::ApplyStrapping(strapping) => {
+                    transport.pin_strapping(strapping)?.apply()?
+                }
+                ExpectStep::RemoveStrapping(strapping) => {
+                    transport.pin_strapping(strapping)?.remove()?
+                }
+                ExpectStep::RunSequence(sequence) => transport.run_sequence(sequence)?,
+                ExpectStep::ResetTarget(reset_delay) => {
+                    transport.reset_target(*reset_delay, true)?
+                }
+                ExpectStep::Delay(duration) => std::thread::sleep(*duration),
+                ExpectStep::Pass(message) => {
+                    log::info!("Script passed: {}", substitute(message, &variables)?);
+                    break;
+                }
+                ExpectStep::Fail(message) => {
+                    bail!(ExpectError::Failed(substitute(message, &variables)?));
+                }
+            }
+        }
+        Ok(variables)
+    }
+}
+
+/// Replaces each `${name}` in `text` with the value of the variable.
+fn substitute(text: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 2..start + len];
+        let Some(value) = variables.get(name) else {
+            bail!(ExpectError::UndefinedVariable(name.to_string()));
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(value);
+        rest = &rest[start + len + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+fn store_captures(regex: &Regex, captures: &Captures, variables: &mut BTreeMap<String, String>) {
+    for name in regex.capture_names().flatten() {
+        if let Some(m) = captures.name(name) {
+            variables.insert(name.to_string(), m.as_str().to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app::config::process_config_file;
+    use crate::app::TransportWrapperBuilder;
+    use crate::transport::sim::{ConsoleResponse, ConsoleScript, Options, Sim};
+
+    fn sim_transport(options: Options) -> Result<TransportWrapper> {
+        let mut builder = TransportWrapperBuilder::new("sim".to_string(), false);
+        process_config_file(&mut builder, Path::new("/__builtin__/opentitan_sim.json"))?;
+        let transport = builder.build(Box::new(Sim::new(options)?))?;
+        transport.apply_default_configuration(None)?;
+        Ok(transport)
+    }
+
+    #[test]
+    fn test_substitute() -> Result<()> {
+        let variables = BTreeMap::from([("serial".to_string(), "1234".to_string())]);
+        assert_eq!(substitute("sn ${serial}\r", &variables)?, "sn 1234\r");
+        assert_eq!(substitute("x{2}$", &variables)?, "x{2}$");
+        assert_eq!(
+            substitute("${other}", &variables).unwrap_err().to_string(),
+            "Undefined variable \"other\""
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_labels() -> Result<()> {
+        let script: ExpectScript = serde_annotate::from_str(
+            r#"{
+                steps: [
+                    { label: "again" },
+                    { expect: { regex: "OK", timeout: "1s", on_timeout: "again" } },
+                    { goto: "done" },
+                ]
+            }"#,
+        )?;
+        assert_eq!(
+            script.resolve_labels().unwrap_err().to_string(),
+            "Unknown label \"done\""
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_expect_script() -> Result<()> {
+        let transport = sim_transport(Options {
+            console_script: ConsoleScript {
+                boot_banner: "ROM:1a2b\r\n".to_string(),
+                responses: vec![ConsoleResponse {
+                    expect: r"ping (\w+)\r".to_string(),
+                    reply: "pong $1\r\n".to_string(),
+                }],
+            },
+            ..Default::default()
+        })?;
+        let script: ExpectScript = serde_annotate::from_str(
+            r#"{
+                variables: { name: "abc" },
+                steps: [
+                    { reset_target: "1ms" },
+                    { expect: { regex: "ROM:(?P<rom>[0-9a-f]+)", timeout: "100ms" } },
+                    { send: "ping ${name}\r" },
+                    { expect: { regex: "pong (?P<reply>\\w+)", timeout: "100ms" } },
+                    { branch: { variable: "reply", regex: "^abc$", goto: "matched" } },
+                    { fail: "unexpected reply ${reply}" },
+                    { label: "matched" },
+                    { expect: { regex: "never", timeout: "10ms", on_timeout: "done" } },
+                    { fail: "matched never" },
+                    { label: "done" },
+                ]
+            }"#,
+        )?;
+        let uart = transport.uart("console")?;
+        let variables = script.run(&transport, &*uart, &mut UartConsole::default(), None)?;
+        assert_eq!(variables["rom"], "1a2b");
+        assert_eq!(variables["reply"], "abc");
+        Ok(())
+    }
+
+    #[test]
+    fn test_expect_same_read() -> Result<()> {
+        let transport = sim_transport(Options::default())?;
+        let script: ExpectScript = serde_annotate::from_str(
+            r#"{
+                steps: [
+                    { expect: { regex: "first (?P<first>\\w+)\r\n", timeout: "50ms" } },
+                    { expect: { regex: "second (?P<second>\\w+)\r\n", timeout: "50ms" } },
+                    { expect: { regex: "first", timeout: "10ms", on_timeout: "done" } },
+                    { fail: "matched consumed output" },
+                    { label: "done" },
+                ]
+            }"#,
+        )?;
+        // Both lines arrived in the single read which completed a previous interaction.
+        let mut console = UartConsole {
+            buffer: "first one\r\nsecond two\r\n".to_string(),
+            ..Default::default()
+        };
+        let uart = transport.uart("console")?;
+        let variables = script.run(&transport, &*uart, &mut console, None)?;
+        assert_eq!(variables["first"], "one");
+        assert_eq!(variables["second"], "two");
+        assert!(console.buffer.is_empty());
+        Ok(())
+    }
+}
@@ -3,3 +3,5 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod console;
+pub mod expect;
+pub mod mux;
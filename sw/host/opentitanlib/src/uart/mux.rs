@@ -0,0 +1,451 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::{bail, Context, Result};
+use mio::net::{UnixListener, UnixStream};
+use mio::unix::SourceFd;
+use mio::{Events, Interest, Poll, Token};
+use mio_signals::{Signal, Signals};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::io::nonblocking_help::NonblockingHelp;
+use crate::io::uart::Uart;
+
+/// Pseudo-terminal or socket connection through which a client observes the UART.
+enum Endpoint {
+    Pty {
+        master: File,
+        // The slave side is kept open, such that the master does not see a hangup each time the
+        // last program having opened the device closes it.
+        _slave: File,
+    },
+    Socket(UnixStream),
+}
+
+impl Endpoint {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Endpoint::Pty { master, .. } => master.as_raw_fd(),
+            Endpoint::Socket(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl Read for Endpoint {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Endpoint::Pty { master, .. } => master.read(buf),
+            Endpoint::Socket(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Endpoint {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Endpoint::Pty { master, .. } => master.write(buf),
+            Endpoint::Socket(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Client {
+    /// Description used in log messages.
+    name: String,
+    endpoint: Endpoint,
+    /// UART data not yet accepted by the endpoint.
+    backlog: VecDeque<u8>,
+}
+
+impl Client {
+    fn queue(&mut self, data: &[u8]) {
+        self.backlog.extend(data);
+        if self.backlog.len() > ConsoleMux::MAX_BACKLOG {
+            let excess = self.backlog.len() - ConsoleMux::MAX_BACKLOG;
+            log::warn!("Discarding {} bytes not consumed by {}", excess, self.name);
+            self.backlog.drain(..excess);
+        }
+    }
+
+    /// Writes as much of the backlog as the endpoint will accept without blocking.
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.backlog.is_empty() {
+            let (data, _) = self.backlog.as_slices();
+            match self.endpoint.write(data) {
+                Ok(n) => {
+                    self.backlog.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps reading a UART, writing everything received to an optional log file, as well as to
+/// any number of clients attached through pseudo-terminals or Unix domain sockets.  Data written
+/// by any client is forwarded to the UART.  This allows e.g. a human running `screen` on one
+/// pseudo-terminal, and a test harness waiting for particular output on another (by opening the
+/// device with `SerialPortUart` and using `UartConsole::wait_for()`), to observe the same stream.
+///
+/// Output is queued for clients not keeping up, up to `MAX_BACKLOG` bytes each, such that no
+/// data is lost due to short stalls.  This also applies to pseudo-terminals while no program
+/// has the device open, such that a reader attaching later sees what it missed.
+pub struct ConsoleMux {
+    uart: Rc<dyn Uart>,
+    poll: Poll,
+    /// Present if the UART supports `mio`, otherwise it is polled at regular intervals.
+    uart_token: Option<Token>,
+    nonblocking_help: Rc<dyn NonblockingHelp>,
+    nonblocking_help_token: Token,
+    logfile: Option<File>,
+    newline: bool,
+    listeners: HashMap<Token, UnixListener>,
+    clients: HashMap<Token, Client>,
+    /// Socket files and symbolic links to be removed when the multiplexer is dropped.
+    cleanup: Vec<PathBuf>,
+}
+
+impl ConsoleMux {
+    /// Maximum number of bytes queued for each client, beyond which the oldest are discarded.
+    pub const MAX_BACKLOG: usize = 1 << 20;
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    pub fn new(uart: Rc<dyn Uart>) -> Result<Self> {
+        let poll = Poll::new()?;
+        let nonblocking_help = uart.nonblocking_help()?;
+        let nonblocking_help_token = Self::get_next_token();
+        nonblocking_help.register_nonblocking_help(poll.registry(), nonblocking_help_token)?;
+        let uart_token = if uart.supports_nonblocking_read()? {
+            let token = Self::get_next_token();
+            uart.register_nonblocking_read(poll.registry(), token)?;
+            Some(token)
+        } else {
+            None
+        };
+        Ok(Self {
+            uart,
+            poll,
+            uart_token,
+            nonblocking_help,
+            nonblocking_help_token,
+            logfile: None,
+            newline: true,
+            listeners: HashMap::new(),
+            clients: HashMap::new(),
+            cleanup: Vec::new(),
+        })
+    }
+
+    /// Writes everything received from the UART to `logfile`, prefixing each line with the
+    /// time at which it started arriving.
+    pub fn set_logfile(&mut self, logfile: File) {
+        self.logfile = Some(logfile);
+    }
+
+    /// Creates a pseudo-terminal in raw mode, returning the path of its device, such as
+    /// `/dev/pts/7`.  If `link` is given, a symbolic link to the device is created there, which
+    /// is removed when the multiplexer is dropped.
+    pub fn add_pty(&mut self, link: Option<&Path>) -> Result<PathBuf> {
+        let pty = nix::pty::openpty(None, None).context("creating pseudo-terminal")?;
+        // SAFETY: `openpty()` returned newly opened file descriptors, not owned elsewhere.
+        let (master, slave) =
+            unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        let mut termios = rustix::termios::tcgetattr(&slave)?;
+        termios.make_raw();
+        rustix::termios::tcsetattr(&slave, rustix::termios::OptionalActions::Now, &termios)?;
+        let flags = rustix::fs::fcntl_getfl(&master)?;
+        rustix::fs::fcntl_setfl(&master, flags | rustix::fs::OFlags::NONBLOCK)?;
+        let path = nix::unistd::ttyname(slave.as_raw_fd()).context("naming pseudo-terminal")?;
+        if let Some(link) = link {
+            // Replace any link left behind by a previous invocation.
+            if link.is_symlink() {
+                std::fs::remove_file(link)?;
+            }
+            std::os::unix::fs::symlink(&path, link)
+                .with_context(|| format!("creating link {}", link.display()))?;
+            self.cleanup.push(link.to_path_buf());
+        }
+        self.add_client(
+            path.display().to_string(),
+            Endpoint::Pty {
+                master,
+                _slave: slave,
+            },
+        )?;
+        Ok(path)
+    }
+
+    /// Listens on a Unix domain socket at `path`, each connection observing the UART from the
+    /// time it is accepted.  The socket file is removed when the multiplexer is dropped.
+    pub fn add_socket(&mut self, path: &Path) -> Result<()> {
+        let mut listener =
+            UnixListener::bind(path).with_context(|| format!("binding {}", path.display()))?;
+        self.cleanup.push(path.to_path_buf());
+        let token = Self::get_next_token();
+        self.poll
+            .registry()
+            .register(&mut listener, token, Interest::READABLE)?;
+        self.listeners.insert(token, listener);
+        Ok(())
+    }
+
+    /// Forwards data between the UART and all clients, until SIGINT or SIGTERM is received.
+    pub fn run(&mut self) -> Result<()> {
+        let mut signals = Signals::new(Signal::Terminate | Signal::Interrupt)?;
+        let signal_token = Self::get_next_token();
+        self.poll
+            .registry()
+            .register(&mut signals, signal_token, Interest::READABLE)?;
+        loop {
+            if self.process_events(Some(signal_token))? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn get_next_token() -> Token {
+        static TOKEN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        Token(TOKEN_COUNTER.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn add_client(&mut self, name: String, endpoint: Endpoint) -> Result<()> {
+        let token = Self::get_next_token();
+        self.poll.registry().register(
+            &mut SourceFd(&endpoint.as_raw_fd()),
+            token,
+            Interest::READABLE | Interest::WRITABLE,
+        )?;
+        log::info!("Console client {} attached", name);
+        self.clients.insert(
+            token,
+            Client {
+                name,
+                endpoint,
+                backlog: VecDeque::new(),
+            },
+        );
+        Ok(())
+    }
+
+    fn remove_client(&mut self, token: Token) -> Result<()> {
+        if let Some(client) = self.clients.remove(&token) {
+            self.poll
+                .registry()
+                .deregister(&mut SourceFd(&client.endpoint.as_raw_fd()))?;
+            log::info!("Console client {} detached", client.name);
+        }
+        Ok(())
+    }
+
+    /// Waits for and processes one batch of events, returning `true` if a signal requesting
+    /// exit was received.
+    fn process_events(&mut self, signal_token: Option<Token>) -> Result<bool> {
+        let mut events = Events::with_capacity(64);
+        let timeout = match self.uart_token {
+            Some(_) => None,
+            None => Some(Self::POLL_INTERVAL),
+        };
+        match self.poll.poll(&mut events, timeout) {
+            Ok(()) => (),
+            Err(err) if err.kind() == ErrorKind::Interrupted => return Ok(false),
+            Err(err) => bail!("poll: {}", err),
+        }
+        if self.uart_token.is_none() {
+            self.process_uart()?;
+        }
+        for event in events.iter() {
+            let token = event.token();
+            if Some(token) == signal_token {
+                return Ok(true);
+            } else if token == self.nonblocking_help_token {
+                self.nonblocking_help.nonblocking_help()?;
+            } else if Some(token) == self.uart_token {
+                self.process_uart()?;
+            } else if self.listeners.contains_key(&token) {
+                self.process_new_connections(token)?;
+            } else if self.clients.contains_key(&token) {
+                if let Err(e) = self.process_client(token, event.is_readable()) {
+                    log::warn!("Console client error: {}", e);
+                    self.remove_client(token)?;
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Reads everything currently available from the UART, passing it to the log file and all
+    /// clients.
+    fn process_uart(&mut self) -> Result<()> {
+        let mut buf = [0u8; 256];
+        loop {
+            let len = self.uart.read_timeout(&mut buf, Duration::from_millis(1))?;
+            if len == 0 {
+                return Ok(());
+            }
+            self.log(&buf[..len])?;
+            let mut failed = Vec::new();
+            for (token, client) in self.clients.iter_mut() {
+                client.queue(&buf[..len]);
+                if let Err(e) = client.flush() {
+                    log::warn!("Console client {} error: {}", client.name, e);
+                    failed.push(*token);
+                }
+            }
+            for token in failed {
+                self.remove_client(token)?;
+            }
+        }
+    }
+
+    fn log(&mut self, data: &[u8]) -> Result<()> {
+        let Some(logfile) = self.logfile.as_mut() else {
+            return Ok(());
+        };
+        for line in data.split_inclusive(|&ch| ch == b'\n') {
+            if self.newline {
+                let t = humantime::format_rfc3339_millis(SystemTime::now());
+                write!(logfile, "[{}  console]", t)?;
+            }
+            logfile.write_all(line)?;
+            self.newline = line.ends_with(b"\n");
+        }
+        logfile.flush()?;
+        Ok(())
+    }
+
+    fn process_new_connections(&mut self, token: Token) -> Result<()> {
+        loop {
+            match self.listeners[&token].accept() {
+                Ok((stream, _address)) => {
+                    let name = format!("connection {:#X}", token.0);
+                    self.add_client(name, Endpoint::Socket(stream))?;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => bail!("Error accepting connection: {}", err),
+            }
+        }
+    }
+
+    /// Forwards any data written by the client to the UART, and resumes sending its backlog.
+    fn process_client(&mut self, token: Token, readable: bool) -> Result<()> {
+        let client = self.clients.get_mut(&token).unwrap();
+        client.flush()?;
+        if !readable {
+            return Ok(());
+        }
+        let mut buf = [0u8; 256];
+        loop {
+            match client.endpoint.read(&mut buf) {
+                Ok(0) => {
+                    // Orderly shutdown of socket connection.
+                    return self.remove_client(token);
+                }
+                Ok(n) => self.uart.write(&buf[..n])?,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for ConsoleMux {
+    fn drop(&mut self) {
+        for path in &self.cleanup {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::os::unix::net;
+
+    #[derive(Default)]
+    struct FakeUart {
+        rx: RefCell<VecDeque<u8>>,
+        tx: RefCell<Vec<u8>>,
+    }
+
+    impl Uart for FakeUart {
+        fn get_baudrate(&self) -> Result<u32> {
+            Ok(115200)
+        }
+
+        fn set_baudrate(&self, _baudrate: u32) -> Result<()> {
+            Ok(())
+        }
+
+        fn read(&self, buf: &mut [u8]) -> Result<usize> {
+            self.read_timeout(buf, Duration::ZERO)
+        }
+
+        fn read_timeout(&self, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+            let mut rx = self.rx.borrow_mut();
+            let len = std::cmp::min(buf.len(), rx.len());
+            for (dst, src) in buf.iter_mut().zip(rx.drain(..len)) {
+                *dst = src;
+            }
+            Ok(len)
+        }
+
+        fn write(&self, buf: &[u8]) -> Result<()> {
+            self.tx.borrow_mut().extend_from_slice(buf);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_multiple_clients() -> Result<()> {
+        let uart = Rc::new(FakeUart::default());
+        let mut mux = ConsoleMux::new(uart.clone())?;
+        let socket_path =
+            std::env::temp_dir().join(format!("console_mux_test_{}", std::process::id()));
+        mux.add_socket(&socket_path)?;
+        let pty_path = mux.add_pty(None)?;
+        let mut socket = net::UnixStream::connect(&socket_path)?;
+        socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+        mux.process_events(None)?;
+        assert_eq!(mux.clients.len(), 2);
+
+        // Output produced before the pseudo-terminal is opened is not lost.
+        uart.rx.borrow_mut().extend(b"Hello\r\n");
+        mux.process_events(None)?;
+        let mut pty = File::options().read(true).write(true).open(&pty_path)?;
+        let mut buf = [0u8; 7];
+        pty.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"Hello\r\n");
+        socket.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"Hello\r\n");
+
+        // Input from either client reaches the UART.
+        pty.write_all(b"a")?;
+        socket.write_all(b"b")?;
+        while uart.tx.borrow().len() < 2 {
+            mux.process_events(None)?;
+        }
+        let mut tx = uart.tx.borrow().clone();
+        tx.sort();
+        assert_eq!(tx, b"ab");
+        drop(mux);
+        assert!(!socket_path.exists());
+        Ok(())
+    }
+}
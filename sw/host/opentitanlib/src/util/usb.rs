@@ -3,8 +3,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{ensure, Context, Result};
+use nix::errno::Errno;
 use rusb;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::transport::TransportError;
 
@@ -12,10 +13,31 @@ use crate::transport::TransportError;
 pub struct UsbBackend {
     device: rusb::Device<rusb::GlobalContext>,
     handle: rusb::DeviceHandle<rusb::GlobalContext>,
+    usb_vid: u16,
+    usb_pid: u16,
     serial_number: String,
+    // Interfaces to claim again when reconnecting.
+    claimed_interfaces: Vec<u8>,
     timeout: Duration,
 }
 
+/// Tells whether an error was caused by the USB device having disappeared from the bus, e.g. as
+/// it reset, after which `UsbBackend::reconnect()` may be able to reestablish the connection.
+pub fn is_disconnected(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<rusb::Error>() {
+            return *e == rusb::Error::NoDevice;
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.raw_os_error().map(Errno::from_i32),
+                Some(Errno::ENODEV | Errno::ENXIO)
+            );
+        }
+        false
+    })
+}
+
 impl UsbBackend {
     /// Scan the USB bus for a device matching VID/PID, and optionally also matching a serial
     /// number.
@@ -102,17 +124,51 @@ impl UsbBackend {
         Ok(UsbBackend {
             handle: device.open().context("USB open error")?,
             device,
+            usb_vid,
+            usb_pid,
             serial_number,
+            claimed_interfaces: Vec::new(),
             timeout: Duration::from_millis(500),
         })
     }
 
+    /// Waits up to `timeout` for the device to (re)appear on the USB bus with the same VID, PID
+    /// and serial number, e.g. after having been reset or power-cycled, and replaces the
+    /// connection with a new one, claiming the same interfaces as before.
+    pub fn reconnect(&mut self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let device = loop {
+            let mut devices =
+                UsbBackend::scan(self.usb_vid, self.usb_pid, Some(&self.serial_number))?;
+            ensure!(devices.len() <= 1, TransportError::MultipleDevices);
+            if let Some((device, _)) = devices.pop() {
+                break device;
+            }
+            ensure!(Instant::now() < deadline, TransportError::NoDevice);
+            std::thread::sleep(Duration::from_millis(100));
+        };
+        let handle = device.open().context("USB open error")?;
+        for &iface in &self.claimed_interfaces {
+            if let Err(e) = handle.claim_interface(iface) {
+                log::warn!(
+                    "Could not claim interface {} after reconnecting: {}",
+                    iface,
+                    e
+                );
+            }
+        }
+        self.device = device;
+        self.handle = handle;
+        log::info!("Reconnected to USB device {}", self.serial_number);
+        Ok(())
+    }
+
     pub fn get_vendor_id(&self) -> u16 {
-        self.device.device_descriptor().unwrap().vendor_id()
+        self.usb_vid
     }
 
     pub fn get_product_id(&self) -> u16 {
-        self.device.device_descriptor().unwrap().product_id()
+        self.usb_pid
     }
 
     /// Gets the usb serial number of the device.
@@ -127,10 +183,15 @@ impl UsbBackend {
     }
 
     pub fn claim_interface(&mut self, iface: u8) -> Result<()> {
-        self.handle.claim_interface(iface).context("USB error")
+        self.handle.claim_interface(iface).context("USB error")?;
+        if !self.claimed_interfaces.contains(&iface) {
+            self.claimed_interfaces.push(iface);
+        }
+        Ok(())
     }
 
     pub fn release_interface(&mut self, iface: u8) -> Result<()> {
+        self.claimed_interfaces.retain(|&i| i != iface);
         self.handle.release_interface(iface).context("USB error")
     }
 
@@ -2,24 +2,27 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use clap::Parser;
 use directories::{BaseDirs, ProjectDirs};
 use erased_serde::Serialize;
 use log::LevelFilter;
 use rustix::process::{Pid, Signal};
+use std::collections::BTreeMap;
 use std::env::{self, args_os, ArgsOs};
 use std::ffi::OsString;
 use std::fs::{self, read_to_string, File};
 use std::io::{self, ErrorKind, Write};
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::num::ParseIntError;
+use std::path::{Path, PathBuf};
 use std::process::{self, ChildStdout, Command, Stdio};
 use std::str::FromStr;
 use std::time::Duration;
 
+use opentitanlib::app::TransportWrapper;
 use opentitanlib::backend;
-use opentitanlib::proxy::SessionHandler;
+use opentitanlib::proxy::{SessionHandler, SessionJtagOpts, SessionSecurityOpts};
 
 #[derive(Debug, Parser)]
 #[command(
@@ -37,6 +40,11 @@ struct Opts {
     #[command(flatten)]
     backend_opts: backend::BackendOpts,
 
+    /// JSON file mapping target names to lists of backend options, for serving multiple
+    /// transports from one session.  Replaces --interface and other backend options.
+    #[arg(long)]
+    backends: Option<PathBuf>,
+
     /// Stop a running session, optionally combine with --listen_port for disambiguation.
     #[arg(long)]
     stop: bool,
@@ -45,6 +53,26 @@ struct Opts {
     #[arg(long)]
     listen_port: Option<u16>,
 
+    /// Listen on a Unix domain socket at the given path, instead of a TCP port.  Also
+    /// identifies the session to --stop.
+    #[arg(long, conflicts_with = "listen_port")]
+    listen_unix: Option<PathBuf>,
+
+    /// Permission bits of the Unix domain socket, in octal.
+    #[arg(long, default_value = "600", value_parser = parse_mode)]
+    listen_unix_mode: u32,
+
+    /// Serve a single client over standard input and output, e.g. when invoked through ssh,
+    /// exiting when the client disconnects.
+    #[arg(long, conflicts_with_all = ["listen_port", "listen_unix", "stop", "foreground"])]
+    stdio: bool,
+
+    #[command(flatten)]
+    security_opts: SessionSecurityOpts,
+
+    #[command(flatten)]
+    jtag_opts: SessionJtagOpts,
+
     /// Start session, staying in foreground (do not daemonize).  Session process will terminate if its parent dies.
     #[arg(long)]
     foreground: bool,
@@ -54,6 +82,10 @@ struct Opts {
     child: bool,
 }
 
+fn parse_mode(s: &str) -> Result<u32, ParseIntError> {
+    u32::from_str_radix(s, 8)
+}
+
 // Given some existing option configuration, maybe re-evaluate command
 // line options by reading an `rcfile`.
 fn parse_command_line(opts: Opts, mut args: ArgsOs) -> Result<Opts> {
@@ -109,16 +141,80 @@ fn parse_command_line(opts: Opts, mut args: ArgsOs) -> Result<Opts> {
     Ok(opts)
 }
 
+/// Backend options of one of the targets listed in a `--backends` file.
+#[derive(Debug, Parser)]
+#[command(no_binary_name = true)]
+struct TargetOpts {
+    #[command(flatten)]
+    backend_opts: backend::BackendOpts,
+}
+
+/// Instantiates the transports to be served, either a single one given by the backend options
+/// on the command line, or each one listed in the `--backends` file.
+fn create_transports(
+    backend_opts: &backend::BackendOpts,
+    backends: Option<&PathBuf>,
+) -> Result<BTreeMap<String, TransportWrapper>> {
+    let mut transports = BTreeMap::new();
+    let Some(backends) = backends else {
+        transports.insert("default".to_string(), backend::create(backend_opts)?);
+        return Ok(transports);
+    };
+    ensure!(
+        backend_opts.interface.is_empty(),
+        "--interface cannot be combined with --backends"
+    );
+    let targets: BTreeMap<String, Vec<String>> =
+        serde_json::from_str(&read_to_string(backends).with_context(|| format!("{backends:?}"))?)
+            .with_context(|| format!("parsing {backends:?}"))?;
+    ensure!(!targets.is_empty(), "No targets in {backends:?}");
+    for (name, args) in targets {
+        let target_opts = TargetOpts::try_parse_from(args)
+            .with_context(|| format!("backend options of target {name}"))?;
+        let transport = backend::create(&target_opts.backend_opts)
+            .with_context(|| format!("creating target {name}"))?;
+        transports.insert(name, transport);
+    }
+    Ok(transports)
+}
+
+/// Binds to the TCP port or Unix domain socket requested on the command line.
+fn init_session<'a>(
+    opts: &Opts,
+    transports: &'a BTreeMap<String, TransportWrapper>,
+) -> Result<SessionHandler<'a>> {
+    match &opts.listen_unix {
+        Some(path) => SessionHandler::init_unix(
+            transports,
+            path,
+            opts.listen_unix_mode,
+            &opts.security_opts,
+            &opts.jtag_opts,
+        ),
+        None => SessionHandler::init(
+            transports,
+            opts.listen_port,
+            &opts.security_opts,
+            &opts.jtag_opts,
+        ),
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct SessionStartResult {
-    port: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    socket: Option<PathBuf>,
 }
 
 /// Spawn a child process, passing all the same arguments to the child, letting it instantiate a
-/// Transport based on the command line arguments, listen on a TCP socket, and run as a daemon
+/// Transport based on the command line arguments, listen on a socket, and run as a daemon
 /// process serving network requests.  Success of the child is verified by means of a
 /// `SessionStartResult` JSON message sent through the standard output pipe.
-fn start_session(run_file_fn: impl FnOnce(u16) -> PathBuf) -> Result<Box<dyn Serialize>> {
+fn start_session(
+    run_file_fn: impl FnOnce(&SessionStartResult) -> PathBuf,
+) -> Result<Box<dyn Serialize>> {
     let mut child = Command::new(env::current_exe()?) // Same executable
         .arg("--child") // Add argument to let the new process know it is the daemon child
         .args(args_os().skip(1)) // Propagate all existing arguments: --interface, etc.
@@ -131,8 +227,8 @@ fn start_session(run_file_fn: impl FnOnce(u16) -> PathBuf) -> Result<Box<dyn Ser
         child.stdout.as_mut().unwrap(),
     ) {
         Ok(Ok(result)) => {
-            // Create a pid file corresponding to the requested TCP port.
-            let path = run_file_fn(result.port);
+            // Create a pid file corresponding to the requested TCP port or socket.
+            let path = run_file_fn(&result);
             File::create(path)?.write_all(format!("{}\n", child.id()).as_bytes())?;
             Ok(Box::new(result))
         }
@@ -145,9 +241,9 @@ fn start_session(run_file_fn: impl FnOnce(u16) -> PathBuf) -> Result<Box<dyn Ser
 // socket, then report the chosen port number to the parent process by means of a serialized
 // `SessionStartResult` sent through the stdout anonymous pipe, and finally enter an infnite
 // loop, processing connections on that socket
-fn session_child(listen_port: Option<u16>, backend_opts: &backend::BackendOpts) -> Result<()> {
-    let transport = backend::create(backend_opts)?;
-    let mut session = SessionHandler::init(&transport, listen_port)?;
+fn session_child(opts: &Opts) -> Result<()> {
+    let transports = create_transports(&opts.backend_opts, opts.backends.as_ref())?;
+    let mut session = init_session(opts, &transports)?;
     // Instantiation of Transport backend, and binding to a socket was successful, now go
     // through the process of making this process a daemon, disconnected from the
     // terminal that was used to start it.
@@ -173,6 +269,7 @@ fn session_child(listen_port: Option<u16>, backend_opts: &backend::BackendOpts)
         io::stdout(),
         &Ok(SessionStartResult {
             port: session.get_port(),
+            socket: opts.listen_unix.clone(),
         }),
     )?;
     io::stdout().flush()?;
@@ -190,11 +287,9 @@ fn session_child(listen_port: Option<u16>, backend_opts: &backend::BackendOpts)
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct SessionStopResult {}
 
-/// Load .pid file based on given port number, and send SIGTERM to the process identified in the
-/// file, to request the daemon gracefully shut down.
-fn stop_session(run_file_fn: impl FnOnce(u16) -> PathBuf, port: u16) -> Result<Box<dyn Serialize>> {
-    // Read the pid file corresponding to the requested TCP port.
-    let path = run_file_fn(port);
+/// Load the given .pid file, and send SIGTERM to the process identified in the file, to request
+/// the daemon gracefully shut down.
+fn stop_session(path: PathBuf) -> Result<Box<dyn Serialize>> {
     let pid = FromStr::from_str(fs::read_to_string(&path)?.trim())?;
     let pid = Pid::from_raw(pid).context("Pid is not valid")?;
     // Send signal to daemon process, asking it to terminate.
@@ -226,16 +321,32 @@ fn main() -> Result<()> {
         // Request a SIGTERM if our parent dies.
         rustix::process::set_parent_process_death_signal(Some(Signal::Term))?;
 
-        let transport = backend::create(&opts.backend_opts)?;
-        let mut session = SessionHandler::init(&transport, opts.listen_port)?;
-        println!("Listening on port {}", session.get_port());
+        let transports = create_transports(&opts.backend_opts, opts.backends.as_ref())?;
+        let mut session = init_session(&opts, &transports)?;
+        match session.get_port() {
+            Some(port) => println!("Listening on port {}", port),
+            None => println!("Listening on {:?}", opts.listen_unix.as_ref().unwrap()),
+        }
+        session.run_loop()?;
+        return Ok(());
+    }
+
+    if opts.stdio {
+        // Serve the single client at the other end of standard input and output, typically an
+        // ssh connection.  The session terminates when the client disconnects.
+        let transports = create_transports(&opts.backend_opts, opts.backends.as_ref())?;
+        let mut session =
+            SessionHandler::init_stdio(&transports, &opts.security_opts, &opts.jtag_opts)?;
+        // The session holds its own handle on standard output, any stray output must not be
+        // interleaved with the protocol.
+        rustix::stdio::dup2_stdout(io::stderr())?;
         session.run_loop()?;
         return Ok(());
     }
 
     if opts.child {
         // This process is a child, which is supposed to stay running as a daemon.
-        match session_child(opts.listen_port, &opts.backend_opts) {
+        match session_child(&opts) {
             Ok(()) => process::exit(0),
             Err(e) => {
                 // Report any error to parent process though stdout pipe.
@@ -253,18 +364,30 @@ fn main() -> Result<()> {
     let run_user_dir = base_dirs
         .runtime_dir()
         .ok_or_else(|| anyhow!("No /run/user directory"))?;
-    let run_file_fn = |port: u16| {
+    let port_run_file = |port: u16| {
         let mut p = PathBuf::from(run_user_dir);
         p.push(format!("opentitansession.{}.pid", port));
         p
     };
+    // A session listening on a Unix domain socket keeps its pid file next to the socket.
+    let socket_run_file = |socket: &Path| {
+        let mut p = socket.as_os_str().to_owned();
+        p.push(".pid");
+        PathBuf::from(p)
+    };
 
     let value = if opts.stop {
         // Send signal to daemon process to stop
-        stop_session(run_file_fn, opts.listen_port.unwrap_or(9900))?
+        stop_session(match &opts.listen_unix {
+            Some(socket) => socket_run_file(socket),
+            None => port_run_file(opts.listen_port.unwrap_or(9900)),
+        })?
     } else {
         // Fork a daemon process
-        start_session(run_file_fn)?
+        start_session(|result| match (&result.socket, result.port) {
+            (Some(socket), _) => socket_run_file(socket),
+            (None, port) => port_run_file(port.unwrap()),
+        })?
     };
     println!("{}", serde_json::to_string_pretty(&value)?);
     Ok(())
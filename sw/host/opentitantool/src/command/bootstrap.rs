@@ -2,16 +2,18 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{ensure, Result};
+use anyhow::{anyhow, ensure, Result};
 use clap::Args;
 use serde_annotate::Annotate;
 use std::any::Any;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::{StagedProgressBar, TransportWrapper};
 use opentitanlib::bootstrap::{Bootstrap, BootstrapOptions, BootstrapProtocol};
 use opentitanlib::image::image::ImageAssembler;
+use opentitanlib::ownership::owner::OwnerConfigItem;
+use opentitanlib::ownership::{OwnerBlock, OwnerFlashConfig};
 use opentitanlib::transport;
 use opentitanlib::util::parse_int::ParseInt;
 
@@ -26,7 +28,14 @@ pub struct BootstrapCommand {
     /// Whether or not the assembled image is mirrored (only valid with multiple FILE arguments).
     #[arg(long, action = clap::ArgAction::Set, default_value = "true")]
     mirror: bool,
+    /// Owner configuration (hjson) whose flash regions the assembled FILE placements are
+    /// checked against.  The basic owner flash configuration is used if slot names are given
+    /// without this option.
+    #[arg(long)]
+    owner_config: Option<PathBuf>,
     /// An image to bootstrap or multiple filename@offset specifiers to assemble into a bootstrap image.
+    /// The offset may also name a slot: rom_ext_a, owner_a, rom_ext_b or owner_b (B slots
+    /// require `--mirror=false`).
     #[arg(value_name = "FILE", required = true, num_args = 1..)]
     filename: Vec<String>,
 }
@@ -45,10 +54,28 @@ impl BootstrapCommand {
         })
     }
 
+    fn flash_config(path: &Path) -> Result<OwnerFlashConfig> {
+        let text = std::fs::read_to_string(path)?;
+        let owner = serde_annotate::from_str::<OwnerBlock>(&text)?;
+        owner
+            .data
+            .into_iter()
+            .find_map(|item| match item {
+                OwnerConfigItem::FlashConfig(config) => Some(config),
+                _ => None,
+            })
+            .ok_or_else(|| anyhow!("{} has no flash configuration", path.display()))
+    }
+
     fn payload(&self) -> Result<Vec<u8>> {
         if self.filename.len() > 1 || self.filename[0].contains('@') {
             let mut image = ImageAssembler::with_params(self.size, self.mirror);
             image.parse(&self.filename)?;
+            if let Some(owner_config) = &self.owner_config {
+                image.validate(&Self::flash_config(owner_config)?)?;
+            } else if image.uses_slots() {
+                image.validate(&OwnerFlashConfig::basic())?;
+            }
             image.assemble()
         } else {
             Ok(std::fs::read(&self.filename[0])?)
@@ -2,19 +2,23 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use clap::Args;
 use regex::Regex;
 use serde_annotate::Annotate;
 use std::any::Any;
 use std::fs::File;
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::time::Duration;
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
-use opentitanlib::io::uart::UartParams;
+use opentitanlib::console::spi::SpiConsoleDevice;
+use opentitanlib::io::uart::{Uart, UartParams};
 use opentitanlib::transport::Capability;
 use opentitanlib::uart::console::{ExitStatus, UartConsole};
+use opentitanlib::uart::expect::ExpectScript;
 use opentitanlib::util::raw_tty::RawTty;
 
 #[derive(Debug, Args)]
@@ -51,6 +55,65 @@ pub struct Console {
     /// Exit with failure if the specified regex is matched.
     #[arg(long)]
     exit_failure: Option<String>,
+
+    /// Run the console interaction described by the given script, rather than interactively.
+    #[arg(long, conflicts_with_all = ["exit_success", "timeout"])]
+    script: Option<PathBuf>,
+
+    /// Set a script variable, overriding any initial value in the script.  May be repeated.
+    #[arg(
+        long = "var",
+        value_name = "NAME=VALUE",
+        requires = "script",
+        value_parser = parse_variable
+    )]
+    variables: Vec<(String, String)>,
+
+    /// Use the SPI console on the given SPI bus, rather than the UART.  The SPI console only
+    /// receives, unless `--spi-experimental-writes` is given.
+    #[arg(long)]
+    spi: Option<String>,
+
+    /// GPIO pin asserted by the device when it has SPI console data to send.  Without it, the
+    /// SPI console is polled.
+    #[arg(long, requires = "spi")]
+    spi_ready_pin: Option<String>,
+
+    /// Allow sending input to the device over the SPI console.  Experimental: no device
+    /// firmware implements the host-to-device direction yet.
+    #[arg(long, requires = "spi")]
+    spi_experimental_writes: bool,
+}
+
+fn parse_variable(s: &str) -> Result<(String, String)> {
+    let (name, value) = s
+        .split_once('=')
+        .with_context(|| format!("expected NAME=VALUE, got {:?}", s))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+impl Console {
+    fn console_device(&self, transport: &TransportWrapper) -> Result<Rc<dyn Uart>> {
+        match &self.spi {
+            Some(spi) => {
+                let ready_pin = self
+                    .spi_ready_pin
+                    .as_ref()
+                    .map(|pin| transport.gpio_pin(pin))
+                    .transpose()?;
+                if self.spi_experimental_writes {
+                    log::warn!(
+                        "Writing to the SPI console is experimental and not implemented by device firmware"
+                    );
+                }
+                Ok(Rc::new(
+                    SpiConsoleDevice::new(transport.spi(spi)?, ready_pin)
+                        .enable_experimental_writes(self.spi_experimental_writes),
+                ))
+            }
+            None => self.params.create(transport),
+        }
+    }
 }
 
 impl CommandDispatch for Console {
@@ -59,8 +122,12 @@ impl CommandDispatch for Console {
         _context: &dyn Any,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        // We need the UART for the console command to operate.
-        transport.capabilities()?.request(Capability::UART).ok()?;
+        // We need the UART (or SPI) for the console command to operate.
+        let needed = match self.spi {
+            Some(_) => Capability::SPI,
+            None => Capability::UART,
+        };
+        transport.capabilities()?.request(needed).ok()?;
 
         // Set up resources specified by the command line parameters.
         let mut console = UartConsole {
@@ -81,6 +148,16 @@ impl CommandDispatch for Console {
             ..Default::default()
         };
 
+        if let Some(script) = &self.script {
+            let mut script = ExpectScript::load(script)?;
+            script.variables.extend(self.variables.iter().cloned());
+            let uart = self.console_device(transport)?;
+            let mut stdout = std::io::stdout();
+            let variables = script.run(transport, &*uart, &mut console, Some(&mut stdout))?;
+            println!();
+            return Ok(Some(Box::new(variables)));
+        }
+
         let status = {
             // Put the terminal into raw mode.  The tty guard will restore the
             // console settings when it goes out of scope.
@@ -91,7 +168,7 @@ impl CommandDispatch for Console {
             };
             let mut stdout = std::io::stdout();
 
-            let uart = self.params.create(transport)?;
+            let uart = self.console_device(transport)?;
             if let Some(send) = self.send.as_ref() {
                 log::info!("Sending: {:?}", send);
                 uart.write(send.as_bytes())?;
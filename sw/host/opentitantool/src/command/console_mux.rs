@@ -0,0 +1,71 @@
+// Copyright lowRISC contributors (OpenTitan project).
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use clap::Args;
+use serde_annotate::Annotate;
+use std::any::Any;
+use std::fs::File;
+use std::path::PathBuf;
+
+use opentitanlib::app::command::CommandDispatch;
+use opentitanlib::app::TransportWrapper;
+use opentitanlib::io::uart::UartParams;
+use opentitanlib::transport::Capability;
+use opentitanlib::uart::mux::ConsoleMux;
+
+/// Share the console between multiple readers, through pseudo-terminals and Unix domain sockets.
+#[derive(Debug, Args)]
+pub struct ConsoleMuxCommand {
+    #[command(flatten)]
+    params: UartParams,
+
+    /// Log console output to a file, with a timestamp on each line.
+    #[arg(short, long)]
+    logfile: Option<String>,
+
+    /// Create a pseudo-terminal, with a symbolic link to it at the given path.  May be repeated.
+    /// If neither this nor `--socket` is given, a single pseudo-terminal is created.
+    #[arg(long)]
+    pty: Vec<PathBuf>,
+
+    /// Listen for connections on a Unix domain socket at the given path.  May be repeated.
+    #[arg(long)]
+    socket: Vec<PathBuf>,
+}
+
+impl CommandDispatch for ConsoleMuxCommand {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        transport.capabilities()?.request(Capability::UART).ok()?;
+
+        let uart = self.params.create(transport)?;
+        let mut mux = ConsoleMux::new(uart)?;
+        if let Some(logfile) = &self.logfile {
+            mux.set_logfile(File::create(logfile)?);
+        }
+        if self.pty.is_empty() && self.socket.is_empty() {
+            let path = mux.add_pty(None)?;
+            eprintln!("Console available on {}", path.display());
+        }
+        for link in &self.pty {
+            let path = mux.add_pty(Some(link))?;
+            eprintln!(
+                "Console available on {} ({})",
+                link.display(),
+                path.display()
+            );
+        }
+        for path in &self.socket {
+            mux.add_socket(path)?;
+            eprintln!("Console available on socket {}", path.display());
+        }
+        eprintln!("[CTRL+C] to exit.");
+        mux.run()?;
+        Ok(None)
+    }
+}
@@ -10,6 +10,7 @@ use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::FromStr;
 use std::time::Duration;
@@ -17,6 +18,8 @@ use std::time::Duration;
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
 use opentitanlib::io::gpio::{ClockNature, Edge, GpioPin, PinMode, PullMode};
+use opentitanlib::io::spi::TransferMode;
+use opentitanlib::test_utils::gpio_decode::Capture;
 use opentitanlib::transport::Capability;
 use opentitanlib::util::file;
 use opentitanlib::util::raw_tty::RawTty;
@@ -236,6 +239,7 @@ pub enum GpioMonitoringCommand {
     Start(GpioMonitoringStart),
     Read(GpioMonitoringRead),
     Vcd(GpioMonitoringVcd),
+    Decode(GpioMonitoringDecode),
 }
 
 #[derive(Debug, Args)]
@@ -514,6 +518,105 @@ impl CommandDispatch for GpioMonitoringVcd {
     }
 }
 
+#[derive(Debug, Subcommand)]
+pub enum DecodeProtocol {
+    /// Decode bytes received by a UART, with 8 data bits, no parity and one stop bit.
+    Uart {
+        /// Signal carrying the UART data.
+        #[arg(long)]
+        rx: String,
+        #[arg(long)]
+        baudrate: u32,
+    },
+    /// Decode SPI transactions.
+    Spi {
+        #[arg(long)]
+        sck: String,
+        #[arg(long)]
+        cs: String,
+        #[arg(long)]
+        copi: Option<String>,
+        #[arg(long)]
+        cipo: Option<String>,
+        /// SPI polarity/phase mode.
+        #[arg(long, default_value = "0")]
+        mode: TransferMode,
+    },
+    /// Decode I2C transfers.
+    I2c {
+        #[arg(long)]
+        scl: String,
+        #[arg(long)]
+        sda: String,
+    },
+    /// Decode JTAG TAP state transitions, and bits shifted in the Shift-DR/IR states.
+    Jtag {
+        #[arg(long)]
+        tck: String,
+        #[arg(long)]
+        tms: String,
+        #[arg(long)]
+        tdi: Option<String>,
+        #[arg(long)]
+        tdo: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+/// Decode UART, SPI, I2C or JTAG traffic from a VCD file written by `monitoring vcd`.  Signals
+/// are referred to by the pin names given when monitoring.
+pub struct GpioMonitoringDecode {
+    /// VCD file to decode.
+    pub vcd: PathBuf,
+
+    #[command(subcommand)]
+    pub protocol: DecodeProtocol,
+}
+
+impl CommandDispatch for GpioMonitoringDecode {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        _transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let capture = Capture::from_vcd(&std::fs::read_to_string(&self.vcd)?)?;
+        let result: Box<dyn Annotate> = match &self.protocol {
+            DecodeProtocol::Uart { rx, baudrate } => Box::new(capture.decode_uart(rx, *baudrate)?),
+            DecodeProtocol::Spi {
+                sck,
+                cs,
+                copi,
+                cipo,
+                mode,
+            } => Box::new(capture.decode_spi(sck, cs, copi.as_deref(), cipo.as_deref(), *mode)?),
+            DecodeProtocol::I2c { scl, sda } => Box::new(capture.decode_i2c(scl, sda)?),
+            DecodeProtocol::Jtag { tck, tms, tdi, tdo } => {
+                Box::new(capture.decode_jtag(tck, tms, tdi.as_deref(), tdo.as_deref())?)
+            }
+        };
+        Ok(Some(result))
+    }
+}
+
+#[derive(Debug, Args)]
+/// Run a configuration-named sequence of strapping changes, pin writes and delays
+pub struct GpioRunSequence {
+    /// The sequence to run.
+    pub name: String,
+}
+
+impl CommandDispatch for GpioRunSequence {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        transport.capabilities()?.request(Capability::GPIO).ok()?;
+        transport.run_sequence(&self.name)?;
+        Ok(None)
+    }
+}
+
 #[derive(Debug, Args)]
 /// Remove a configuration-named pin strapping
 pub struct GpioRemoveStrapping {
@@ -639,6 +742,7 @@ impl CommandDispatch for GpioBitbang {
 pub enum GpioCommand {
     Apply(GpioApplyStrapping),
     Remove(GpioRemoveStrapping),
+    Sequence(GpioRunSequence),
     Read(GpioRead),
     Write(GpioWrite),
     SetMode(GpioSetMode),
@@ -6,6 +6,7 @@ pub mod bootstrap;
 pub mod certificate;
 pub mod clear_bitstream;
 pub mod console;
+pub mod console_mux;
 pub mod ecdsa;
 pub mod emulator;
 pub mod fpga;
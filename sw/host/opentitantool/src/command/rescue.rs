@@ -4,7 +4,6 @@
 
 use anyhow::{anyhow, Result};
 use clap::{Args, Subcommand};
-use opentitanlib::io::uart::UartParams;
 use serde_annotate::Annotate;
 use std::any::Any;
 use std::fs::File;
@@ -12,12 +11,13 @@ use std::path::PathBuf;
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
-use opentitanlib::chip::boot_svc::BootSlot;
+use opentitanlib::chip::boot_svc::{BootSlot, BootSvcKind};
 use opentitanlib::chip::helper::{OwnershipActivateParams, OwnershipUnlockParams};
 use opentitanlib::image::image::Image;
 use opentitanlib::image::manifest::ManifestKind;
-use opentitanlib::rescue::serial::RescueSerial;
+use opentitanlib::rescue::{RescueParams, RescueProtocol};
 use opentitanlib::util::file::FromReader;
+use opentitanlib::util::parse_int::ParseInt;
 
 #[derive(Debug, serde::Serialize, Annotate)]
 pub struct RawBytes(
@@ -26,10 +26,25 @@ pub struct RawBytes(
     Vec<u8>,
 );
 
+/// Fetches the response to a boot services `request` if `wait` is set.
+fn boot_svc_response(
+    rescue: &dyn RescueProtocol,
+    transport: &TransportWrapper,
+    request: BootSvcKind,
+    wait: bool,
+) -> Result<Option<Box<dyn Annotate>>> {
+    if wait {
+        let response = rescue.wait_boot_svc_response(transport, request)?;
+        Ok(Some(Box::new(response)))
+    } else {
+        Ok(None)
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct Firmware {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(long, default_value_t = false, help = "Upload the file contents as-is")]
     raw: bool,
     #[arg(value_name = "FILE")]
@@ -54,8 +69,7 @@ impl CommandDispatch for Firmware {
             log::info!("Found application image at offset {:#x}", subimage.offset);
             subimage.data
         };
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
+        let rescue = self.params.create(transport)?;
         rescue.enter(transport)?;
         rescue.update_firmware(payload)?;
         Ok(None)
@@ -65,7 +79,7 @@ impl CommandDispatch for Firmware {
 #[derive(Debug, Args)]
 pub struct GetBootLog {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(long, short, default_value = "false")]
     raw: bool,
 }
@@ -76,8 +90,7 @@ impl CommandDispatch for GetBootLog {
         _context: &dyn Any,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
+        let rescue = self.params.create(transport)?;
         rescue.enter(transport)?;
         if self.raw {
             let data = rescue.get_boot_log_raw()?;
@@ -92,7 +105,7 @@ impl CommandDispatch for GetBootLog {
 #[derive(Debug, Args)]
 pub struct GetBootSvc {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(long, short, default_value = "false")]
     raw: bool,
 }
@@ -103,8 +116,7 @@ impl CommandDispatch for GetBootSvc {
         _context: &dyn Any,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
+        let rescue = self.params.create(transport)?;
         rescue.enter(transport)?;
         if self.raw {
             let data = rescue.get_boot_svc_raw()?;
@@ -116,10 +128,61 @@ impl CommandDispatch for GetBootSvc {
     }
 }
 
+#[derive(Debug, Args)]
+pub struct Empty {
+    #[command(flatten)]
+    params: RescueParams,
+    #[arg(long, help = "Reboot and fetch the boot services response")]
+    wait_response: bool,
+}
+
+impl CommandDispatch for Empty {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let rescue = self.params.create(transport)?;
+        rescue.enter(transport)?;
+        rescue.clear_boot_svc()?;
+        boot_svc_response(&*rescue, transport, BootSvcKind::Empty, self.wait_response)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SetMinBl0SecVer {
+    #[command(flatten)]
+    params: RescueParams,
+    #[arg(long, help = "Reboot and fetch the boot services response")]
+    wait_response: bool,
+    #[arg(value_parser = u32::from_str, help = "The minimum BL0 security version")]
+    ver: u32,
+}
+
+impl CommandDispatch for SetMinBl0SecVer {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let rescue = self.params.create(transport)?;
+        rescue.enter(transport)?;
+        rescue.set_min_bl0_sec_ver(self.ver)?;
+        boot_svc_response(
+            &*rescue,
+            transport,
+            BootSvcKind::MinBl0SecVerRequest,
+            self.wait_response,
+        )
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct SetNextBl0Slot {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
+    #[arg(long, help = "Reboot and fetch the boot services response")]
+    wait_response: bool,
     #[arg(default_value = "SlotA")]
     slot: BootSlot,
 }
@@ -130,18 +193,24 @@ impl CommandDispatch for SetNextBl0Slot {
         _context: &dyn Any,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
+        let rescue = self.params.create(transport)?;
         rescue.enter(transport)?;
         rescue.set_next_bl0_slot(self.slot)?;
-        Ok(None)
+        boot_svc_response(
+            &*rescue,
+            transport,
+            BootSvcKind::NextBl0SlotRequest,
+            self.wait_response,
+        )
     }
 }
 
 #[derive(Debug, Args)]
 pub struct SetPrimaryBl0Slot {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
+    #[arg(long, help = "Reboot and fetch the boot services response")]
+    wait_response: bool,
     #[arg(default_value = "SlotA")]
     slot: BootSlot,
 }
@@ -152,18 +221,24 @@ impl CommandDispatch for SetPrimaryBl0Slot {
         _context: &dyn Any,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
+        let rescue = self.params.create(transport)?;
         rescue.enter(transport)?;
         rescue.set_primary_bl0_slot(self.slot)?;
-        Ok(None)
+        boot_svc_response(
+            &*rescue,
+            transport,
+            BootSvcKind::PrimaryBl0SlotRequest,
+            self.wait_response,
+        )
     }
 }
 
 #[derive(Debug, Args)]
 pub struct OwnershipUnlock {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
+    #[arg(long, help = "Reboot and fetch the boot services response")]
+    wait_response: bool,
     #[command(flatten)]
     unlock: OwnershipUnlockParams,
     #[arg(short, long, help = "A file containing a binary unlock request")]
@@ -180,18 +255,24 @@ impl CommandDispatch for OwnershipUnlock {
             .unlock
             .apply_to(self.input.as_ref().map(File::open).transpose()?.as_mut())?;
 
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
+        let rescue = self.params.create(transport)?;
         rescue.enter(transport)?;
         rescue.ownership_unlock(unlock)?;
-        Ok(None)
+        boot_svc_response(
+            &*rescue,
+            transport,
+            BootSvcKind::OwnershipUnlockRequest,
+            self.wait_response,
+        )
     }
 }
 
 #[derive(Debug, Args)]
 pub struct OwnershipActivate {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
+    #[arg(long, help = "Reboot and fetch the boot services response")]
+    wait_response: bool,
     #[command(flatten)]
     activate: OwnershipActivateParams,
     #[arg(short, long, help = "A file containing a binary activate request")]
@@ -208,18 +289,22 @@ impl CommandDispatch for OwnershipActivate {
             .activate
             .apply_to(self.input.as_ref().map(File::open).transpose()?.as_mut())?;
 
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
+        let rescue = self.params.create(transport)?;
         rescue.enter(transport)?;
         rescue.ownership_activate(activate)?;
-        Ok(None)
+        boot_svc_response(
+            &*rescue,
+            transport,
+            BootSvcKind::OwnershipActivateRequest,
+            self.wait_response,
+        )
     }
 }
 
 #[derive(Debug, Args)]
 pub struct SetOwnerConfig {
     #[command(flatten)]
-    params: UartParams,
+    params: RescueParams,
     #[arg(help = "A signed owner configuration block")]
     input: PathBuf,
 }
@@ -231,8 +316,7 @@ impl CommandDispatch for SetOwnerConfig {
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
         let data = std::fs::read(&self.input)?;
-        let uart = self.params.create(transport)?;
-        let rescue = RescueSerial::new(uart);
+        let rescue = self.params.create(transport)?;
         rescue.enter(transport)?;
         rescue.set_owner_config(&data)?;
         Ok(None)
@@ -242,6 +326,8 @@ impl CommandDispatch for SetOwnerConfig {
 #[derive(Debug, Subcommand, CommandDispatch)]
 pub enum BootSvc {
     Get(GetBootSvc),
+    Empty(Empty),
+    SetMinBl0SecVer(SetMinBl0SecVer),
     SetNextBl0Slot(SetNextBl0Slot),
     SetPrimaryBl0Slot(SetPrimaryBl0Slot),
     OwnershipUnlock(OwnershipUnlock),
@@ -256,3 +342,41 @@ pub enum RescueCommand {
     Firmware(Firmware),
     SetOwnerConfig(SetOwnerConfig),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, Parser};
+    use opentitanlib::chip::boot_svc::UnlockMode;
+
+    #[derive(Parser)]
+    struct Opts {
+        #[command(subcommand)]
+        command: RescueCommand,
+    }
+
+    #[test]
+    fn test_unique_arguments() {
+        // The rescue options are flattened into every command, and must not clash with the
+        // options of the command itself.
+        Opts::command().debug_assert();
+    }
+
+    #[test]
+    fn test_unlock_mode() {
+        let opts = Opts::try_parse_from([
+            "rescue",
+            "boot-svc",
+            "ownership-unlock",
+            "--mode",
+            "Any",
+            "--spi-mode",
+            "mode3",
+        ])
+        .unwrap();
+        let RescueCommand::BootSvc(BootSvc::OwnershipUnlock(unlock)) = opts.command else {
+            panic!("unexpected command");
+        };
+        assert_eq!(unlock.unlock.mode, Some(UnlockMode::Any));
+    }
+}
@@ -14,6 +14,7 @@ use std::time::Duration;
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::{StagedProgressBar, TransportWrapper};
+use opentitanlib::proxy::protocol::{LeaseInfo, LeaseKind};
 use opentitanlib::transport::verilator::transport::Watch;
 use opentitanlib::transport::UpdateFirmware;
 
@@ -140,6 +141,119 @@ impl CommandDispatch for TransportQueryAll {
     }
 }
 
+/// Print the configuration resulting from all configuration files, including the files declaring
+/// each pin, strapping, bus and UART, and the source of each `provides` entry.
+#[derive(Debug, Args)]
+pub struct TransportConfig {}
+
+impl CommandDispatch for TransportConfig {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        Ok(Some(Box::new(transport.resolved_configuration())))
+    }
+}
+
+/// Reserve a transport shared through `opentitansession`.  The lease is held until released, the
+/// connection is closed (i.e. this invocation of opentitantool exits), or the timeout passes.
+/// Use together with `--exec` to run several commands under the same lease.
+#[derive(Debug, Args)]
+pub struct TransportLeaseAcquire {
+    /// Kind of lease, an exclusive lease cannot be held while any other lease is.
+    #[arg(long, value_enum, default_value = "exclusive")]
+    kind: LeaseKind,
+    /// Automatically release the lease after this long.
+    #[arg(short, long, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+    /// Name identifying the holder to other clients, defaults to the user name.
+    #[arg(long)]
+    owner: Option<String>,
+}
+
+impl CommandDispatch for TransportLeaseAcquire {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        let owner = match &self.owner {
+            Some(owner) => owner.clone(),
+            None => std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+        };
+        transport
+            .proxy_ops()?
+            .acquire_lease(self.kind, self.timeout, &owner)?;
+        Ok(None)
+    }
+}
+
+/// Release the lease held by this connection.
+#[derive(Debug, Args)]
+pub struct TransportLeaseRelease {}
+
+impl CommandDispatch for TransportLeaseRelease {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        transport.proxy_ops()?.release_lease()?;
+        Ok(None)
+    }
+}
+
+/// List the leases currently held on the transport.
+#[derive(Debug, Args)]
+pub struct TransportLeaseList {}
+
+#[derive(serde::Serialize)]
+pub struct TransportLeaseListResult {
+    leases: Vec<LeaseInfo>,
+}
+
+impl CommandDispatch for TransportLeaseList {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        Ok(Some(Box::new(TransportLeaseListResult {
+            leases: transport.proxy_ops()?.leases()?,
+        })))
+    }
+}
+
+/// List the names of the transports served by `opentitansession`, for use with `--proxy-target`.
+#[derive(Debug, Args)]
+pub struct TransportListTargets {}
+
+#[derive(serde::Serialize)]
+pub struct TransportListTargetsResult {
+    targets: Vec<String>,
+}
+
+impl CommandDispatch for TransportListTargets {
+    fn run(
+        &self,
+        _context: &dyn Any,
+        transport: &TransportWrapper,
+    ) -> Result<Option<Box<dyn Annotate>>> {
+        Ok(Some(Box::new(TransportListTargetsResult {
+            targets: transport.proxy_ops()?.targets()?,
+        })))
+    }
+}
+
+/// Commands for reserving a transport shared through `opentitansession`.
+#[derive(Debug, Subcommand, CommandDispatch)]
+pub enum TransportLeaseCommand {
+    Acquire(TransportLeaseAcquire),
+    Release(TransportLeaseRelease),
+    List(TransportLeaseList),
+}
+
 /// Commands for interacting with the transport debugger device itself.
 #[derive(Debug, Subcommand, CommandDispatch)]
 pub enum TransportCommand {
@@ -148,4 +262,8 @@ pub enum TransportCommand {
     UpdateFirmware(TransportUpdateFirmware),
     Query(TransportQuery),
     QueryAll(TransportQueryAll),
+    Config(TransportConfig),
+    #[command(subcommand)]
+    Lease(TransportLeaseCommand),
+    ListTargets(TransportListTargets),
 }
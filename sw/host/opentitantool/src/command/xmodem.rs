@@ -2,22 +2,29 @@
 // Licensed under the Apache License, Version 2.0, see LICENSE for details.
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::Result;
+use anyhow::{anyhow, ensure, Result};
 use clap::{Args, Subcommand};
 use opentitanlib::io::uart::UartParams;
 use serde_annotate::Annotate;
 use std::any::Any;
+use std::path::PathBuf;
 
 use opentitanlib::app::command::CommandDispatch;
 use opentitanlib::app::TransportWrapper;
-use opentitanlib::rescue::xmodem::Xmodem;
+use opentitanlib::rescue::xmodem::{Xmodem, XmodemBlock};
 
 #[derive(Debug, Args)]
 pub struct XmodemSend {
     #[command(flatten)]
     params: UartParams,
-    #[arg(value_name = "FILE")]
-    filename: String,
+    /// Block size to use (the receiver may force 128-byte blocks).
+    #[arg(long, value_enum, default_value = "1k")]
+    block_len: XmodemBlock,
+    /// Send the files as a YMODEM batch, with their names and sizes.
+    #[arg(long)]
+    ymodem: bool,
+    #[arg(value_name = "FILE", required = true, num_args = 1..)]
+    filename: Vec<PathBuf>,
 }
 
 impl CommandDispatch for XmodemSend {
@@ -26,11 +33,39 @@ impl CommandDispatch for XmodemSend {
         _context: &dyn Any,
         transport: &TransportWrapper,
     ) -> Result<Option<Box<dyn Annotate>>> {
-        let payload = std::fs::read(&self.filename)?;
-        let xmodem = Xmodem::new();
+        ensure!(
+            self.ymodem || self.filename.len() == 1,
+            "Sending multiple files requires --ymodem"
+        );
+        let payloads = self
+            .filename
+            .iter()
+            .map(std::fs::read)
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let xmodem = Xmodem {
+            block_len: self.block_len,
+            ..Xmodem::new()
+        };
         let uart = self.params.create(transport)?;
         uart.clear_rx_buffer()?;
-        xmodem.send(&*uart, payload.as_slice())?;
+        if self.ymodem {
+            let names = self
+                .filename
+                .iter()
+                .map(|f| {
+                    f.file_name()
+                        .and_then(|n| n.to_str())
+                        .ok_or_else(|| anyhow!("Invalid file name: {}", f.display()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let files = names
+                .into_iter()
+                .zip(payloads.iter().map(Vec::as_slice))
+                .collect::<Vec<_>>();
+            xmodem.send_batch(&*uart, &files)?;
+        } else {
+            xmodem.send(&*uart, payloads[0].as_slice())?;
+        }
         Ok(None)
     }
 }
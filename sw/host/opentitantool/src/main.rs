@@ -29,6 +29,8 @@ enum RootCommandHierarchy {
     Bootstrap(command::bootstrap::BootstrapCommand),
     // Not flattened because `Console` is a leaf command.
     Console(command::console::Console),
+    // Not flattened because `ConsoleMux` is a leaf command.
+    ConsoleMux(command::console_mux::ConsoleMuxCommand),
 
     #[command(subcommand)]
     Gpio(command::gpio::GpioCommand),
@@ -5,7 +5,9 @@
 use anyhow::{anyhow, Result};
 use mio::net::TcpStream;
 use num_enum::TryFromPrimitive;
+use opentitanlib::app::TransportWrapper;
 use opentitanlib::tpm::Driver;
+use opentitanlib::util::usb;
 use std::io::{Read, Write};
 use std::net::Shutdown;
 
@@ -65,7 +67,11 @@ const NO_LOCALITY_CTL: u32 = 0x20;
 const NO_NV_CTL: u32 = 0x40;
 
 /// Serve the command port for the TPM, forwarding commands to the bus specified in `opts`.
-pub(crate) fn serve_command(stream: &mut TcpStream, tpm: &dyn Driver) -> Result<bool> {
+pub(crate) fn serve_command(
+    stream: &mut TcpStream,
+    tpm: &dyn Driver,
+    transport: &TransportWrapper,
+) -> Result<bool> {
     let mut data = [0u8; CMD_SIZE];
     let len = stream.read(&mut data)?;
     if len == 0 {
@@ -78,13 +84,18 @@ pub(crate) fn serve_command(stream: &mut TcpStream, tpm: &dyn Driver) -> Result<
         stream.shutdown(Shutdown::Both)?;
         Ok(true)
     } else {
-        handle_cmd(cmd, stream, tpm).map(|_| false)
+        handle_cmd(cmd, stream, tpm, transport).map(|_| false)
     }
 }
 
 /// Handle the requested command and send the reply on `stream`. If this it a TPM command, send it
 /// to `tpm`.
-fn handle_cmd(cmd: TcpTpmCommands, stream: &mut TcpStream, tpm: &dyn Driver) -> Result<()> {
+fn handle_cmd(
+    cmd: TcpTpmCommands,
+    stream: &mut TcpStream,
+    tpm: &dyn Driver,
+    transport: &TransportWrapper,
+) -> Result<()> {
     const CFG: u32 = PLATFORM_AVAILABLE | NO_POWER_CTL | NO_LOCALITY_CTL | NO_NV_CTL;
     log::info!("CMD {:?}", cmd);
     match cmd {
@@ -98,7 +109,7 @@ fn handle_cmd(cmd: TcpTpmCommands, stream: &mut TcpStream, tpm: &dyn Driver) ->
             stream.write_all(&[0u8; 4])?;
             Ok(())
         }
-        TcpTpmCommands::SendCommand => handle_send(stream, tpm),
+        TcpTpmCommands::SendCommand => handle_send(stream, tpm, transport),
         _ => {
             let _ = stream.write(&[0u8; 4])?;
             Ok(())
@@ -106,8 +117,15 @@ fn handle_cmd(cmd: TcpTpmCommands, stream: &mut TcpStream, tpm: &dyn Driver) ->
     }
 }
 
-/// Forward a TPM command to the device and send the reponse back to `stream`.
-fn handle_send(stream: &mut TcpStream, tpm: &dyn Driver) -> Result<()> {
+/// Forward a TPM command to the device and send the reponse back to `stream`.  If the debugger
+/// has disconnected, e.g. due to being power-cycled, reconnect such that the next command can
+/// be executed.  The failed command is not retried, as it may already have reached the TPM, and
+/// TPM commands are generally not idempotent.
+fn handle_send(
+    stream: &mut TcpStream,
+    tpm: &dyn Driver,
+    transport: &TransportWrapper,
+) -> Result<()> {
     let mut locality = [0u8; 1];
     let mut sz = [0u8; 4];
     stream.read_exact(&mut locality)?;
@@ -122,7 +140,14 @@ fn handle_send(stream: &mut TcpStream, tpm: &dyn Driver) -> Result<()> {
     }
 
     log::debug!("TPM cmd {:02x?}", cmd);
-    match tpm.execute_command(&cmd) {
+    let result = tpm.execute_command(&cmd);
+    if let Err(e) = &result {
+        if usb::is_disconnected(e) {
+            log::warn!("Debugger disconnected, reconnecting: {}", e);
+            transport.reconnect()?;
+        }
+    }
+    match result {
         Ok(res) => {
             stream.write_all(&(res.len() as u32).to_be_bytes())?;
             stream.write_all(&res)?;
@@ -107,12 +107,12 @@ pub fn main() -> anyhow::Result<()> {
         for event in events.iter() {
             match event.token() {
                 CMD_TOKEN => {
-                    if serve_command(&mut cmd_stream, &*bus)? {
+                    if serve_command(&mut cmd_stream, &*bus, &transport)? {
                         return Ok(());
                     }
                 }
                 PLATFORM_TOKEN => {
-                    if serve_command(&mut platform_stream, &*bus)? {
+                    if serve_command(&mut platform_stream, &*bus, &transport)? {
                         return Ok(());
                     }
                 }
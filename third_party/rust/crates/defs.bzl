@@ -331,6 +331,7 @@ _NORMAL_DEPENDENCIES = {
             "memoffset": "@crate_index__memoffset-0.9.0//:memoffset",
             "mio": "@crate_index__mio-0.8.8//:mio",
             "mio-signals": "@crate_index__mio-signals-0.2.0//:mio_signals",
+            "nix": "@crate_index__nix-0.26.4//:nix",
             "num-bigint-dig": "@crate_index__num-bigint-dig-0.8.4//:num_bigint_dig",
             "num-traits": "@crate_index__num-traits-0.2.17//:num_traits",
             "num_enum": "@crate_index__num_enum-0.7.0//:num_enum",